@@ -1,47 +1,228 @@
 use std::collections::{BTreeSet};
 use std::env::current_dir;
 use std::fs;
-use std::io::Write;
+use std::fs::canonicalize;
+use std::io;
+use std::io::{Read, Write};
 use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::{ArgMatches};
-use crate::common::{get_path_from_project_root, get_project_root};
+use crate::attributes::Attributes;
+use crate::config::autocrlf;
+use crate::common::{cmp_path_bytes, common_dir, get_path_from, get_path_from_project_root, get_project_root, is_pattern,
+                    path_from_root, pathspec_from, pathspec_from_cwd, pathspec_matches};
+use crate::ignore::Ignore;
 use crate::index::{Index, IndexEntry};
 use crate::object::{Blob};
 
 pub fn run(matches: &ArgMatches) -> Result<(), String>{
-    let path = matches.value_of("path").ok_or("no path specified")?;
+    let specs = match matches.value_of("pathspec-from-file") {
+        Some(file) => read_pathspec_file(file, matches.is_present("pathspec-file-nul"))?,
+        None => vec![matches.value_of("path").ok_or("no path specified")?.to_string()],
+    };
 
     let proj_root = get_project_root()?;
-    let object_path = proj_root.join(".git/objects");
+    let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
     let mut blob_list: Vec<Box<Blob>> = Vec::new();
-    let index = Index::from_file(&proj_root);
+    let index = Index::from_file(&proj_root)?;
     let index_box = match index {
         None => None,
         Some(s) => Some(Box::new(s)),
     };
 
-    let search_root = get_path_from_project_root(&PathBuf::from(path))?;
-    let mut parser = DiffParser::from(index_box.clone(), search_root)?;
-    let results = parser.parse()?;
+    let (mut new, mut modify, mut delete) = (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+    for spec in &specs {
+        let changes = changes_in(&proj_root, &prefix, &index_box, spec)?;
+        new.extend(changes.0);
+        modify.extend(changes.1);
+        delete.extend(changes.2);
+    }
+    if matches.is_present("verbose") {
+        for line in verbose_lines(&new, &modify, &delete) {
+            println!("{}", line);
+        }
+    }
 
-    let new_index = match &index_box {
+    if matches.is_present("intent-to-add") {
+        let mut index = index_box.unwrap_or_else(|| Box::new(Index::new()));
+        add_intent_to_add(&proj_root, &new, &mut index)?;
+        return write_index_and_blobs(&proj_root, &index, &blob_list);
+    }
+
+    let mut new_index = match &index_box {
         None => {
-            let index= create_index(&proj_root, results.0, &mut blob_list)?;
+            let index= create_index(&proj_root, &new, &mut blob_list)?;
             index
         },
         Some(_) => {
             let mut new_index = index_box.unwrap();
             update_index(&proj_root, &mut new_index,
-                         results.0, results.1, results.2, &mut blob_list)?;
+                         &new, &modify, &delete, &mut blob_list)?;
             new_index
         },
     };
+    if let Some(chmod) = matches.value_of("chmod") {
+        for spec in &specs {
+            let spec = pathspec_from_cwd(spec)?;
+            match is_pattern(&spec) {
+                true => {
+                    for node in new_index.entries().into_keys().filter(|n| pathspec_matches(&spec, n)) {
+                        new_index.chmod(&node, chmod == "+x")?;
+                    }
+                },
+                false => new_index.chmod(Path::new(&spec), chmod == "+x")?,
+            }
+        }
+    }
+
+    write_index_and_blobs(&proj_root, &new_index, &blob_list)
+}
+
+type NodeSets = (BTreeSet<PathBuf>, BTreeSet<PathBuf>, BTreeSet<PathBuf>);
+
+// new, modified and deleted files of one pathspec given in the directory at prefix
+fn changes_in(proj_root: &Path, prefix: &Path, index: &Option<Box<Index>>, spec: &str) -> Result<NodeSets, String> {
+    let no_match = || format!("pathspec '{}' did not match any files", spec);
+    let tracked = |spec: &str| -> BTreeSet<PathBuf> {
+        index.as_ref().map(|i| i.entries().into_keys().filter(|n| pathspec_matches(spec, n)).collect()).
+            unwrap_or_default()
+    };
+
+    // a pattern picks from every file of the working tree
+    if is_pattern(spec) {
+        let spec = pathspec_from(prefix, spec)?;
+        let mut parser = DiffParser::from(index.clone(), PathBuf::new(), proj_root)?;
+        parser.search_root = proj_root.to_path_buf();
+        let (new, modify, delete) = parser.parse()?;
+        let pick = |nodes: &BTreeSet<PathBuf>| -> BTreeSet<PathBuf> {
+            nodes.iter().filter(|n| pathspec_matches(&spec, n)).cloned().collect()
+        };
+        if tracked(&spec).is_empty() && pick(new).is_empty() {
+            return Err(no_match());
+        }
+        return Ok((pick(new), pick(modify), pick(delete)));
+    }
+
+    // gone from the working tree, only tracked files can be staged as deleted
+    let search_root = path_from_root(prefix, spec)?;
+    if fs::symlink_metadata(proj_root.join(&search_root)).is_err() {
+        let deleted = tracked(&search_root.to_string_lossy());
+        if deleted.is_empty() {
+            return Err(no_match());
+        }
+        return Ok((BTreeSet::new(), BTreeSet::new(), deleted));
+    }
+
+    // naming an ignored file that is not tracked yet is a mistake, unlike a directory with some in it
+    let is_dir = proj_root.join(&search_root).is_dir();
+    if tracked(&search_root.to_string_lossy()).is_empty() &&
+        Ignore::from_work_tree(proj_root)?.is_ignored(&search_root, is_dir) {
+        return Err(format!("The following paths are ignored by one of your .gitignore files:\n{}", spec));
+    }
+    let mut parser = DiffParser::from(index.clone(), search_root.clone(), proj_root)?;
+    // from the project root, the relative path would be taken from the current directory
+    parser.search_root = proj_root.join(search_root);
+    let (new, modify, delete) = parser.parse()?;
+    Ok((new.clone(), modify.clone(), delete.clone()))
+}
+
+// `--pathspec-from-file`: one path per line, or NUL separated, "-" for stdin
+fn read_pathspec_file(file: &str, nul: bool) -> Result<Vec<String>, String> {
+    let content = match file {
+        "-" => {
+            let mut buf = String::new();
+            io::stdin().read_to_string(&mut buf).map_err(|e| e.to_string())?;
+            buf
+        },
+        _ => fs::read_to_string(file).map_err(|e| format!("could not open '{}': {}", file, e))?,
+    };
+    Ok(split_pathspecs(&content, nul))
+}
+
+fn split_pathspecs(content: &str, nul: bool) -> Vec<String> {
+    content.split(if nul { '\0' } else { '\n' }).filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
 
-    let index_bytes = new_index.to_bytes();
-    let mut index_file = fs::File::create(proj_root.join(".git/index")).
-                        map_err(|e| e.to_string())?;
-    index_file.write_all(&index_bytes).map_err(|e| e.to_string())?;
+#[test]
+fn test_split_pathspecs() {
+    assert_eq!(split_pathspecs("a.txt\nsrc/b c.rs\n\n", false), vec!["a.txt", "src/b c.rs"]);
+    assert_eq!(split_pathspecs("a\nb\0c\0", true), vec!["a\nb", "c"]);
+    assert!(split_pathspecs("", false).is_empty());
+}
+
+#[test]
+fn test_changes_in() {
+    let proj_root = crate::common::test_dir("add_changes_in");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    fs::create_dir_all(proj_root.join("sub")).unwrap();
+    for file in ["a.txt", "gone.txt", "sub/x", "sub/y.rs"] {
+        fs::write(proj_root.join(file), file).unwrap();
+    }
+    let mut index = Index::new();
+    index.add_entry_in(&proj_root, Path::new("gone.txt"), crate::hash::Hash::default()).unwrap();
+    fs::remove_file(proj_root.join("gone.txt")).unwrap();
+    let index = Some(Box::new(index));
+    let paths = |nodes: BTreeSet<PathBuf>| nodes.iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+    let changes = |prefix: &str, spec: &str| changes_in(&proj_root, Path::new(prefix), &index, spec).
+        map(|(new, modify, delete)| (paths(new), paths(modify), paths(delete)));
+
+    // the specs of --pathspec-from-file, each staging just what it names
+    let specs = split_pathspecs("a.txt\nsub\ngone.txt\n", false);
+    let staged: Vec<_> = specs.iter().map(|spec| changes("", spec).unwrap()).collect();
+    assert_eq!(staged[0], (vec!["a.txt".to_string()], vec![], vec![]));
+    assert_eq!(staged[1], (vec!["sub/x".to_string(), "sub/y.rs".to_string()], vec![], vec![]));
+    assert_eq!(staged[2], (vec![], vec![], vec!["gone.txt".to_string()]));
+
+    // relative to the current directory
+    assert_eq!(changes("sub", "../a.txt").unwrap().0, vec!["a.txt"]);
+    assert_eq!(changes("sub", "*.rs").unwrap().0, vec!["sub/y.rs"]);
+    assert_eq!(changes("", "missing.txt"), Err("pathspec 'missing.txt' did not match any files".to_string()));
+}
+
+/*
+    `add -v`: "add '<path>'" or "remove '<path>'" per staged path, in git's order
+    the tracked files first, then the new ones, each sorted byte-wise on the whole path
+ */
+fn verbose_lines(new: &BTreeSet<PathBuf>, modify: &BTreeSet<PathBuf>, delete: &BTreeSet<PathBuf>) -> Vec<String> {
+    let mut tracked: Vec<(&PathBuf, &str)> = modify.iter().map(|p| (p, "add")).
+        chain(delete.iter().map(|p| (p, "remove"))).collect();
+    tracked.sort_by(|a, b| cmp_path_bytes(a.0, b.0));
+    let mut new: Vec<&PathBuf> = new.iter().collect();
+    new.sort_by(|a, b| cmp_path_bytes(a, b));
+    tracked.into_iter().chain(new.into_iter().map(|p| (p, "add"))).
+        map(|(path, action)| format!("{} '{}'", action, path.display())).collect()
+}
+
+#[test]
+fn test_verbose_lines() {
+    let set = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<BTreeSet<_>>();
+    assert_eq!(verbose_lines(&set(&["z/a", "a.new", "a/b/c", "a-b"]), &set(&["src/main.rs", "a/x"]), &set(&["README"])),
+               vec!["remove 'README'", "add 'a/x'", "add 'src/main.rs'",
+                    "add 'a-b'", "add 'a.new'", "add 'a/b/c'", "add 'z/a'"]);
+}
+
+// `commit -a`: stage modified and deleted tracked files of the whole tree, new files are left as is
+// the index is written with the blobs unless dry_run, either way it is returned
+pub fn update_tracked(proj_root: &Path, dry_run: bool) -> Result<Box<Index>, String> {
+    let proj_root = proj_root.to_path_buf();
+    let mut index = Box::new(Index::from_file(&proj_root)?.ok_or("no index found".to_string())?);
+    let mut blob_list: Vec<Box<Blob>> = Vec::new();
+
+    // every entry is a deletion candidate, the search starts at the project root instead of the current dir
+    let mut parser = DiffParser::from(Some(index.clone()), PathBuf::new(), &proj_root)?;
+    parser.search_root = proj_root.clone();
+    let (_, modify, delete) = parser.parse()?;
+    update_index(&proj_root, &mut index, &BTreeSet::new(), modify, delete, &mut blob_list)?;
+
+    if !dry_run {
+        write_index_and_blobs(&proj_root, &index, &blob_list)?;
+    }
+    Ok(index)
+}
+
+fn write_index_and_blobs(proj_root: &Path, index: &Index, blob_list: &[Box<Blob>]) -> Result<(), String> {
+    let object_path = common_dir(proj_root).join("objects");
+    index.write_file(proj_root)?;
 
     for blob in blob_list {
         let blob_path = object_path.join(blob.hash.generate_path());
@@ -53,7 +234,6 @@ pub fn run(matches: &ArgMatches) -> Result<(), String>{
         file.write_all(&data).map_err(|e| e.to_string())?;
     }
 
-
     Ok(())
 }
 
@@ -78,18 +258,30 @@ fn create_index(proj_root: &PathBuf, new: &BTreeSet<PathBuf>, blob_list:&mut Vec
 
 fn add_entries(proj_root: &PathBuf, nodes: &BTreeSet<PathBuf>,
                index: &mut Box<Index>, blob_list:&mut Vec<Box<Blob>>) -> Result<(),String> {
+    let attributes = Attributes::from_work_tree(proj_root)?.with_autocrlf(autocrlf(proj_root)?);
     for node in nodes {
         let abs_path = proj_root.join(&node);
-        let blob = Box::new(Blob::from_file(&abs_path).
-            ok_or(format!("could not fetch file: {}", abs_path.to_str().unwrap()))?);
+        let fetch_error = |_| format!("could not fetch file: {}", abs_path.to_str().unwrap());
+        // stat before reading, so a change while hashing still shows up as modified
+        let metadata = fs::symlink_metadata(&abs_path).map_err(fetch_error)?;
+        let content = fs::read(&abs_path).map_err(fetch_error)?;
+        let blob = Box::new(Blob::new(&attributes.to_stored(node, content)));
         let hash = blob.generate_digest_bytes();
 
-        index.add_entry(node, hash)?;
+        index.add_entry_with_stat(IndexEntry::from_metadata(node.clone(), &metadata, hash)?);
         blob_list.push(blob);
     }
     Ok(())
 }
 
+// `add -N`: new files get an entry without content, tracked files are left as they are
+fn add_intent_to_add(proj_root: &Path, nodes: &BTreeSet<PathBuf>, index: &mut Index) -> Result<(), String> {
+    for node in nodes {
+        let metadata = fs::symlink_metadata(proj_root.join(node)).map_err(|e| e.to_string())?;
+        index.add_entry_with_stat(IndexEntry::intent_to_add(node.clone(), &metadata)?);
+    }
+    Ok(())
+}
 
 #[derive(Debug, PartialEq)]
 struct DiffParser {
@@ -98,10 +290,13 @@ struct DiffParser {
     mod_nodes: BTreeSet<PathBuf>,
     delete_nodes: BTreeSet<PathBuf>,
     search_root: PathBuf,
+    proj_root: PathBuf,
+    // untracked files ignored here are left out
+    ignore: Ignore,
 }
 
 impl DiffParser {
-    pub fn from(index: Option<Box<Index>>, search_root: PathBuf) -> Result<Self, String>{
+    pub fn from(index: Option<Box<Index>>, search_root: PathBuf, proj_root: &Path) -> Result<Self, String>{
         let index = index;
         let new_nodes = BTreeSet::new();
         let mod_nodes = BTreeSet::new();
@@ -120,7 +315,9 @@ impl DiffParser {
             new_nodes,
             mod_nodes,
             delete_nodes,
-            search_root
+            search_root,
+            proj_root: canonicalize(proj_root).map_err(|e| e.to_string())?,
+            ignore: Ignore::from_work_tree(proj_root)?,
         })
     }
 
@@ -143,12 +340,15 @@ impl DiffParser {
                 self.search_partial(&entry.path())?;
             }
         } else if path.is_file() {
+            if self.is_ignored(path)? {
+                return Ok(());
+            }
             match &self.index {
                 Some(_) => {
                     self.update_node(path)?;
                 },
                 None => {
-                    let trimmed_path = get_path_from_project_root(path)?;
+                    let trimmed_path = get_path_from(path, &self.proj_root)?;
                     self.new_nodes.insert(trimmed_path);
                 },
             }
@@ -157,9 +357,9 @@ impl DiffParser {
     }
 
     fn update_node(&mut self, path: &PathBuf) -> Result<(), String>{
-        let trimmed_path = get_path_from_project_root(path)?;
+        let trimmed_path = get_path_from(path, &self.proj_root)?;
         self.delete_nodes.remove(&trimmed_path);
-        match self.index.as_ref().unwrap().get_entry(path.clone()) {
+        match self.index.as_ref().unwrap().entry(&trimmed_path).cloned() {
             Some(ie) => if self.is_modified(path, ie)? {
                     self.mod_nodes.insert(trimmed_path);
                 },
@@ -170,7 +370,18 @@ impl DiffParser {
         Ok(())
     }
 
+    // only files outside of the index can be ignored
+    fn is_ignored(&self, path: &PathBuf) -> Result<bool, String> {
+        let trimmed_path = get_path_from(path, &self.proj_root)?;
+        let tracked = self.index.as_ref().is_some_and(|i| i.entry(&trimmed_path).is_some());
+        Ok(!tracked && self.ignore.is_ignored(&trimmed_path, false))
+    }
+
     fn is_modified(&self, path: &PathBuf, index_entry: IndexEntry) -> Result<bool, String> {
+        // the content of an intent-to-add entry is still to be added
+        if index_entry.is_intent_to_add() {
+            return Ok(true);
+        }
         let ref_time = index_entry.mod_time();
         let meta_data = path.metadata().map_err(|e| e.to_string())?;
         let mod_time = ((meta_data.mtime() as u64) << 32) + (meta_data.mtime_nsec() as u64);
@@ -183,12 +394,33 @@ impl DiffParser {
 fn test_diff_parser_search() {
     let cur_dir = PathBuf::from("testspace/");
     //let index = Index::from_file(&get_project_root_from(&cur_dir).expect("get root error"));
-    let mut parser = DiffParser::from(None, cur_dir).expect("create parser error");
+    let mut parser = DiffParser::from(None, cur_dir, Path::new(".")).expect("create parser error");
     let result = parser.parse().expect("parse failed");
     println!("{:?}\n{:?}\n{:?}", result.0, result.1, result.2);
 }
 
 
+#[test]
+fn test_diff_parser_ignore() {
+    let proj_root = crate::common::test_dir("add_ignore");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    fs::create_dir_all(proj_root.join("build")).unwrap();
+    for file in ["a.txt", "b.o", "c.o", "build/out"] {
+        fs::write(proj_root.join(file), file).unwrap();
+    }
+    fs::write(proj_root.join(".gitignore"), "*.o\nbuild/\n").unwrap();
+    // c.o was added before it was ignored, its removal is still staged
+    let mut index = Index::new();
+    index.add_entry_in(&proj_root, Path::new("c.o"), crate::hash::Hash::default()).unwrap();
+    fs::remove_file(proj_root.join("c.o")).unwrap();
+
+    let mut parser = DiffParser::from(Some(Box::new(index)), PathBuf::new(), &proj_root).unwrap();
+    parser.search_root = proj_root.clone();
+    let (new, _, delete) = parser.parse().unwrap();
+    assert_eq!(new.iter().collect::<Vec<_>>(), [Path::new(".gitignore"), Path::new("a.txt")]);
+    assert_eq!(delete.iter().collect::<Vec<_>>(), [Path::new("c.o")]);
+}
+
 fn get_all_sub_nodes(root: &PathBuf, all_nodes: Vec<PathBuf>) -> BTreeSet<PathBuf>{
     let start = match all_nodes.binary_search(root){
         Ok(n) => n,
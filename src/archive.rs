@@ -0,0 +1,279 @@
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use chrono::Utc;
+use clap::ArgMatches;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use crate::common::{common_dir, get_project_root};
+use crate::hash::Hash;
+use crate::object::{peel, read_object, Commit, FileType, ObjType, Tree};
+use crate::rev;
+
+// tar works in 512-byte headers and data blocks, written out in records of 20 blocks
+const BLOCK: usize = 512;
+const RECORD: usize = BLOCK * 20;
+// the largest time the 11 octal digits of the header hold
+const USTAR_MAX_MTIME: i64 = 0o77777777777;
+
+/*
+    archive [--prefix=<dir>/] [-o <file>] <tree-ish>
+    the tree as a tar on the standard output or into the file, laid out as git archive does:
+    the commit id in a pax global header, every entry dated with the commit time, files 0664 or 0775
+    a file named *.tar.gz or *.tgz is gzipped
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let rev = matches.value_of("tree-ish").unwrap();
+    let (tree, commit, mtime) = match peel(&objects_root, rev::resolve(&proj_root, rev)?)? {
+        (hash, ObjType::Commit) => {
+            let commit = Commit::load(&objects_root, hash)?;
+            (commit.tree(), Some(hash), commit.committer().timestamp().timestamp())
+        },
+        (hash, ObjType::Tree) => (hash, None, Utc::now().timestamp()),
+        (hash, _) => return Err(format!("not a tree object: {}", hash.string())),
+    };
+    let tar = tar(&objects_root, tree, matches.value_of("prefix").unwrap_or(""), commit, mtime)?;
+
+    match matches.value_of("output") {
+        Some(file) if file.ends_with(".tar.gz") || file.ends_with(".tgz") => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&tar).map_err(|e| e.to_string())?;
+            fs::write(file, encoder.finish().map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+        },
+        Some(file) => fs::write(file, tar).map_err(|e| e.to_string()),
+        None => io::stdout().write_all(&tar).map_err(|e| e.to_string()),
+    }
+}
+
+// a prefix ending with '/' gets a directory entry of its own, any other is put in front of the names as is
+fn tar(objects_root: &Path, tree: Hash, prefix: &str, commit: Option<Hash>, mtime: i64) -> Result<Vec<u8>, String> {
+    let mut tar = Tar { out: Vec::new(), mtime: mtime.clamp(0, USTAR_MAX_MTIME) };
+    let mut global = Vec::new();
+    if let Some(commit) = commit {
+        global.extend(pax_record("comment", commit.string().as_bytes()));
+    }
+    // a time too late for the headers is kept in full here, as git does, one before 1970 is just 0
+    if mtime > USTAR_MAX_MTIME {
+        global.extend(pax_record("mtime", mtime.to_string().as_bytes()));
+    }
+    if !global.is_empty() {
+        tar.header(b"pax_global_header", b"", b'g', 0o666, global.len(), b"");
+        tar.data(&global);
+    }
+    if prefix.ends_with('/') {
+        tar.entry(prefix.as_bytes(), tree, b'5', 0o775, &[], b"");
+    }
+
+    Tree::load(objects_root, tree)?.walk(objects_root, &mut |path, node| {
+        let mut name = prefix.as_bytes().to_vec();
+        name.extend_from_slice(path.as_os_str().as_bytes());
+        match node.file_type() {
+            // a submodule is left as an empty directory
+            FileType::Directory | FileType::Submodule => {
+                name.push(b'/');
+                tar.entry(&name, node.hash(), b'5', 0o775, &[], b"");
+            },
+            FileType::File => {
+                let content = read_object(objects_root, node.hash())?.1;
+                let mode = if node.is_executable() { 0o775 } else { 0o664 };
+                tar.entry(&name, node.hash(), b'0', mode, &content, b"");
+            },
+            FileType::SymbolicLink => {
+                let target = read_object(objects_root, node.hash())?.1;
+                tar.entry(&name, node.hash(), b'2', 0o777, &[], &target);
+            },
+        }
+        Ok(())
+    })?;
+
+    // at least two zero blocks at the end, up to a whole record
+    let mut out = tar.out;
+    out.resize((out.len() + 2 * BLOCK).div_ceil(RECORD) * RECORD, 0);
+    Ok(out)
+}
+
+// the archive being written, every entry with the same time, within what the header holds
+struct Tar {
+    out: Vec<u8>,
+    mtime: i64,
+}
+
+impl Tar {
+    /*
+        a path longer than the name field is split at a '/' into the prefix field and the name field
+        when it can't be, or a link target is too long, a pax extended header carries them
+        and the fields get "<hash>.data" and "see <hash>.paxheader" as git writes
+     */
+    fn entry(&mut self, path: &[u8], hash: Hash, typeflag: u8, mode: u32, content: &[u8], link: &[u8]) {
+        let mut ext = Vec::new();
+        let (mut prefix, mut name) = (&path[..0], path.to_vec());
+        if path.len() > 100 {
+            let split = path_prefix_len(path);
+            if split > 0 && path.len() - split - 1 <= 100 {
+                prefix = &path[..split];
+                name = path[split+1..].to_vec();
+            } else {
+                ext.extend(pax_record("path", path));
+                name = format!("{}.data", hash.string()).into_bytes();
+            }
+        }
+        let mut link = link.to_vec();
+        if link.len() > 100 {
+            ext.extend(pax_record("linkpath", &link));
+            link = format!("see {}.paxheader", hash.string()).into_bytes();
+        }
+
+        if !ext.is_empty() {
+            self.header(format!("{}.paxheader", hash.string()).as_bytes(), b"", b'x', 0o666, ext.len(), b"");
+            self.data(&ext);
+        }
+        self.header(&name, prefix, typeflag, mode, content.len(), &link);
+        self.data(content);
+    }
+
+    // a ustar header, numbers in octal, owned by root with the id 0
+    fn header(&mut self, name: &[u8], prefix: &[u8], typeflag: u8, mode: u32, size: usize, link: &[u8]) {
+        let mut header = [0u8; BLOCK];
+        let mut put = |offset: usize, bytes: &[u8]| header[offset..offset+bytes.len()].copy_from_slice(bytes);
+        put(0, name);
+        put(100, format!("{:07o}", mode & 0o7777).as_bytes());
+        put(108, b"0000000");
+        put(116, b"0000000");
+        put(124, format!("{:011o}", size).as_bytes());
+        put(136, format!("{:011o}", self.mtime).as_bytes());
+        put(148, b"        ");
+        put(156, &[typeflag]);
+        put(157, link);
+        put(257, b"ustar\x0000");
+        put(265, b"root");
+        put(297, b"root");
+        put(329, b"0000000");
+        put(337, b"0000000");
+        put(345, prefix);
+        let checksum: u32 = header.iter().map(|b| *b as u32).sum();
+        header[148..156].copy_from_slice(format!("{:07o}\0", checksum).as_bytes());
+        self.out.extend_from_slice(&header);
+    }
+
+    fn data(&mut self, data: &[u8]) {
+        self.out.extend_from_slice(data);
+        self.out.resize(self.out.len().div_ceil(BLOCK) * BLOCK, 0);
+    }
+}
+
+// the longest leading directories fitting the 155 bytes of the prefix field, 0 when there are none
+fn path_prefix_len(path: &[u8]) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i-1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(155);
+    loop {
+        i -= 1;
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+// "<length> <keyword>=<value>\n", the length counting its own digits
+fn pax_record(keyword: &str, value: &[u8]) -> Vec<u8> {
+    // one digit to start with, one more whenever the length reaches the next power of ten
+    let mut len = keyword.len() + value.len() + 4;
+    let mut tmp = 1;
+    while len / 10 >= tmp {
+        len += 1;
+        tmp *= 10;
+    }
+    let mut ret = format!("{} {}=", len, keyword).into_bytes();
+    ret.extend_from_slice(value);
+    ret.push(b'\n');
+    ret
+}
+
+#[test]
+fn test_tar() {
+    use crate::object::{write_object, write_test_tree};
+
+    let objects_root = crate::common::test_dir("archive_tar");
+    let blob = |content: &str| write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+    let tree = |entries: &[(&str, &str, Hash)]| write_test_tree(&objects_root, entries);
+    let sub = tree(&[("100644", "hello.txt", blob("hello\n"))]);
+    let root = tree(&[("120000", "link", blob("sub/hello.txt")), ("100755", "run.sh", blob("#!/bin/sh\n")),
+                      ("40000", "sub", sub)]);
+    let commit = Hash::from_string(&"c".repeat(40)).unwrap();
+
+    let out = tar(&objects_root, root, "p/", Some(commit), 1234567890).unwrap();
+    assert_eq!(out.len(), RECORD);
+    // (offset of the header, name, typeflag, mode, size)
+    let headers = [(0, "pax_global_header", b'g', "0000666", 52), (1024, "p/", b'5', "0000775", 0),
+                   (1536, "p/link", b'2', "0000777", 0), (2048, "p/run.sh", b'0', "0000775", 10),
+                   (3072, "p/sub/", b'5', "0000775", 0), (3584, "p/sub/hello.txt", b'0', "0000664", 6)];
+    for (offset, name, typeflag, mode, size) in headers {
+        let header = &out[offset..offset+BLOCK];
+        let field = |range: std::ops::Range<usize>| String::from_utf8_lossy(&header[range]).trim_end_matches('\0').to_string();
+        assert_eq!((field(0..100), header[156], field(100..108)), (name.to_string(), typeflag, mode.to_string()));
+        assert_eq!(usize::from_str_radix(&field(124..136), 8), Ok(size));
+        assert_eq!(field(136..148), "11145401322");
+        let mut blank = header.to_vec();
+        blank[148..156].copy_from_slice(b"        ");
+        assert_eq!(u32::from_str_radix(&field(148..156), 8), Ok(blank.iter().map(|b| *b as u32).sum()));
+    }
+    assert_eq!(&out[512..564], format!("52 comment={}\n", "c".repeat(40)).as_bytes());
+    assert_eq!(&out[1536+157..1536+171], b"sub/hello.txt\0");
+    assert_eq!(&out[2560..2570], b"#!/bin/sh\n");
+    assert_eq!(&out[4096..4102], b"hello\n");
+    assert!(out[4102..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn test_long_paths() {
+    let hash = Hash::from_string(&"a".repeat(40)).unwrap();
+    let dir = "d".repeat(60);
+    let long = format!("{}/{}/{}", dir, dir, "f".repeat(50));
+    assert_eq!(path_prefix_len(long.as_bytes()), 121);
+    assert_eq!(pax_record("path", b"x"), b"9 path=x\n");
+    assert_eq!(pax_record("path", b"abcd"), b"13 path=abcd\n");
+    assert_eq!(pax_record("comment", &[b'c'; 40]).len(), 52);
+    // right at the boundaries, 995 bytes without the length itself
+    assert_eq!(&pax_record("path", &[b'x'; 991])[..10], b"1002 path=");
+    assert_eq!(pax_record("path", &[b'x'; 991]).len(), 1002);
+    assert_eq!(&pax_record("path", &[b'x'; 91])[..9], b"101 path=");
+    assert_eq!(&pax_record("path", &[b'x'; 90])[..8], b"99 path=");
+
+    let mut tar = Tar { out: Vec::new(), mtime: 0 };
+    tar.entry(long.as_bytes(), hash, b'0', 0o664, b"", b"");
+    let out = tar.out;
+    assert_eq!(out.len(), BLOCK);
+    assert_eq!(&out[..51], format!("{}\0", "f".repeat(50)).as_bytes());
+    assert_eq!(&out[345..467], format!("{}/{}\0", dir, dir).as_bytes());
+
+    // no directory boundary to split at
+    let flat = "f".repeat(120);
+    let mut tar = Tar { out: Vec::new(), mtime: 0 };
+    tar.entry(flat.as_bytes(), hash, b'0', 0o664, b"", b"");
+    let out = tar.out;
+    assert_eq!((out.len(), out[156]), (3 * BLOCK, b'x'));
+    assert_eq!(&out[..51], format!("{}.paxheader\0", "a".repeat(40)).as_bytes());
+    assert_eq!(&out[BLOCK..BLOCK+130], format!("130 path={}\n", flat).as_bytes());
+    assert_eq!(&out[2*BLOCK..2*BLOCK+46], format!("{}.data\0", "a".repeat(40)).as_bytes());
+}
+
+#[test]
+fn test_mtime_out_of_range() {
+    let objects_root = crate::common::test_dir("archive_mtime");
+    let tree = crate::object::Tree::new().write(&objects_root).unwrap();
+    let field = |out: &[u8], range: std::ops::Range<usize>| String::from_utf8_lossy(&out[range]).to_string();
+
+    let out = tar(&objects_root, tree, "p/", None, -1).unwrap();
+    assert_eq!((out[156], field(&out, 136..148)), (b'5', "00000000000\0".to_string()));
+
+    let late = USTAR_MAX_MTIME + 1;
+    let out = tar(&objects_root, tree, "p/", None, late).unwrap();
+    assert_eq!(out[156], b'g');
+    assert_eq!(field(&out, BLOCK..BLOCK+20), format!("20 mtime={}\n", late));
+    assert_eq!(field(&out, 2*BLOCK+136..2*BLOCK+148), "77777777777\0");
+}
@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::Path;
+use crate::common::{glob_match, is_binary};
+use crate::config::AutoCrlf;
+use crate::hash::Hash;
+use crate::object::{read_object, Tree};
+
+/*
+    .gitattributes at the project root
+    <pattern> <attr>...
+    attr: text, -text, text=auto, binary, eol=lf, eol=crlf, diff, -diff
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Attributes {
+    rules: Vec<(String, Vec<String>)>,
+    // core.autocrlf for files without text or eol
+    autocrlf: AutoCrlf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Text {
+    Set,
+    Unset,
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+// attributes which apply to one path
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FileAttributes {
+    pub text: Option<Text>,
+    pub eol: Option<Eol>,
+    // false for -diff and binary
+    pub diff: Option<bool>,
+}
+
+impl Attributes {
+    pub fn parse(content: &str) -> Self {
+        let rules = content.lines().filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let mut words = line.split_whitespace();
+            let pattern = words.next()?.to_string();
+            Some((pattern, words.map(|w| w.to_string()).collect()))
+        }).collect();
+        Self { rules, autocrlf: AutoCrlf::False }
+    }
+
+    pub fn with_autocrlf(mut self, autocrlf: AutoCrlf) -> Self {
+        self.autocrlf = autocrlf;
+        self
+    }
+
+    pub fn from_work_tree(proj_root: &Path) -> Result<Self, String> {
+        let path = proj_root.join(".gitattributes");
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        Ok(Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?))
+    }
+
+    // .gitattributes stored at the root of a tree
+    pub fn from_tree(objects_root: &Path, tree_hash: Hash) -> Result<Self, String> {
+        let tree = Tree::load(objects_root, tree_hash)?;
+        match tree.nodes().iter().find(|n| n.file_name() == ".gitattributes") {
+            Some(node) => {
+                let (_, body) = read_object(objects_root, node.hash())?;
+                Ok(Self::parse(&String::from_utf8_lossy(&body)))
+            },
+            None => Ok(Default::default()),
+        }
+    }
+
+    // later lines override earlier ones
+    pub fn lookup(&self, path: &Path) -> FileAttributes {
+        let path = path.to_string_lossy();
+        let name = path.rsplit('/').next().unwrap_or(&path);
+        let mut ret = FileAttributes::default();
+        for (pattern, attrs) in &self.rules {
+            // a pattern without '/' matches the file name in any directory
+            let matched = match pattern.strip_prefix('/') {
+                Some(p) => glob_match(p, &path, false),
+                None if pattern.contains('/') => glob_match(pattern, &path, false),
+                None => glob_match(pattern, name, false),
+            };
+            if !matched {
+                continue;
+            }
+            for attr in attrs {
+                match attr.as_str() {
+                    "text" => ret.text = Some(Text::Set),
+                    "-text" => ret.text = Some(Text::Unset),
+                    "text=auto" => ret.text = Some(Text::Auto),
+                    "binary" => {
+                        ret.text = Some(Text::Unset);
+                        ret.diff = Some(false);
+                    },
+                    "eol=lf" => ret.eol = Some(Eol::Lf),
+                    "eol=crlf" => ret.eol = Some(Eol::Crlf),
+                    "diff" => ret.diff = Some(true),
+                    "-diff" => ret.diff = Some(false),
+                    _ => {},
+                }
+            }
+        }
+        ret
+    }
+
+    // bytes to store in a blob, CRLF normalized to LF for text files
+    pub fn to_stored(&self, path: &Path, content: Vec<u8>) -> Vec<u8> {
+        if self.is_text(path, &content) {
+            crlf_to_lf(&content)
+        } else {
+            content
+        }
+    }
+
+    // bytes to write into the working tree
+    pub fn to_work_tree(&self, path: &Path, content: Vec<u8>) -> Vec<u8> {
+        let eol = match self.lookup(path).eol {
+            Some(eol) => Some(eol),
+            None if self.autocrlf == AutoCrlf::True => Some(Eol::Crlf),
+            None => None,
+        };
+        if eol == Some(Eol::Crlf) && self.is_text(path, &content) {
+            lf_to_crlf(&content)
+        } else {
+            content
+        }
+    }
+
+    // diff shows "Binary files differ" for these
+    pub fn is_binary(&self, path: &Path, content: &[u8]) -> bool {
+        match self.lookup(path).diff {
+            Some(diff) => !diff,
+            None => is_binary(content),
+        }
+    }
+
+    fn is_text(&self, path: &Path, content: &[u8]) -> bool {
+        let attrs = self.lookup(path);
+        match attrs.text {
+            Some(Text::Set) => true,
+            Some(Text::Unset) => false,
+            Some(Text::Auto) => !is_binary(content),
+            // eol alone implies text, autocrlf implies text=auto
+            None => attrs.eol.is_some() || (self.autocrlf != AutoCrlf::False && !is_binary(content)),
+        }
+    }
+}
+
+fn crlf_to_lf(content: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(content.len());
+    for (i, b) in content.iter().enumerate() {
+        if *b == b'\r' && content.get(i+1) == Some(&b'\n') {
+            continue;
+        }
+        ret.push(*b);
+    }
+    ret
+}
+
+fn lf_to_crlf(content: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(content.len());
+    for (i, b) in content.iter().enumerate() {
+        if *b == b'\n' && (i == 0 || content[i-1] != b'\r') {
+            ret.push(b'\r');
+        }
+        ret.push(*b);
+    }
+    ret
+}
+
+#[test]
+fn test_attributes_lookup() {
+    let attrs = Attributes::parse("# comment\n*.txt text\n*.bat text eol=crlf\n*.png binary\n\
+                                   /docs/*.md -text\nvendor/** -diff\n");
+    let lookup = |p: &str| attrs.lookup(Path::new(p));
+    assert_eq!(lookup("a/b.txt").text, Some(Text::Set));
+    assert_eq!(lookup("run.bat"), FileAttributes { text: Some(Text::Set), eol: Some(Eol::Crlf), diff: None });
+    assert_eq!(lookup("img/logo.png"), FileAttributes { text: Some(Text::Unset), eol: None, diff: Some(false) });
+    assert_eq!(lookup("docs/a.md").text, Some(Text::Unset));
+    assert_eq!(lookup("src/docs/a.md").text, None);
+    assert_eq!(lookup("vendor/lib/x.c").diff, Some(false));
+    assert_eq!(lookup("main.rs"), FileAttributes::default());
+}
+
+#[test]
+fn test_attributes_conversion() {
+    let attrs = Attributes::parse("*.txt text\n*.bat eol=crlf\n*.bin binary\n");
+    let crlf = b"a\r\nb\r\n".to_vec();
+    assert_eq!(attrs.to_stored(Path::new("x.txt"), crlf.clone()), b"a\nb\n");
+    assert_eq!(attrs.to_stored(Path::new("x.bat"), crlf.clone()), b"a\nb\n");
+    assert_eq!(attrs.to_stored(Path::new("x.bin"), crlf.clone()), crlf);
+    assert_eq!(attrs.to_stored(Path::new("x.rs"), crlf.clone()), crlf);
+
+    assert_eq!(attrs.to_work_tree(Path::new("x.bat"), b"a\nb\r\n".to_vec()), crlf);
+    assert_eq!(attrs.to_work_tree(Path::new("x.txt"), b"a\nb\n".to_vec()), b"a\nb\n");
+    assert!(attrs.is_binary(Path::new("x.bin"), b"text"));
+    assert!(!attrs.is_binary(Path::new("x.txt"), b"text"));
+}
+
+#[test]
+fn test_autocrlf_round_trip() {
+    let crlf = b"a\r\nb\r\n".to_vec();
+    let binary = b"a\r\n\0".to_vec();
+    let path = Path::new("x.txt");
+    let round_trip = |attrs: &Attributes, content: &Vec<u8>| {
+        let stored = attrs.to_stored(path, content.clone());
+        let checked_out = attrs.to_work_tree(path, stored.clone());
+        (stored, checked_out)
+    };
+
+    let attrs = Attributes::default();
+    assert_eq!(round_trip(&attrs, &crlf), (crlf.clone(), crlf.clone()));
+
+    let attrs = Attributes::default().with_autocrlf(AutoCrlf::Input);
+    assert_eq!(round_trip(&attrs, &crlf), (b"a\nb\n".to_vec(), b"a\nb\n".to_vec()));
+    assert_eq!(round_trip(&attrs, &binary), (binary.clone(), binary.clone()));
+
+    let attrs = Attributes::default().with_autocrlf(AutoCrlf::True);
+    assert_eq!(round_trip(&attrs, &crlf), (b"a\nb\n".to_vec(), crlf.clone()));
+    assert_eq!(round_trip(&attrs, &binary), (binary.clone(), binary.clone()));
+
+    // attributes take precedence over autocrlf
+    let attrs = Attributes::parse("*.txt -text\n").with_autocrlf(AutoCrlf::True);
+    assert_eq!(round_trip(&attrs, &crlf), (crlf.clone(), crlf.clone()));
+    let attrs = Attributes::parse("*.txt eol=lf\n").with_autocrlf(AutoCrlf::True);
+    assert_eq!(round_trip(&attrs, &crlf), (b"a\nb\n".to_vec(), b"a\nb\n".to_vec()));
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+use clap::ArgMatches;
+use crate::changes::{diff_snapshots, render_stat, Snapshot};
+use crate::checkout::{check_clean, switch_tree};
+use crate::common::{common_dir, get_project_root, info};
+use crate::hash::Hash;
+use crate::head::{detach_head, write_symbolic_ref, Head};
+use crate::index::Index;
+use crate::merge_base::ancestors;
+use crate::object::Commit;
+use crate::refs::read_ref;
+use crate::rev;
+use crate::state::BisectState;
+
+/*
+    bisect start [<bad> [<good>...]]
+    bisect bad [<rev>], bisect good [<rev>...]    HEAD by default
+    bisect reset                                  back to the branch bisect started on
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let revs = |sub: &ArgMatches| -> Result<Vec<Hash>, String> {
+        sub.values_of("rev").map(|v| v.map(|r| rev::resolve_commit(&proj_root, r)).collect()).unwrap_or(Ok(Vec::new()))
+    };
+    let head_hash = || Head::from_root(&proj_root)?.hash().ok_or("HEAD does not have any commits yet".to_string());
+
+    let state = match matches.subcommand() {
+        Some(("start", sub)) => {
+            if BisectState::load(&proj_root)?.is_some() {
+                return Err("a bisect is already in progress, run bisect reset first".to_string());
+            }
+            let head = Head::from_root(&proj_root)?;
+            let start = match (head.branch(), head.hash()) {
+                (Some(branch), _) => branch,
+                (None, Some(hash)) => hash.string(),
+                (None, None) => return Err("HEAD does not have any commits yet".to_string()),
+            };
+            let mut revs = revs(sub)?.into_iter();
+            BisectState { start, bad: revs.next(), good: revs.collect() }
+        },
+        Some(("bad", sub)) => {
+            let mut state = BisectState::load(&proj_root)?.ok_or(NOT_STARTED)?;
+            state.bad = Some(revs(sub)?.first().cloned().map_or_else(head_hash, Ok)?);
+            state
+        },
+        Some(("good", sub)) => {
+            let mut state = BisectState::load(&proj_root)?.ok_or(NOT_STARTED)?;
+            match revs(sub)? {
+                revs if revs.is_empty() => state.good.push(head_hash()?),
+                revs => state.good.extend(revs),
+            }
+            state
+        },
+        Some(("reset", _)) => return reset(&proj_root),
+        _ => return Err("no bisect command specified".to_string()),
+    };
+    state.save(&proj_root)?;
+    next(&proj_root, &state)
+}
+
+const NOT_STARTED: &str = "not bisecting, start with bisect start";
+
+// check out the next commit to test, or report the first bad one
+fn next(proj_root: &Path, state: &BisectState) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let bad = match (state.bad, state.good.len()) {
+        (None, 0) => {
+            println!("status: waiting for both good and bad commits");
+            return Ok(());
+        },
+        (None, n) => {
+            println!("status: waiting for bad commit, {} good {} known", n, if n == 1 { "commit" } else { "commits" });
+            return Ok(());
+        },
+        (Some(_), 0) => {
+            println!("status: waiting for good commit(s), bad commit known");
+            return Ok(());
+        },
+        (Some(bad), _) => bad,
+    };
+
+    let candidates = candidates(&objects_root, bad, &state.good)?;
+    let (best, left) = match bisection(&objects_root, bad, &candidates)? {
+        Some(best) => best,
+        None => return first_bad(&objects_root, bad),
+    };
+    println!("Bisecting: {} {} left to test after this (roughly {} {})", left,
+             if left == 1 { "revision" } else { "revisions" }, estimate_steps(candidates.len()),
+             if estimate_steps(candidates.len()) == 1 { "step" } else { "steps" });
+    let commit = Commit::load(&objects_root, best)?;
+    println!("[{}] {}", best.string(), commit.message().lines().next().unwrap_or(""));
+
+    // tested on a detached HEAD, moved only once the work tree is
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    check_clean(proj_root, &index, head_tree(proj_root)?, "bisect")?;
+    switch_tree(proj_root, &index, commit.tree())?;
+    detach_head(proj_root, best)
+}
+
+fn head_tree(proj_root: &Path) -> Result<Option<Hash>, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    match Head::from_root(proj_root)?.hash() {
+        Some(hash) => Ok(Some(Commit::load(&objects_root, hash)?.tree())),
+        None => Ok(None),
+    }
+}
+
+// commits reachable from bad but from none of the good ones, bad included
+fn candidates(objects_root: &Path, bad: Hash, good: &[Hash]) -> Result<BTreeSet<Hash>, String> {
+    let mut ret = ancestors(objects_root, bad)?;
+    for good in good {
+        for hash in ancestors(objects_root, *good)? {
+            ret.remove(&hash);
+        }
+    }
+    Ok(ret)
+}
+
+/*
+    the commit splitting the candidates most evenly, with the number left to test after it
+    None when bad is the only candidate, the first bad commit
+    a tie goes to the older commit, as git does
+ */
+fn bisection(objects_root: &Path, bad: Hash, candidates: &BTreeSet<Hash>) -> Result<Option<(Hash, usize)>, String> {
+    let all = candidates.len();
+    let mut best: Option<(usize, usize, Hash)> = None;
+    for hash in candidates.iter().filter(|h| **h != bad) {
+        let reach = ancestors(objects_root, *hash)?.intersection(candidates).count();
+        let score = reach.min(all - reach);
+        let better = match best {
+            None => true,
+            Some((s, r, _)) => score > s || (score == s && reach < r),
+        };
+        if better {
+            best = Some((score, reach, *hash));
+        }
+    }
+    Ok(best.map(|(_, reach, hash)| (hash, all - reach - 1)))
+}
+
+// git's estimate of the bisect steps left for the number of candidates
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = (usize::BITS - 1 - all.leading_zeros()) as usize;
+    let x = all - (1 << n);
+    if (1 << n) < 3 * x { n } else { n - 1 }
+}
+
+fn first_bad(objects_root: &Path, bad: Hash) -> Result<(), String> {
+    let commit = Commit::load(objects_root, bad)?;
+    let old = Snapshot::from_tree(objects_root, commit.parent_tree(objects_root)?)?;
+    let new = Snapshot::from_tree(objects_root, commit.tree())?;
+    let changes = diff_snapshots(&old, &new);
+    println!("{} is the first bad commit", bad.string());
+    print!("{}", commit.log_entry(bad, &Vec::new()));
+    if !changes.is_empty() {
+        print!("\n{}", render_stat(objects_root, &old, &new, &changes)?);
+    }
+    Ok(())
+}
+
+fn reset(proj_root: &Path) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let state = match BisectState::load(proj_root)? {
+        Some(state) => state,
+        None => {
+            info("We are not bisecting.");
+            return Ok(());
+        },
+    };
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    // started on a branch unless on a detached HEAD, the work tree is switched before HEAD moves
+    let (target, branch) = match Hash::from_string(&state.start) {
+        Some(hash) => (hash, None),
+        None => {
+            let branch = format!("refs/heads/{}", state.start);
+            (read_ref(proj_root, &branch)?.ok_or(format!("branch {} has no commits", state.start))?, Some(branch))
+        },
+    };
+    check_clean(proj_root, &index, head_tree(proj_root)?, "bisect")?;
+    switch_tree(proj_root, &index, Commit::load(&objects_root, target)?.tree())?;
+    let message = match &branch {
+        Some(branch) => {
+            write_symbolic_ref(proj_root, "HEAD", branch)?;
+            format!("Switched to branch '{}'", state.start)
+        },
+        None => {
+            detach_head(proj_root, target)?;
+            format!("HEAD is now at {}", rev::abbreviate(&objects_root, target))
+        },
+    };
+    BisectState::remove(proj_root)?;
+    info(&message);
+    Ok(())
+}
+
+#[test]
+fn test_bisection() {
+    use crate::object::{write_object, ObjType};
+
+    let objects_root = crate::common::test_dir("bisection");
+    let tree = crate::object::Tree::new().write(&objects_root).unwrap();
+    // c1 <- c2 <- ... <- c10
+    let mut commits = Vec::new();
+    for i in 1..=10 {
+        let parent = commits.last().map(|p: &Hash| format!("parent {}\n", p.string())).unwrap_or_default();
+        let body = format!("tree {}\n{}author A <a@example.com> {} +0000\ncommitter A <a@example.com> {} +0000\n\nc{}\n",
+                           tree.string(), parent, i, i, i);
+        commits.push(write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap());
+    }
+    let c = |i: usize| commits[i-1];
+
+    let set = candidates(&objects_root, c(10), &[c(1)]).unwrap();
+    assert_eq!(set.len(), 9);
+    assert_eq!(bisection(&objects_root, c(10), &set).unwrap(), Some((c(5), 4)));
+    let set = candidates(&objects_root, c(10), &[c(1), c(5)]).unwrap();
+    assert_eq!(bisection(&objects_root, c(10), &set).unwrap(), Some((c(7), 2)));
+    let set = candidates(&objects_root, c(7), &[c(5)]).unwrap();
+    assert_eq!(bisection(&objects_root, c(7), &set).unwrap(), Some((c(6), 0)));
+    let set = candidates(&objects_root, c(6), &[c(5)]).unwrap();
+    assert_eq!(bisection(&objects_root, c(6), &set).unwrap(), None);
+
+    assert_eq!([2, 5, 9, 100].map(estimate_steps), [0, 1, 2, 6]);
+}
@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::common::{common_dir, get_path_from_project_root, get_project_root};
+use crate::diff::{diff_lines, Hunk};
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::mailmap::Mailmap;
+use crate::object::{read_object, Commit, Tree};
+use crate::rev::abbreviate;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let path = matches.value_of("path").ok_or("no path specified")?;
+
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let path = get_path_from_project_root(&PathBuf::from(path))?;
+    let head_hash = Head::new()?.hash().ok_or("HEAD does not have any commits yet".to_string())?;
+
+    let lines = blame(&objects_root, head_hash, &path)?;
+    let mailmap = Mailmap::from_work_tree(&proj_root)?;
+    let width = lines.len().to_string().len();
+    for (i, (hash, line)) in lines.iter().enumerate() {
+        let commit = Commit::load(&objects_root, *hash)?;
+        println!("{} ({} {} {:>width$}) {}", abbreviate(&objects_root, *hash), mailmap.map(commit.author()).name(),
+                 commit.timestamp().format("%Y-%m-%d %H:%M:%S %z"), i+1, line, width = width);
+    }
+
+    Ok(())
+}
+
+// first-parent blame, returns the commit which last touched each line of the file
+fn blame(objects_root: &Path, start: Hash, path: &Path) -> Result<Vec<(Hash, String)>, String> {
+    let mut commit_hash = start;
+    let mut commit = Commit::load(objects_root, commit_hash)?;
+    let mut blob = find_blob(objects_root, &commit, path)?.
+        ok_or(format!("no such path '{}' in HEAD", path.display()))?;
+    let mut content = read_blob(objects_root, blob)?;
+
+    let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
+    let mut origins: Vec<Option<Hash>> = vec![None; lines.len()];
+    // (line number in the final file, line number in the current version)
+    let mut pending: Vec<(usize, usize)> = (0..lines.len()).map(|i| (i, i)).collect();
+
+    while !pending.is_empty() {
+        let parent_hash = match commit.parents().first() {
+            Some(h) => *h,
+            None => break,
+        };
+        let parent = Commit::load(objects_root, parent_hash)?;
+        let parent_blob = match find_blob(objects_root, &parent, path)? {
+            Some(b) => b,
+            None => break,
+        };
+
+        if parent_blob != blob {
+            let parent_content = read_blob(objects_root, parent_blob)?;
+            // line in the current version -> line in the parent version
+            let mut mapping: Vec<Option<usize>> = vec![None; content.lines().count()];
+            for hunk in diff_lines(&parent_content, &content) {
+                if let Hunk::Equal { old_start, new_start, len } = hunk {
+                    for k in 0..len {
+                        mapping[new_start+k] = Some(old_start+k);
+                    }
+                }
+            }
+
+            let mut still_pending = Vec::new();
+            for (line, current) in pending {
+                match mapping[current] {
+                    Some(old) => still_pending.push((line, old)),
+                    None => origins[line] = Some(commit_hash),
+                }
+            }
+            pending = still_pending;
+            content = parent_content;
+        }
+
+        commit_hash = parent_hash;
+        commit = parent;
+        blob = parent_blob;
+    }
+    for (line, _) in pending {
+        origins[line] = Some(commit_hash);
+    }
+
+    Ok(origins.into_iter().map(|o| o.unwrap()).zip(lines).collect())
+}
+
+fn find_blob(objects_root: &Path, commit: &Commit, path: &Path) -> Result<Option<Hash>, String> {
+    let tree = Tree::load(objects_root, commit.tree())?;
+    Ok(tree.find(objects_root, path)?.map(|(_, hash)| hash))
+}
+
+fn read_blob(objects_root: &Path, hash: Hash) -> Result<String, String> {
+    let (_, body) = read_object(objects_root, hash)?;
+    String::from_utf8(body).map_err(|_| "binary files are not supported".to_string())
+}
+
+#[test]
+fn test_blame() {
+    use crate::object::{write_object, write_test_tree, ObjType};
+
+    let objects_root = crate::common::test_dir("blame");
+    let commit = |content: &str, parents: &[Hash]| {
+        let blob = write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+        let tree = write_test_tree(&objects_root, &[("100644", "f.txt", blob)]);
+        let parents: String = parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let body = format!("tree {}\n{}author A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\n{}",
+                           tree.string(), parents, content);
+        write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    let c1 = commit("a\nb\nc\n", &[]);
+    let c2 = commit("a\nB\nc\n", &[c1]);
+    let c3 = commit("a\nB\nc\nd\n", &[c2]);
+
+    let lines = blame(&objects_root, c3, Path::new("f.txt")).unwrap();
+    let expected = [(c1, "a"), (c2, "B"), (c1, "c"), (c3, "d")].map(|(h, l)| (h, l.to_string()));
+    assert_eq!(lines, expected);
+    assert_eq!(blame(&objects_root, c1, Path::new("f.txt")).unwrap().len(), 3);
+    assert!(blame(&objects_root, c3, Path::new("none.txt")).is_err());
+}
@@ -0,0 +1,145 @@
+use clap::ArgMatches;
+use std::path::Path;
+use crate::common::{common_dir, get_project_root, info};
+use crate::config::{local_config_path, GitConfig};
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::merge_base::is_ancestor;
+use crate::refs::{list_refs, read_ref};
+use crate::rev;
+
+// list branches, "* " marks the current one, or set the upstream of the current branch
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let current = Head::new()?.branch();
+
+    if let Some(upstream) = matches.value_of("set-upstream-to") {
+        let branch = current.ok_or("HEAD is not on a branch".to_string())?;
+        return set_upstream(&proj_root, &branch, upstream);
+    }
+    if matches.is_present("unset-upstream") {
+        let branch = current.ok_or("HEAD is not on a branch".to_string())?;
+        let config_path = local_config_path(&proj_root);
+        let mut config = GitConfig::from_file(&config_path)?;
+        let removed = config.unset("branch", Some(&branch), "remote");
+        if !(config.unset("branch", Some(&branch), "merge") || removed) {
+            return Err(format!("branch '{}' has no upstream information", branch));
+        }
+        return config.write_file(&config_path);
+    }
+
+    // --merged and --no-merged without a value mean HEAD
+    let rev_of = |name: &str| match matches.value_of(name) {
+        Some(rev) => rev::resolve_commit(&proj_root, rev).map(Some),
+        None if matches.is_present(name) => rev::resolve_commit(&proj_root, "HEAD").map(Some),
+        None => Ok(None),
+    };
+    let contains = rev_of("contains")?;
+    let merged = rev_of("merged")?;
+    let no_merged = rev_of("no-merged")?;
+
+    for name in branches(&proj_root, &objects_root, contains, merged, no_merged)? {
+        let mark = if current.as_deref() == Some(name.as_str()) { "*" } else { " " };
+        println!("{} {}", mark, name);
+    }
+    Ok(())
+}
+
+// the local branches containing contains, merged into merged and not merged into no_merged, when given
+fn branches(proj_root: &Path, objects_root: &Path, contains: Option<Hash>, merged: Option<Hash>,
+            no_merged: Option<Hash>) -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    for (refname, tip) in list_refs(proj_root, "refs/heads/")? {
+        if let Some(commit) = contains {
+            if !is_ancestor(objects_root, commit, tip)? {
+                continue;
+            }
+        }
+        if let Some(commit) = merged {
+            if !is_ancestor(objects_root, tip, commit)? {
+                continue;
+            }
+        }
+        if let Some(commit) = no_merged {
+            if is_ancestor(objects_root, tip, commit)? {
+                continue;
+            }
+        }
+        ret.push(refname.strip_prefix("refs/heads/").unwrap().to_string());
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_branches() {
+    use crate::object::{write_object, ObjType, Tree};
+    use crate::refs::write_ref;
+
+    let proj_root = crate::common::test_dir("branch_list");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let tree = Tree::new().write(&objects_root).unwrap();
+    let commit = |message: &str, parents: &[Hash]| {
+        let parents: String = parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let body = format!("tree {}\n{}author A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\n{}\n",
+                           tree.string(), parents, message);
+        write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    // base <- main, base <- side
+    let base = commit("base", &[]);
+    let main = commit("main", &[base]);
+    let side = commit("side", &[base]);
+    for (name, tip) in [("master", main), ("old", base), ("side", side)] {
+        write_ref(&proj_root, &format!("refs/heads/{}", name), tip).unwrap();
+    }
+    let list = |contains, merged, no_merged| branches(&proj_root, &objects_root, contains, merged, no_merged).unwrap();
+
+    assert_eq!(list(None, None, None), vec!["master", "old", "side"]);
+    assert_eq!(list(Some(base), None, None), vec!["master", "old", "side"]);
+    assert_eq!(list(Some(side), None, None), vec!["side"]);
+    assert_eq!(list(None, Some(main), None), vec!["master", "old"]);
+    assert_eq!(list(None, None, Some(main)), vec!["side"]);
+    assert_eq!(list(Some(base), Some(main), Some(base)), vec!["master"]);
+}
+
+// <remote>/<branch> for a remote-tracking branch, otherwise a local branch
+fn set_upstream(proj_root: &Path, branch: &str, upstream: &str) -> Result<(), String> {
+    let config_path = local_config_path(proj_root);
+    let mut config = GitConfig::from_file(&config_path)?;
+    // the longest remote name wins, as remote names may contain '/'
+    let remote = config.sections("remote").filter_map(|s| s.subsection()).
+        filter(|r| upstream.starts_with(&format!("{}/", r))).max_by_key(|r| r.len()).map(|r| r.to_string());
+    let (remote, merge, refname) = match remote {
+        Some(remote) => {
+            let name = &upstream[remote.len()+1..];
+            (remote.clone(), name.to_string(), format!("refs/remotes/{}/{}", remote, name))
+        },
+        None => (".".to_string(), upstream.to_string(), format!("refs/heads/{}", upstream)),
+    };
+    if read_ref(proj_root, &refname)?.is_none() {
+        return Err(format!("the requested upstream branch '{}' does not exist", upstream));
+    }
+
+    config.set("branch", Some(branch), "remote", &remote);
+    config.set("branch", Some(branch), "merge", &format!("refs/heads/{}", merge));
+    config.write_file(&config_path)?;
+    info(&format!("branch '{}' set up to track '{}'.", branch, upstream));
+    Ok(())
+}
+
+// remote-tracking ref of branch.<name>.remote and branch.<name>.merge
+pub fn upstream_of(proj_root: &Path, branch: &str) -> Result<Option<String>, String> {
+    let config = GitConfig::from_file(&local_config_path(proj_root))?;
+    let (remote, merge) = match (config.get("branch", Some(branch), "remote"),
+                                 config.get("branch", Some(branch), "merge")) {
+        (Some(remote), Some(merge)) => (remote, merge),
+        _ => return Ok(None),
+    };
+    let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+    // "." is the repository itself
+    Ok(Some(match remote {
+        "." => format!("refs/heads/{}", merge),
+        remote => format!("refs/remotes/{}/{}", remote, merge),
+    }))
+}
@@ -0,0 +1,117 @@
+use std::io;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root, is_binary};
+use crate::object::{read_object, read_raw_object, ObjType, Tree};
+use crate::rev;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    if matches.is_present("batch") || matches.is_present("batch-check") {
+        return batch(&proj_root, io::stdin().lock(), &mut io::stdout().lock(), matches.is_present("batch"));
+    }
+
+    let object = matches.value_of("object").ok_or("no object specified")?;
+    let hash = rev::resolve(&proj_root, object)?;
+    let (obj_type, body) = read_object(&objects_root, hash)?;
+
+    let out = if matches.is_present("raw") && !matches.is_present("pretty") {
+        read_raw_object(&objects_root, hash)?
+    } else if matches.is_present("type") {
+        format!("{}\n", obj_type.name()).into_bytes()
+    } else if matches.is_present("size") {
+        format!("{}\n", body.len()).into_bytes()
+    } else if matches.is_present("pretty") {
+        match obj_type {
+            ObjType::Tree => {
+                let tree = Tree::load(&objects_root, hash)?;
+                tree.pretty_string().into_bytes()
+            },
+            // binary bytes would mess up the terminal
+            ObjType::Blob if is_binary(&body) && !matches.is_present("no-hex-dump") && io::stdout().is_terminal() => {
+                hex_dump(&body).into_bytes()
+            },
+            ObjType::Blob | ObjType::Commit | ObjType::Tag => body,
+        }
+    } else {
+        return Err("one of -t, -s, -p or --raw is required".to_string());
+    };
+
+    io::stdout().write_all(&out).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+// one object name per input line, "<hash> <type> <size>" and the content if with_content
+fn batch(proj_root: &Path, input: impl BufRead, out: &mut impl Write, with_content: bool) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    for line in input.lines() {
+        let input = line.map_err(|e| e.to_string())?;
+        let object = rev::resolve(proj_root, input.trim()).ok().
+            and_then(|hash| read_object(&objects_root, hash).ok().map(|o| (hash, o)));
+        match object {
+            Some((hash, (obj_type, body))) => {
+                writeln!(out, "{} {} {}", hash.string(), obj_type.name(), body.len()).map_err(|e| e.to_string())?;
+                if with_content {
+                    out.write_all(&body).map_err(|e| e.to_string())?;
+                    writeln!(out).map_err(|e| e.to_string())?;
+                }
+            },
+            None => writeln!(out, "{} missing", input).map_err(|e| e.to_string())?,
+        }
+        // a reader may wait for each answer before asking the next
+        out.flush().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// `hexdump -C` format: offset, 16 bytes in hex and as ascii, and the total length
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut ret = String::new();
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        ret.push_str(&format!("{:08x}  ", i*16));
+        for j in 0..16 {
+            match chunk.get(j) {
+                Some(b) => ret.push_str(&format!("{:02x} ", b)),
+                None => ret.push_str("   "),
+            }
+            if j == 7 {
+                ret.push(' ');
+            }
+        }
+        let ascii: String = chunk.iter().
+            map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        ret.push_str(&format!(" |{}|\n", ascii));
+    }
+    ret.push_str(&format!("{:08x}\n", bytes.len()));
+    ret
+}
+
+#[test]
+fn test_batch() {
+    use crate::object::write_object;
+
+    let proj_root = crate::common::test_dir("cat_file_batch");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let blob = write_object(&proj_root.join(".git/objects"), ObjType::Blob, b"hello\n").unwrap();
+    let tree = Tree::new().write(&proj_root.join(".git/objects")).unwrap();
+    let input = format!("{}\n{}\nnothing\n", blob.string(), tree.string());
+    let run = |with_content| {
+        let mut out = Vec::new();
+        batch(&proj_root, input.as_bytes(), &mut out, with_content).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    assert_eq!(run(false), format!("{} blob 6\n{} tree 0\nnothing missing\n", blob.string(), tree.string()));
+    assert_eq!(run(true), format!("{} blob 6\nhello\n\n{} tree 0\n\nnothing missing\n", blob.string(), tree.string()));
+}
+
+#[test]
+fn test_hex_dump() {
+    assert_eq!(hex_dump(b"hello\0world, binary\n"),
+               "00000000  68 65 6c 6c 6f 00 77 6f  72 6c 64 2c 20 62 69 6e  |hello.world, bin|\n\
+                00000010  61 72 79 0a                                       |ary.|\n\
+                00000014\n");
+}
@@ -0,0 +1,365 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use crate::attributes::Attributes;
+use crate::config::autocrlf;
+use crate::diff::{diff_lines, render_unified, Hunk};
+use crate::hash::Hash;
+use crate::index::Index;
+use crate::object::{read_object, Blob, Tree};
+
+// files of a tree, the index or the working tree, keyed by the path from the project root
+pub struct Snapshot {
+    files: BTreeMap<PathBuf, Hash>,
+    // contents are read from the working tree instead of the object store
+    work_tree: Option<PathBuf>,
+    // .gitattributes which applies to the files
+    attributes: Attributes,
+}
+
+impl Snapshot {
+    pub fn empty() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            work_tree: None,
+            attributes: Default::default(),
+        }
+    }
+
+    pub fn from_tree(objects_root: &Path, tree_hash: Hash) -> Result<Self, String> {
+        let mut files = BTreeMap::new();
+        collect_tree_files(objects_root, tree_hash, &mut files)?;
+        Ok(Self {
+            files,
+            work_tree: None,
+            attributes: Attributes::from_tree(objects_root, tree_hash)?,
+        })
+    }
+
+    // intent-to-add entries have no content yet and are left out
+    pub fn from_index(index: &Index) -> Self {
+        Self {
+            files: index.entries().into_iter().filter(|(_, entry)| !entry.is_intent_to_add()).
+                map(|(path, entry)| (path, entry.hash())).collect(),
+            work_tree: None,
+            attributes: Default::default(),
+        }
+    }
+
+    // tracked files in the working tree, a removed file is missing from the snapshot
+    // contents are hashed as they would be stored by add
+    pub fn from_work_tree(proj_root: &Path, index: &Index) -> Result<Self, String> {
+        let attributes = Attributes::from_work_tree(proj_root)?.with_autocrlf(autocrlf(proj_root)?);
+        let mut files = BTreeMap::new();
+        for path in index.entries().keys() {
+            if let Some(content) = read_work_tree_file(&proj_root.join(path))? {
+                files.insert(path.clone(), Blob::new(&attributes.to_stored(path, content)).hash);
+            }
+        }
+        Ok(Self {
+            files,
+            work_tree: Some(proj_root.to_path_buf()),
+            attributes,
+        })
+    }
+
+    pub fn content(&self, objects_root: &Path, path: &Path) -> Result<Vec<u8>, String> {
+        match &self.work_tree {
+            Some(root) => read_work_tree_file(&root.join(path))?.
+                map(|content| self.attributes.to_stored(path, content)).
+                ok_or(format!("{} does not exist", path.display())),
+            None => {
+                let hash = self.files.get(path).ok_or(format!("{} is not in the snapshot", path.display()))?;
+                Ok(read_object(objects_root, *hash)?.1)
+            },
+        }
+    }
+}
+
+fn collect_tree_files(objects_root: &Path, tree_hash: Hash,
+                      files: &mut BTreeMap<PathBuf, Hash>) -> Result<(), String> {
+    Tree::load(objects_root, tree_hash)?.walk(objects_root, &mut |path, node| {
+        if !node.is_dir() {
+            files.insert(path.to_path_buf(), node.hash());
+        }
+        Ok(())
+    })
+}
+
+// file content, or the target for a symbolic link
+fn read_work_tree_file(path: &Path) -> Result<Option<Vec<u8>>, String> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(None),
+    };
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path).map_err(|e| e.to_string())?;
+        return Ok(Some(target.as_os_str().as_bytes().to_vec()));
+    }
+    fs::read(path).map(Some).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub old: Option<Hash>,
+    pub new: Option<Hash>,
+    // source path when the file was renamed to `path`
+    pub old_path: Option<PathBuf>,
+    // known when trees are compared, the patch header shows them then
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+}
+
+impl FileChange {
+    // letters of `status --porcelain`
+    pub fn status(&self) -> char {
+        match (self.old, self.new) {
+            _ if self.old_path.is_some() => 'R',
+            (None, _) => 'A',
+            (_, None) => 'D',
+            _ => 'M',
+        }
+    }
+
+    pub fn display_path(&self) -> String {
+        match &self.old_path {
+            Some(old_path) => format!("{} => {}", old_path.display(), self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+
+    fn old_path(&self) -> &Path {
+        self.old_path.as_deref().unwrap_or(&self.path)
+    }
+}
+
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> Vec<FileChange> {
+    let paths: BTreeSet<&PathBuf> = old.files.keys().chain(new.files.keys()).collect();
+    let changes = paths.into_iter().filter_map(|path| {
+        let (o, n) = (old.files.get(path).cloned(), new.files.get(path).cloned());
+        if o == n {
+            return None;
+        }
+        Some(FileChange {
+            path: path.clone(),
+            old: o,
+            new: n,
+            old_path: None,
+            old_mode: None,
+            new_mode: None,
+        })
+    }).collect();
+    detect_renames(changes)
+}
+
+// pair a deleted file with an added file of exactly the same content
+fn detect_renames(changes: Vec<FileChange>) -> Vec<FileChange> {
+    let mut deleted: BTreeMap<Hash, Vec<PathBuf>> = BTreeMap::new();
+    for change in &changes {
+        if let (Some(hash), None) = (change.old, change.new) {
+            deleted.entry(hash).or_default().push(change.path.clone());
+        }
+    }
+
+    let mut renamed_from = BTreeSet::new();
+    let mut ret: Vec<FileChange> = changes.into_iter().map(|mut change| {
+        if let (None, Some(hash)) = (change.old, change.new) {
+            if let Some(old_path) = deleted.get_mut(&hash).and_then(|paths| paths.pop()) {
+                change.old = Some(hash);
+                renamed_from.insert(old_path.clone());
+                change.old_path = Some(old_path);
+            }
+        }
+        change
+    }).collect();
+    ret.retain(|c| !(c.new.is_none() && renamed_from.contains(&c.path)));
+    ret
+}
+
+// (insertions, deletions) of a changed file, None for binary files
+pub fn count_lines(objects_root: &Path, old: &Snapshot, new: &Snapshot,
+                   change: &FileChange) -> Result<Option<(usize, usize)>, String> {
+    let old_content = match change.old {
+        Some(_) => old.content(objects_root, change.old_path())?,
+        None => Vec::new(),
+    };
+    let new_content = match change.new {
+        Some(_) => new.content(objects_root, &change.path)?,
+        None => Vec::new(),
+    };
+    if is_binary_change(old, new, change, &old_content, &new_content) {
+        return Ok(None);
+    }
+
+    let mut counts = (0, 0);
+    for hunk in diff_lines(&String::from_utf8_lossy(&old_content), &String::from_utf8_lossy(&new_content)) {
+        match hunk {
+            Hunk::Insert { len, .. } => counts.0 += len,
+            Hunk::Delete { len, .. } => counts.1 += len,
+            Hunk::Equal { .. } => {},
+        }
+    }
+    Ok(Some(counts))
+}
+
+// binary content, or marked as binary or -diff in .gitattributes
+fn is_binary_change(old: &Snapshot, new: &Snapshot, change: &FileChange,
+                    old_content: &[u8], new_content: &[u8]) -> bool {
+    old.attributes.is_binary(change.old_path(), old_content) || new.attributes.is_binary(&change.path, new_content)
+}
+
+// "<path>\n", or "<status>\t<path>\n" with "<old>\t<new>" for a rename, NUL separated with -z
+pub fn render_names(changes: &[FileChange], with_status: bool, nul: bool) -> String {
+    let (sep, term) = if nul { ("\0", "\0") } else { ("\t", "\n") };
+    let mut ret = String::new();
+    for change in changes {
+        if with_status {
+            ret.push(change.status());
+            ret.push_str(sep);
+            if let Some(old_path) = &change.old_path {
+                ret.push_str(&old_path.to_string_lossy());
+                ret.push_str(sep);
+            }
+        }
+        ret.push_str(&change.path.to_string_lossy());
+        ret.push_str(term);
+    }
+    ret
+}
+
+// git style patch of all the changes
+pub fn render_patch(objects_root: &Path, old: &Snapshot, new: &Snapshot, changes: &[FileChange],
+                    context: usize) -> Result<String, String> {
+    let mut ret = String::new();
+    for change in changes {
+        let old_name = change.old_path().display().to_string();
+        let new_name = change.path.display().to_string();
+        ret.push_str(&format!("diff --git a/{} b/{}\n", old_name, new_name));
+        if change.old_path.is_some() {
+            ret.push_str(&format!("similarity index 100%\nrename from {}\nrename to {}\n", old_name, new_name));
+            continue;
+        }
+        let short = |hash: Option<Hash>| match hash {
+            Some(hash) => crate::rev::abbreviate(objects_root, hash),
+            None => "0000000".to_string(),
+        };
+        // as git, a mode is on the index line unless it changed
+        let mut index_mode = String::new();
+        match (change.old_mode, change.new_mode) {
+            (_, Some(mode)) if change.old.is_none() => ret.push_str(&format!("new file mode {:06o}\n", mode)),
+            (Some(mode), _) if change.new.is_none() => ret.push_str(&format!("deleted file mode {:06o}\n", mode)),
+            (Some(old_mode), Some(new_mode)) if old_mode != new_mode =>
+                ret.push_str(&format!("old mode {:06o}\nnew mode {:06o}\n", old_mode, new_mode)),
+            (Some(mode), Some(_)) => index_mode = format!(" {:06o}", mode),
+            _ => {},
+        }
+        // nothing more when only the mode changed
+        if change.old == change.new {
+            continue;
+        }
+        ret.push_str(&format!("index {}..{}{}\n", short(change.old), short(change.new), index_mode));
+
+        let old_content = match change.old {
+            Some(_) => old.content(objects_root, &change.path)?,
+            None => Vec::new(),
+        };
+        let new_content = match change.new {
+            Some(_) => new.content(objects_root, &change.path)?,
+            None => Vec::new(),
+        };
+        let old_label = if change.old.is_some() { format!("a/{}", old_name) } else { "/dev/null".to_string() };
+        let new_label = if change.new.is_some() { format!("b/{}", new_name) } else { "/dev/null".to_string() };
+        if is_binary_change(old, new, change, &old_content, &new_content) {
+            ret.push_str(&format!("Binary files {} and {} differ\n", old_label, new_label));
+            continue;
+        }
+        ret.push_str(&format!("--- {}\n+++ {}\n", old_label, new_label));
+        ret.push_str(&render_unified(&String::from_utf8_lossy(&old_content),
+                                     &String::from_utf8_lossy(&new_content), context));
+    }
+    Ok(ret)
+}
+
+// " <path> | +N -M" per file and the summary line
+pub fn render_stat(objects_root: &Path, old: &Snapshot, new: &Snapshot,
+                   changes: &[FileChange]) -> Result<String, String> {
+    let width = changes.iter().map(|c| c.display_path().len()).max().unwrap_or(0);
+    let mut ret = String::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for change in changes {
+        let counts = match count_lines(objects_root, old, new, change)? {
+            Some((i, d)) => {
+                insertions += i;
+                deletions += d;
+                format!("+{} -{}", i, d)
+            },
+            None => "Bin".to_string(),
+        };
+        ret.push_str(&format!(" {:<width$} | {}\n", change.display_path(), counts, width = width));
+    }
+
+    let plural = |n: usize, word: &str| if n == 1 { word.to_string() } else { format!("{}s", word) };
+    ret.push_str(&format!(" {} {} changed", changes.len(), plural(changes.len(), "file")));
+    if insertions > 0 || deletions == 0 {
+        ret.push_str(&format!(", {} {}(+)", insertions, plural(insertions, "insertion")));
+    }
+    if deletions > 0 || insertions == 0 {
+        ret.push_str(&format!(", {} {}(-)", deletions, plural(deletions, "deletion")));
+    }
+    ret.push('\n');
+    Ok(ret)
+}
+
+#[test]
+fn test_render_stat() {
+    let objects_root = crate::common::test_dir("render_stat");
+    let write = |content: &[u8]| crate::object::write_object(&objects_root, crate::object::ObjType::Blob, content).unwrap();
+    let snapshot = |files: Vec<(&str, Hash)>| Snapshot {
+        files: files.into_iter().map(|(p, h)| (PathBuf::from(p), h)).collect(),
+        work_tree: None,
+        attributes: Default::default(),
+    };
+    let old = snapshot(vec![("a.txt", write(b"1\n2\n3\n")), ("gone", write(b"x\n")), ("img", write(b"\xff\x00"))]);
+    let new = snapshot(vec![("a.txt", write(b"1\ntwo\n3\n4\n")), ("img", write(b"\xff\x01")), ("new", write(b"y\n"))]);
+
+    let changes = diff_snapshots(&old, &new);
+    assert_eq!(render_patch(&objects_root, &old, &new, &changes[..2], 3).unwrap(),
+               format!("diff --git a/a.txt b/a.txt\nindex {}..{}\n--- a/a.txt\n+++ b/a.txt\n\
+                        @@ -1,3 +1,4 @@\n 1\n-2\n+two\n 3\n+4\n\
+                        diff --git a/gone b/gone\nindex {}..0000000\n--- a/gone\n+++ /dev/null\n@@ -1 +0,0 @@\n-x\n",
+                       &changes[0].old.unwrap().string()[..7], &changes[0].new.unwrap().string()[..7],
+                       &changes[1].old.unwrap().string()[..7]));
+    assert_eq!(render_stat(&objects_root, &old, &new, &changes).unwrap(),
+               " a.txt | +2 -1\n gone  | +0 -1\n img   | Bin\n new   | +1 -0\n \
+                4 files changed, 3 insertions(+), 2 deletions(-)\n");
+}
+
+#[test]
+fn test_detect_renames() {
+    let objects_root = crate::common::test_dir("detect_renames");
+    let content = crate::object::write_object(&objects_root, crate::object::ObjType::Blob, b"same\n").unwrap();
+    let other = crate::object::write_object(&objects_root, crate::object::ObjType::Blob, b"other\n").unwrap();
+    let snapshot = |files: Vec<(&str, Hash)>| Snapshot {
+        files: files.into_iter().map(|(p, h)| (PathBuf::from(p), h)).collect(),
+        work_tree: None,
+        attributes: Default::default(),
+    };
+    let old = snapshot(vec![("old.txt", content), ("removed", other)]);
+    let new = snapshot(vec![("dir/new.txt", content)]);
+
+    let changes = diff_snapshots(&old, &new);
+    assert_eq!(changes, vec![
+        FileChange { path: PathBuf::from("dir/new.txt"), old: Some(content), new: Some(content),
+                     old_path: Some(PathBuf::from("old.txt")), old_mode: None, new_mode: None },
+        FileChange { path: PathBuf::from("removed"), old: Some(other), new: None, old_path: None,
+                     old_mode: None, new_mode: None },
+    ]);
+    assert_eq!(render_names(&changes, false, false), "dir/new.txt\nremoved\n");
+    assert_eq!(render_names(&changes, true, false), "R\told.txt\tdir/new.txt\nD\tremoved\n");
+    assert_eq!(render_names(&changes, true, true), "R\0old.txt\0dir/new.txt\0D\0removed\0");
+    assert_eq!(render_stat(&objects_root, &old, &new, &changes).unwrap(),
+               " old.txt => dir/new.txt | +0 -0\n removed                | +0 -1\n \
+                2 files changed, 1 deletion(-)\n");
+}
@@ -0,0 +1,42 @@
+use std::env::current_dir;
+use clap::ArgMatches;
+use crate::common::{get_path_from_project_root, get_project_root, path_from_root};
+use crate::error::Error;
+use crate::ignore::Ignore;
+use crate::index::Index;
+
+/*
+    check-ignore <path>...       the paths ignored by .gitignore or .git/info/exclude, tracked ones never are
+    check-ignore -v <path>...    with "<source>:<line>:<pattern>" of the deciding rule, negated ones included
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let proj_root = get_project_root()?;
+    let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
+    let ignore = Ignore::from_work_tree(&proj_root)?;
+    let index = Index::from_file(&proj_root)?.unwrap_or_default();
+    let verbose = matches.is_present("verbose");
+
+    let mut found = false;
+    for arg in matches.values_of("path").ok_or("no path specified".to_string())? {
+        let path = path_from_root(&prefix, arg)?;
+        if index.entries().contains_key(&path) {
+            continue;
+        }
+        let is_dir = arg.ends_with('/') || proj_root.join(&path).is_dir();
+        let rule = match ignore.matching(&path, is_dir) {
+            Some(rule) if verbose || !rule.is_negated() => rule,
+            _ => continue,
+        };
+        found |= !rule.is_negated();
+        match verbose {
+            true => println!("{}:{}:{}\t{}", rule.source.display(), rule.line, rule.pattern, arg),
+            false => println!("{}", arg),
+        }
+    }
+
+    // as git, nothing ignored is reported only by the exit status
+    if !found {
+        return Err(Error::Silent);
+    }
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::Path;
+use clap::ArgMatches;
+use crate::attributes::Attributes;
+use crate::changes::{diff_snapshots, Snapshot};
+use crate::config::autocrlf;
+use crate::common::{common_dir, get_project_root, info};
+use crate::hash::Hash;
+use crate::head::{detach_head, write_symbolic_ref, Head};
+use crate::index::Index;
+use crate::object::{peel, read_object, Commit, FileType, Tree};
+use crate::refs::{check_branch_name, read_ref};
+use crate::rev::{self, abbreviate};
+use crate::status::files_under;
+
+/*
+    checkout <branch>               switch to the branch
+    checkout [--detach] <commit>    detach HEAD at the commit, a branch too with --detach
+    checkout --orphan <name>        start an unborn branch
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    if let Some(name) = matches.value_of("orphan") {
+        return checkout_orphan(&proj_root, name);
+    }
+    let target = matches.value_of("target").ok_or("no branch or commit specified")?;
+    for line in checkout(&proj_root, target, matches.is_present("detach"))? {
+        info(&line);
+    }
+    Ok(())
+}
+
+// switch the working tree, the index and HEAD, returning the messages to show
+fn checkout(proj_root: &Path, target: &str, detach: bool) -> Result<Vec<String>, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let head = Head::from_root(proj_root)?;
+    let branch = match read_ref(proj_root, &format!("refs/heads/{}", target))? {
+        Some(_) if !detach => Some(target),
+        _ => None,
+    };
+    // a tag checks out the commit it points to
+    let hash = peel(&objects_root, rev::resolve(proj_root, target)?)?.0;
+    let commit = Commit::load(&objects_root, hash).map_err(|_| format!("reference is not a tree: {}", target))?;
+
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    let head_tree = match head.hash() {
+        Some(hash) => Some(Commit::load(&objects_root, hash)?.tree()),
+        None => None,
+    };
+    check_clean(proj_root, &index, head_tree, "checkout")?;
+    switch_tree(proj_root, &index, commit.tree())?;
+
+    let describe = |hash: Hash, commit: &Commit| format!("{} {}", abbreviate(&objects_root, hash),
+                                                         commit.message().lines().next().unwrap_or(""));
+    let mut ret = Vec::new();
+    match branch {
+        Some(branch) => {
+            if let (true, Some(old)) = (head.is_dangling(), head.hash()) {
+                ret.push(format!("Previous HEAD position was {}", describe(old, &Commit::load(&objects_root, old)?)));
+            }
+            write_symbolic_ref(proj_root, "HEAD", &format!("refs/heads/{}", branch))?;
+            ret.push(match head.branch().as_deref() {
+                Some(b) if b == branch => format!("Already on '{}'", branch),
+                _ => format!("Switched to branch '{}'", branch),
+            });
+        },
+        None => {
+            detach_head(proj_root, hash)?;
+            ret.push(format!("HEAD is now at {}", describe(hash, &commit)));
+        },
+    }
+    Ok(ret)
+}
+
+// point HEAD at an unborn branch, the working tree and the index are kept as they are
+fn checkout_orphan(proj_root: &Path, name: &str) -> Result<(), String> {
+    check_branch_name(name)?;
+    let refname = format!("refs/heads/{}", name);
+    if read_ref(proj_root, &refname)?.is_some() {
+        return Err(format!("a branch named '{}' already exists", name));
+    }
+    write_symbolic_ref(proj_root, "HEAD", &refname)?;
+    info(&format!("Switched to a new branch '{}'", name));
+    Ok(())
+}
+
+#[test]
+fn test_checkout_orphan() {
+    let proj_root = crate::common::test_dir("checkout_orphan");
+    fs::create_dir_all(proj_root.join(".git/refs/heads")).unwrap();
+    fs::write(proj_root.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+    fs::write(proj_root.join(".git/refs/heads/master"), "064a92d783f99851d1517b51ba0b2aed4a1d3128\n").unwrap();
+
+    assert!(checkout_orphan(&proj_root, "master").is_err());
+    checkout_orphan(&proj_root, "gh-pages").unwrap();
+    let head = crate::head::Head::from_root(&proj_root).unwrap();
+    assert_eq!(head.branch(), Some("gh-pages".to_string()));
+    // no parent for the next commit
+    assert_eq!(head.hash(), None);
+    assert!(!proj_root.join(".git/refs/heads/gh-pages").exists());
+}
+
+#[test]
+fn test_checkout_detach() {
+    use crate::object::{write_object, ObjType};
+
+    let proj_root = crate::common::test_dir("checkout_detach");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let mut commits = Vec::new();
+    for (i, content) in [b"one\n", b"two\n"].iter().enumerate() {
+        let blob = write_object(&objects_root, ObjType::Blob, *content).unwrap();
+        let mut tree = Tree::new();
+        let entry = crate::index::IndexEntry::without_stat("a.txt".into(), 0o100644, blob);
+        tree.add_tree_node(crate::object::TreeNode::from_index_entry(&entry).unwrap());
+        let parent = commits.last().map(|p: &Hash| format!("parent {}\n", p.string())).unwrap_or_default();
+        let body = format!("tree {}\n{}author A <a@example.com> {} +0000\ncommitter A <a@example.com> {} +0000\n\nc{}\n",
+                           tree.write(&objects_root).unwrap().string(), parent, i, i, i);
+        commits.push(write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap());
+    }
+    crate::refs::write_ref(&proj_root, "refs/heads/master", commits[1]).unwrap();
+    checkout_tree(&proj_root, Commit::load(&objects_root, commits[1]).unwrap().tree()).unwrap().
+        write_file(&proj_root).unwrap();
+
+    checkout(&proj_root, &commits[0].string(), false).unwrap();
+    let head = Head::from_root(&proj_root).unwrap();
+    assert_eq!((head.branch(), head.hash(), head.is_dangling()), (None, Some(commits[0]), true));
+    assert_eq!(fs::read_to_string(proj_root.join("a.txt")).unwrap(), "one\n");
+
+    // local changes are not thrown away
+    fs::write(proj_root.join("a.txt"), "changed\n").unwrap();
+    assert!(checkout(&proj_root, "master", false).is_err());
+    fs::write(proj_root.join("a.txt"), "one\n").unwrap();
+
+    let messages = checkout(&proj_root, "master", false).unwrap();
+    assert!(messages[0].starts_with("Previous HEAD position was "));
+    assert_eq!(messages[1], "Switched to branch 'master'");
+    let head = Head::from_root(&proj_root).unwrap();
+    assert_eq!((head.branch(), head.hash()), (Some("master".to_string()), Some(commits[1])));
+    assert_eq!(fs::read_to_string(proj_root.join("a.txt")).unwrap(), "two\n");
+
+    checkout(&proj_root, "master", true).unwrap();
+    assert!(Head::from_root(&proj_root).unwrap().is_dangling());
+
+    // an untracked file in the way of the branch stays as it is
+    let blob = write_object(&objects_root, ObjType::Blob, b"topic\n").unwrap();
+    let tree = crate::object::write_test_tree(&objects_root, &[("100644", "b.txt", blob)]);
+    let body = format!("tree {}\nparent {}\nauthor A <a@example.com> 2 +0000\ncommitter A <a@example.com> 2 +0000\n\nc2\n",
+                       tree.string(), commits[1].string());
+    let topic = write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap();
+    crate::refs::write_ref(&proj_root, "refs/heads/topic", topic).unwrap();
+    checkout(&proj_root, "master", false).unwrap();
+    fs::write(proj_root.join("b.txt"), "precious\n").unwrap();
+    let err = checkout(&proj_root, "topic", false).unwrap_err();
+    assert!(err.contains("untracked working tree files would be overwritten"), "{}", err);
+    assert_eq!(fs::read_to_string(proj_root.join("b.txt")).unwrap(), "precious\n");
+    assert_eq!(fs::read_to_string(proj_root.join("a.txt")).unwrap(), "two\n");
+    assert_eq!(Head::from_root(&proj_root).unwrap().branch(), Some("master".to_string()));
+}
+
+// the working tree must match the index, and the index HEAD when given
+pub fn check_clean(proj_root: &Path, index: &Index, head_tree: Option<Hash>, action: &str) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let staged = Snapshot::from_index(index);
+    let dirty = || format!("cannot {}: you have uncommitted changes", action);
+    if let Some(head_tree) = head_tree {
+        if !diff_snapshots(&Snapshot::from_tree(&objects_root, head_tree)?, &staged).is_empty() {
+            return Err(dirty());
+        }
+    }
+    if !diff_snapshots(&staged, &Snapshot::from_work_tree(proj_root, index)?).is_empty() {
+        return Err(dirty());
+    }
+    Ok(())
+}
+
+// write the files of a tree into the working tree, returning the matching index
+pub fn checkout_tree(proj_root: &Path, tree_hash: Hash) -> Result<Index, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let attributes = Attributes::from_tree(&objects_root, tree_hash)?.with_autocrlf(autocrlf(proj_root)?);
+    let mut index = Index::new();
+    write_tree(proj_root, &objects_root, &attributes, tree_hash, Path::new(""), &mut index)?;
+    Ok(index)
+}
+
+// move the working tree and the index from old_index to the tree, files missing from it are removed
+// nothing is touched when an untracked file is in the way
+pub fn switch_tree(proj_root: &Path, old_index: &Index, tree_hash: Hash) -> Result<Index, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let tracked = old_index.entries();
+    let mut target = BTreeSet::new();
+    let mut untracked = BTreeSet::new();
+    Tree::load(&objects_root, tree_hash)?.walk(&objects_root, &mut |path, node| {
+        if matches!(node.file_type(), FileType::File | FileType::SymbolicLink) {
+            target.insert(path.to_path_buf());
+        }
+        let meta = match fs::symlink_metadata(proj_root.join(path)) {
+            Ok(meta) if !tracked.contains_key(path) => meta,
+            _ => return Ok(()),
+        };
+        match node.file_type() {
+            FileType::Directory if !meta.is_dir() => {
+                untracked.insert(path.display().to_string());
+            },
+            // a directory holding only tracked files is removed below
+            FileType::File | FileType::SymbolicLink if meta.is_dir() => {
+                untracked.extend(files_under(proj_root, path, None)?.into_iter().
+                    filter(|p| !tracked.contains_key(Path::new(p))));
+            },
+            FileType::File | FileType::SymbolicLink => {
+                untracked.insert(path.display().to_string());
+            },
+            _ => {},
+        }
+        Ok(())
+    })?;
+    if !untracked.is_empty() {
+        let list: String = untracked.iter().map(|p| format!("\t{}\n", p)).collect();
+        return Err(format!("The following untracked working tree files would be overwritten:\n{}\
+                            Please move or remove them first.", list));
+    }
+
+    // removed first, so that a directory can take the place of a file and the other way round
+    for path in tracked.keys().filter(|p| !target.contains(*p)) {
+        let abs_path = proj_root.join(path);
+        if fs::symlink_metadata(&abs_path).is_ok() {
+            fs::remove_file(&abs_path).map_err(|e| e.to_string())?;
+        }
+        // directories left empty go too
+        let mut dir = abs_path.parent();
+        while let Some(d) = dir.filter(|d| *d != proj_root) {
+            if fs::remove_dir(d).is_err() {
+                break;
+            }
+            dir = d.parent();
+        }
+    }
+    let index = checkout_tree(proj_root, tree_hash)?;
+    index.write_file(proj_root)?;
+    Ok(index)
+}
+
+fn write_tree(proj_root: &Path, objects_root: &Path, attributes: &Attributes, tree_hash: Hash, dir: &Path,
+              index: &mut Index) -> Result<(), String> {
+    let tree = Tree::load(objects_root, tree_hash)?;
+    fs::create_dir_all(proj_root.join(dir)).map_err(|e| e.to_string())?;
+
+    for node in tree.nodes() {
+        let path = dir.join(node.file_name());
+        let abs_path = proj_root.join(&path);
+        match node.file_type() {
+            FileType::Directory => {
+                // never create the contents through a symbolic link
+                if fs::symlink_metadata(&abs_path).is_ok_and(|m| !m.is_dir()) {
+                    fs::remove_file(&abs_path).map_err(|e| e.to_string())?;
+                }
+                write_tree(proj_root, objects_root, attributes, node.hash(), &path, index)?;
+            },
+            FileType::File => {
+                let (_, body) = read_object(objects_root, node.hash())?;
+                // fs::write would follow a symbolic link left there
+                match fs::symlink_metadata(&abs_path) {
+                    Ok(meta) if meta.is_dir() => fs::remove_dir(&abs_path).map_err(|e| e.to_string())?,
+                    Ok(meta) if !meta.is_file() => fs::remove_file(&abs_path).map_err(|e| e.to_string())?,
+                    _ => {},
+                }
+                fs::write(&abs_path, attributes.to_work_tree(&path, body)).map_err(|e| e.to_string())?;
+                fs::set_permissions(&abs_path, fs::Permissions::from_mode(node.mode() & 0o777)).
+                    map_err(|e| e.to_string())?;
+                index.add_entry_in(proj_root, &path, node.hash())?;
+            },
+            FileType::SymbolicLink => {
+                let (_, target) = read_object(objects_root, node.hash())?;
+                let target = String::from_utf8(target).map_err(|e| e.to_string())?;
+                if fs::symlink_metadata(&abs_path).is_ok() {
+                    fs::remove_file(&abs_path).map_err(|e| e.to_string())?;
+                }
+                symlink(target, &abs_path).map_err(|e| e.to_string())?;
+                index.add_entry_in(proj_root, &path, node.hash())?;
+            },
+            FileType::Submodule => {
+                fs::create_dir_all(&abs_path).map_err(|e| e.to_string())?;
+            },
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_switch_tree_in_the_way() {
+    use crate::object::{write_object, write_test_tree, ObjType};
+
+    let proj_root = crate::common::test_dir("switch_tree_in_the_way");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let one = write_object(&objects_root, ObjType::Blob, b"one\n").unwrap();
+    let two = write_object(&objects_root, ObjType::Blob, b"two\n").unwrap();
+    let link = write_object(&objects_root, ObjType::Blob, b"a.txt").unwrap();
+    let base = write_test_tree(&objects_root, &[("100644", "a.txt", one), ("120000", "l", link)]);
+    let topic = write_test_tree(&objects_root, &[("100644", "a.txt", one), ("100644", "b.txt", two),
+                                                 ("100644", "l", two)]);
+    let index = checkout_tree(&proj_root, base).unwrap();
+
+    // an untracked file is kept and nothing is written
+    fs::write(proj_root.join("b.txt"), "precious\n").unwrap();
+    let err = switch_tree(&proj_root, &index, topic).unwrap_err();
+    assert!(err.contains("untracked working tree files would be overwritten:\n\tb.txt\n"), "{}", err);
+    assert_eq!(fs::read_to_string(proj_root.join("b.txt")).unwrap(), "precious\n");
+    assert!(fs::symlink_metadata(proj_root.join("l")).unwrap().file_type().is_symlink());
+
+    // so is an untracked link pointing outside
+    let outside = crate::common::test_dir("switch_tree_in_the_way_outside");
+    fs::remove_file(proj_root.join("b.txt")).unwrap();
+    symlink(outside.join("x"), proj_root.join("b.txt")).unwrap();
+    assert!(switch_tree(&proj_root, &index, topic).is_err());
+    assert!(!outside.join("x").exists());
+    fs::remove_file(proj_root.join("b.txt")).unwrap();
+
+    // the tracked link is replaced, not written through
+    let index = switch_tree(&proj_root, &index, topic).unwrap();
+    assert_eq!(fs::read_to_string(proj_root.join("a.txt")).unwrap(), "one\n");
+    assert!(fs::symlink_metadata(proj_root.join("l")).unwrap().is_file());
+    assert_eq!(fs::read_to_string(proj_root.join("l")).unwrap(), "two\n");
+
+    // files going away make room for a directory, but an untracked one there does not
+    let nested = write_test_tree(&objects_root, &[("100644", "x", one)]);
+    let dirs = write_test_tree(&objects_root, &[("40000", "b.txt", nested)]);
+    let index = switch_tree(&proj_root, &index, dirs).unwrap();
+    assert_eq!(fs::read_to_string(proj_root.join("b.txt/x")).unwrap(), "one\n");
+    assert!(!proj_root.join("a.txt").exists());
+    fs::write(proj_root.join("b.txt/y"), "precious\n").unwrap();
+    assert!(switch_tree(&proj_root, &index, topic).unwrap_err().contains("\tb.txt/y\n"));
+    fs::remove_file(proj_root.join("b.txt/y")).unwrap();
+    switch_tree(&proj_root, &index, topic).unwrap();
+    assert_eq!(fs::read_to_string(proj_root.join("b.txt")).unwrap(), "two\n");
+}
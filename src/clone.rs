@@ -0,0 +1,71 @@
+use std::fs;
+use std::path::PathBuf;
+use clap::ArgMatches;
+use crate::checkout::checkout_tree;
+use crate::common::{copy_dir_all, info};
+use crate::config::{local_config_path, GitConfig};
+use crate::head::Head;
+use crate::init;
+use crate::object::Commit;
+use crate::refs::{list_refs_in, write_ref};
+use crate::remote::add_remote;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let src = PathBuf::from(matches.value_of("source").ok_or("no source specified")?);
+    let dst = PathBuf::from(matches.value_of("destination").ok_or("no destination specified")?);
+
+    // normal repository or bare one
+    let src_git = if src.join(".git").is_dir() {
+        src.join(".git")
+    } else if src.join("objects").is_dir() && src.join("HEAD").is_file() {
+        src.clone()
+    } else {
+        return Err(format!("repository '{}' does not exist", src.display()));
+    };
+
+    if dst.exists() && fs::read_dir(&dst).map_err(|e| e.to_string())?.next().is_some() {
+        return Err(format!("destination path '{}' already exists and is not an empty directory",
+                           dst.display()));
+    }
+    info(&format!("Cloning into '{}'...", dst.display()));
+
+    let git_dir = dst.join(".git");
+    init::create_layout(&git_dir, false)?;
+    copy_dir_all(&src_git.join("objects"), &git_dir.join("objects"))?;
+    fs::copy(src_git.join("HEAD"), git_dir.join("HEAD")).map_err(|e| e.to_string())?;
+
+    // branches of the source become remote-tracking refs, the default branch also a local one
+    let default_branch = Head::from_root(&dst)?.branch();
+    for (name, hash) in list_refs_in(&src_git, "refs/")? {
+        match name.strip_prefix("refs/heads/") {
+            Some(branch) => {
+                write_ref(&dst, &format!("refs/remotes/origin/{}", branch), hash)?;
+                if Some(branch) == default_branch.as_deref() {
+                    write_ref(&dst, &name, hash)?;
+                }
+            },
+            None if name.starts_with("refs/tags/") => write_ref(&dst, &name, hash)?,
+            None => {},
+        }
+    }
+    if let Some(branch) = &default_branch {
+        fs::write(git_dir.join("refs/remotes/origin/HEAD"),
+                  format!("ref: refs/remotes/origin/{}\n", branch)).map_err(|e| e.to_string())?;
+    }
+
+    let mut config = GitConfig::from_file(&local_config_path(&dst))?;
+    let url = fs::canonicalize(&src).map_err(|e| e.to_string())?;
+    add_remote(&mut config, "origin", &url.to_string_lossy())?;
+    config.write_file(&local_config_path(&dst))?;
+
+    // nothing to check out in an empty repository
+    let head_hash = match Head::from_root(&dst)?.hash() {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+    let commit = Commit::load(&git_dir.join("objects"), head_hash)?;
+    let index = checkout_tree(&dst, commit.tree())?;
+    index.write_file(&dst)?;
+
+    Ok(())
+}
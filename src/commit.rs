@@ -1,38 +1,51 @@
-use std::path::PathBuf;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::fs;
+use chrono::{DateTime, FixedOffset};
 use std::io::Write;
 use clap::ArgMatches;
-use crate::common::get_project_root;
-use crate::head::Head;
+use crate::add;
+use crate::common::{common_dir, get_project_root};
+use crate::head::{update_head, Head};
+use crate::state::MergeState;
+use crate::trailer::add_trailer;
 use crate::index::{Index, IndexEntry};
-use crate::object::{Tree, TreeNode, Commit, CommitUser, CommitterType};
+use crate::object::{object_type, parse_date, ObjType, Tree, TreeNode, Commit, CommitUser, CommitterType};
 use super::config as config;
 use super::hash::Hash;
 
 pub fn run(matches: &ArgMatches) -> Result<(), String>{
     let conf: config::Config = config::parse_config()?;
-    let message = matches.value_of("message").map(|m| m.to_string());
-
     let proj_root = get_project_root()?;
-    let head = Head::new()?;
-    if head.is_dangling() {
-        return Err("header is detached, please create branch".to_string());
-    }
+    let merge = MergeState::load(&proj_root)?;
+    let message = matches.value_of("message").map(|m| m.to_string()).
+        or_else(|| merge.as_ref().map(|m| m.message.clone()).filter(|m| !m.is_empty()));
 
-    let user_name = conf.user.name;
-    let user_email = conf.user.email;
-    let config= CommitConf{
-        user_name,
-        user_email,
-        is_amend: false
-    };
-
-    let index = Index::from_file(&proj_root).ok_or("no index found".to_string())?;
+    let head = Head::new()?;
 
+    let mut config = CommitConf::from_config(&conf, |name| env::var(name).ok())?;
+    config.author_override = matches.value_of("author").map(|a| a.to_string());
+    config.author_date = date_from_env("GIT_AUTHOR_DATE")?;
+    config.committer_date = date_from_env("GIT_COMMITTER_DATE")?;
+    config.dry_run = matches.is_present("dry-run");
+    config.is_amend = matches.is_present("amend");
+    config.no_edit = matches.is_present("no-edit");
+    config.signoff = matches.is_present("signoff");
+    config.merge_head = merge.as_ref().map(|m| m.head);
+    config.committer_date_is_author_date = matches.is_present("committer-date-is-author-date");
+
+    let dry_run = config.dry_run;
+    let index = commit_index(&proj_root, matches.is_present("all"), dry_run)?;
+    let staged = index.clone();
     let mut generator = CommitGenerator::new(index,proj_root.clone(), config, message, head.clone())?;
     let commit_obj =  generator.exec()?;
     let (hash, body) = commit_obj.generate_hash_and_depress()?;
-    let obj_path = proj_root.join(".git/objects").join(hash.generate_path());
+    if dry_run {
+        print!("{}", dry_run_report(&proj_root, &commit_obj, hash, &staged)?);
+        return Ok(());
+    }
+    check_commit_objects(&common_dir(&proj_root).join("objects"), &commit_obj)?;
+    let obj_path = common_dir(&proj_root).join("objects").join(hash.generate_path());
 
     let path_parent = obj_path.parent().unwrap();
     fs::create_dir_all(path_parent).map_err(|e| e.to_string())?;
@@ -40,14 +53,166 @@ pub fn run(matches: &ArgMatches) -> Result<(), String>{
         fs::write(obj_path, &body).map_err(|e| e.to_string())?;
     }
 
-    let branch_path = proj_root.join(".git/refs/heads").
-                                join(&head.branch().unwrap());
-    fs::write(branch_path,hash.string()).map_err(|e| e.to_string())?;
+    // based on the HEAD read before the commit was made
+    update_head(&proj_root, hash, head.hash())?;
+    if merge.is_some() {
+        MergeState::remove(&proj_root)?;
+    }
+    Ok(())
+}
+
+// the index to commit, with -a the tracked files are updated first, only in memory for --dry-run
+fn commit_index(proj_root: &Path, all: bool, dry_run: bool) -> Result<Index, String> {
+    match all {
+        true => Ok(*add::update_tracked(proj_root, dry_run)?),
+        false => Index::from_file(proj_root)?.ok_or("no index found".to_string()),
+    }
+}
 
+#[test]
+fn test_commit_index_dry_run() {
+    use crate::object::{object_exists, write_object, Blob};
+
+    let proj_root = crate::common::test_dir("commit_index_dry_run");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    fs::write(proj_root.join("a.txt"), "one\n").unwrap();
+    let one = write_object(&proj_root.join(".git/objects"), ObjType::Blob, b"one\n").unwrap();
+    let metadata = fs::symlink_metadata(proj_root.join("a.txt")).unwrap();
+    let mut index = Index::new();
+    index.add_entry_with_stat(IndexEntry::from_metadata(PathBuf::from("a.txt"), &metadata, one).unwrap());
+    index.write_file(&proj_root).unwrap();
+    fs::write(proj_root.join("a.txt"), "one two\n").unwrap();
+    let written = fs::read(proj_root.join(".git/index")).unwrap();
+
+    let index = commit_index(&proj_root, true, true).unwrap();
+    let two = Blob::new(&b"one two\n".to_vec()).generate_digest_bytes();
+    assert_eq!(index.entries()[Path::new("a.txt")].hash(), two);
+    assert_eq!(fs::read(proj_root.join(".git/index")).unwrap(), written);
+    assert!(!object_exists(&proj_root.join(".git/objects"), two));
+
+    assert_eq!(commit_index(&proj_root, true, false).unwrap().entries()[Path::new("a.txt")].hash(), two);
+    assert_ne!(fs::read(proj_root.join(".git/index")).unwrap(), written);
+}
 
+// the tree and the parents must be in the repository before the commit pointing at them is
+fn check_commit_objects(objects_root: &Path, commit: &Commit) -> Result<(), String> {
+    match object_type(objects_root, commit.tree()) {
+        Ok(ObjType::Tree) => {},
+        Ok(t) => return Err(format!("tree {} of the new commit is a {}", commit.tree().string(), t.name())),
+        Err(_) => return Err(format!("tree {} of the new commit was not written", commit.tree().string())),
+    }
+    for parent in commit.parents() {
+        match object_type(objects_root, *parent) {
+            Ok(ObjType::Commit) => {},
+            Ok(t) => return Err(format!("parent {} of the new commit is a {}", parent.string(), t.name())),
+            Err(_) => return Err(format!("parent {} of the new commit does not exist", parent.string())),
+        }
+    }
     Ok(())
 }
 
+#[test]
+fn test_check_commit_objects() {
+    use crate::object::write_object;
+
+    let objects_root = crate::common::test_dir("check_commit_objects");
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Author);
+    let commit_of = |tree: Hash, parents: Vec<Hash>| {
+        Commit::from(tree, parents, user.clone(), user.change_committer_type_as(CommitterType::Committer),
+                     "message\n".to_string())
+    };
+    let tree = Tree::new().write(&objects_root).unwrap();
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    assert!(check_commit_objects(&objects_root, &commit_of(tree, vec![])).is_ok());
+    assert!(check_commit_objects(&objects_root, &commit_of(Hash::default(), vec![])).is_err());
+    assert!(check_commit_objects(&objects_root, &commit_of(blob, vec![])).is_err());
+
+    let parent = commit_of(tree, vec![]).write(&objects_root).unwrap();
+    assert!(check_commit_objects(&objects_root, &commit_of(tree, vec![parent])).is_ok());
+    assert!(check_commit_objects(&objects_root, &commit_of(tree, vec![parent, Hash::default()])).is_err());
+    assert!(check_commit_objects(&objects_root, &commit_of(tree, vec![blob])).is_err());
+}
+
+// the would-be commit and the files it changes, nothing is written
+fn dry_run_report(proj_root: &Path, commit: &Commit, hash: Hash, index: &Index) -> Result<String, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let (added, modified, deleted) = match commit.parents().first() {
+        Some(parent) => index.diff_tree(&objects_root, Commit::load(&objects_root, *parent)?.tree())?,
+        None => (index.entries().into_iter().filter(|(_, e)| !e.is_intent_to_add()).map(|(p, _)| p).collect(),
+                 Vec::new(), Vec::new()),
+    };
+    if added.is_empty() && modified.is_empty() && deleted.is_empty() {
+        return Err("nothing to commit, the tree is the same as HEAD".to_string());
+    }
+    let mut out = format!("{}\n", commit.raw_log_entry(hash));
+    for (label, paths) in [("new file:", added), ("modified:", modified), ("deleted:", deleted)] {
+        for path in paths {
+            out.push_str(&format!("\t{:<12}{}\n", label, path.display()));
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_commit_dry_run() {
+    use crate::object::{object_exists, write_object, write_test_tree};
+
+    let proj_root = crate::common::test_dir("commit_dry_run");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let blob = |content: &str| write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+    let tree = write_test_tree(&objects_root, &[("100644", "a.txt", blob("a\n")), ("100644", "b.txt", blob("b\n"))]);
+    let body = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nbase\n",
+                       tree.string());
+    let base = write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap();
+    crate::refs::write_ref(&proj_root, "refs/heads/master", base).unwrap();
+
+    let mut index = Index::new();
+    for (name, content) in [("a.txt", "changed\n"), ("c.txt", "c\n")] {
+        index.add_entry_with_stat(IndexEntry::without_stat(PathBuf::from(name), 0o100644, blob(content)));
+    }
+    let config = || CommitConf { dry_run: true, ..CommitConf::with_identity("A", "a@example.com") };
+    let head = Head::from_root(&proj_root).unwrap();
+    let commit = CommitGenerator::new(index.clone(), proj_root.clone(), config(), None, head.clone()).unwrap().
+        exec().unwrap();
+    let (hash, _) = commit.generate_hash_and_depress().unwrap();
+    // neither the commit nor its tree is written
+    assert!(!object_exists(&objects_root, commit.tree()));
+    assert!(!object_exists(&objects_root, hash));
+    assert_eq!(commit.parents(), &vec![base]);
+    let report = dry_run_report(&proj_root, &commit, hash, &index).unwrap();
+    assert!(report.starts_with(&format!("commit {}\n", hash.string())), "{}", report);
+    assert!(report.ends_with("\n\tnew file:   c.txt\n\tmodified:   a.txt\n\tdeleted:    b.txt\n"), "{}", report);
+
+    let mut same = Index::new();
+    for (name, content) in [("a.txt", "a\n"), ("b.txt", "b\n")] {
+        same.add_entry_with_stat(IndexEntry::without_stat(PathBuf::from(name), 0o100644, blob(content)));
+    }
+    let commit = CommitGenerator::new(same.clone(), proj_root.clone(), config(), None, head).unwrap().exec().unwrap();
+    assert!(dry_run_report(&proj_root, &commit, Hash::default(), &same).unwrap_err().starts_with("nothing to commit"));
+}
+
+// committer of commits made by other commands, from the same config and environment as commit
+pub fn current_committer() -> Result<CommitUser, String> {
+    let conf = CommitConf::from_config(&config::parse_config()?, |name| env::var(name).ok())?;
+    let committer = CommitUser::from(conf.committer_name, conf.committer_email, CommitterType::Committer);
+    Ok(match date_from_env("GIT_COMMITTER_DATE")? {
+        Some(date) => committer.change_timestamp_as(date),
+        None => committer,
+    })
+}
+
+// store the trees of the index, returning the root tree
+pub fn write_index_tree(proj_root: &Path, index: Index) -> Result<Hash, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut tree_list: Vec<(Hash, Tree)> = Vec::new();
+    let root_hash = CommitTree::from_index(index)?.generate_tree_obj(&mut tree_list)?;
+    for (_, tree) in &tree_list {
+        tree.write(&objects_root)?;
+    }
+    Ok(root_hash)
+}
+
 struct CommitGenerator {
     commit_tree: CommitTree,
     obj_root: PathBuf,
@@ -59,7 +224,7 @@ struct CommitGenerator {
 impl CommitGenerator {
     pub fn new(index: Index, proj_root: PathBuf, config: CommitConf,
                message: Option<String>, head: Head) -> Result<Self, String> {
-        let obj_root = proj_root.join(".git/objects");
+        let obj_root = common_dir(&proj_root).join("objects");
         let commit_tree = CommitTree::from_index(index)?;
         Ok(CommitGenerator {
             commit_tree,
@@ -74,26 +239,108 @@ impl CommitGenerator {
         let mut tree_list: Vec<(Hash, Tree)> = Vec::new();
         let root_hash = self.commit_tree.generate_tree_obj(&mut tree_list)?;
 
-        self.generate_tree_file(&mut tree_list)?;
+        if !self.config.dry_run {
+            self.generate_tree_file(&tree_list)?;
+        }
 
         if self.config.is_amend {
-            todo!()
+            self.generate_amend_commit(root_hash)
         } else {
             self.generate_new_commit(root_hash)
         }
     }
 
+    /*
+        replaces HEAD: its parents and author, the message too with --no-edit
+        the author keeps the original timestamp, --author replaces the identity only as for a new commit
+     */
+    fn generate_amend_commit(&mut self, root_hash: Hash) -> Result<Commit, String> {
+        let old_hash = self.head.hash().ok_or("You have nothing to amend.".to_string())?;
+        let old = Commit::load(&self.obj_root, old_hash)?;
+        let committer = self.committer();
+        let author = match &self.config.author_override {
+            Some(ident) => {
+                let (name, email) = parse_ident(ident)?;
+                old.author().change_identity_as(name, email)
+            },
+            None => old.author().clone(),
+        };
+        let message = match &self.message {
+            Some(message) if message.ends_with('\n') => message.clone(),
+            Some(message) => format!("{}\n", message),
+            None if self.config.no_edit || self.config.dry_run => old.message().to_string(),
+            None => return Err("no commit message, use -m or --no-edit to keep the old one".to_string()),
+        };
+        let message = self.sign_off(message);
+        let committer = self.committer_date_of(committer, &author);
+        Ok(Commit::from(root_hash, old.parents().clone(), author, committer, message))
+    }
+
+    // -s: the committer's Signed-off-by line at the end
+    fn sign_off(&self, message: String) -> String {
+        if !self.config.signoff {
+            return message;
+        }
+        let ident = format!("{} <{}>", self.config.committer_name, self.config.committer_email);
+        add_trailer(&message, "Signed-off-by", &ident)
+    }
+
+    // --committer-date-is-author-date
+    fn committer_date_of(&self, committer: CommitUser, author: &CommitUser) -> CommitUser {
+        match self.config.committer_date_is_author_date {
+            true => committer.change_timestamp_as(author.timestamp()),
+            false => committer,
+        }
+    }
+
+    fn committer(&self) -> CommitUser {
+        let committer = CommitUser::from(self.config.committer_name.clone(), self.config.committer_email.clone(),
+                                         CommitterType::Committer);
+        match self.config.committer_date {
+            Some(date) => committer.change_timestamp_as(date),
+            None => committer,
+        }
+    }
+
     fn generate_new_commit(&mut self, root_hash: Hash) -> Result<Commit, String> {
-        let parents =  match self.head.hash() {
+        let mut parents =  match self.head.hash() {
             None => { Vec::new() }
             Some(h) => { vec![h] }
         };
+        parents.extend(self.config.merge_head);
 
-        let author = CommitUser::from(self.config.user_name.clone(),
-                                      self.config.user_email.clone(),
+        let author = CommitUser::from(self.config.author_name.clone(),
+                                      self.config.author_email.clone(),
                                       CommitterType::Author);
-        let committer = author.change_committer_type_as(CommitterType::Committer);
-        let message = self.message.clone().ok_or("no commit message")?;
+        let committer = author.change_committer_type_as(CommitterType::Committer).
+            change_identity_as(self.config.committer_name.clone(), self.config.committer_email.clone());
+        // --author replaces the identity only, the timestamp stays the same
+        let author = match &self.config.author_override {
+            Some(ident) => {
+                let (name, email) = parse_ident(ident)?;
+                author.change_identity_as(name, email)
+            },
+            None => author,
+        };
+        let author = match self.config.author_date {
+            Some(date) => author.change_timestamp_as(date),
+            None => author,
+        };
+        let committer = match self.config.committer_date {
+            Some(date) => committer.change_timestamp_as(date),
+            None => committer,
+        };
+        // a message is not needed just to see the commit
+        let mut message = match &self.message {
+            Some(message) => message.clone(),
+            None if self.config.dry_run => String::new(),
+            None => return Err("no commit message".to_string()),
+        };
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
+        let message = self.sign_off(message);
+        let committer = self.committer_date_of(committer, &author);
         Ok(Commit::from(root_hash,parents,author, committer,message))
     }
 
@@ -116,9 +363,237 @@ impl CommitGenerator {
 }
 
 struct CommitConf {
-    user_name: String,
-    user_email: String,
+    author_name: String,
+    author_email: String,
+    committer_name: String,
+    committer_email: String,
+    author_override: Option<String>,
+    author_date: Option<DateTime<FixedOffset>>,
+    committer_date: Option<DateTime<FixedOffset>>,
     is_amend: bool,
+    // keep the message of the amended commit
+    no_edit: bool,
+    signoff: bool,
+    // the second parent when concluding a merge stopped by a conflict
+    merge_head: Option<Hash>,
+    // the committer timestamp copied from the author, for reproducible commits
+    committer_date_is_author_date: bool,
+    dry_run: bool,
+}
+
+impl CommitConf {
+    // environment variables, looked up by `env`, take precedence over the config
+    fn from_config(conf: &config::Config, env: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let identity = |name: &str, config_value: &str| match env(name) {
+            Some(value) if !value.is_empty() => value,
+            _ => config_value.to_string(),
+        };
+        let author_name = identity("GIT_AUTHOR_NAME", &conf.user.name);
+        let author_email = identity("GIT_AUTHOR_EMAIL", &conf.user.email);
+        let committer_name = identity("GIT_COMMITTER_NAME", &conf.user.name);
+        let committer_email = identity("GIT_COMMITTER_EMAIL", &conf.user.email);
+        if [&author_name, &author_email, &committer_name, &committer_email].iter().any(|v| v.trim().is_empty()) {
+            return Err("please set user.name and user.email".to_string());
+        }
+        Ok(Self {
+            author_name,
+            author_email,
+            committer_name,
+            committer_email,
+            author_override: None,
+            author_date: None,
+            committer_date: None,
+            is_amend: false,
+            no_edit: false,
+            signoff: false,
+            merge_head: None,
+            committer_date_is_author_date: false,
+            dry_run: false,
+        })
+    }
+}
+
+// the given identity as author and committer, with every option off
+#[cfg(test)]
+impl CommitConf {
+    fn with_identity(name: &str, email: &str) -> Self {
+        Self {
+            author_name: name.to_string(),
+            author_email: email.to_string(),
+            committer_name: name.to_string(),
+            committer_email: email.to_string(),
+            author_override: None,
+            author_date: None,
+            committer_date: None,
+            is_amend: false,
+            no_edit: false,
+            signoff: false,
+            merge_head: None,
+            committer_date_is_author_date: false,
+            dry_run: false,
+        }
+    }
+}
+
+#[test]
+fn test_commit_conf_without_identity() {
+    let unset = |_: &str| None;
+    let mut conf = config::Config::default();
+    assert_eq!(CommitConf::from_config(&conf, unset).err(), Some("please set user.name and user.email".to_string()));
+
+    conf.user.name = "A U Thor".to_string();
+    assert!(CommitConf::from_config(&conf, unset).is_err());
+    conf.user.email = "author@example.com".to_string();
+    let commit_conf = CommitConf::from_config(&conf, unset).unwrap();
+    assert_eq!(commit_conf.committer_name, "A U Thor");
+
+    // the environment overrides the config, and fills in what it lacks
+    let env = |name: &str| match name {
+        "GIT_COMMITTER_NAME" => Some("C O Mitter".to_string()),
+        _ => None,
+    };
+    let commit_conf = CommitConf::from_config(&conf, env).unwrap();
+    assert_eq!((commit_conf.author_name.as_str(), commit_conf.committer_name.as_str()), ("A U Thor", "C O Mitter"));
+    let env = |name: &str| Some(format!("{}@example.com", name));
+    assert!(CommitConf::from_config(&config::Config::default(), env).is_ok());
+}
+
+fn date_from_env(name: &str) -> Result<Option<DateTime<FixedOffset>>, String> {
+    date_from(name, env::var(name).ok().as_deref())
+}
+
+// the date given in the variable, if it is set
+fn date_from(name: &str, value: Option<&str>) -> Result<Option<DateTime<FixedOffset>>, String> {
+    match value {
+        Some(value) => parse_date(value).map(Some).
+            ok_or(format!("invalid date format in {}: {}", name, value)),
+        None => Ok(None),
+    }
+}
+
+#[test]
+fn test_commit_date_from_env() {
+    assert_eq!(date_from("GIT_AUTHOR_DATE", None), Ok(None));
+    assert_eq!(date_from("GIT_AUTHOR_DATE", Some("yesterday")).err(),
+               Some("invalid date format in GIT_AUTHOR_DATE: yesterday".to_string()));
+
+    let proj_root = crate::common::test_dir("commit_date_from_env");
+    let config = CommitConf {
+        author_date: date_from("GIT_AUTHOR_DATE", Some("@1633332967 +0900")).unwrap(),
+        committer_date: date_from("GIT_COMMITTER_DATE", Some("2021-10-05 10:00:00 +0000")).unwrap(),
+        ..CommitConf::with_identity("A U Thor", "author@example.com")
+    };
+    let head = Head::from_root(&proj_root).unwrap();
+    let mut generator = CommitGenerator::new(Index::new(), proj_root, config,
+                                             Some("initial".to_string()), head).unwrap();
+    let (hash, _) = generator.exec().unwrap().generate_hash_and_depress().unwrap();
+    assert_eq!(hash.string(), "f800f4380846fad5f4166ad2d1a256ed7e9cea16");
+}
+
+#[test]
+fn test_commit_amend_no_edit() {
+    let proj_root = crate::common::test_dir("commit_amend_no_edit");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let tree = Tree::new().write(&objects_root).unwrap();
+    let body = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\n\
+                        subject\n\n  body kept as is\n", tree.string());
+    let old = crate::object::write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap();
+    crate::refs::write_ref(&proj_root, "refs/heads/master", old).unwrap();
+
+    let config = |no_edit| CommitConf { is_amend: true, no_edit, ..CommitConf::with_identity("B", "b@example.com") };
+    let amend = |no_edit, message: Option<&str>| {
+        let head = Head::from_root(&proj_root).unwrap();
+        CommitGenerator::new(Index::new(), proj_root.clone(), config(no_edit), message.map(|m| m.to_string()), head).
+            unwrap().exec()
+    };
+    let commit = amend(true, None).unwrap();
+    assert_eq!(commit.message(), "subject\n\n  body kept as is\n");
+    assert!(commit.parents().is_empty());
+    assert_eq!(amend(false, Some("reworded")).unwrap().message(), "reworded\n");
+    assert!(amend(false, None).is_err());
+}
+
+#[test]
+fn test_commit_amend_keeps_author() {
+    let proj_root = crate::common::test_dir("commit_amend_author");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let tree = Tree::new().write(&objects_root).unwrap();
+    let commit = |parent: String, message: &str| {
+        let body = format!("tree {}\n{}author A U Thor <author@example.com> 1633332967 +0900\n\
+                            committer C O Mitter <committer@example.com> 1633333000 +0900\n\n{}\n",
+                           tree.string(), parent, message);
+        crate::object::write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    let parent = commit(String::new(), "first");
+    let old = commit(format!("parent {}\n", parent.string()), "second");
+    crate::refs::write_ref(&proj_root, "refs/heads/master", old).unwrap();
+
+    // only a file changes
+    fs::write(proj_root.join("a.txt"), "changed\n").unwrap();
+    let mut index = Index::new();
+    let blob = crate::object::write_object(&objects_root, ObjType::Blob, b"changed\n").unwrap();
+    index.add_entry_in(&proj_root, Path::new("a.txt"), blob).unwrap();
+
+    let config = |author_override: Option<&str>| CommitConf {
+        author_override: author_override.map(|a| a.to_string()),
+        is_amend: true,
+        no_edit: true,
+        ..CommitConf::with_identity("B", "b@example.com")
+    };
+    let head = Head::from_root(&proj_root).unwrap();
+    let amended = CommitGenerator::new(index.clone(), proj_root.clone(), config(None), None, head.clone()).unwrap().
+        exec().unwrap();
+    let old = Commit::load(&objects_root, old).unwrap();
+    assert_eq!(amended.author().to_bytes(), old.author().to_bytes());
+    assert_eq!(amended.committer().name(), "B");
+    assert_eq!(amended.parents(), &vec![parent]);
+    assert_ne!(amended.tree(), old.tree());
+
+    let config = config(Some("N <n@example.com>"));
+    let amended = CommitGenerator::new(index, proj_root.clone(), config, None, head).unwrap().exec().unwrap();
+    assert_eq!(amended.author().to_string(), "author N <n@example.com> 1633332967 +0900");
+}
+
+#[test]
+fn test_commit_committer_date_is_author_date() {
+    let proj_root = crate::common::test_dir("commit_committer_date_is_author_date");
+    let config = CommitConf {
+        author_name: "A U Thor".to_string(),
+        author_email: "author@example.com".to_string(),
+        author_date: parse_date("@1633332967 +0900"),
+        committer_date: parse_date("@1700000000 +0000"),
+        committer_date_is_author_date: true,
+        ..CommitConf::with_identity("C O Mitter", "committer@example.com")
+    };
+    let head = Head::from_root(&proj_root).unwrap();
+    let commit = CommitGenerator::new(Index::new(), proj_root, config, Some("initial".to_string()), head).unwrap().
+        exec().unwrap();
+    assert_eq!(commit.committer().timestamp(), commit.author().timestamp());
+    assert_eq!(commit.committer().to_string(), "committer C O Mitter <committer@example.com> 1633332967 +0900");
+}
+
+// "Name <email>"
+fn parse_ident(ident: &str) -> Result<(String, String), String> {
+    let malformed = || format!("malformed author '{}', expected 'Name <email>'", ident);
+    let (name, rest) = ident.split_once('<').ok_or_else(malformed)?;
+    let email = rest.trim_end().strip_suffix('>').ok_or_else(malformed)?;
+    let name = name.trim();
+    if name.is_empty() || email.is_empty() || email.contains(['<', '>']) {
+        return Err(malformed());
+    }
+    Ok((name.to_string(), email.to_string()))
+}
+
+#[test]
+fn test_parse_ident() {
+    assert_eq!(parse_ident("A U Thor <author@example.com>"),
+               Ok(("A U Thor".to_string(), "author@example.com".to_string())));
+    assert_eq!(parse_ident("  Name   <n@example.com> "), Ok(("Name".to_string(), "n@example.com".to_string())));
+    for ident in ["Name", "<n@example.com>", "Name <>", "Name <n@example.com", "Name <a<b>"] {
+        assert!(parse_ident(ident).is_err(), "{}", ident);
+    }
 }
 
 enum CommitTree {
@@ -134,7 +609,8 @@ impl CommitTree {
     pub fn from_index(index: Index) -> Result<Self, String> {
         let mut tree = Self::new();
 
-        let entries = index.entries();
+        // intent-to-add entries are not committed
+        let entries = index.entries().into_iter().filter(|(_, e)| !e.is_intent_to_add());
         for entry in entries {
             let node_array = entry.0.to_str().
                 ok_or("cannot parse pathbuf".to_string())?.split("/").collect::<Vec<_>>();
@@ -224,4 +700,3 @@ impl CommitTree {
         ret
     }
 }
-
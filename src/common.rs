@@ -2,7 +2,22 @@ pub const DEFAULT_BRANCH_NAME: &str="master";
 
 use std::env;
 use std::fs::canonicalize;
-use std::path::{PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// set by the global --quiet flag
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+// informational message on stdout, suppressed by --quiet
+pub fn info(message: &str) {
+    if !QUIET.load(Ordering::Relaxed) {
+        println!("{}", message);
+    }
+}
 
 pub fn get_project_root()-> Result<PathBuf, String> {
     let curr_path = env::current_dir().unwrap();
@@ -14,16 +29,76 @@ pub fn get_project_root_from(path: &PathBuf) -> Result<PathBuf, String> {
 }
 
 fn search_project_root(current_dir: PathBuf)-> Result<PathBuf, String> {
-    if current_dir.join(".git").exists() {
+    let dot_git = current_dir.join(".git");
+    if dot_git.is_file() {
+        // the directory it links to has to be there too
+        let git_dir = read_gitfile(&dot_git)?;
+        if !git_dir.join("HEAD").is_file() {
+            return Err(format!("not a git repository: {}", git_dir.display()));
+        }
+    }
+    if dot_git.exists() {
         return canonicalize(current_dir).map_err(|e| e.to_string());
     }
 
     match current_dir.parent(){
         Some(s)=> search_project_root(s.to_path_buf()),
-        None=> Err(String::from("no .git/ found")) // reach root dir
+        None=> Err(String::from("not a git repository (or any of the parent directories): .git")) // reach root dir
+    }
+}
+
+// control directory of the working tree: .git, or the directory a .git file links to
+// ("gitdir: <path>") for a linked working tree
+pub fn git_dir(proj_root: &Path) -> PathBuf {
+    let dot_git = proj_root.join(".git");
+    match dot_git.is_file() {
+        true => read_gitfile(&dot_git).unwrap_or(dot_git),
+        false => dot_git,
     }
 }
 
+// "gitdir: <path>", a relative path is from the directory of the file
+fn read_gitfile(dot_git: &Path) -> Result<PathBuf, String> {
+    let content = std::fs::read_to_string(dot_git).map_err(|e| e.to_string())?;
+    let dir = content.trim_end().strip_prefix("gitdir: ").
+        ok_or(format!("invalid gitfile format: {}", dot_git.display()))?;
+    Ok(dot_git.parent().unwrap_or(Path::new("")).join(dir))
+}
+
+// directory shared by all working trees, with objects, refs and config
+// a linked working tree names it in its commondir file
+pub fn common_dir(proj_root: &Path) -> PathBuf {
+    let git_dir = git_dir(proj_root);
+    match std::fs::read_to_string(git_dir.join("commondir")) {
+        Ok(content) => {
+            let dir = git_dir.join(content.trim_end());
+            canonicalize(&dir).unwrap_or(dir)
+        },
+        Err(_) => git_dir,
+    }
+}
+
+#[test]
+fn test_project_root_with_gitfile() {
+    let base = test_dir("gitfile");
+    std::fs::create_dir_all(base.join("main/.git/modules/sub")).unwrap();
+    std::fs::write(base.join("main/.git/modules/sub/HEAD"), "ref: refs/heads/master\n").unwrap();
+    let sub = base.join("main/sub");
+    std::fs::create_dir_all(sub.join("src")).unwrap();
+    std::fs::write(sub.join(".git"), "gitdir: ../.git/modules/sub\n").unwrap();
+
+    let proj_root = get_project_root_from(&sub.join("src")).unwrap();
+    assert_eq!(proj_root, canonicalize(&sub).unwrap());
+    let git_dir = canonicalize(git_dir(&proj_root)).unwrap();
+    assert_eq!(git_dir, canonicalize(base.join("main/.git/modules/sub")).unwrap());
+    assert_eq!(canonicalize(common_dir(&proj_root)).unwrap(), git_dir);
+
+    std::fs::write(sub.join(".git"), "gitdir: ../.git/modules/none\n").unwrap();
+    assert!(get_project_root_from(&sub).unwrap_err().starts_with("not a git repository"));
+    std::fs::write(sub.join(".git"), "garbage\n").unwrap();
+    assert!(get_project_root_from(&sub).unwrap_err().starts_with("invalid gitfile format"));
+}
+
 pub fn get_path_from_project_root(path: &PathBuf) -> Result<PathBuf, String> {
     let project_root = get_project_root()?;
     get_path_from(path, &project_root)
@@ -36,6 +111,239 @@ pub fn get_path_from(path: &PathBuf, base_path: &PathBuf) -> Result<PathBuf, Str
     Ok(trimmed.to_path_buf())
 }
 
+// the path from the project root, it may not exist in the working tree, "" for the root itself
+pub fn path_from_root(prefix: &Path, path: &str) -> Result<PathBuf, String> {
+    let mut ret = prefix.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => ret.push(name),
+            Component::CurDir => {},
+            Component::ParentDir if ret.pop() => {},
+            _ => return Err(format!("invalid path '{}'", path)),
+        }
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_path_from_root() {
+    assert_eq!(path_from_root(Path::new(""), "a/b.txt"), Ok(PathBuf::from("a/b.txt")));
+    assert_eq!(path_from_root(Path::new("src"), "./main.rs"), Ok(PathBuf::from("src/main.rs")));
+    assert_eq!(path_from_root(Path::new("src"), "../README"), Ok(PathBuf::from("README")));
+    assert_eq!(path_from_root(Path::new("src"), ".."), Ok(PathBuf::new()));
+    assert!(path_from_root(Path::new(""), "../x").is_err());
+    assert!(path_from_root(Path::new(""), "/etc/passwd").is_err());
+}
+
+// pathspec given in the current directory, made relative to the project root with its magic kept in front
+pub fn pathspec_from_cwd(spec: &str) -> Result<String, String> {
+    pathspec_from(&get_path_from_project_root(&env::current_dir().map_err(|e| e.to_string())?)?, spec)
+}
+
+// the same from the directory at prefix in the project
+pub fn pathspec_from(prefix: &Path, spec: &str) -> Result<String, String> {
+    let (magic, pattern) = spec.strip_prefix(ICASE_MAGIC).map_or(("", spec), |p| (ICASE_MAGIC, p));
+    Ok(format!("{}{}", magic, path_from_root(prefix, pattern)?.display()))
+}
+
+const ICASE_MAGIC: &str = ":(icase)";
+
+// a pathspec with *, ? or [ is a glob, matched as .gitignore patterns are but with '*' also matching '/',
+// any other one is a literal path matching itself and everything under it; ":(icase)" in front ignores case
+pub fn pathspec_matches(spec: &str, path: &Path) -> bool {
+    let (spec, path) = match spec.strip_prefix(ICASE_MAGIC) {
+        Some(spec) => (spec.to_lowercase(), path.to_string_lossy().to_lowercase()),
+        None => (spec.to_string(), path.to_string_lossy().to_string()),
+    };
+    match is_pattern(&spec) {
+        true => glob_match(&spec, &path, false),
+        false => Path::new(&path).starts_with(&spec),
+    }
+}
+
+// a pathspec to match against paths instead of looking up as is
+pub fn is_pattern(spec: &str) -> bool {
+    spec.starts_with(ICASE_MAGIC) || spec.contains(&['*', '?', '['][..])
+}
+
+#[test]
+fn test_pathspec_matches() {
+    let tests = [
+        ("src/*.rs", "src/main.rs", true),
+        ("src/*.rs", "src/sub/lib.rs", true),
+        ("src/*.rs", "README", false),
+        ("*.tmp", "a/b/c.tmp", true),
+        ("src", "src/main.rs", true),
+        ("src", "src", true),
+        ("sr", "src/main.rs", false),
+        ("", "README", true),
+        (":(icase)*.RS", "src/Main.rs", true),
+        (":(icase)Src", "src/main.rs", true),
+        ("Src", "src/main.rs", false),
+    ];
+    for t in tests {
+        assert_eq!(pathspec_matches(t.0, Path::new(t.1)), t.2, "{} {}", t.0, t.1);
+    }
+    assert!(is_pattern(":(icase)a") && is_pattern("a?") && !is_pattern("src/a"));
+}
+
+/*
+    the order git presents paths in, byte-wise on the whole paths, "a-b" coming before "a/b"
+    PathBuf compares component by component and would put "a/b" first
+ */
+pub fn cmp_path_bytes(a: &Path, b: &Path) -> std::cmp::Ordering {
+    use std::os::unix::ffi::OsStrExt;
+    a.as_os_str().as_bytes().cmp(b.as_os_str().as_bytes())
+}
+
+#[test]
+fn test_cmp_path_bytes() {
+    let mut paths: Vec<PathBuf> = ["a/b", "a.txt", "a-b", "a", "B", "a/b/c"].iter().map(PathBuf::from).collect();
+    paths.sort_by(|a, b| cmp_path_bytes(a, b));
+    assert_eq!(paths, ["B", "a", "a-b", "a.txt", "a/b", "a/b/c"].iter().map(PathBuf::from).collect::<Vec<_>>());
+}
+
+pub fn copy_dir_all(src: &Path, dst: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dst).map_err(|e| e.to_string())?;
+    for entry in std::fs::read_dir(src).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let target = dst.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// git's heuristic, a NUL byte in the first 8000 bytes
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8000).any(|b| *b == 0)
+}
+
+#[test]
+fn test_is_binary() {
+    assert!(!is_binary("fn main() {}\n".as_bytes()));
+    assert!(is_binary(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"));
+    assert!(!is_binary(b""));
+    // only the beginning of the file is checked
+    let mut late = vec![b'a'; 8000];
+    late.push(0);
+    assert!(!is_binary(&late));
+}
+
+// shell style pattern with *, ? and [...] classes, '*' also matches '/' unless pathname
+// with pathname, as for paths in .gitignore, '*', '?' and classes stop at '/',
+// "**/" matches any number of leading directories and a trailing "**" everything inside
+pub fn glob_match(pattern: &str, text: &str, pathname: bool) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_chars(&pattern, &text, pathname)
+}
+
+fn glob_match_chars(pattern: &[char], text: &[char], pathname: bool) -> bool {
+    let any = |c: &char| !pathname || *c != '/';
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] if pathname => glob_match_chars(rest, text, pathname) ||
+            (0..text.len()).any(|i| text[i] == '/' && glob_match_chars(rest, &text[i+1..], pathname)),
+        ['*', '*'] if pathname => true,
+        ['*', rest @ ..] => (0..=text.len()).take_while(|i| *i == 0 || any(&text[i-1])).
+            any(|i| glob_match_chars(rest, &text[i..], pathname)),
+        ['?', rest @ ..] => text.first().is_some_and(any) && glob_match_chars(rest, &text[1..], pathname),
+        ['[', class @ ..] => {
+            let c = match text.first() {
+                Some(c) if any(c) => *c,
+                _ => return false,
+            };
+            match match_class(class, c) {
+                Some((matched, len)) => matched && glob_match_chars(&class[len..], &text[1..], pathname),
+                // no closing bracket, '[' is a literal
+                None => c == '[' && glob_match_chars(class, &text[1..], pathname),
+            }
+        },
+        ['\\', p, rest @ ..] | [p, rest @ ..] => {
+            text.first() == Some(p) && glob_match_chars(rest, &text[1..], pathname)
+        },
+    }
+}
+
+#[test]
+fn test_glob_match_pathname() {
+    let tests = [
+        ("*.o", "main.o", true),
+        ("*.o", "src/main.o", false),
+        ("src/*.o", "src/main.o", true),
+        ("**/build", "build", true),
+        ("**/build", "a/b/build", true),
+        ("target/**", "target/debug/x", true),
+        ("a/**/b", "a/b", true),
+        ("a/**/b", "a/x/y/b", true),
+        ("a/**/b", "a/xb", false),
+        ("a?c", "a/c", false),
+        ("[a-c]x", "bx", true),
+        ("\\#x", "#x", true),
+    ];
+    for t in tests {
+        assert_eq!(glob_match(t.0, t.1, true), t.2, "{} {}", t.0, t.1);
+    }
+}
+
+// "abc]", "!a-z]", returns whether c matched and the length consumed including ']'
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let negate = matches!(class.first(), Some('!') | Some('^'));
+    let mut i = if negate { 1 } else { 0 };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negate, i+1));
+        }
+        first = false;
+        if i+2 < class.len() && class[i+1] == '-' && class[i+2] != ']' {
+            matched |= class[i] <= c && c <= class[i+2];
+            i += 3;
+        } else {
+            matched |= class[i] == c;
+            i += 1;
+        }
+    }
+    None
+}
+
+#[test]
+fn test_glob_match() {
+    let tests = [
+        ("v1.*", "v1.0", true),
+        ("v1.*", "v1.0/rc", true),
+        ("v1.*", "v2.0", false),
+        ("*", "", true),
+        ("v?", "v1", true),
+        ("v?", "v10", false),
+        ("v[0-9]", "v5", true),
+        ("v[!0-9]", "v5", false),
+        ("v[!0-9]", "vx", true),
+        ("[]]", "]", true),
+        ("a\\*", "a*", true),
+        ("a\\*", "ab", false),
+        ("a[b", "a[b", true),
+        ("release-*-final", "release-1.2-final", true),
+    ];
+    for t in tests {
+        assert_eq!(glob_match(t.0, t.1, false), t.2, "{} {}", t.0, t.1);
+    }
+}
+
+// fresh scratch directory for tests touching the file system
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("mugit-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
 #[test]
 fn test_get_path_from_project_root() {
     let path = PathBuf::from("./src/main.rs");
@@ -1,41 +1,614 @@
 use std::env;
+use std::fmt;
+use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use std::io::Read;
+use serde::{Deserialize, Deserializer};
 use serde_derive::Deserialize;
+use crate::common::{common_dir, get_project_root};
 
-//confはglobalのみ
+//userはglobal(toml)と.git/config, .git/configはGitConfigで読み書き
 
 #[derive(Default, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub user: User,
+    pub core: Core,
 }
 
 #[derive(Default, Deserialize)]
+#[serde(default)]
 pub struct User {
     pub name: String,
     pub email: String,
 }
 
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Core {
+    #[serde(deserialize_with = "deserialize_autocrlf")]
+    pub autocrlf: AutoCrlf,
+    pub compression: Option<i64>,
+    #[serde(rename = "looseCompression")]
+    pub loose_compression: Option<i64>,
+    #[serde(rename = "objectChecksum")]
+    pub object_checksum: Option<bool>,
+    #[serde(deserialize_with = "deserialize_abbrev")]
+    pub abbrev: Option<usize>,
+}
+
+// core.autocrlf: true converts CRLF to LF on add and back on checkout, input only on add
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AutoCrlf {
+    #[default]
+    False,
+    Input,
+    True,
+}
+
+impl AutoCrlf {
+    pub fn from_value(value: &str) -> Result<Self, String> {
+        match value.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Ok(AutoCrlf::True),
+            "false" | "no" | "off" | "0" | "" => Ok(AutoCrlf::False),
+            "input" => Ok(AutoCrlf::Input),
+            _ => Err(format!("bad core.autocrlf value: {}", value)),
+        }
+    }
+}
+
+// autocrlf = true or autocrlf = "input" in the toml
+fn deserialize_autocrlf<'de, D: Deserializer<'de>>(deserializer: D) -> Result<AutoCrlf, D::Error> {
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Boolean(true) => Ok(AutoCrlf::True),
+        toml::Value::Boolean(false) => Ok(AutoCrlf::False),
+        toml::Value::String(s) => AutoCrlf::from_value(&s).map_err(serde::de::Error::custom),
+        v => Err(serde::de::Error::custom(format!("bad core.autocrlf value: {}", v))),
+    }
+}
+
+// abbrev = 12 or abbrev = "auto" in the toml
+fn deserialize_abbrev<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Integer(n) => parse_abbrev(&n.to_string()).map(Some).map_err(serde::de::Error::custom),
+        toml::Value::String(s) => parse_abbrev(&s).map(Some).map_err(serde::de::Error::custom),
+        v => Err(serde::de::Error::custom(format!("bad core.abbrev value: {}", v))),
+    }
+}
+
 pub fn parse_config() -> Result<Config, String>{
+    let home_dir = env::var("HOME").map_err(|e| e.to_string())?;
+    // outside of a repository only the global config applies
+    let proj_root = get_project_root().ok();
+    parse_config_in(Path::new(&home_dir), proj_root.as_deref())
+}
+
+// core.autocrlf which applies to the repository at proj_root
+pub fn autocrlf(proj_root: &Path) -> Result<AutoCrlf, String> {
+    let home_dir = env::var("HOME").map_err(|e| e.to_string())?;
+    Ok(parse_config_in(Path::new(&home_dir), Some(proj_root))?.core.autocrlf)
+}
+
+static REPOSITORY: OnceLock<PathBuf> = OnceLock::new();
+static CORE: OnceLock<Core> = OnceLock::new();
+
+// the repository whose objects this run reads and writes, set once at startup
+pub fn set_repository(proj_root: &Path) {
+    let _ = REPOSITORY.set(proj_root.to_path_buf());
+}
+
+/*
+    [core] of that repository, parsed the first time an object is read or written
+    the defaults outside of one or when the config can't be parsed, e.g. a ~/.gitconfig in git's own format,
+    as these settings only tune how objects are stored and shown
+ */
+fn core() -> &'static Core {
+    CORE.get_or_init(|| match (env::var("HOME"), REPOSITORY.get()) {
+        (Ok(home_dir), Some(proj_root)) => core_in(Path::new(&home_dir), proj_root),
+        _ => Core::default(),
+    })
+}
+
+fn core_in(home_dir: &Path, proj_root: &Path) -> Core {
+    parse_config_in(home_dir, Some(proj_root)).map(|c| c.core).unwrap_or_default()
+}
+
+// zlib level of loose objects: core.looseCompression, else core.compression, else 1 (best speed) as git does
+pub fn loose_compression() -> u32 {
+    let core = core();
+    compression_level(core.loose_compression.or(core.compression).unwrap_or(1)).unwrap_or(1)
+}
+
+// core.objectChecksum, whether objects read are checked against their names, true unless set
+pub fn object_checksum() -> bool {
+    core().object_checksum.unwrap_or(true)
+}
+
+// core.abbrev, the least number of digits of a short hash, 7 unless set
+pub fn abbrev() -> usize {
+    core().abbrev.unwrap_or(7)
+}
+
+// a number of digits clamped to 4..=40, "auto" for the default and "no" for full hashes
+pub fn parse_abbrev(value: &str) -> Result<usize, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(7),
+        "no" => Ok(40),
+        n => n.parse::<usize>().map(|n| n.clamp(4, 40)).map_err(|_| format!("bad abbrev value '{}'", value)),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" | "" => Ok(false),
+        _ => Err(format!("bad boolean config value '{}'", value)),
+    }
+}
+
+// -1 is zlib's default
+fn compression_level(level: i64) -> Result<u32, String> {
+    match level {
+        -1 => Ok(6),
+        0..=9 => Ok(level as u32),
+        _ => Err(format!("bad zlib compression level {}", level)),
+    }
+}
+
+fn parse_config_in(home_dir: &Path, proj_root: Option<&Path>) -> Result<Config, String> {
     let ret: Config = Default::default();
 
     //global values
-    let global_path = get_global_config_path()?;
-    let ret =parse_from_file(global_path, ret)?;
+    let global_path = home_dir.join(".gitconfig");
+    let mut ret = parse_from_file(global_path, ret)?;
 
-    Ok(ret)
-}
+    //local values, [user] in .git/config
+    if let Some(proj_root) = proj_root {
+        let local = GitConfig::from_file(&local_config_path(proj_root))?;
+        if let Some(name) = local.get("user", None, "name") {
+            ret.user.name = name.to_string();
+        }
+        if let Some(email) = local.get("user", None, "email") {
+            ret.user.email = email.to_string();
+        }
+        if let Some(autocrlf) = local.get("core", None, "autocrlf") {
+            ret.core.autocrlf = AutoCrlf::from_value(autocrlf)?;
+        }
+        let level = |value: &str| value.parse::<i64>().map_err(|_| format!("bad numeric config value '{}'", value));
+        if let Some(compression) = local.get("core", None, "compression") {
+            ret.core.compression = Some(level(compression)?);
+        }
+        if let Some(compression) = local.get("core", None, "looseCompression") {
+            ret.core.loose_compression = Some(level(compression)?);
+        }
+        if let Some(checksum) = local.get("core", None, "objectChecksum") {
+            ret.core.object_checksum = Some(parse_bool(checksum)?);
+        }
+        if let Some(abbrev) = local.get("core", None, "abbrev") {
+            ret.core.abbrev = Some(parse_abbrev(abbrev)?);
+        }
+    }
 
-fn get_global_config_path() -> Result<PathBuf, String> {
-    let home_dir = env::var("HOME").map_err(|e| e.to_string())?;
-    Ok(Path::new(&home_dir).join(".gitconfig"))
+    Ok(ret)
 }
 
-fn parse_from_file(path: PathBuf, _conf: Config) -> Result<Config, String> {
+fn parse_from_file(path: PathBuf, conf: Config) -> Result<Config, String> {
+    // a missing file just means no settings
+    if !path.exists() {
+        return Ok(conf);
+    }
     let mut file = File::open(path).map_err(|e| e.to_string())?;
     let mut buf = Vec::new();
-    let _ = file.read_to_end(&mut buf);
+    file.read_to_end(&mut buf).map_err(|e| e.to_string())?;
     let conf_after: Config = toml::from_slice(&buf[..]).map_err(|e| e.to_string())?;
     Ok(conf_after)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_parse_config_without_global_file() {
+    let home_dir = crate::common::test_dir("config_home");
+    let conf = parse_config_in(&home_dir, None).unwrap();
+    assert_eq!(conf.user.name, "");
+
+    let proj_root = crate::common::test_dir("config_local");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    fs::write(proj_root.join(".git/config"), "[user]\n\tname = A U Thor\n\temail = author@example.com\n").unwrap();
+    let conf = parse_config_in(&home_dir, Some(&proj_root)).unwrap();
+    assert_eq!((conf.user.name.as_str(), conf.user.email.as_str()), ("A U Thor", "author@example.com"));
+
+    assert_eq!(conf.core.autocrlf, AutoCrlf::False);
+
+    fs::write(home_dir.join(".gitconfig"), "[core]\nautocrlf = true\n").unwrap();
+    assert_eq!(parse_config_in(&home_dir, None).unwrap().core.autocrlf, AutoCrlf::True);
+    fs::write(proj_root.join(".git/config"), "[core]\n\tautocrlf = input\n").unwrap();
+    assert_eq!(parse_config_in(&home_dir, Some(&proj_root)).unwrap().core.autocrlf, AutoCrlf::Input);
+
+    fs::write(home_dir.join(".gitconfig"), "[user\n").unwrap();
+    assert!(parse_config_in(&home_dir, None).is_err());
+}
+
+#[test]
+fn test_compression_config() {
+    let home_dir = crate::common::test_dir("compression_home");
+    let proj_root = crate::common::test_dir("compression_local");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    let level = || {
+        let core = parse_config_in(&home_dir, Some(&proj_root)).unwrap().core;
+        compression_level(core.loose_compression.or(core.compression).unwrap_or(1))
+    };
+    assert_eq!(level(), Ok(1));
+    fs::write(home_dir.join(".gitconfig"), "[core]\ncompression = 9\n").unwrap();
+    assert_eq!(level(), Ok(9));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tcompression = -1\n").unwrap();
+    assert_eq!(level(), Ok(6));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tcompression = 9\n\tlooseCompression = 0\n").unwrap();
+    assert_eq!(level(), Ok(0));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tlooseCompression = 10\n").unwrap();
+    assert!(level().is_err());
+    fs::write(proj_root.join(".git/config"), "[core]\n\tlooseCompression = fast\n").unwrap();
+    assert!(parse_config_in(&home_dir, Some(&proj_root)).is_err());
+}
+
+#[test]
+fn test_object_checksum_config() {
+    let home_dir = crate::common::test_dir("object_checksum_home");
+    let proj_root = crate::common::test_dir("object_checksum_local");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    let checksum = || parse_config_in(&home_dir, Some(&proj_root)).map(|c| c.core.object_checksum);
+    assert_eq!(checksum(), Ok(None));
+    fs::write(home_dir.join(".gitconfig"), "[core]\nobjectChecksum = false\n").unwrap();
+    assert_eq!(checksum(), Ok(Some(false)));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tobjectchecksum = yes\n").unwrap();
+    assert_eq!(checksum(), Ok(Some(true)));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tobjectChecksum = maybe\n").unwrap();
+    assert!(checksum().is_err());
+}
+
+#[test]
+fn test_abbrev_config() {
+    let home_dir = crate::common::test_dir("abbrev_home");
+    let proj_root = crate::common::test_dir("abbrev_local");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    let abbrev = || parse_config_in(&home_dir, Some(&proj_root)).map(|c| c.core.abbrev);
+    assert_eq!(abbrev(), Ok(None));
+    fs::write(home_dir.join(".gitconfig"), "[core]\nabbrev = 2\n").unwrap();
+    assert_eq!(abbrev(), Ok(Some(4)));
+    fs::write(home_dir.join(".gitconfig"), "[core]\nabbrev = \"no\"\n").unwrap();
+    assert_eq!(abbrev(), Ok(Some(40)));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tabbrev = 12\n").unwrap();
+    assert_eq!(abbrev(), Ok(Some(12)));
+    fs::write(proj_root.join(".git/config"), "[core]\n\tabbrev = short\n").unwrap();
+    assert!(abbrev().is_err());
+    assert_eq!(parse_abbrev("auto"), Ok(7));
+}
+
+#[test]
+fn test_core_falls_back_to_defaults() {
+    let home_dir = crate::common::test_dir("core_fallback_home");
+    let proj_root = crate::common::test_dir("core_fallback_local");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    fs::write(proj_root.join(".git/config"), "[core]\n\tabbrev = 12\n").unwrap();
+    assert_eq!(core_in(&home_dir, &proj_root).abbrev, Some(12));
+    // git's own format isn't toml
+    fs::write(home_dir.join(".gitconfig"), "[user]\n\tname = A U Thor\n").unwrap();
+    let core = core_in(&home_dir, &proj_root);
+    assert_eq!((core.abbrev, core.object_checksum, core.compression), (None, None, None));
+}
+
+/*
+    repository local config (.git/config) in the git ini format
+    the lines are kept as read, so comments and blank lines survive a rewrite; only values set here are formatted
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct GitConfig {
+    // comments before the first section
+    leading: Vec<String>,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    name: String,
+    subsection: Option<String>,
+    // the header as written, None for a section added here
+    header: Option<String>,
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Line {
+    // key, value and the line as written, None for a value set here
+    Entry(String, String, Option<String>),
+    // comments and blank lines
+    Other(String),
+}
+
+impl Section {
+    fn new(name: &str, subsection: Option<&str>) -> Self {
+        Self {
+            name: name.to_ascii_lowercase(),
+            subsection: subsection.map(|s| s.to_string()),
+            header: None,
+            lines: Vec::new(),
+        }
+    }
+
+    pub fn subsection(&self) -> Option<&str> {
+        self.subsection.as_deref()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match line {
+            Line::Entry(k, v, _) if k.eq_ignore_ascii_case(key) => Some(v.as_str()),
+            _ => None,
+        })
+    }
+
+    fn is(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name.eq_ignore_ascii_case(name) && self.subsection.as_deref() == subsection
+    }
+
+    fn has_entries(&self) -> bool {
+        self.lines.iter().any(|line| matches!(line, Line::Entry(..)))
+    }
+}
+
+impl GitConfig {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Default::default());
+        }
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut ret = GitConfig::default();
+        for (i, raw) in content.lines().enumerate() {
+            let bad = || format!("bad config line {}", i+1);
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                match ret.sections.last_mut() {
+                    Some(section) => section.lines.push(Line::Other(raw.to_string())),
+                    None => ret.leading.push(raw.to_string()),
+                }
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[') {
+                // [name] or [name "subsection"], a comment may follow
+                let (header, rest) = header.rsplit_once(']').ok_or_else(bad)?;
+                if !strip_comment(rest).trim().is_empty() {
+                    return Err(bad());
+                }
+                let (name, subsection) = match header.split_once(' ') {
+                    Some((name, sub)) => {
+                        let sub = sub.trim().strip_prefix('"').and_then(|s| s.strip_suffix('"')).ok_or_else(bad)?;
+                        (name, Some(sub))
+                    },
+                    None => (header, None),
+                };
+                ret.sections.push(Section { header: Some(raw.to_string()), ..Section::new(name, subsection) });
+                continue;
+            }
+            let section = ret.sections.last_mut().ok_or_else(bad)?;
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim().to_string(), parse_value(v).ok_or_else(bad)?),
+                // a bare key means true
+                None => (strip_comment(line).trim().to_string(), "true".to_string()),
+            };
+            section.lines.push(Line::Entry(key, value, Some(raw.to_string())));
+        }
+        Ok(ret)
+    }
+
+    pub fn write_file(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_string()).map_err(|e| e.to_string())
+    }
+
+    pub fn get(&self, name: &str, subsection: Option<&str>, key: &str) -> Option<&str> {
+        self.sections.iter().rev().filter(|s| s.is(name, subsection)).find_map(|s| s.get(key))
+    }
+
+    pub fn sections(&self, name: &str) -> impl Iterator<Item = &Section> {
+        let name = name.to_string();
+        self.sections.iter().filter(move |s| s.name.eq_ignore_ascii_case(&name))
+    }
+
+    pub fn has_section(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.sections.iter().any(|s| s.is(name, subsection))
+    }
+
+    // append a value even if the key already exists, as multi-valued keys do
+    pub fn add(&mut self, name: &str, subsection: Option<&str>, key: &str, value: &str) {
+        let pos = match self.sections.iter().rposition(|s| s.is(name, subsection)) {
+            Some(pos) => pos,
+            None => {
+                self.sections.push(Section::new(name, subsection));
+                self.sections.len()-1
+            },
+        };
+        // right after the last value, comments at the end of the section stay there
+        let lines = &mut self.sections[pos].lines;
+        let at = lines.iter().rposition(|l| matches!(l, Line::Entry(..))).map_or(0, |i| i+1);
+        lines.insert(at, Line::Entry(key.to_string(), value.to_string(), None));
+    }
+
+    // replace every value of the key with a single one, where the last of them was
+    pub fn set(&mut self, name: &str, subsection: Option<&str>, key: &str, value: &str) {
+        let is_key = |line: &Line| matches!(line, Line::Entry(k, _, _) if k.eq_ignore_ascii_case(key));
+        let mut found = false;
+        for section in self.sections.iter_mut().rev().filter(|s| s.is(name, subsection)) {
+            for i in (0..section.lines.len()).rev() {
+                if !is_key(&section.lines[i]) {
+                    continue;
+                }
+                if found {
+                    section.lines.remove(i);
+                } else {
+                    section.lines[i] = Line::Entry(key.to_string(), value.to_string(), None);
+                    found = true;
+                }
+            }
+        }
+        if !found {
+            self.add(name, subsection, key, value);
+        }
+    }
+
+    // remove every value of the key, and the section when no value is left in it
+    pub fn unset(&mut self, name: &str, subsection: Option<&str>, key: &str) -> bool {
+        let mut removed = false;
+        for section in self.sections.iter_mut().filter(|s| s.is(name, subsection)) {
+            let before = section.lines.len();
+            section.lines.retain(|line| !matches!(line, Line::Entry(k, _, _) if k.eq_ignore_ascii_case(key)));
+            removed |= before != section.lines.len();
+        }
+        self.sections.retain(|s| !s.is(name, subsection) || s.has_entries());
+        removed
+    }
+
+    pub fn remove_section(&mut self, name: &str, subsection: Option<&str>) -> bool {
+        let before = self.sections.len();
+        self.sections.retain(|s| !s.is(name, subsection));
+        before != self.sections.len()
+    }
+}
+
+impl fmt::Display for GitConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.leading {
+            writeln!(f, "{}", line)?;
+        }
+        for section in &self.sections {
+            match (&section.header, &section.subsection) {
+                (Some(header), _) => writeln!(f, "{}", header)?,
+                (None, Some(sub)) => writeln!(f, "[{} \"{}\"]", section.name, sub)?,
+                (None, None) => writeln!(f, "[{}]", section.name)?,
+            }
+            for line in &section.lines {
+                match line {
+                    Line::Entry(_, _, Some(raw)) | Line::Other(raw) => writeln!(f, "{}", raw)?,
+                    Line::Entry(key, value, None) => writeln!(f, "\t{} = {}", key, format_value(value))?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// the text before a '#' or ';' comment which is outside of quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return &text[..i],
+            _ => {},
+        }
+    }
+    text
+}
+
+// a value after the '=': quotes removed, escapes resolved, spaces around it and a comment dropped
+fn parse_value(text: &str) -> Option<String> {
+    let mut ret = String::new();
+    // spaces outside of quotes, kept only when something follows
+    let mut spaces = String::new();
+    let mut quoted = false;
+    let mut chars = strip_comment(text).trim().chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && !quoted {
+            spaces.push(c);
+            continue;
+        }
+        ret.push_str(&spaces);
+        spaces.clear();
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => ret.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'b' => '\u{8}',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => ret.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    Some(ret)
+}
+
+// quoted when parse_value would not give the value back as it is
+fn format_value(value: &str) -> String {
+    let needs_quotes = value.trim() != value || value.contains(['#', ';']);
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t");
+    match needs_quotes {
+        true => format!("\"{}\"", escaped),
+        false => escaped,
+    }
+}
+
+pub fn local_config_path(proj_root: &Path) -> PathBuf {
+    common_dir(proj_root).join("config")
+}
+
+#[test]
+fn test_git_config() {
+    let content = "[core]\n\tbare = false\n# comment\n[remote \"origin\"]\n\turl = /tmp/a\n\
+                   \tfetch = +refs/heads/*:refs/remotes/origin/*\n";
+    let mut conf = GitConfig::parse(content).unwrap();
+    let origin = conf.sections("remote").next().unwrap();
+    assert_eq!(origin.subsection(), Some("origin"));
+    assert_eq!(origin.get("url"), Some("/tmp/a"));
+    assert!(conf.has_section("core", None));
+    assert!(!conf.has_section("remote", Some("upstream")));
+
+    conf.add("remote", Some("upstream"), "url", "/tmp/c");
+    assert_eq!(conf.sections("remote").filter_map(|s| s.subsection()).collect::<Vec<_>>(),
+               vec!["origin", "upstream"]);
+
+    assert!(conf.remove_section("remote", Some("origin")));
+    assert!(!conf.remove_section("remote", Some("origin")));
+    assert_eq!(conf.to_string(), "[core]\n\tbare = false\n# comment\n[remote \"upstream\"]\n\turl = /tmp/c\n");
+    assert_eq!(GitConfig::parse(&conf.to_string()).unwrap().to_string(), conf.to_string());
+
+    conf.add("remote", Some("upstream"), "url", "/tmp/d");
+    conf.set("remote", Some("upstream"), "url", "/tmp/e");
+    assert_eq!(conf.get("remote", Some("upstream"), "url"), Some("/tmp/e"));
+    assert!(conf.unset("remote", Some("upstream"), "url"));
+    assert!(!conf.unset("remote", Some("upstream"), "url"));
+    assert!(!conf.has_section("remote", Some("upstream")));
+}
+
+#[test]
+fn test_git_config_round_trip() {
+    let content = "; written by hand\n[core]\n\tbare = false ; not bare\n\n# the main remote\n\
+                   [remote \"origin\"] # first\n\turl = \"/tmp/a b\" ; note\n\
+                   \tfetch = +refs/heads/*:refs/remotes/origin/*\n\t# keep this\n[branch \"master\"]\n\tremote = origin\n";
+    let mut conf = GitConfig::parse(content).unwrap();
+    assert_eq!(conf.to_string(), content);
+    assert_eq!(conf.get("core", None, "bare"), Some("false"));
+    assert_eq!(conf.get("remote", Some("origin"), "url"), Some("/tmp/a b"));
+
+    // only the lines of the values touched change
+    conf.set("branch", Some("master"), "merge", "refs/heads/master");
+    conf.set("remote", Some("origin"), "url", " /tmp/c#d");
+    conf.add("remote", Some("upstream"), "url", "/tmp/e");
+    assert_eq!(conf.to_string(), "; written by hand\n[core]\n\tbare = false ; not bare\n\n# the main remote\n\
+                                  [remote \"origin\"] # first\n\turl = \" /tmp/c#d\"\n\
+                                  \tfetch = +refs/heads/*:refs/remotes/origin/*\n\t# keep this\n[branch \"master\"]\n\
+                                  \tremote = origin\n\tmerge = refs/heads/master\n[remote \"upstream\"]\n\turl = /tmp/e\n");
+    assert_eq!(GitConfig::parse(&conf.to_string()).unwrap().get("remote", Some("origin"), "url"), Some(" /tmp/c#d"));
+
+    assert_eq!(parse_value(r#"a\tb \"c\" "d ; e"  # f"#), Some("a\tb \"c\" d ; e".to_string()));
+    assert_eq!(parse_value("\"open"), None);
+    assert!(GitConfig::parse("[core\n").is_err());
+}
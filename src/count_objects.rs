@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{bytes_to_u32, common_dir, get_project_root};
+use crate::object::read_object_header;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let verbose = matches.is_present("verbose");
+    let objects_root = common_dir(&get_project_root()?).join("objects");
+
+    let mut count: usize = 0;
+    let mut size: u64 = 0;
+    let mut types: BTreeMap<&str, usize> = BTreeMap::new();
+    for obj_type in ["blob", "tree", "commit", "tag"] {
+        types.insert(obj_type, 0);
+    }
+    for path in list_loose_objects(&objects_root)? {
+        count += 1;
+        size += fs::metadata(&path).map_err(|e| e.to_string())?.len();
+        if verbose {
+            let (obj_type, _) = read_object_header(&path)?;
+            *types.entry(obj_type.name()).or_insert(0) += 1;
+        }
+    }
+    println!("count: {}, size: {} KiB", count, size/1024);
+
+    if verbose {
+        for obj_type in ["blob", "tree", "commit", "tag"] {
+            println!("{}: {}", obj_type, types[obj_type]);
+        }
+        let (packs, in_pack, size_pack) = count_packed_objects(&objects_root)?;
+        println!("in-pack: {}", in_pack);
+        println!("packs: {}", packs);
+        println!("size-pack: {} KiB", size_pack/1024);
+    }
+
+    Ok(())
+}
+
+// files under .git/objects/<2 hex digits>/
+pub fn list_loose_objects(objects_root: &Path) -> Result<Vec<std::path::PathBuf>, String> {
+    let mut ret = Vec::new();
+    if !objects_root.is_dir() {
+        return Ok(ret);
+    }
+    for dir in fs::read_dir(objects_root).map_err(|e| e.to_string())? {
+        let dir = dir.map_err(|e| e.to_string())?;
+        let name = dir.file_name();
+        let name = name.to_str().unwrap_or("");
+        if name.len() != 2 || !name.chars().all(|c| c.is_ascii_hexdigit()) || !dir.path().is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir.path()).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            if entry.file_name().len() == 38 {
+                ret.push(entry.path());
+            }
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+// (number of packs, objects in packs, total pack size)
+fn count_packed_objects(objects_root: &Path) -> Result<(usize, usize, u64), String> {
+    let pack_dir = objects_root.join("pack");
+    let (mut packs, mut objects, mut size) = (0, 0, 0);
+    if !pack_dir.is_dir() {
+        return Ok((packs, objects, size));
+    }
+    for entry in fs::read_dir(pack_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension() != Some("pack".as_ref()) {
+            continue;
+        }
+        let bytes = fs::read(&path).map_err(|e| e.to_string())?;
+        // "PACK" <version> <number of objects>
+        if bytes.len() < 12 || !bytes.starts_with("PACK".as_bytes()) {
+            return Err(format!("broken pack file: {}", path.display()));
+        }
+        packs += 1;
+        objects += bytes_to_u32(&bytes[8..12]).unwrap() as usize;
+        size += bytes.len() as u64;
+    }
+    Ok((packs, objects, size))
+}
+
+#[test]
+fn test_count_objects() {
+    use crate::object::{write_object, ObjType};
+
+    let objects_root = crate::common::test_dir("count_objects");
+    assert!(list_loose_objects(&objects_root.join("none")).unwrap().is_empty());
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    write_object(&objects_root, ObjType::Blob, b"world\n").unwrap();
+    // neither is an object
+    fs::create_dir_all(objects_root.join("info")).unwrap();
+    fs::write(objects_root.join("info/packs"), "").unwrap();
+    fs::write(objects_root.join(&blob.string()[..2]).join("tmp_obj"), "").unwrap();
+    assert_eq!(list_loose_objects(&objects_root).unwrap().len(), 2);
+    assert!(list_loose_objects(&objects_root).unwrap().contains(&objects_root.join(blob.generate_path())));
+
+    assert_eq!(count_packed_objects(&objects_root).unwrap(), (0, 0, 0));
+    fs::create_dir_all(objects_root.join("pack")).unwrap();
+    let mut pack = b"PACK\0\0\0\x02\0\0\0\x03".to_vec();
+    pack.extend_from_slice(&[0; 20]);
+    fs::write(objects_root.join("pack/pack-a.pack"), &pack).unwrap();
+    fs::write(objects_root.join("pack/pack-a.idx"), "").unwrap();
+    assert_eq!(count_packed_objects(&objects_root).unwrap(), (1, 3, 32));
+    fs::write(objects_root.join("pack/pack-b.pack"), "PACK").unwrap();
+    assert!(count_packed_objects(&objects_root).is_err());
+}
@@ -0,0 +1,306 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use clap::ArgMatches;
+use crate::changes::{diff_snapshots, render_names, render_patch, render_stat, Snapshot};
+use crate::common::{common_dir, get_project_root};
+use crate::head::Head;
+use crate::index::Index;
+use crate::object::empty_tree;
+use crate::rev;
+
+/*
+    diff             index and working tree
+    diff --cached    HEAD and index
+    diff <a>         <a> and working tree
+    diff <a> <b>     <a> and <b>
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let index = Index::from_file(&proj_root)?.unwrap_or_default();
+    let revs: Vec<&str> = matches.values_of("rev").map(|v| v.collect()).unwrap_or_default();
+
+    let (old, new) = match (revs.as_slice(), matches.is_present("cached")) {
+        ([], false) => (Snapshot::from_index(&index), Snapshot::from_work_tree(&proj_root, &index)?),
+        ([], true) => {
+            let old = match Head::from_root(&proj_root)?.hash() {
+                Some(_) => rev::resolve_tree(&proj_root, "HEAD")?,
+                None => empty_tree(),
+            };
+            (Snapshot::from_tree(&objects_root, old)?, Snapshot::from_index(&index))
+        },
+        ([a], false) => (Snapshot::from_tree(&objects_root, rev::resolve_tree(&proj_root, a)?)?,
+                         Snapshot::from_work_tree(&proj_root, &index)?),
+        ([a], true) => (Snapshot::from_tree(&objects_root, rev::resolve_tree(&proj_root, a)?)?,
+                        Snapshot::from_index(&index)),
+        ([a, b], false) => (Snapshot::from_tree(&objects_root, rev::resolve_tree(&proj_root, a)?)?,
+                            Snapshot::from_tree(&objects_root, rev::resolve_tree(&proj_root, b)?)?),
+        _ => return Err("usage: diff [--cached] [<commit> [<commit>]]".to_string()),
+    };
+
+    let out = render_changes(matches, &objects_root, &old, &new)?;
+    io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string())
+}
+
+// patch, or the output selected by --stat, --name-only, --name-status and -z, shared with show
+pub fn render_changes(matches: &ArgMatches, objects_root: &Path, old: &Snapshot,
+                      new: &Snapshot) -> Result<String, String> {
+    let changes = diff_snapshots(old, new);
+    let nul = matches.is_present("null");
+    if matches.is_present("name-only") {
+        Ok(render_names(&changes, false, nul))
+    } else if matches.is_present("name-status") {
+        Ok(render_names(&changes, true, nul))
+    } else if matches.is_present("stat") {
+        if changes.is_empty() {
+            return Ok(String::new());
+        }
+        render_stat(objects_root, old, new, &changes)
+    } else {
+        let context = match matches.value_of("unified") {
+            Some(n) => n.parse().map_err(|_| format!("invalid context length: {}", n))?,
+            None => 3,
+        };
+        render_patch(objects_root, old, new, &changes, context)
+    }
+}
+
+// line based diff
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Hunk {
+    Equal { old_start: usize, new_start: usize, len: usize },
+    Delete { old_start: usize, len: usize },
+    Insert { new_start: usize, len: usize },
+}
+
+pub fn diff_lines(a: &str, b: &str) -> Vec<Hunk> {
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+    diff_slices(&a, &b)
+}
+
+// Myers' O(ND) algorithm, deletions come before insertions in each changed block
+pub fn diff_slices(a: &[&str], b: &[&str]) -> Vec<Hunk> {
+    let mut ret: Vec<Hunk> = Vec::new();
+    let (mut deletes, mut inserts) = (Vec::new(), Vec::new());
+    for edit in shortest_edit(a, b) {
+        match edit {
+            Edit::Delete(i) => deletes.push(Hunk::Delete { old_start: i, len: 1 }),
+            Edit::Insert(j) => inserts.push(Hunk::Insert { new_start: j, len: 1 }),
+            Edit::Equal(i, j) => {
+                for hunk in deletes.drain(..).chain(inserts.drain(..)) {
+                    push_hunk(&mut ret, hunk);
+                }
+                push_hunk(&mut ret, Hunk::Equal { old_start: i, new_start: j, len: 1 });
+            },
+        }
+    }
+    for hunk in deletes.into_iter().chain(inserts) {
+        push_hunk(&mut ret, hunk);
+    }
+    ret
+}
+
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+// v[k] is the furthest x reached on the diagonal k = x - y, one copy of v is kept per d for backtracking
+fn shortest_edit(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2*offset as usize + 1];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=(n+m) {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx-1] < v[idx+1]) {
+                v[idx+1]
+            } else {
+                v[idx-1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[(k-1+offset) as usize] < v[(k+1+offset) as usize]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k+offset) as usize];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+// merge a single line hunk into the previous one when they are contiguous
+fn push_hunk(hunks: &mut Vec<Hunk>, hunk: Hunk) {
+    match (hunks.last_mut(), &hunk) {
+        (Some(Hunk::Equal { len, .. }), Hunk::Equal { .. }) |
+        (Some(Hunk::Delete { len, .. }), Hunk::Delete { .. }) |
+        (Some(Hunk::Insert { len, .. }), Hunk::Insert { .. }) => *len += 1,
+        _ => hunks.push(hunk),
+    }
+}
+
+// unified diff body with `context` lines around the changes, "@@ -l,s +l,s @@" per hunk
+pub fn render_unified(a: &str, b: &str, context: usize) -> String {
+    // newlines kept, so that a missing newline at the end counts as a change
+    let a_lines: Vec<&str> = a.split_inclusive('\n').collect();
+    let b_lines: Vec<&str> = b.split_inclusive('\n').collect();
+
+    // (kind, old line, new line) for every line of both versions
+    let mut lines: Vec<(char, usize, usize)> = Vec::new();
+    for hunk in diff_slices(&a_lines, &b_lines) {
+        let (old_pos, new_pos) = lines.last().map(|&(kind, o, n)| match kind {
+            ' ' => (o+1, n+1),
+            '-' => (o+1, n),
+            _ => (o, n+1),
+        }).unwrap_or((0, 0));
+        match hunk {
+            Hunk::Equal { len, .. } => lines.extend((0..len).map(|k| (' ', old_pos+k, new_pos+k))),
+            Hunk::Delete { len, .. } => lines.extend((0..len).map(|k| ('-', old_pos+k, new_pos))),
+            Hunk::Insert { len, .. } => lines.extend((0..len).map(|k| ('+', old_pos, new_pos+k))),
+        }
+    }
+
+    let changed: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+        match groups.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => groups.push((start, end)),
+        }
+    }
+
+    let range = |start: usize, count: usize| match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start+1),
+        _ => format!("{},{}", start+1, count),
+    };
+    let mut ret = String::new();
+    for (start, end) in groups {
+        let group = &lines[start..end];
+        let old_count = group.iter().filter(|l| l.0 != '+').count();
+        let new_count = group.iter().filter(|l| l.0 != '-').count();
+        ret.push_str(&format!("@@ -{} +{} @@\n", range(group[0].1, old_count), range(group[0].2, new_count)));
+        for &(kind, o, n) in group {
+            let line = if kind == '+' { b_lines[n] } else { a_lines[o] };
+            ret.push(kind);
+            ret.push_str(line);
+            if !line.ends_with('\n') {
+                ret.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_diff_lines() {
+    let tests = [
+        ("", "", vec![]),
+        ("a\nb\n", "a\nb\n", vec![Hunk::Equal { old_start: 0, new_start: 0, len: 2 }]),
+        ("", "a\n", vec![Hunk::Insert { new_start: 0, len: 1 }]),
+        ("a\n", "", vec![Hunk::Delete { old_start: 0, len: 1 }]),
+        ("a\nb\nc\n", "a\nx\nc\n", vec![
+            Hunk::Equal { old_start: 0, new_start: 0, len: 1 },
+            Hunk::Delete { old_start: 1, len: 1 },
+            Hunk::Insert { new_start: 1, len: 1 },
+            Hunk::Equal { old_start: 2, new_start: 2, len: 1 },
+        ]),
+        ("a\nb\n", "x\na\nb\ny\n", vec![
+            Hunk::Insert { new_start: 0, len: 1 },
+            Hunk::Equal { old_start: 0, new_start: 1, len: 2 },
+            Hunk::Insert { new_start: 3, len: 1 },
+        ]),
+    ];
+    for t in tests {
+        assert_eq!(diff_lines(t.0, t.1), t.2, "{:?} -> {:?}", t.0, t.1);
+    }
+}
+
+#[test]
+fn test_diff_lines_myers() {
+    // the classic example of the Myers paper, ABCABBA -> CBABAC
+    let a = ["A", "B", "C", "A", "B", "B", "A"];
+    let b = ["C", "B", "A", "B", "A", "C"];
+    let hunks = diff_slices(&a, &b);
+    let equal: usize = hunks.iter().map(|h| match h { Hunk::Equal { len, .. } => *len, _ => 0 }).sum();
+    let edits: usize = hunks.iter().map(|h| match h {
+        Hunk::Delete { len, .. } | Hunk::Insert { len, .. } => *len,
+        _ => 0,
+    }).sum();
+    // longest common subsequence of 4 lines, so the edit distance is 5
+    assert_eq!((equal, edits), (4, 5));
+
+    // applying the hunks to a gives b
+    let mut out = Vec::new();
+    for hunk in &hunks {
+        match hunk {
+            Hunk::Equal { old_start, len, .. } => out.extend_from_slice(&a[*old_start..old_start+len]),
+            Hunk::Insert { new_start, len } => out.extend_from_slice(&b[*new_start..new_start+len]),
+            Hunk::Delete { .. } => {},
+        }
+    }
+    assert_eq!(out, b);
+
+    assert_eq!(diff_lines("a\nb\n", "c\nd\n"), vec![
+        Hunk::Delete { old_start: 0, len: 2 },
+        Hunk::Insert { new_start: 0, len: 2 },
+    ]);
+}
+
+#[test]
+fn test_render_unified() {
+    let a = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+    let b = "1\ntwo\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+    assert_eq!(render_unified(a, b, 3),
+               "@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n\
+                @@ -10,3 +10,4 @@\n 10\n 11\n 12\n+13\n");
+    // close changes share a hunk
+    assert_eq!(render_unified("1\n2\n3\n", "one\n2\nthree\n", 1),
+               "@@ -1,3 +1,3 @@\n-1\n+one\n 2\n-3\n+three\n");
+    assert_eq!(render_unified("", "a\n", 3), "@@ -0,0 +1 @@\n+a\n");
+    assert_eq!(render_unified("a\n", "", 3), "@@ -1 +0,0 @@\n-a\n");
+    assert_eq!(render_unified("a\n", "a", 3),
+               "@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n");
+    assert_eq!(render_unified("a\n", "a\n", 3), "");
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::changes::{render_names, render_patch, FileChange, Snapshot};
+use crate::common::{common_dir, get_project_root};
+use crate::hash::Hash;
+use crate::object::Tree;
+use crate::rev;
+
+/*
+    diff-tree [-r] [-p] [-z] <tree-ish> <tree-ish>
+    "<status>\t<path>" per entry that differs, compared by path, hash and mode without looking for renames
+    a changed directory is a single entry unless -r, -p shows the patch instead and implies -r
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let a = rev::resolve_tree(&proj_root, matches.value_of("tree-a").unwrap())?;
+    let b = rev::resolve_tree(&proj_root, matches.value_of("tree-b").unwrap())?;
+    let patch = matches.is_present("patch");
+
+    let out = match patch {
+        true => tree_patch(&objects_root, a, b)?,
+        false => render_names(&diff_trees(&objects_root, a, b, matches.is_present("recursive"))?, true,
+                              matches.is_present("null")),
+    };
+    io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn tree_patch(objects_root: &Path, a: Hash, b: Hash) -> Result<String, String> {
+    render_patch(objects_root, &Snapshot::from_tree(objects_root, a)?, &Snapshot::from_tree(objects_root, b)?,
+                 &diff_trees(objects_root, a, b, true)?, 3)
+}
+
+// the changed entries in git's order, a file turned into a directory is a deletion and an addition
+fn diff_trees(objects_root: &Path, a: Hash, b: Hash, recursive: bool) -> Result<Vec<FileChange>, String> {
+    let (old, new) = (flatten(objects_root, a, recursive)?, flatten(objects_root, b, recursive)?);
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut ret = Vec::new();
+    for key in keys {
+        let (o, n) = (old.get(key), new.get(key));
+        if o == n {
+            continue;
+        }
+        ret.push(FileChange {
+            path: PathBuf::from(key.trim_end_matches('/')),
+            old: o.map(|e| e.0),
+            new: n.map(|e| e.0),
+            old_path: None,
+            old_mode: o.map(|e| e.1),
+            new_mode: n.map(|e| e.1),
+        });
+    }
+    Ok(ret)
+}
+
+/*
+    (hash, mode) of the entries by path, only the files of every depth with recursive,
+    the top level entries without, directories keyed with a trailing '/' so that the keys sort in git's order
+ */
+fn flatten(objects_root: &Path, tree: Hash, recursive: bool) -> Result<BTreeMap<String, (Hash, u32)>, String> {
+    let tree = Tree::load(objects_root, tree)?;
+    let mut ret = BTreeMap::new();
+    if !recursive {
+        for node in tree.nodes() {
+            let key = if node.is_dir() { format!("{}/", node.file_name()) } else { node.file_name().to_string() };
+            ret.insert(key, (node.hash(), node.mode()));
+        }
+        return Ok(ret);
+    }
+    tree.walk(objects_root, &mut |path, node| {
+        if !node.is_dir() {
+            ret.insert(path.to_string_lossy().to_string(), (node.hash(), node.mode()));
+        }
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+#[test]
+fn test_diff_trees() {
+    use crate::object::{write_object, write_test_tree, ObjType};
+
+    let objects_root = crate::common::test_dir("diff_trees");
+    let blob = |content: &str| write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+    let tree = |entries: &[(&str, &str, Hash)]| write_test_tree(&objects_root, entries);
+    let (one, two) = (blob("1\n"), blob("2\n"));
+
+    let a = tree(&[("100644", "a.b", one), ("40000", "a", tree(&[("100644", "x", one), ("100644", "y", one)])),
+                   ("100644", "gone", one), ("100644", "keep", one), ("100644", "run", one)]);
+    let b = tree(&[("100644", "a.b", two), ("40000", "a", tree(&[("100644", "x", two), ("100644", "z", one)])),
+                   ("40000", "gone", tree(&[("100644", "f", one)])), ("100644", "keep", one), ("100755", "run", one)]);
+    let names = |recursive| render_names(&diff_trees(&objects_root, a, b, recursive).unwrap(), true, false);
+
+    assert_eq!(names(false), "M\ta.b\nM\ta\nD\tgone\nA\tgone\nM\trun\n");
+    assert_eq!(names(true), "M\ta.b\nM\ta/x\nD\ta/y\nA\ta/z\nD\tgone\nA\tgone/f\nM\trun\n");
+    assert!(diff_trees(&objects_root, a, a, true).unwrap().is_empty());
+
+    let a = tree(&[("100644", "gone", one), ("100644", "keep", one), ("100644", "run", one)]);
+    let b = tree(&[("100644", "keep", two), ("100644", "new", one), ("100755", "run", one)]);
+    assert_eq!(tree_patch(&objects_root, a, b).unwrap(),
+               "diff --git a/gone b/gone\ndeleted file mode 100644\nindex d00491f..0000000\n\
+                --- a/gone\n+++ /dev/null\n@@ -1 +0,0 @@\n-1\n\
+                diff --git a/keep b/keep\nindex d00491f..0cfbf08 100644\n\
+                --- a/keep\n+++ b/keep\n@@ -1 +1 @@\n-1\n+2\n\
+                diff --git a/new b/new\nnew file mode 100644\nindex 0000000..d00491f\n\
+                --- /dev/null\n+++ b/new\n@@ -0,0 +1 @@\n+1\n\
+                diff --git a/run b/run\nold mode 100644\nnew mode 100755\n");
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+
+// failure of a command, exit codes follow git
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    // 1, the command failed
+    Failed(String),
+    // 1 with nothing printed, the exit status is the answer, e.g. grep finding nothing
+    Silent,
+    // 128, the command cannot run at all, e.g. outside of a repository
+    Fatal(String),
+    // 129, bad command line, the message comes with the usage
+    Usage(String),
+}
+
+impl Error {
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Failed(_) | Error::Silent => 1,
+            Error::Fatal(_) => 128,
+            Error::Usage(_) => 129,
+        }
+    }
+}
+
+// commands report their errors as strings
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Failed(message)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Failed(message) => write!(f, "error: {}", message),
+            Error::Silent => Ok(()),
+            Error::Fatal(message) => write!(f, "fatal: {}", message),
+            Error::Usage(message) => write!(f, "{}", message.trim_end()),
+        }
+    }
+}
+
+#[test]
+fn test_error_code() {
+    let failed: Error = "bad revision".to_string().into();
+    assert_eq!((failed.code(), failed.to_string()), (1, "error: bad revision".to_string()));
+    let fatal = Error::Fatal("not a git repository".to_string());
+    assert_eq!((fatal.code(), fatal.to_string()), (128, "fatal: not a git repository".to_string()));
+    assert_eq!(Error::Usage("error: unexpected argument\n".to_string()).code(), 129);
+    assert_eq!((Error::Silent.code(), Error::Silent.to_string()), (1, String::new()));
+}
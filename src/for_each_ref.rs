@@ -0,0 +1,160 @@
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root, glob_match};
+use crate::hash::Hash;
+use crate::object::{object_type, Commit, ObjType};
+use crate::refs::list_refs;
+use crate::rev::abbreviate;
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/*
+    for-each-ref [--format=<format>] [--sort=[-]<key>] [<pattern>...]
+    a pattern matches the refname itself, the refs under it, or as a glob
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let patterns: Vec<&str> = matches.values_of("pattern").map(|v| v.collect()).unwrap_or_default();
+    let format = matches.value_of("format").unwrap_or(DEFAULT_FORMAT);
+
+    let mut refs = Vec::new();
+    for (name, hash) in list_refs(&proj_root, "refs/")? {
+        if patterns.is_empty() || patterns.iter().any(|p| ref_matches(p, &name)) {
+            refs.push(RefInfo::load(&objects_root, name, hash)?);
+        }
+    }
+    if let Some(key) = matches.value_of("sort") {
+        sort_refs(&objects_root, &mut refs, key)?;
+    }
+    for info in &refs {
+        println!("{}", expand(&objects_root, format, info)?);
+    }
+    Ok(())
+}
+
+fn ref_matches(pattern: &str, refname: &str) -> bool {
+    let prefix = pattern.trim_end_matches('/');
+    refname == prefix || refname.starts_with(&format!("{}/", prefix)) || glob_match(pattern, refname, false)
+}
+
+struct RefInfo {
+    name: String,
+    hash: Hash,
+    obj_type: ObjType,
+    // loaded for the commit fields
+    commit: Option<Commit>,
+}
+
+impl RefInfo {
+    fn load(objects_root: &Path, name: String, hash: Hash) -> Result<Self, String> {
+        let obj_type = object_type(objects_root, hash)?;
+        let commit = match obj_type {
+            ObjType::Commit => Some(Commit::load(objects_root, hash)?),
+            _ => None,
+        };
+        Ok(Self { name, hash, obj_type, commit })
+    }
+}
+
+// dates sort by time, the others as strings; "-" in front reverses the order
+fn sort_refs(objects_root: &Path, refs: &mut Vec<RefInfo>, key: &str) -> Result<(), String> {
+    let (key, reverse) = match key.strip_prefix('-') {
+        Some(key) => (key, true),
+        None => (key, false),
+    };
+    let mut keyed = Vec::new();
+    for info in refs.drain(..) {
+        let time = match (key, &info.commit) {
+            ("authordate", Some(commit)) => commit.author().timestamp().timestamp(),
+            ("committerdate", Some(commit)) => commit.committer().timestamp().timestamp(),
+            _ => 0,
+        };
+        keyed.push(((time, field(objects_root, key, &info)?), info));
+    }
+    // stable, so refs with equal keys stay in refname order
+    keyed.sort_by(|(a, _), (b, _)| if reverse { b.cmp(a) } else { a.cmp(b) });
+    refs.extend(keyed.into_iter().map(|(_, info)| info));
+    Ok(())
+}
+
+// %(<field>) replaced, %% for a literal %
+fn expand(objects_root: &Path, format: &str, info: &RefInfo) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut rest = format;
+    while let Some(pos) = rest.find('%') {
+        ret.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(after) = rest.strip_prefix("%%") {
+            ret.push('%');
+            rest = after;
+        } else if let Some((name, after)) = rest.strip_prefix("%(").and_then(|r| r.split_once(')')) {
+            ret.push_str(&field(objects_root, name, info)?);
+            rest = after;
+        } else {
+            ret.push('%');
+            rest = &rest[1..];
+        }
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+// commit fields are empty for refs to other objects
+fn field(objects_root: &Path, name: &str, info: &RefInfo) -> Result<String, String> {
+    let commit = info.commit.as_ref();
+    let date = |date: chrono::DateTime<chrono::FixedOffset>| date.format("%c %z").to_string();
+    Ok(match name {
+        "refname" => info.name.clone(),
+        "refname:short" => short_refname(&info.name).to_string(),
+        "objectname" => info.hash.string(),
+        "objectname:short" => abbreviate(objects_root, info.hash),
+        "objecttype" => info.obj_type.name().to_string(),
+        "authorname" => commit.map(|c| c.author().name().to_string()).unwrap_or_default(),
+        "authordate" => commit.map(|c| date(c.author().timestamp())).unwrap_or_default(),
+        "committername" => commit.map(|c| c.committer().name().to_string()).unwrap_or_default(),
+        "committerdate" => commit.map(|c| date(c.committer().timestamp())).unwrap_or_default(),
+        "subject" => commit.map(|c| c.message().lines().next().unwrap_or("").to_string()).unwrap_or_default(),
+        _ => return Err(format!("unknown field name: {}", name)),
+    })
+}
+
+fn short_refname(refname: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"].iter().
+        find_map(|prefix| refname.strip_prefix(prefix)).unwrap_or(refname)
+}
+
+#[test]
+fn test_for_each_ref_format() {
+    use crate::object::write_object;
+
+    let objects_root = crate::common::test_dir("for_each_ref").join("objects");
+    let tree = crate::object::Tree::new().write(&objects_root).unwrap();
+    let mut refs = Vec::new();
+    for (name, time) in [("refs/heads/master", 2), ("refs/tags/v1", 1)] {
+        let body = format!("tree {}\nauthor A <a@example.com> {} +0000\ncommitter C <c@example.com> {} +0000\n\n\
+                            {}\n\nbody\n", tree.string(), time, time, name);
+        let hash = write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap();
+        refs.push(RefInfo::load(&objects_root, name.to_string(), hash).unwrap());
+    }
+    let blob = write_object(&objects_root, ObjType::Blob, b"x").unwrap();
+    refs.push(RefInfo::load(&objects_root, "refs/remotes/origin/x".to_string(), blob).unwrap());
+
+    assert_eq!(expand(&objects_root, "%(refname:short) %(objecttype) %(authorname)/%(committername): %(subject) 100%%",
+                      &refs[0]).unwrap(), "master commit A/C: refs/heads/master 100%");
+    assert_eq!(expand(&objects_root, "%(refname:short) %(objecttype) [%(subject)]", &refs[2]).unwrap(),
+               "origin/x blob []");
+    assert_eq!(expand(&objects_root, DEFAULT_FORMAT, &refs[1]).unwrap(),
+               format!("{} commit\trefs/tags/v1", refs[1].hash.string()));
+    assert!(expand(&objects_root, "%(unknown)", &refs[0]).is_err());
+
+    let names = |refs: &Vec<RefInfo>| refs.iter().map(|r| r.name.as_str()).collect::<Vec<_>>().join(" ");
+    sort_refs(&objects_root, &mut refs, "committerdate").unwrap();
+    assert_eq!(names(&refs), "refs/remotes/origin/x refs/tags/v1 refs/heads/master");
+    sort_refs(&objects_root, &mut refs, "-refname").unwrap();
+    assert_eq!(names(&refs), "refs/tags/v1 refs/remotes/origin/x refs/heads/master");
+
+    assert!(ref_matches("refs/heads", "refs/heads/master"));
+    assert!(ref_matches("refs/tags/v*", "refs/tags/v1"));
+    assert!(!ref_matches("refs/head", "refs/heads/master"));
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::Path;
+use clap::ArgMatches;
+use regex::{Regex, RegexBuilder};
+use crate::common::{common_dir, get_project_root, is_binary};
+use crate::error::Error;
+use crate::index::Index;
+use crate::object::read_object;
+
+/*
+    grep <pattern>             tracked files in the working tree
+    grep --cached <pattern>    staged blobs
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let proj_root = get_project_root()?;
+    let index = Index::from_file(&proj_root)?.unwrap_or_default();
+    let pattern = matches.value_of("pattern").ok_or("no pattern given".to_string())?;
+    let re = RegexBuilder::new(pattern).case_insensitive(matches.is_present("ignore-case")).build().
+        map_err(|e| e.to_string())?;
+
+    let out = grep(&proj_root, &index, &re, matches.is_present("cached"), matches.is_present("files-with-matches"))?;
+    // no match is reported only by the exit status, as git does
+    if out.is_empty() {
+        return Err(Error::Silent);
+    }
+    print!("{}", out);
+    Ok(())
+}
+
+// "<path>:<line number>:<line>" per matching line, or the path of each file with a match when names_only
+fn grep(proj_root: &Path, index: &Index, re: &Regex, cached: bool, names_only: bool) -> Result<String, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut out = String::new();
+    for (path, entry) in index.entries() {
+        // regular files only, 0b1000 is the type bits of 100644 and 100755
+        if entry.file_type() != 0b1000 {
+            continue;
+        }
+        let content = if cached {
+            read_object(&objects_root, entry.hash())?.1
+        } else {
+            // deleted from the working tree
+            match fs::read(proj_root.join(&path)) {
+                Ok(content) => content,
+                Err(_) => continue,
+            }
+        };
+        if is_binary(&content) {
+            continue;
+        }
+        let content = String::from_utf8_lossy(&content);
+        let lines = grep_lines(re, &content);
+        if lines.is_empty() {
+            continue;
+        }
+        if names_only {
+            out.push_str(&format!("{}\n", path.display()));
+            continue;
+        }
+        for (no, line) in lines {
+            out.push_str(&format!("{}:{}:{}\n", path.display(), no, line));
+        }
+    }
+    Ok(out)
+}
+
+// matching lines with their 1-based numbers
+fn grep_lines<'a>(re: &Regex, content: &'a str) -> Vec<(usize, &'a str)> {
+    content.lines().enumerate().filter(|(_, line)| re.is_match(line)).map(|(i, line)| (i+1, line)).collect()
+}
+
+#[test]
+fn test_grep_lines() {
+    let content = "fn main() {\n    println!(\"Hello\");\n}\r\nhello again\n";
+    let re = Regex::new("hel+o").unwrap();
+    assert_eq!(grep_lines(&re, content), vec![(4, "hello again")]);
+    let re = RegexBuilder::new("hel+o").case_insensitive(true).build().unwrap();
+    assert_eq!(grep_lines(&re, content), vec![(2, "    println!(\"Hello\");"), (4, "hello again")]);
+    assert!(grep_lines(&re, "").is_empty());
+}
+
+#[test]
+fn test_grep() {
+    use crate::object::{write_object, ObjType};
+
+    let proj_root = crate::common::test_dir("grep");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let mut index = Index::new();
+    for (name, content) in [("a.txt", "one\ntodo: two\n"), ("b.bin", "todo\0"), ("gone.txt", "todo\n"),
+                            ("c.txt", "staged\n")] {
+        fs::write(proj_root.join(name), content).unwrap();
+        let blob = write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+        index.add_entry_in(&proj_root, Path::new(name), blob).unwrap();
+    }
+    fs::remove_file(proj_root.join("gone.txt")).unwrap();
+    fs::write(proj_root.join("c.txt"), "todo in the work tree\n").unwrap();
+    fs::write(proj_root.join("untracked.txt"), "todo\n").unwrap();
+    let re = Regex::new("todo").unwrap();
+
+    // binary, deleted and untracked files are skipped
+    assert_eq!(grep(&proj_root, &index, &re, false, false).unwrap(),
+               "a.txt:2:todo: two\nc.txt:1:todo in the work tree\n");
+    assert_eq!(grep(&proj_root, &index, &re, false, true).unwrap(), "a.txt\nc.txt\n");
+    assert_eq!(grep(&proj_root, &index, &re, true, true).unwrap(), "a.txt\ngone.txt\n");
+    assert!(grep(&proj_root, &index, &Regex::new("nowhere").unwrap(), false, false).unwrap().is_empty());
+}
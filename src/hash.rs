@@ -6,6 +6,9 @@ use crate::common::{bytes_to_hex, hex_to_bytes};
 use self::crypto::digest::Digest;
 use self::crypto::sha1::Sha1;
 
+// object hash algorithms this build reads and writes
+pub const OBJECT_FORMATS: &[&str] = &["sha1"];
+
 #[derive(Debug,PartialEq,Clone,Copy,Default,Ord,PartialOrd,Eq)]
 pub struct Hash([u8;20]);
 
@@ -0,0 +1,72 @@
+use std::env::current_dir;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::attributes::Attributes;
+use crate::common::{common_dir, get_path_from_project_root, get_project_root, path_from_root};
+use crate::config::autocrlf;
+use crate::hash::Hash;
+use crate::object::{write_object, Blob, ObjType};
+
+/*
+    hash-object [-w] <file>...     the blob ids of the files, converted as add does at their paths
+    hash-object [-w] --stdin       the blob id of the standard input, as is
+    --path <name> converts as for a file stored at <name> instead, the standard input included
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
+    let attributes = Attributes::from_work_tree(&proj_root)?.with_autocrlf(autocrlf(&proj_root)?);
+    let forced_path = matches.value_of("path").map(|p| path_from_root(&prefix, p)).transpose()?;
+    let write = matches.is_present("write");
+
+    let mut inputs: Vec<(Vec<u8>, Option<PathBuf>)> = Vec::new();
+    if matches.is_present("stdin") {
+        let mut content = Vec::new();
+        io::stdin().read_to_end(&mut content).map_err(|e| e.to_string())?;
+        inputs.push((content, forced_path.clone()));
+    }
+    for file in matches.values_of("file").into_iter().flatten() {
+        let content = fs::read(file).map_err(|e| format!("could not open '{}': {}", file, e))?;
+        let path = forced_path.clone().or_else(|| path_from_root(&prefix, file).ok());
+        inputs.push((content, path));
+    }
+
+    for (content, path) in inputs {
+        let hash = hash_blob(&objects_root, &attributes, content, path.as_deref(), write)?;
+        println!("{}", hash.string());
+    }
+    Ok(())
+}
+
+// the id of the content stored as a blob at the path, written into the objects with write
+fn hash_blob(objects_root: &Path, attributes: &Attributes, content: Vec<u8>, path: Option<&Path>,
+             write: bool) -> Result<Hash, String> {
+    let content = match path {
+        Some(path) => attributes.to_stored(path, content),
+        None => content,
+    };
+    match write {
+        true => write_object(objects_root, ObjType::Blob, &content),
+        false => Ok(Blob::new(&content).generate_digest_bytes()),
+    }
+}
+
+#[test]
+fn test_hash_blob() {
+    let objects_root = crate::common::test_dir("hash_blob");
+    let attributes = Attributes::parse("*.txt text\n");
+    let crlf = b"a\r\nb\r\n".to_vec();
+    let lf = Blob::new(&b"a\nb\n".to_vec()).generate_digest_bytes();
+
+    let hash = |path: Option<&str>, write| hash_blob(&objects_root, &attributes, crlf.clone(),
+                                                     path.map(Path::new), write).unwrap();
+    assert_eq!(hash(Some("dir/a.txt"), false), lf);
+    assert_eq!(hash(None, false), Blob::new(&crlf).generate_digest_bytes());
+    assert_eq!(hash(Some("a.bin"), false), hash(None, false));
+    assert!(!objects_root.join(lf.generate_path()).exists());
+    assert_eq!(hash(Some("a.txt"), true), lf);
+    assert_eq!(crate::object::read_object(&objects_root, lf).unwrap().1, b"a\nb\n");
+}
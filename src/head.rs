@@ -1,11 +1,35 @@
 use std::fs;
+use std::path::Path;
 use clap::ArgMatches;
 use crate::hash::Hash;
-use crate::common::{get_project_root};
+use crate::common::{common_dir, get_project_root, git_dir, info};
+use crate::refs;
 
-pub fn run(_matches: &ArgMatches) -> Result<(), String>{
-    let head = Head::new()?;
-    println!("{:?}", head);
+pub fn run(matches: &ArgMatches) -> Result<(), String>{
+    let proj_root = get_project_root()?;
+    let head = Head::from_root(&proj_root)?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let short_hash = head.hash.map(|h| crate::rev::abbreviate(&objects_root, h));
+
+    // branch name or short hash only, for shell prompts
+    if matches.is_present("short") {
+        match (&head.branch, short_hash) {
+            (Some(branch), _) => println!("{}", branch),
+            (None, Some(hash)) => println!("{}", hash),
+            (None, None) => return Err("HEAD is not valid".to_string()),
+        }
+        return Ok(());
+    }
+
+    match (&head.branch, short_hash) {
+        (Some(branch), _) => info(&format!("On branch {}", branch)),
+        (None, Some(hash)) => info(&format!("HEAD detached at {}", hash)),
+        (None, None) => return Err("HEAD is not valid".to_string()),
+    }
+    match head.hash {
+        Some(hash) => info(&format!("commit {}", hash.string())),
+        None => info("No commits yet"),
+    }
     Ok(())
 }
 
@@ -19,7 +43,11 @@ pub struct Head {
 impl Head {
     pub fn new() -> Result<Self, String> {
         let proj_root = get_project_root()?;
-        let head_file = proj_root.join(".git/HEAD");
+        Self::from_root(&proj_root)
+    }
+
+    pub fn from_root(proj_root: &Path) -> Result<Self, String> {
+        let head_file = git_dir(proj_root).join("HEAD");
         if !head_file.exists() {
             return Ok(Self {
                 branch: None,
@@ -30,27 +58,19 @@ impl Head {
 
         let ref_str = fs::read(head_file).map_err(|e| e.to_string())?;
         if is_head_dangling(&ref_str) {
+            let hash_str = String::from_utf8(ref_str).map_err(|e| e.to_string())?;
             return Ok(Self {
                 branch: None,
-                hash: Hash::from(&ref_str),
+                hash: Hash::from_string(hash_str.trim_end()),
                 is_dangling: true,
             });
         }
-        let branch_path = String::from_utf8(ref_str.
-            strip_prefix("ref: ".as_bytes()).ok_or("parse failed".to_string())?.
-            strip_suffix("\n".as_bytes()).ok_or("trim suffix failed".to_string())?.
-            to_vec()).
-            map_err(|e| e.to_string())?;
+        let branch_path = read_symbolic_ref(proj_root, "HEAD")?.ok_or("parse failed".to_string())?;
 
-        let ref_path = proj_root.join(".git").join(&branch_path);
         let branch_name = branch_path.strip_prefix("refs/heads/").
                 ok_or("parse failed".to_string())?;
 
-        let hash = fs::read(ref_path).ok();
-        let hash =  match hash {
-            Some(h) => Hash::from_string(&String::from_utf8(h).unwrap()),
-            None=> None,
-        };
+        let hash = refs::read_ref(proj_root, &branch_path)?;
 
         Ok(Self {
             branch: Some(branch_name.to_string()),
@@ -77,3 +97,46 @@ pub fn is_head_dangling(ref_str: &[u8]) -> bool {
 
     !ref_str.starts_with("ref: ".as_bytes())
 }
+
+// target of a symbolic ref such as HEAD, None when it holds a hash
+pub fn read_symbolic_ref(proj_root: &Path, name: &str) -> Result<Option<String>, String> {
+    let content = fs::read_to_string(refs::ref_path(proj_root, name)).map_err(|e| e.to_string())?;
+    Ok(content.trim_end().strip_prefix("ref: ").map(|t| t.to_string()))
+}
+
+// HEAD holding the hash itself, on no branch
+pub fn detach_head(proj_root: &Path, hash: Hash) -> Result<(), String> {
+    fs::write(git_dir(proj_root).join("HEAD"), format!("{}\n", hash.string())).map_err(|e| e.to_string())
+}
+
+// move the branch HEAD is on, or HEAD itself when detached, from old as refs::update_ref does
+pub fn update_head(proj_root: &Path, hash: Hash, old: Option<Hash>) -> Result<(), String> {
+    let head = Head::from_root(proj_root)?;
+    if head.is_dangling() {
+        return refs::update_ref(proj_root, "HEAD", hash, old);
+    }
+    let branch = head.branch().ok_or("HEAD is not valid".to_string())?;
+    refs::update_ref(proj_root, &format!("refs/heads/{}", branch), hash, old)
+}
+
+pub fn write_symbolic_ref(proj_root: &Path, name: &str, target: &str) -> Result<(), String> {
+    if !target.starts_with("refs/") {
+        return Err(format!("refusing to point {} outside of refs/: {}", name, target));
+    }
+    fs::write(refs::ref_path(proj_root, name), format!("ref: {}\n", target)).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_symbolic_ref() {
+    let proj_root = crate::common::test_dir("symbolic_ref");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    fs::write(proj_root.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+    assert_eq!(read_symbolic_ref(&proj_root, "HEAD"), Ok(Some("refs/heads/master".to_string())));
+
+    write_symbolic_ref(&proj_root, "HEAD", "refs/heads/gh-pages").unwrap();
+    assert_eq!(Head::from_root(&proj_root).unwrap().branch(), Some("gh-pages".to_string()));
+    assert!(write_symbolic_ref(&proj_root, "HEAD", "gh-pages").is_err());
+
+    fs::write(proj_root.join(".git/HEAD"), "064a92d783f99851d1517b51ba0b2aed4a1d3128\n").unwrap();
+    assert_eq!(read_symbolic_ref(&proj_root, "HEAD"), Ok(None));
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::common::{git_dir, glob_match};
+
+/*
+    .git/info/exclude and the .gitignore files of the working tree
+    <pattern> per line, # for comments, ! in front to re-include, / at the end for directories only
+    a pattern with a / anywhere but at the end is matched from the directory of its .gitignore,
+    one without against the name at any depth
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ignore {
+    // the later a rule, the higher its precedence
+    rules: Vec<Rule>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    // the file of the rule from the project root, with its line number and the pattern as written
+    pub source: PathBuf,
+    pub line: usize,
+    pub pattern: String,
+    base: PathBuf,
+    glob: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let rel = match path.strip_prefix(&self.base) {
+            Ok(rel) => rel,
+            Err(_) => return false,
+        };
+        match self.anchored {
+            true => glob_match(&self.glob, &rel.to_string_lossy(), true),
+            false => rel.file_name().is_some_and(|name| glob_match(&self.glob, &name.to_string_lossy(), true)),
+        }
+    }
+}
+
+impl Ignore {
+    // rules of one file applying under base, source is the path of the file from the project root
+    pub fn parse(content: &str, base: &Path, source: &Path) -> Self {
+        let rules = content.lines().enumerate().filter_map(|(i, line)| {
+            let pattern = line.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                return None;
+            }
+            let (negated, glob) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            // "\#" and "\!" for names starting with them
+            let glob = glob.strip_prefix('\\').filter(|g| g.starts_with(['#', '!'])).unwrap_or(glob);
+            let (dir_only, glob) = match glob.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, glob),
+            };
+            Some(Rule {
+                source: source.to_path_buf(),
+                line: i + 1,
+                pattern: pattern.to_string(),
+                base: base.to_path_buf(),
+                glob: glob.trim_start_matches('/').to_string(),
+                negated,
+                dir_only,
+                anchored: glob.contains('/'),
+            })
+        }).collect();
+        Self { rules }
+    }
+
+    // .git/info/exclude first, then the .gitignore files from the root down
+    pub fn from_work_tree(proj_root: &Path) -> Result<Self, String> {
+        let mut ret = Self::default();
+        let exclude = git_dir(proj_root).join("info/exclude");
+        if exclude.is_file() {
+            let content = fs::read_to_string(&exclude).map_err(|e| e.to_string())?;
+            ret.rules.extend(Self::parse(&content, Path::new(""), Path::new(".git/info/exclude")).rules);
+        }
+
+        let mut files = Vec::new();
+        find_gitignores(proj_root, Path::new(""), &mut files)?;
+        files.sort_by_key(|f| (f.components().count(), f.clone()));
+        for file in files {
+            let content = fs::read_to_string(proj_root.join(&file)).map_err(|e| e.to_string())?;
+            ret.rules.extend(Self::parse(&content, file.parent().unwrap_or(Path::new("")), &file).rules);
+        }
+        Ok(ret)
+    }
+
+    /*
+        the rule deciding whether the path, from the project root, is ignored
+        an ignored directory decides for everything in it, a negated rule can't re-include from there
+     */
+    pub fn matching(&self, path: &Path, is_dir: bool) -> Option<&Rule> {
+        let mut dir = PathBuf::new();
+        for component in path.parent().unwrap_or(Path::new("")).components() {
+            dir.push(component);
+            if let Some(rule) = self.last_match(&dir, true).filter(|r| !r.negated) {
+                return Some(rule);
+            }
+        }
+        self.last_match(path, is_dir)
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matching(path, is_dir).is_some_and(|r| !r.negated)
+    }
+
+    fn last_match(&self, path: &Path, is_dir: bool) -> Option<&Rule> {
+        self.rules.iter().rev().find(|r| r.matches(path, is_dir))
+    }
+}
+
+fn find_gitignores(proj_root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    for entry in fs::read_dir(proj_root.join(dir)).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let name = entry.file_name();
+        let path = dir.join(&name);
+        if name == ".git" {
+            continue;
+        }
+        if entry.file_type().map_err(|e| e.to_string())?.is_dir() {
+            find_gitignores(proj_root, &path, files)?;
+        } else if name == ".gitignore" {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_ignore() {
+    let mut ignore = Ignore::parse("# build output\n*.o\n/target\nlogs/\n!keep.o\ndoc/*.html\n\\#notes\n",
+                                   Path::new(""), Path::new(".gitignore"));
+    ignore.rules.extend(Ignore::parse("*.tmp\n!important.tmp\n", Path::new("sub"), Path::new("sub/.gitignore")).rules);
+    let tests = [
+        ("main.o", false, true),
+        ("src/main.o", false, true),
+        ("keep.o", false, false),
+        ("target", true, true),
+        ("target/debug/app", false, true),
+        ("src/target", true, false),
+        ("logs", false, false),
+        ("logs/today.txt", false, true),
+        ("doc/index.html", false, true),
+        ("doc/api/index.html", false, false),
+        ("#notes", false, true),
+        ("sub/a.tmp", false, true),
+        ("a.tmp", false, false),
+        ("sub/important.tmp", false, false),
+        ("README", false, false),
+    ];
+    for t in tests {
+        assert_eq!(ignore.is_ignored(Path::new(t.0), t.1), t.2, "{}", t.0);
+    }
+
+    let rule = ignore.matching(Path::new("target/debug/app"), false).unwrap();
+    assert_eq!((rule.source.as_path(), rule.line, rule.pattern.as_str()), (Path::new(".gitignore"), 3, "/target"));
+    assert!(ignore.matching(Path::new("keep.o"), false).unwrap().is_negated());
+}
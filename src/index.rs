@@ -3,15 +3,20 @@ use std::collections::BTreeMap;
 use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use crate::common::{bytes_to_u16, bytes_to_u32, extract_until_null, get_path_from_project_root, u16_to_bytes, u32_to_bytes};
-use super::hash::Hash;
+use crate::common::{bytes_to_u16, bytes_to_u32, extract_until_null, git_dir, u16_to_bytes, u32_to_bytes};
+use super::hash::{calc_sha1_bytes, Hash};
+use crate::object::{Blob, Tree};
 
 
 // Index format https://github.com/git/git/blob/v2.12.0/Documentation/technical/index-format.txt
-#[derive(Debug, PartialEq, Default, Clone)]
+// versions this build reads and writes, version 4 compresses the paths and is not supported
+pub const INDEX_VERSIONS: &[u32] = &[2, 3];
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Index {
     version: u32,
     entry_num: u32,
@@ -19,6 +24,13 @@ pub struct Index {
 }
 
 
+// an empty index is version 2
+impl Default for Index {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Index {
     pub fn new() -> Self {
         Self {
@@ -28,72 +40,135 @@ impl Index {
         }
     }
 
-    pub fn from(bytes: &[u8]) -> Option<Self> {
+    // a version this build can't read is an error, not an empty index
+    pub fn from(bytes: &[u8]) -> Result<Self, String> {
         if !bytes.starts_with("DIRC".as_bytes()) {
-            return None;
+            return Err("index file corrupt: bad signature".to_string());
         }
+        let corrupt = || "index file corrupt".to_string();
         let len = bytes.len();
         let mut offset: usize= 4;
-        let version = bytes_to_u32(&bytes[offset..offset+4])?;
-        offset += 4;
-        let entry_num = bytes_to_u32(&bytes[offset..offset+4])?;
-        offset += 4;
+        let version = take(bytes, &mut offset, 4).and_then(bytes_to_u32).ok_or_else(corrupt)?;
+        if !INDEX_VERSIONS.contains(&version) {
+            return Err(format!("index file version {} is not supported", version));
+        }
+        let entry_num = take(bytes, &mut offset, 4).and_then(bytes_to_u32).ok_or_else(corrupt)?;
         let mut entries: BTreeMap<PathBuf, IndexEntry> = BTreeMap::new();
         while offset < len && entries.len() < entry_num as usize {
-            let out = IndexEntry::from(&bytes[offset..])?;
+            let out = IndexEntry::from(&bytes[offset..]).ok_or_else(corrupt)?;
             entries.insert(out.0.file_name.clone(), out.0);
             offset += out.1;
         }
-        Some(Self {
+        Ok(Self {
             version,
             entry_num,
             entries
         })
     }
 
-    pub fn from_file(proj_root: &PathBuf) -> Option<Self> {
-        let index_path = proj_root.join(".git/index");
-        let mut file = File::open(index_path).ok()?;
+    // None without an index file yet
+    pub fn from_file(proj_root: &Path) -> Result<Option<Self>, String> {
+        let index_path = git_dir(proj_root).join("index");
+        if !index_path.exists() {
+            return Ok(None);
+        }
+        let mut file = File::open(index_path).map_err(|e| e.to_string())?;
         let mut buf = Vec::new();
-        let _ = file.read_to_end(&mut buf).ok()?;
-        Self::from(&buf)
+        file.read_to_end(&mut buf).map_err(|e| e.to_string())?;
+        Self::from(&buf).map(Some)
+    }
+
+    pub fn write_file(&self, proj_root: &Path) -> Result<(), String> {
+        fs::write(git_dir(proj_root).join("index"), self.to_bytes()).map_err(|e| e.to_string())
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut buf = Vec::from("DIRC".as_bytes());
-        buf.append(&mut u32_to_bytes(self.version));
+        // extended flags need version 3
+        let version = match self.entries.values().any(|e| e.flags & EXTENDED != 0) {
+            true => self.version.max(3),
+            false => self.version,
+        };
+        buf.append(&mut u32_to_bytes(version));
         buf.append(&mut u32_to_bytes(self.entry_num));
 
-        for entry in &self.entries {
-            buf.append(&mut entry.1.to_bytes());
+        // git sorts by the raw bytes of the path then the stage, PathBuf compares by components
+        let mut entries: Vec<&IndexEntry> = self.entries.values().collect();
+        entries.sort_by_key(|e| (e.file_name.as_os_str().as_bytes(), e.stage()));
+        for entry in entries {
+            buf.append(&mut entry.to_bytes());
         }
 
+        // SHA-1 of everything before it, `git fsck` rejects an index without it
+        let checksum = calc_sha1_bytes(&buf);
+        buf.extend_from_slice(&checksum.bytes());
         buf
     }
 
-    pub fn add_entry(&mut self, path: &PathBuf, hash: Hash) -> Result<(),String>{
-        let ie = IndexEntry::from_file(path, hash)?;
-        self.entries.insert(ie.file_name.clone(), ie);
-        self.update_entry_num();
+    // add a file given by its path from the project root
+    pub fn add_entry_in(&mut self, proj_root: &Path, path: &Path, hash: Hash) -> Result<(),String>{
+        self.add_entry_with_stat(IndexEntry::from_file_in(proj_root, path, hash)?);
         Ok(())
     }
 
-    pub fn get_entry(& self, path: PathBuf) -> Option<IndexEntry> {
-        let key = get_path_from_project_root(&path).ok()?;
-        let ret = self.entries.get(key.as_path())?;
-        Some(ret.clone())
+    // add an entry built by the caller, the file is not looked at again
+    pub fn add_entry_with_stat(&mut self, entry: IndexEntry) {
+        self.entries.insert(entry.file_name.clone(), entry);
+        self.update_entry_num();
+    }
+
+    pub fn entry(&self, path_from_root: &Path) -> Option<&IndexEntry> {
+        self.entries.get(path_from_root)
     }
 
     pub fn entries(&self) -> BTreeMap<PathBuf, IndexEntry> {
         self.entries.clone()
     }
 
+    // `add --chmod`: mode of the regular files at or under the path, the content is kept
+    pub fn chmod(&mut self, path_from_root: &Path, executable: bool) -> Result<(), String> {
+        let mut matched = false;
+        for (path, entry) in self.entries.iter_mut().filter(|(p, _)| p.starts_with(path_from_root)) {
+            if entry.file_type() != 0b1000 {
+                return Err(format!("cannot chmod {}x '{}'", if executable { '+' } else { '-' }, path.display()));
+            }
+            entry.mode = if executable { 0o100755 } else { 0o100644 };
+            matched = true;
+        }
+        match matched {
+            true => Ok(()),
+            false => Err(format!("pathspec '{}' did not match any tracked files", path_from_root.display())),
+        }
+    }
+
     pub fn delete_entry(&mut self, path_from_root: &PathBuf) -> Result<(),String>{
         self.entries.remove(path_from_root.as_path());
         self.update_entry_num();
         Ok(())
     }
 
+    // paths added, modified and deleted in the index compared with the tree, by their hashes
+    pub fn diff_tree(&self, objects_root: &Path, tree: Hash) -> Result<PathChanges, String> {
+        let mut tree_files = BTreeMap::new();
+        Tree::load(objects_root, tree)?.walk(objects_root, &mut |path, node| {
+            if !node.is_dir() {
+                tree_files.insert(path.to_path_buf(), node.hash());
+            }
+            Ok(())
+        })?;
+
+        let mut added = Vec::new();
+        let mut modified = Vec::new();
+        for (path, entry) in self.entries.iter().filter(|(_, e)| !e.is_intent_to_add()) {
+            match tree_files.remove(path) {
+                None => added.push(path.clone()),
+                Some(hash) if hash != entry.hash => modified.push(path.clone()),
+                Some(_) => {},
+            }
+        }
+        Ok((added, modified, tree_files.into_keys().collect()))
+    }
+
     fn update_entry_num(&mut self) {
         self.entry_num = self.entries.len() as u32;
     }
@@ -102,6 +177,21 @@ impl Index {
 }
 
 
+// added, modified and deleted paths
+pub type PathChanges = (Vec<PathBuf>, Vec<PathBuf>, Vec<PathBuf>);
+
+// reads n bytes at pos and advances it, or None on short input
+fn take<'a>(bytes: &'a [u8], pos: &mut usize, n: usize) -> Option<&'a [u8]> {
+    let ret = bytes.get(*pos..*pos+n)?;
+    *pos += n;
+    Some(ret)
+}
+
+// bits of flags
+const EXTENDED: u16 = 0x4000;
+// bits of extended_flags
+const INTENT_TO_ADD: u16 = 0x2000;
+
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct IndexEntry {
     ctime: u32,
@@ -116,40 +206,42 @@ pub struct IndexEntry {
     size: u32,
     hash: Hash,
     flags: u16,
+    // present in the file only when flags has EXTENDED
+    extended_flags: u16,
     file_name: PathBuf,
 }
 
 impl IndexEntry {
     fn from(bytes: &[u8]) -> Option<(Self, usize)> {
         let mut pos: usize = 0;
-        let ctime = bytes_to_u32(&bytes[pos..pos + 4])?;
-        pos+=4;
-        let ctime_nano = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let mtime = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let mtime_nano = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let dev = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let inode = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let mode = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let uid = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let gid = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let size = bytes_to_u32(&bytes[pos..pos+4])?;
-        pos+=4;
-        let hash = Hash::from(&bytes[pos..pos+20])?;
-        pos+=20;
-        let flags = bytes_to_u16(&bytes[pos..pos+2])?;
-        pos+=2;
-        let file_name_str = String::from_utf8(extract_until_null(&bytes[pos..])).ok()?;
+        let ctime = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let ctime_nano = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let mtime = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let mtime_nano = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let dev = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let inode = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let mode = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let uid = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let gid = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let size = bytes_to_u32(take(bytes, &mut pos, 4)?)?;
+        let hash = Hash::from(take(bytes, &mut pos, 20)?)?;
+        let flags = bytes_to_u16(take(bytes, &mut pos, 2)?)?;
+        let extended_flags = match flags & EXTENDED {
+            0 => 0,
+            _ => bytes_to_u16(take(bytes, &mut pos, 2)?)?,
+        };
+        let name_bytes = extract_until_null(bytes.get(pos..)?);
+        // no NUL terminator means the entry was cut off
+        if bytes.get(pos+name_bytes.len()) != Some(&0) {
+            return None;
+        }
+        let file_name_str = String::from_utf8(name_bytes).ok()?;
         let file_name= PathBuf::from_str(&file_name_str).ok()?;
         pos+=file_name_str.len();
         let len = (pos/8+1)*8;
+        if len > bytes.len() {
+            return None;
+        }
 
         Some((IndexEntry{
             ctime,
@@ -164,13 +256,18 @@ impl IndexEntry {
             size,
             hash,
             flags,
+            extended_flags,
             file_name
         }, len))
     }
 
-    pub fn from_file(path: &PathBuf, hash: Hash) -> Result<Self,String> {
-        let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    pub fn from_file_in(proj_root: &Path, path: &Path, hash: Hash) -> Result<Self,String> {
+        let metadata = fs::symlink_metadata(proj_root.join(path)).map_err(|e| e.to_string())?;
+        Self::from_metadata(path.to_path_buf(), &metadata, hash)
+    }
 
+    // an entry for file_name (from the project root) with the stat values already read
+    pub fn from_metadata(file_name: PathBuf, metadata: &fs::Metadata, hash: Hash) -> Result<Self,String> {
         let ctime = metadata.ctime() as u32;
         let ctime_nano = metadata.ctime_nsec() as u32;
         let mtime = metadata.mtime() as u32;
@@ -181,7 +278,6 @@ impl IndexEntry {
         let uid = metadata.uid();
         let gid = metadata.gid();
         let size = metadata.size() as u32;
-        let file_name = get_path_from_project_root(path)?;
         let flags = min(file_name.to_str().ok_or("convert path to string failed")?.len(),
                         0xfff) as u16;
         
@@ -198,10 +294,36 @@ impl IndexEntry {
             size,
             hash,
             flags,
+            extended_flags: 0,
             file_name
         })
     }
 
+    // an entry for an object which is not in the working tree, the stat values are left zero
+    pub fn without_stat(file_name: PathBuf, mode: u32, hash: Hash) -> Self {
+        let flags = min(file_name.as_os_str().len(), 0xfff) as u16;
+        Self {
+            mode,
+            hash,
+            flags,
+            file_name,
+            ..Default::default()
+        }
+    }
+
+    // `add -N`: the path is tracked with the empty blob until its content is added
+    // git rejects entries with the null hash, so the empty blob stands in for it as git does
+    pub fn intent_to_add(file_name: PathBuf, metadata: &fs::Metadata) -> Result<Self, String> {
+        let mut ret = Self::from_metadata(file_name, metadata, Blob::new(&Vec::new()).hash)?;
+        ret.flags |= EXTENDED;
+        ret.extended_flags |= INTENT_TO_ADD;
+        Ok(ret)
+    }
+
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & INTENT_TO_ADD != 0
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut ret = Vec::new();
         ret.append(&mut u32_to_bytes(self.ctime));
@@ -216,6 +338,9 @@ impl IndexEntry {
         ret.append(&mut u32_to_bytes(self.size));
         ret.append(&mut self.hash.bytes().to_vec());
         ret.append(&mut u16_to_bytes(self.flags));
+        if self.flags & EXTENDED != 0 {
+            ret.append(&mut u16_to_bytes(self.extended_flags));
+        }
         ret.append(&mut self.file_name.to_str().unwrap().as_bytes().to_vec());
 
         let zero_pad_len = if ret.len() % 8 == 0 {
@@ -229,6 +354,11 @@ impl IndexEntry {
         ret
     }
 
+    // merge stage in bits 12-13 of the flags, 0 for a normal entry
+    fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0b11
+    }
+
     pub fn mod_time(&self) -> u64 {
         let a = ((self.mtime as u64) << 32) + self.mtime_nano as u64;
         a
@@ -242,6 +372,10 @@ impl IndexEntry {
         (self.mode & 0b111_111_111) as u16
     }
 
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
     pub fn file_name(&self) -> String {
         self.file_name.file_name().unwrap().to_str().unwrap().to_string()
     }
@@ -276,7 +410,152 @@ fn test_index_entry_from() {
         size: 3,
         hash: Hash::from_string("9766475a4185a151dc9d56d614ffb9aaea3bfd42").unwrap(),
         flags: 6,
+        extended_flags: 0,
         file_name: PathBuf::from("ok.txt"),
     });
     assert_eq!(out.1, 72 as usize);
 }
+
+#[test]
+fn test_index_entry_from_truncated() {
+    let input = vec!(0x61u8, 0x61, 0x26, 0x33, 0x0e, 0xfd, 0xac, 0x2d, 0x61, 0x61, 0x26, 0x33, 0x0e,
+        0xfd, 0xac, 0x2d, 0x01, 0x00, 0x00, 0x04, 0x05, 0xb6, 0x93, 0x32, 0x00, 0x00, 0x81, 0xa4,
+        0x00, 0x00, 0x01, 0xf5, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x03, 0x97, 0x66, 0x47,
+        0x5a, 0x41, 0x85, 0xa1, 0x51, 0xdc, 0x9d, 0x56, 0xd6, 0x14, 0xff, 0xb9, 0xaa, 0xea, 0x3b,
+        0xfd, 0x42, 0x00, 0x06, 0x6f, 0x6b, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x00, 0x00, 0x00);
+    for cut in [0usize, 3, 4, 17, 39, 40, 50, 59, 60, 61] {
+        assert_eq!(IndexEntry::from(&input[..cut]), None, "cut at {}", cut);
+    }
+}
+
+#[test]
+fn test_index_entry_from_without_null() {
+    let input = vec!(0x61u8, 0x61, 0x26, 0x33, 0x0e, 0xfd, 0xac, 0x2d, 0x61, 0x61, 0x26, 0x33, 0x0e,
+        0xfd, 0xac, 0x2d, 0x01, 0x00, 0x00, 0x04, 0x05, 0xb6, 0x93, 0x32, 0x00, 0x00, 0x81, 0xa4,
+        0x00, 0x00, 0x01, 0xf5, 0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x03, 0x97, 0x66, 0x47,
+        0x5a, 0x41, 0x85, 0xa1, 0x51, 0xdc, 0x9d, 0x56, 0xd6, 0x14, 0xff, 0xb9, 0xaa, 0xea, 0x3b,
+        0xfd, 0x42, 0x00, 0x06, 0x6f, 0x6b, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x00, 0x00, 0x00);
+    // file name without its terminating NUL
+    assert_eq!(IndexEntry::from(&input[..68]), None);
+    // NUL present but the padding is cut off
+    assert_eq!(IndexEntry::from(&input[..70]), None);
+    // truncated right before the file name
+    assert_eq!(IndexEntry::from(&input[..62]), None);
+}
+
+#[test]
+fn test_add_entry_with_stat() {
+    let proj_root = crate::common::test_dir("index_with_stat");
+    fs::write(proj_root.join("a.txt"), "abc").unwrap();
+    let metadata = fs::symlink_metadata(proj_root.join("a.txt")).unwrap();
+    let hash = Hash::from_string("f2ba8f84ab5c1bce84a7b441cb1959cfc7093b7f").unwrap();
+
+    let mut index = Index::new();
+    index.add_entry_with_stat(IndexEntry::from_metadata(PathBuf::from("a.txt"), &metadata, hash).unwrap());
+    let mut expected = Index::new();
+    expected.add_entry_in(&proj_root, Path::new("a.txt"), hash).unwrap();
+    assert_eq!(index, expected);
+    assert_eq!(index.entry_num, 1);
+    assert_eq!(Index::from(&index.to_bytes()), Ok(index));
+}
+
+#[test]
+fn test_index_entry_order() {
+    let mut index = Index::new();
+    for name in ["a.txt", "a/b", "a-b"] {
+        index.add_entry_with_stat(IndexEntry { file_name: PathBuf::from(name), flags: name.len() as u16,
+                                               ..Default::default() });
+    }
+    let bytes = index.to_bytes();
+    let names: Vec<PathBuf> = (0..3).scan(12, |pos, _| {
+        let (entry, len) = IndexEntry::from(&bytes[*pos..])?;
+        *pos += len;
+        Some(entry.file_name)
+    }).collect();
+    assert_eq!(names, vec![PathBuf::from("a-b"), PathBuf::from("a.txt"), PathBuf::from("a/b")]);
+    assert_eq!(Index::from(&bytes), Ok(index));
+}
+
+#[test]
+fn test_index_unsupported_version() {
+    let mut bytes = Index::new().to_bytes();
+    bytes[4..8].copy_from_slice(&u32_to_bytes(4));
+    assert_eq!(Index::from(&bytes), Err("index file version 4 is not supported".to_string()));
+    assert!(Index::from(b"DIRC").is_err());
+    assert!(Index::from(b"not an index").is_err());
+}
+
+#[test]
+fn test_index_diff_tree() {
+    use crate::object::TreeNode;
+    let objects_root = crate::common::test_dir("index_diff_tree");
+    let one = Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+    let two = Hash::from_string("f2ba8f84ab5c1bce84a7b441cb1959cfc7093b7f").unwrap();
+    let entry = |name: &str, hash: Hash| IndexEntry { file_name: PathBuf::from(name), mode: 0o100644, hash,
+                                                     ..Default::default() };
+    let node = |name: &str, hash: Hash| TreeNode::from_index_entry(&entry(name, hash)).unwrap();
+
+    let mut sub = Tree::new();
+    sub.add_tree_node(node("b.txt", one));
+    let mut root = Tree::new();
+    root.add_tree_node(node("a.txt", one));
+    root.add_tree_node(node("old.txt", one));
+    root.add_tree_node(TreeNode::from_tree_node(sub.write(&objects_root).unwrap(), "sub".to_string()).unwrap());
+    let tree = root.write(&objects_root).unwrap();
+
+    let mut index = Index::new();
+    for (name, hash) in [("a.txt", one), ("new.txt", one), ("sub/b.txt", two)] {
+        index.add_entry_with_stat(entry(name, hash));
+    }
+    assert_eq!(index.diff_tree(&objects_root, tree).unwrap(),
+               (vec![PathBuf::from("new.txt")], vec![PathBuf::from("sub/b.txt")], vec![PathBuf::from("old.txt")]));
+}
+
+#[test]
+fn test_intent_to_add_entry() {
+    let proj_root = crate::common::test_dir("index_intent_to_add");
+    fs::write(proj_root.join("new.txt"), "abc").unwrap();
+    let metadata = fs::symlink_metadata(proj_root.join("new.txt")).unwrap();
+    let entry = IndexEntry::intent_to_add(PathBuf::from("new.txt"), &metadata).unwrap();
+    assert!(entry.is_intent_to_add());
+    assert_eq!(entry.hash().string(), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+
+    let mut index = Index::new();
+    index.add_entry_with_stat(entry);
+    let bytes = index.to_bytes();
+    assert_eq!(bytes_to_u32(&bytes[4..8]), Some(3));
+    // 62 bytes of stat and hash, 2 of flags, 2 of extended flags, the name and the padding
+    assert_eq!(bytes.len(), 12 + 72 + 20);
+    let parsed = Index::from(&bytes).unwrap();
+    assert!(parsed.entries()[Path::new("new.txt")].is_intent_to_add());
+    assert_eq!(parsed.to_bytes(), bytes);
+}
+
+#[test]
+fn test_index_chmod() {
+    use crate::object::TreeNode;
+    let entry = |name: &str, mode: u32| IndexEntry { file_name: PathBuf::from(name), mode, ..Default::default() };
+    let mut index = Index::new();
+    index.add_entry_with_stat(entry("run.sh", 0o100644));
+    index.add_entry_with_stat(entry("bin/a", 0o100644));
+    index.add_entry_with_stat(entry("bin/b", 0o100755));
+    index.add_entry_with_stat(entry("link", 0o120777));
+    let mode = |index: &Index, name: &str| TreeNode::from_index_entry(&index.entries()[Path::new(name)]).unwrap().mode();
+
+    index.chmod(Path::new("run.sh"), true).unwrap();
+    assert_eq!(mode(&index, "run.sh"), 0o100755);
+    index.chmod(Path::new("bin"), false).unwrap();
+    assert_eq!((mode(&index, "bin/a"), mode(&index, "bin/b")), (0o100644, 0o100644));
+    index.chmod(Path::new("run.sh"), false).unwrap();
+    assert_eq!(mode(&index, "run.sh"), 0o100644);
+
+    assert!(index.chmod(Path::new("link"), true).is_err());
+    assert!(index.chmod(Path::new("none"), true).is_err());
+}
+
+#[test]
+fn test_index_checksum() {
+    let bytes = Index::new().to_bytes();
+    assert_eq!(bytes.len(), 12 + 20);
+    assert_eq!(calc_sha1_bytes(&bytes[..12]).bytes(), bytes[12..]);
+}
@@ -5,10 +5,12 @@ use std::io::{Write};
 use clap::{ArgMatches};
 
 use super::common as common;
+use crate::config::GitConfig;
 
 /*
     git init
     .git -- HEAD
+         |- config
          |- objects/
          |    |- info/
          |    |- pack/
@@ -18,13 +20,54 @@ use super::common as common;
  */
 pub fn run(matches: &ArgMatches) -> Result<(), String> {
     let path = matches.value_of("path").unwrap_or(".");
-    let path_base = Path::new(path).join(".git");
+    init(Path::new(path), matches.is_present("bare"), matches.is_present("no-reinit"))
+}
+
+fn init(path: &Path, bare: bool, no_reinit: bool) -> Result<(), String> {
+    // a bare repository has no working tree, the layout is made at the path itself
+    let path_base = if bare { path.to_path_buf() } else { path.join(".git") };
 
     if !path_base.exists() {
-        println!("initialize git to {}", path_base.display());
-        fs::create_dir_all(&path_base).map_err(|e| e.to_string())?;
+        common::info(&format!("initialize git to {}", path_base.display()));
+    } else if no_reinit {
+        return Err(format!("{} already exists, not reinitializing", path_base.display()));
     } else {
-        println!("reinitialize git to {}", path_base.display());
+        common::info(&format!("reinitialize git to {}", path_base.display()));
+    }
+
+    create_layout(&path_base, bare)
+}
+
+#[test]
+fn test_init_no_reinit() {
+    let path = common::test_dir("init_no_reinit");
+    init(&path, false, true).unwrap();
+    assert!(path.join(".git/refs/heads").is_dir());
+    assert!(init(&path, false, true).is_err());
+    assert!(init(&path, false, false).is_ok());
+}
+
+#[test]
+fn test_init_config() {
+    let path = common::test_dir("init_config");
+    init(&path, false, false).unwrap();
+    let config = GitConfig::from_file(&path.join(".git/config")).unwrap();
+    assert_eq!(config.to_string(), "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n");
+
+    // an existing config is kept on reinit
+    fs::write(path.join(".git/config"), "[user]\n\tname = A U Thor\n").unwrap();
+    init(&path, false, false).unwrap();
+    assert_eq!(fs::read_to_string(path.join(".git/config")).unwrap(), "[user]\n\tname = A U Thor\n");
+
+    let bare = common::test_dir("init_config_bare");
+    init(&bare, true, false).unwrap();
+    assert!(bare.join("objects").is_dir() && !bare.join(".git").exists());
+    assert_eq!(GitConfig::from_file(&bare.join("config")).unwrap().get("core", None, "bare"), Some("true"));
+}
+
+pub fn create_layout(path_base: &Path, bare: bool) -> Result<(), String> {
+    if !path_base.exists() {
+        fs::create_dir_all(path_base).map_err(|e| e.to_string())?;
     }
 
     let path_head = path_base.join("HEAD");
@@ -62,6 +105,15 @@ pub fn run(matches: &ArgMatches) -> Result<(), String> {
         fs::create_dir(&path_refs_tags).map_err(|e| e.to_string())?;
     }
 
+    let path_config = path_base.join("config");
+    if !path_config.exists() {
+        let mut config = GitConfig::default();
+        config.set("core", None, "repositoryformatversion", "0");
+        config.set("core", None, "filemode", "true");
+        config.set("core", None, "bare", &bare.to_string());
+        config.write_file(&path_config)?;
+    }
+
 
     Ok(())
 }
@@ -1,24 +1,72 @@
-use std::collections::{BTreeSet};
-use std::path::PathBuf;
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env::current_dir;
+use std::path::{Path, PathBuf};
 use std::io;
 use std::io::Write;
 use clap::ArgMatches;
-use crate::common::get_project_root;
+use crate::changes::{diff_snapshots, render_names, render_patch, render_stat, Snapshot};
+use crate::common::{common_dir, get_path_from_project_root, get_project_root, path_from_root};
 use crate::head::Head;
+use crate::mailmap::Mailmap;
+use crate::merge_base::ancestors;
 use crate::hash::Hash;
-use crate::object::Commit;
+use crate::notes::{load_notes, note_text};
+use crate::object::{peel, Commit, ObjType, Tree};
+use crate::refs::{list_refs, shorten_ref};
+use crate::rev::{abbreviate, resolve_commit};
+use crate::verify_commit::signature_report;
 
 
-pub fn run(_matches: &ArgMatches) -> Result<(), String>{
-    let object_root = get_project_root()?.join(".git/objects/");
+pub fn run(matches: &ArgMatches) -> Result<(), String>{
+    let proj_root = get_project_root()?;
+    let object_root = common_dir(&proj_root).join("objects");
 
-    // HEAD only
     let head = Head::new()?;
+    // the commits the refs point to, through annotated tags, for --all and the decorations
+    let mut refs = BTreeMap::new();
+    for (name, hash) in list_refs(&proj_root, "refs/")? {
+        if let (hash, ObjType::Commit) = peel(&object_root, hash)? {
+            refs.insert(name, hash);
+        }
+    }
+
+    let (mut starts, exclude) = match matches.values_of("rev") {
+        None => (head.hash().into_iter().collect(), BTreeSet::new()),
+        Some(revs) => walk_revs(&proj_root, &object_root, revs)?,
+    };
+    if matches.is_present("all") {
+        starts.extend(refs.values().cloned());
+    }
+    if starts.is_empty() {
+        return Err(" HEAD does not any commits yes".to_string());
+    }
 
-    let head_hash = head.hash().ok_or(" HEAD does not any commits yes".to_string())?;
+    let raw = match matches.value_of("pretty") {
+        None | Some("medium") | Some("oneline") => false,
+        Some("raw") => true,
+        Some(format) => return Err(format!("invalid --pretty format: {}", format)),
+    };
 
-    let mut parser = LogParser::from(object_root, head_hash);
+    let mut parser = LogParser::from(object_root, starts, decorations(&head, &refs), raw);
+    parser.stat = matches.is_present("stat");
+    parser.whatchanged = matches.is_present("whatchanged");
+    parser.patch = matches.is_present("patch");
+    parser.show_signature = matches.is_present("show-signature");
+    parser.exclude = exclude;
+    parser.first_parent = matches.is_present("first-parent");
+    parser.no_walk = matches.is_present("no-walk");
+    parser.oneline = matches.is_present("oneline") || matches.value_of("pretty") == Some("oneline");
+    if let Some(n) = matches.value_of("max-count") {
+        parser.max_count = Some(n.parse().map_err(|_| format!("invalid max count: {}", n))?);
+    }
+    if let Some(path) = matches.value_of("path") {
+        let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
+        parser.path = Some(path_from_root(&prefix, path)?);
+    }
+    parser.mailmap = Mailmap::from_work_tree(&proj_root)?;
+    if matches.is_present("show-notes") {
+        parser.notes = load_notes(&proj_root)?;
+    }
     let result = parser.parse()?;
 
     io::stdout().write_all(result.as_bytes()).map_err(|e| e.to_string())?;
@@ -26,30 +74,176 @@ pub fn run(_matches: &ArgMatches) -> Result<(), String>{
     Ok(())
 }
 
+// <rev2> and what it reaches, minus what <rev1> reaches with <rev1>..<rev2>: the starts and the excluded commits
+fn walk_revs<'a>(proj_root: &Path, object_root: &Path, revs: impl Iterator<Item = &'a str>)
+                 -> Result<(Vec<Hash>, BTreeSet<Hash>), String> {
+    let mut starts = Vec::new();
+    let mut exclude = BTreeSet::new();
+    for rev in revs {
+        match rev.split_once("..") {
+            Some((from, to)) => {
+                // a missing end is HEAD, as in "origin/master.."
+                let endpoint = |r: &str| resolve_commit(proj_root, if r.is_empty() { "HEAD" } else { r });
+                exclude.extend(ancestors(object_root, endpoint(from)?)?);
+                starts.push(endpoint(to)?);
+            },
+            None => starts.push(resolve_commit(proj_root, rev)?),
+        }
+    }
+    Ok((starts, exclude))
+}
+
+// ref names pointing to each commit, "HEAD -> <branch>" first
+fn decorations(head: &Head, refs: &BTreeMap<String, Hash>) -> BTreeMap<Hash, Vec<String>> {
+    let mut ret: BTreeMap<Hash, Vec<String>> = BTreeMap::new();
+    let head_ref = head.branch().map(|b| format!("refs/heads/{}", b));
+    if let Some(hash) = head.hash() {
+        let name = match &head.branch() {
+            Some(branch) => format!("HEAD -> {}", branch),
+            None => "HEAD".to_string(),
+        };
+        ret.entry(hash).or_default().push(name);
+    }
+    for (name, hash) in refs {
+        if Some(name) != head_ref.as_ref() {
+            ret.entry(*hash).or_default().push(shorten_ref(name));
+        }
+    }
+    ret
+}
+
 struct LogParser {
     object_root: PathBuf,
-    head_hash: Hash,
+    starts: Vec<Hash>,
+    decorations: BTreeMap<Hash, Vec<String>>,
+    raw: bool,
+    stat: bool,
+    // the status and path of each changed file
+    whatchanged: bool,
+    patch: bool,
+    show_signature: bool,
+    notes: BTreeMap<Hash, Hash>,
+    mailmap: Mailmap,
+    exclude: BTreeSet<Hash>,
+    path: Option<PathBuf>,
+    first_parent: bool,
+    // the starting commits only, their parents are not followed
+    no_walk: bool,
+    max_count: Option<usize>,
+    // the short hash, the decorations and the subject on one line
+    oneline: bool,
     commits: Vec<(Hash, Commit)>,
 }
 
 impl LogParser {
-    pub fn from(object_root: PathBuf, head_hash: Hash) -> Self {
+    pub fn from(object_root: PathBuf, starts: Vec<Hash>, decorations: BTreeMap<Hash, Vec<String>>,
+                raw: bool) -> Self {
         Self{
             object_root,
-            head_hash,
+            starts,
+            decorations,
+            raw,
+            stat: false,
+            whatchanged: false,
+            patch: false,
+            show_signature: false,
+            notes: BTreeMap::new(),
+            mailmap: Mailmap::default(),
+            exclude: BTreeSet::new(),
+            path: None,
+            first_parent: false,
+            no_walk: false,
+            max_count: None,
+            oneline: false,
             commits: Vec::new(),
         }
     }
 
     pub fn parse(&mut self) -> Result<String,String> {
-        let mut visit : BTreeSet<Hash> = BTreeSet::new();
-        self.dfs(self.head_hash, &mut visit)?;
+        // excluded commits count as visited, so the search stops at them
+        let mut visit : BTreeSet<Hash> = self.exclude.clone();
+        for start in self.starts.clone() {
+            if !self.no_walk {
+                self.dfs(start, &mut visit)?;
+            } else if visit.insert(start) {
+                self.commits.push((start, Commit::load(&self.object_root, start)?));
+            }
+        }
 
         self.commits.sort_by_key(|c| c.1.timestamp());
         self.commits.reverse();
-        let ret = self.commits.iter().
-            map(|c| c.1.log_entry(c.0, &Vec::new())).
-            collect::<Vec<_>>().join("\n");
+        let no_refs = Vec::new();
+        let mut entries = Vec::new();
+        for (hash, commit) in &self.commits {
+            if let Some(path) = &self.path {
+                if !self.touches(commit, path)? {
+                    continue;
+                }
+            }
+            if self.max_count == Some(entries.len()) {
+                break;
+            }
+            let mut entry = if self.oneline {
+                let refs = self.decorations.get(hash).filter(|r| !r.is_empty()).
+                    map(|r| format!(" ({})", r.join(", "))).unwrap_or_default();
+                format!("{}{} {}\n", abbreviate(&self.object_root, *hash), refs,
+                        commit.message().lines().next().unwrap_or(""))
+            } else if self.raw {
+                commit.raw_log_entry(*hash)
+            } else {
+                commit.change_author_as(self.mailmap.map(commit.author())).
+                    log_entry(*hash, self.decorations.get(hash).unwrap_or(&no_refs))
+            };
+            // right after the "commit <hash>" line
+            if let Some(report) = signature_report(commit).filter(|_| self.show_signature) {
+                let at = entry.find('\n').map_or(entry.len(), |i| i+1);
+                entry.insert_str(at, &report);
+            }
+            if let Some(blob) = self.notes.get(hash) {
+                let text = note_text(&self.object_root, *blob)?;
+                entry.push_str(&format!("\nNotes:\n    {}\n", text.trim_end_matches('\n').replace('\n', "\n    ")));
+            }
+            entry.push_str(&self.changes_of(commit)?);
+            entries.push(entry);
+        }
+        // one line entries are not separated by a blank line
+        Ok(entries.join(if self.oneline { "" } else { "\n" }))
+    }
+
+    // whether the path differs from the first parent, appearing and disappearing included
+    fn touches(&self, commit: &Commit, path: &Path) -> Result<bool, String> {
+        let find = |tree: Hash| Tree::load(&self.object_root, tree)?.find(&self.object_root, path);
+        let old = match commit.parents().first() {
+            Some(parent) => find(Commit::load(&self.object_root, *parent)?.tree())?,
+            None => None,
+        };
+        Ok(find(commit.tree())? != old)
+    }
+
+    // changes from the first parent as --stat, --whatchanged and -p ask, each after a blank line but in --oneline
+    fn changes_of(&self, commit: &Commit) -> Result<String, String> {
+        if !self.stat && !self.whatchanged && !self.patch {
+            return Ok(String::new());
+        }
+        let old = Snapshot::from_tree(&self.object_root, commit.parent_tree(&self.object_root)?)?;
+        let new = Snapshot::from_tree(&self.object_root, commit.tree())?;
+        let changes = diff_snapshots(&old, &new);
+        let mut ret = String::new();
+        if changes.is_empty() {
+            return Ok(ret);
+        }
+        // right under a one line entry
+        let sep = if self.oneline { "" } else { "\n" };
+        if self.stat {
+            ret.push_str(&format!("{}{}", sep, render_stat(&self.object_root, &old, &new, &changes)?));
+        }
+        // the list of files comes first, so that a long patch can be skimmed
+        if self.whatchanged {
+            ret.push_str(&format!("{}{}", sep, render_names(&changes, true, false)));
+        }
+        if self.patch {
+            ret.push_str(&format!("{}{}", sep, render_patch(&self.object_root, &old, &new, &changes, 3)?));
+        }
         Ok(ret)
     }
 
@@ -59,11 +253,10 @@ impl LogParser {
         }
         visit.insert(node.clone());
 
-        let path = self.get_object_path(node);
-        let bytes = fs::read(path).map_err(|e| e.to_string())?;
-        let commit = Commit::from_depressed_bytes(&bytes).
-                ok_or("parse commit error".to_string())?;
+        let commit = Commit::load(&self.object_root, node)?;
         let parents = commit.parents();
+        // the mainline only, merged side branches are not followed
+        let parents = if self.first_parent { &parents[..parents.len().min(1)] } else { &parents[..] };
         for parent in parents {
             self.dfs(*parent, visit)?;
         }
@@ -72,19 +265,126 @@ impl LogParser {
 
         Ok(())
     }
+}
 
-    fn get_object_path(&self, hash: Hash) -> PathBuf {
-        self.object_root.join(hash.generate_path())
-    }
+#[test]
+fn test_log_first_parent() {
+    use crate::object::{write_object, ObjType};
+
+    let object_root = crate::common::test_dir("log_first_parent");
+    let tree = Tree::new().write(&object_root).unwrap();
+    let commit = |message: &str, time: u32, parents: &[Hash]| {
+        let parents: String = parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let body = format!("tree {}\n{}author A <a@example.com> {} +0000\ncommitter A <a@example.com> {} +0000\n\n{}\n",
+                           tree.string(), parents, time, time, message);
+        write_object(&object_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    // base <- main <- merge, base <- side <- merge
+    let base = commit("base", 1, &[]);
+    let side = commit("side", 2, &[base]);
+    let main = commit("main", 3, &[base]);
+    let merge = commit("merge", 4, &[main, side]);
+
+    let log = |first_parent: bool, max_count: Option<usize>| {
+        let mut parser = LogParser::from(object_root.clone(), vec![merge], BTreeMap::new(), false);
+        parser.first_parent = first_parent;
+        parser.max_count = max_count;
+        parser.parse().unwrap().lines().filter_map(|l| l.strip_prefix("    ")).map(|l| l.to_string()).
+            collect::<Vec<_>>()
+    };
+    assert_eq!(log(false, None), vec!["merge", "main", "side", "base"]);
+    assert_eq!(log(true, None), vec!["merge", "main", "base"]);
+    assert_eq!(log(true, Some(2)), vec!["merge", "main"]);
+
+    let mut parser = LogParser::from(object_root.clone(), vec![side, merge, side], BTreeMap::new(), false);
+    parser.no_walk = true;
+    let subjects: Vec<_> = parser.parse().unwrap().lines().filter_map(|l| l.strip_prefix("    ")).
+        map(|l| l.to_string()).collect();
+    assert_eq!(subjects, vec!["merge", "side"]);
 }
 
-/*
-struct Refs(BTreeMap<Hash, Vec<String>>);
+#[test]
+fn test_log_oneline_abbrev() {
+    use crate::object::{write_object, ObjType};
 
-impl Refs {
-    fn new() -> Result<Self,String> {
-        let refs = get_project_root()?;
+    let object_root = crate::common::test_dir("log_oneline_abbrev");
+    let tree = Tree::new().write(&object_root).unwrap();
+    let body = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\n\
+                        subject\n\nbody\n", tree.string());
+    let root = write_object(&object_root, ObjType::Commit, body.as_bytes()).unwrap();
+    let decorations = BTreeMap::from([(root, vec!["HEAD -> master".to_string()])]);
 
-    }
+    crate::rev::set_abbrev(12);
+    let mut parser = LogParser::from(object_root.clone(), vec![root], decorations, false);
+    parser.oneline = true;
+    assert_eq!(parser.parse().unwrap(), format!("{} (HEAD -> master) subject\n", &root.string()[..12]));
+}
+
+#[test]
+fn test_log_whatchanged() {
+    use crate::index::IndexEntry;
+    use crate::object::{write_object, ObjType, TreeNode};
+
+    let object_root = crate::common::test_dir("log_whatchanged");
+    let blob = write_object(&object_root, ObjType::Blob, b"hello\n").unwrap();
+    let mut tree = Tree::new();
+    tree.add_tree_node(TreeNode::from_index_entry(&IndexEntry::without_stat(PathBuf::from("a.txt"), 0o100644, blob)).
+        unwrap());
+    let tree = tree.write(&object_root).unwrap();
+    let body = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nadd a\n",
+                       tree.string());
+    let root = write_object(&object_root, ObjType::Commit, body.as_bytes()).unwrap();
+
+    let mut parser = LogParser::from(object_root.clone(), vec![root], BTreeMap::new(), false);
+    parser.whatchanged = true;
+    parser.patch = true;
+    let log = parser.parse().unwrap();
+    let (_, changes) = log.split_once("    add a\n").unwrap();
+    assert!(changes.starts_with("\nA\ta.txt\n\ndiff --git a/a.txt b/a.txt\n"), "{}", changes);
+    assert!(changes.ends_with("+hello\n"), "{}", changes);
+}
+
+#[test]
+fn test_log_ranges_and_path() {
+    use crate::object::{write_object, write_test_tree};
+    use crate::refs::write_ref;
+
+    let proj_root = crate::common::test_dir("log_ranges");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let object_root = proj_root.join(".git/objects");
+    let (one, two) = (write_object(&object_root, ObjType::Blob, b"1\n").unwrap(),
+                      write_object(&object_root, ObjType::Blob, b"2\n").unwrap());
+    let commit = |message: &str, time: u32, files: &[(&str, &str, Hash)], parents: &[Hash]| {
+        let parents: String = parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let body = format!("tree {}\n{}author A <a@example.com> {} +0000\ncommitter A <a@example.com> {} +0000\n\n{}\n",
+                           write_test_tree(&object_root, files).string(), parents, time, time, message);
+        write_object(&object_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    // c1 <- c2 <- c3, topic at c1 and master, the current branch, at c3
+    let c1 = commit("c1", 1, &[("100644", "a.txt", one)], &[]);
+    let c2 = commit("c2", 2, &[("100644", "a.txt", one), ("100644", "b.txt", one)], &[c1]);
+    let c3 = commit("c3", 3, &[("100644", "a.txt", two), ("100644", "b.txt", one)], &[c2]);
+    write_ref(&proj_root, "refs/heads/topic", c1).unwrap();
+    write_ref(&proj_root, "refs/heads/master", c3).unwrap();
+
+    let log = |revs: &[&str], path: Option<&str>| {
+        let (starts, exclude) = walk_revs(&proj_root, &object_root, revs.iter().copied()).unwrap();
+        let mut parser = LogParser::from(object_root.clone(), starts, BTreeMap::new(), false);
+        parser.exclude = exclude;
+        parser.path = path.map(PathBuf::from);
+        parser.parse().unwrap().lines().filter_map(|l| l.strip_prefix("    ")).map(|l| l.to_string()).
+            collect::<Vec<_>>()
+    };
+    assert_eq!(log(&["topic..master"], None), vec!["c3", "c2"]);
+    assert_eq!(log(&["topic.."], None), vec!["c3", "c2"]);
+    assert_eq!(log(&[&format!("{}..{}", c2.string(), c3.string())], None), vec!["c3"]);
+    assert!(log(&["master..topic"], None).is_empty());
+    assert_eq!(log(&["topic", "master"], None), vec!["c3", "c2", "c1"]);
+    assert!(walk_revs(&proj_root, &object_root, std::iter::once("topic..nothing")).is_err());
+
+    // only the commits changing the path from their first parent
+    assert_eq!(log(&["master"], Some("a.txt")), vec!["c3", "c1"]);
+    assert_eq!(log(&["master"], Some("b.txt")), vec!["c2"]);
+    assert_eq!(log(&["topic..master"], Some("a.txt")), vec!["c3"]);
+    assert!(log(&["master"], Some("c.txt")).is_empty());
 }
- */
@@ -0,0 +1,74 @@
+use std::collections::BTreeSet;
+use std::env::current_dir;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::common::{get_path_from_project_root, get_project_root};
+use crate::ignore::Ignore;
+use crate::index::Index;
+use crate::status::files_under;
+
+/*
+    ls-files                                 the tracked files, --cached being the default
+    ls-files --others [--exclude-standard]   the untracked files, without the ignored ones with --exclude-standard
+    paths under the current directory, relative to it, the untracked ones first as git does
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
+    let index = Index::from_file(&proj_root)?.unwrap_or_default();
+    let tracked: BTreeSet<PathBuf> = index.entries().into_keys().collect();
+    let show_others = matches.is_present("others");
+    let show_cached = matches.is_present("cached") || !show_others;
+
+    let mut paths = Vec::new();
+    if show_others {
+        let ignore = match matches.is_present("exclude-standard") {
+            true => Some(Ignore::from_work_tree(&proj_root)?),
+            false => None,
+        };
+        paths.extend(untracked_files(&proj_root, &prefix, &tracked, ignore.as_ref())?);
+    }
+    if show_cached {
+        paths.extend(tracked.iter().filter(|p| p.starts_with(&prefix)).cloned());
+    }
+
+    let term = if matches.is_present("z") { '\0' } else { '\n' };
+    let mut out = String::new();
+    for path in paths {
+        out.push_str(&path.strip_prefix(&prefix).unwrap_or(&path).to_string_lossy());
+        out.push(term);
+    }
+    io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string())
+}
+
+// every file under dir outside of the index, in git's order, ignored directories are not entered
+fn untracked_files(proj_root: &Path, dir: &Path, tracked: &BTreeSet<PathBuf>, ignore: Option<&Ignore>)
+                   -> Result<Vec<PathBuf>, String> {
+    let mut ret = files_under(proj_root, dir, ignore)?;
+    ret.retain(|p| !tracked.contains(Path::new(p)));
+    // "a-b" comes before "a/b", as the whole paths are compared
+    ret.sort();
+    Ok(ret.into_iter().map(PathBuf::from).collect())
+}
+
+#[test]
+fn test_untracked_files() {
+    use std::fs;
+
+    let proj_root = crate::common::test_dir("ls_files_others");
+    for dir in [".git", "a", "build", "src"] {
+        fs::create_dir_all(proj_root.join(dir)).unwrap();
+    }
+    for file in [".gitignore", "a/x", "a-b", "build/out", "src/main.rs", "src/main.o", "src/lib.rs", ".git/HEAD"] {
+        fs::write(proj_root.join(file), "").unwrap();
+    }
+    fs::write(proj_root.join(".gitignore"), "*.o\nbuild/\n").unwrap();
+    let tracked: BTreeSet<PathBuf> = ["src/main.rs"].iter().map(PathBuf::from).collect();
+    let others = |ignore: Option<&Ignore>| untracked_files(&proj_root, Path::new(""), &tracked, ignore).unwrap().
+        iter().map(|p| p.display().to_string()).collect::<Vec<_>>();
+
+    assert_eq!(others(None), vec![".gitignore", "a-b", "a/x", "build/out", "src/lib.rs", "src/main.o"]);
+    let ignore = Ignore::from_work_tree(&proj_root).unwrap();
+    assert_eq!(others(Some(&ignore)), vec![".gitignore", "a-b", "a/x", "src/lib.rs"]);
+}
@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+use crate::object::CommitUser;
+
+/*
+    .mailmap at the project root, mapping the identities in commits to canonical ones for display
+    Proper Name <commit@email>
+    <proper@email> <commit@email>
+    Proper Name <proper@email> <commit@email>
+    Proper Name <proper@email> Commit Name <commit@email>
+ */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    // the entry applies to this name only when given
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+impl Mailmap {
+    pub fn parse(content: &str) -> Self {
+        let entries = content.lines().filter_map(|line| {
+            let line = line.split('#').next().unwrap_or("");
+            let (first_name, first_email, rest) = split_ident(line)?;
+            let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+            match split_ident(rest) {
+                Some((commit_name, commit_email, _)) => Some(Entry {
+                    proper_name: non_empty(first_name),
+                    proper_email: non_empty(first_email),
+                    commit_name: non_empty(commit_name),
+                    commit_email: commit_email.to_string(),
+                }),
+                // a name for an email, the name is required
+                None => Some(Entry {
+                    proper_name: Some(non_empty(first_name)?),
+                    proper_email: None,
+                    commit_name: None,
+                    commit_email: first_email.to_string(),
+                }),
+            }
+        }).collect();
+        Self { entries }
+    }
+
+    pub fn from_work_tree(proj_root: &Path) -> Result<Self, String> {
+        let path = proj_root.join(".mailmap");
+        if !path.is_file() {
+            return Ok(Default::default());
+        }
+        Ok(Self::parse(&fs::read_to_string(path).map_err(|e| e.to_string())?))
+    }
+
+    /*
+        the canonical identity, the user as is without a matching entry
+        emails and names compare case-insensitively, entries with the commit name win over those without
+        as in git, later lines for the same identity override the fields they give
+     */
+    pub fn map(&self, user: &CommitUser) -> CommitUser {
+        let same = |a: &str, b: &str| a.to_lowercase() == b.to_lowercase();
+        let by_email: Vec<&Entry> = self.entries.iter().filter(|e| same(&e.commit_email, user.email())).collect();
+        let by_name: Vec<&Entry> = by_email.iter().copied().
+            filter(|e| e.commit_name.as_deref().is_some_and(|n| same(n, user.name()))).collect();
+        let chosen = match by_name.is_empty() {
+            true => by_email.into_iter().filter(|e| e.commit_name.is_none()).collect(),
+            false => by_name,
+        };
+
+        let (mut name, mut email) = (user.name(), user.email());
+        for entry in chosen {
+            name = entry.proper_name.as_deref().unwrap_or(name);
+            email = entry.proper_email.as_deref().unwrap_or(email);
+        }
+        user.change_identity_as(name.to_string(), email.to_string())
+    }
+}
+
+// "Name <email>" at the start of s, with what follows it
+fn split_ident(s: &str) -> Option<(&str, &str, &str)> {
+    let open = s.find('<')?;
+    let close = open + s[open..].find('>')?;
+    Some((s[..open].trim(), s[open+1..close].trim(), &s[close+1..]))
+}
+
+#[test]
+fn test_mailmap() {
+    use crate::object::CommitterType;
+
+    let mailmap = Mailmap::parse("# comment\n\
+                                  Jane Doe <jane@example.com>\n\
+                                  <jane@example.com> <jane@old.example.com>\n\
+                                  Jane Doe <jane@example.com> <JDOE@laptop.local>  # a trailing comment\n\
+                                  Build Bot <bot@example.com> ci <ci@example.com>\n\
+                                  not an entry\n");
+    let map = |name: &str, email: &str| {
+        let user = mailmap.map(&CommitUser::from(name.to_string(), email.to_string(), CommitterType::Author));
+        format!("{} <{}>", user.name(), user.email())
+    };
+    assert_eq!(map("jane", "jane@example.com"), "Jane Doe <jane@example.com>");
+    assert_eq!(map("Jane", "jane@old.example.com"), "Jane <jane@example.com>");
+    assert_eq!(map("jd", "jdoe@LAPTOP.local"), "Jane Doe <jane@example.com>");
+    assert_eq!(map("CI", "ci@example.com"), "Build Bot <bot@example.com>");
+    assert_eq!(map("someone", "ci@example.com"), "someone <ci@example.com>");
+    assert_eq!(map("Other", "other@example.com"), "Other <other@example.com>");
+}
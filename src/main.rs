@@ -1,31 +1,146 @@
+use std::process;
 use clap::{App, load_yaml};
+use crate::error::Error;
 
 mod init;
 mod add;
+mod attributes;
+mod bisect;
 mod common;
 mod config;
+mod error;
 mod object;
 mod hash;
 mod index;
 mod commit;
+mod trailer;
 mod head;
 mod log;
+mod refs;
+mod rev;
+mod rev_parse;
+mod cat_file;
+mod hash_object;
+mod archive;
+mod mktree;
+mod diff_tree;
+mod diff;
+mod blame;
+mod grep;
+mod ignore;
+mod check_ignore;
+mod ls_files;
+mod count_objects;
+mod prune;
+mod pack;
+mod unpack_objects;
+mod checkout;
+mod clone;
+mod remote;
+mod symbolic_ref;
+mod show_ref;
+mod pack_refs;
+mod for_each_ref;
+mod tag;
+mod changes;
+mod show;
+mod verify_commit;
+mod merge_base;
+mod branch;
+mod mailmap;
+mod merge;
+mod notes;
+mod rebase;
+mod reset;
+mod state;
+mod status;
+mod update_index;
+mod version;
+mod worktree;
 
 //#[cfg(feature = "yaml")]
-fn main() -> Result<(), String> {
+fn main() {
+    let code = match run() {
+        Ok(()) => 0,
+        Err(Error::Silent) => 1,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.code()
+        },
+    };
+    process::exit(code);
+}
+
+fn run() -> Result<(), Error> {
     let yaml = load_yaml!("commands.yml");
-    let matches = App::from(yaml).get_matches();
+    let long_version = version::long_version();
+    let app = App::from(yaml).version(env!("CARGO_PKG_VERSION")).long_version(long_version.as_str());
+    let matches = match app.try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) if e.use_stderr() => return Err(Error::Usage(e.to_string())),
+        // --help and --version
+        Err(e) => return e.print().map_err(|e| Error::Failed(e.to_string())),
+    };
+    common::set_quiet(matches.is_present("quiet"));
+
+    // every other command works on a repository
+    if !matches!(matches.subcommand_name(), Some("init") | Some("clone") | Some("version") | None) {
+        let proj_root = common::get_project_root().map_err(Error::Fatal)?;
+        // its config is only read once an object is
+        config::set_repository(&proj_root);
+        if matches.is_present("no-verify-objects") {
+            object::set_verify_objects(false);
+        }
+        if let Some(n) = matches.value_of("abbrev") {
+            rev::set_abbrev(config::parse_abbrev(n)?);
+        }
+    }
 
     let res = match matches.subcommand_name() {
         Some("init") => init::run(matches.subcommand_matches("init").unwrap()),
         Some("add") => add::run(matches.subcommand_matches("add").unwrap()),
         Some("commit") => commit::run(matches.subcommand_matches("commit").unwrap()),
         Some("log") => log::run(matches.subcommand_matches("log").unwrap()),
+        Some("rev-parse") => rev_parse::run(matches.subcommand_matches("rev-parse").unwrap()),
+        Some("cat-file") => cat_file::run(matches.subcommand_matches("cat-file").unwrap()),
+        Some("hash-object") => hash_object::run(matches.subcommand_matches("hash-object").unwrap()),
+        Some("archive") => archive::run(matches.subcommand_matches("archive").unwrap()),
+        Some("mktree") => mktree::run(matches.subcommand_matches("mktree").unwrap()),
+        Some("diff-tree") => diff_tree::run(matches.subcommand_matches("diff-tree").unwrap()),
+        Some("diff") => diff::run(matches.subcommand_matches("diff").unwrap()),
+        Some("show") => show::run(matches.subcommand_matches("show").unwrap()),
+        Some("grep") => return grep::run(matches.subcommand_matches("grep").unwrap()),
+        Some("check-ignore") => return check_ignore::run(matches.subcommand_matches("check-ignore").unwrap()),
+        Some("ls-files") => ls_files::run(matches.subcommand_matches("ls-files").unwrap()),
+        Some("blame") => blame::run(matches.subcommand_matches("blame").unwrap()),
+        Some("count-objects") => count_objects::run(matches.subcommand_matches("count-objects").unwrap()),
+        Some("prune") => prune::run(matches.subcommand_matches("prune").unwrap()),
+        Some("unpack-objects") => unpack_objects::run(matches.subcommand_matches("unpack-objects").unwrap()),
+        Some("clone") => clone::run(matches.subcommand_matches("clone").unwrap()),
+        Some("remote") => remote::run(matches.subcommand_matches("remote").unwrap()),
+        Some("checkout") => checkout::run(matches.subcommand_matches("checkout").unwrap()),
+        Some("tag") => tag::run(matches.subcommand_matches("tag").unwrap()),
+        Some("branch") => branch::run(matches.subcommand_matches("branch").unwrap()),
+        Some("status") => status::run(matches.subcommand_matches("status").unwrap()),
+        Some("rebase") => rebase::run(matches.subcommand_matches("rebase").unwrap()),
+        Some("merge") => merge::run(matches.subcommand_matches("merge").unwrap()),
+        Some("reset") => reset::run(matches.subcommand_matches("reset").unwrap()),
+        Some("merge-base") => return merge_base::run(matches.subcommand_matches("merge-base").unwrap()),
+        Some("bisect") => bisect::run(matches.subcommand_matches("bisect").unwrap()),
+        Some("verify-commit") => verify_commit::run(matches.subcommand_matches("verify-commit").unwrap()),
+        Some("update-index") => update_index::run(matches.subcommand_matches("update-index").unwrap()),
+        Some("notes") => notes::run(matches.subcommand_matches("notes").unwrap()),
+        Some("worktree") => worktree::run(matches.subcommand_matches("worktree").unwrap()),
+        Some("version") => version::run(matches.subcommand_matches("version").unwrap()),
+        Some("symbolic-ref") => symbolic_ref::run(matches.subcommand_matches("symbolic-ref").unwrap()),
+        Some("show-ref") => return show_ref::run(matches.subcommand_matches("show-ref").unwrap()),
+        Some("pack-refs") => pack_refs::run(matches.subcommand_matches("pack-refs").unwrap()),
+        Some("for-each-ref") => for_each_ref::run(matches.subcommand_matches("for-each-ref").unwrap()),
 
         Some("head") => head::run(matches.subcommand_matches("head").unwrap()),
         None => Ok(()),
-        _ => Err("invalid command".to_string()),
+        _ => return Err(Error::Usage("invalid command".to_string())),
     };
 
-    res
+    res.map_err(Error::from)
 }
@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::changes::{diff_snapshots, render_stat, Snapshot};
+use crate::checkout::{check_clean, switch_tree};
+use crate::commit::current_committer;
+use crate::common::{common_dir, get_project_root, info};
+use crate::hash::Hash;
+use crate::head::{update_head, Head};
+use crate::index::Index;
+use crate::merge_base::merge_base;
+use crate::object::{peel, read_object, Commit, CommitUser, CommitterType, FileType, Tree, TreeNode};
+use crate::refs::{read_ref, write_ref};
+use crate::rev::{abbreviate, resolve};
+use crate::state::{in_progress, MergeState};
+
+/*
+    merge <commit>             fast-forward, or a merge commit with <commit> as the second parent
+    merge --ff-only <commit>   fast-forward or fail
+    merge --no-ff <commit>     a merge commit even when a fast-forward is possible
+    merge --squash <commit>    the merged tree in the index and the working tree, HEAD left alone
+    merge --abort              give up a merge stopped by a conflict
+    a conflict stops with markers in the files, commit concludes the merge once they are added
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    if matches.is_present("abort") {
+        return merge_abort(&proj_root);
+    }
+    let fast_forward = if matches.is_present("ff-only") {
+        FastForward::Only
+    } else if matches.is_present("no-ff") {
+        FastForward::Never
+    } else {
+        FastForward::Allow
+    };
+    let options = MergeOptions {
+        squash: matches.is_present("squash"),
+        fast_forward,
+        message: matches.value_of("message").map(|m| m.to_string()),
+    };
+    merge(&proj_root, matches.value_of("commit").unwrap(), &options, &current_committer()?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FastForward {
+    Allow,
+    Only,
+    Never,
+}
+
+struct MergeOptions {
+    squash: bool,
+    fast_forward: FastForward,
+    message: Option<String>,
+}
+
+fn merge(proj_root: &Path, name: &str, options: &MergeOptions, committer: &CommitUser) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    if let Some(op) = in_progress(proj_root) {
+        return Err(format!("a {} is already in progress", op.name()));
+    }
+
+    let theirs = peel(&objects_root, resolve(proj_root, name)?)?.0;
+    let theirs_tree = Commit::load(&objects_root, theirs).map_err(|_| format!("{} is not a commit", name))?.tree();
+    let head = Head::from_root(proj_root)?;
+    let ours = head.hash().ok_or("HEAD does not have any commits yet".to_string())?;
+    let ours_tree = Commit::load(&objects_root, ours)?.tree();
+
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    check_clean(proj_root, &index, Some(ours_tree), "merge")?;
+
+    let base = merge_base(&objects_root, ours, theirs)?.ok_or("refusing to merge unrelated histories".to_string())?;
+    if base == theirs {
+        info("Already up to date.");
+        return Ok(());
+    }
+    if base != ours && options.fast_forward == FastForward::Only {
+        return Err("Not possible to fast-forward, aborting.".to_string());
+    }
+    let squash = options.squash;
+    if !squash {
+        write_ref(proj_root, "ORIG_HEAD", ours)?;
+    }
+    if base == ours && !squash && options.fast_forward != FastForward::Never {
+        info(&format!("Updating {}..{}", abbreviate(&objects_root, ours), abbreviate(&objects_root, theirs)));
+        info("Fast-forward");
+        switch_tree(proj_root, &index, theirs_tree)?;
+        update_head(proj_root, theirs, Some(ours))?;
+        return print_stat(&objects_root, ours_tree, theirs_tree);
+    }
+
+    let base_tree = Commit::load(&objects_root, base)?.tree();
+    let (tree, conflicts) = merge_trees(&objects_root, base_tree, ours_tree, theirs_tree)?;
+    switch_tree(proj_root, &index, tree)?;
+    let message = match &options.message {
+        Some(message) => format!("{}\n", message.trim_end_matches('\n')),
+        None => {
+            let is_branch = read_ref(proj_root, &format!("refs/heads/{}", name))?.is_some();
+            merge_message(name, is_branch, head.branch().as_deref())
+        },
+    };
+    if !conflicts.is_empty() {
+        write_conflict_markers(proj_root, &objects_root, ours_tree, theirs_tree, name, &conflicts)?;
+        // a squash is committed as an ordinary commit
+        if !squash {
+            MergeState { head: theirs, message }.save(proj_root)?;
+        }
+        for path in &conflicts {
+            info(&format!("CONFLICT (content): Merge conflict in {}", path.display()));
+        }
+        return Err("Automatic merge failed; fix conflicts, add them and commit the result.".to_string());
+    }
+    if squash {
+        info("Squash commit -- not updating HEAD");
+        return print_stat(&objects_root, ours_tree, tree);
+    }
+
+    let author = committer.change_committer_type_as(CommitterType::Author);
+    let commit = Commit::from(tree, vec![ours, theirs], author, committer.clone(), message).write(&objects_root)?;
+    update_head(proj_root, commit, Some(ours))?;
+    info(&format!("Merge made as {}.", abbreviate(&objects_root, commit)));
+    print_stat(&objects_root, ours_tree, tree)
+}
+
+// back to ORIG_HEAD and its working tree, forgetting the merge
+fn merge_abort(proj_root: &Path) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    if MergeState::load(proj_root)?.is_none() {
+        return Err("There is no merge to abort (MERGE_HEAD missing).".to_string());
+    }
+    let orig_head = read_ref(proj_root, "ORIG_HEAD")?.ok_or("ORIG_HEAD is missing".to_string())?;
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    switch_tree(proj_root, &index, Commit::load(&objects_root, orig_head)?.tree())?;
+    let head = Head::from_root(proj_root)?.hash();
+    if head != Some(orig_head) {
+        update_head(proj_root, orig_head, head)?;
+    }
+    MergeState::remove(proj_root)
+}
+
+#[test]
+fn test_merge_abort() {
+    use crate::checkout::checkout_tree;
+    use crate::object::{write_object, write_test_tree, ObjType};
+
+    let proj_root = crate::common::test_dir("merge_abort");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Committer);
+    let commit = |files: &[(&str, &str)], parents: Vec<Hash>| {
+        let entries: Vec<(&str, &str, Hash)> = files.iter().
+            map(|(name, content)| ("100644", *name, write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap())).
+            collect();
+        let author = user.change_committer_type_as(CommitterType::Author);
+        Commit::from(write_test_tree(&objects_root, &entries), parents, author, user.clone(), "c\n".to_string()).
+            write(&objects_root).unwrap()
+    };
+    // both change f, topic adds n too
+    let base = commit(&[("f", "base\n")], vec![]);
+    let master = commit(&[("f", "master\n")], vec![base]);
+    let topic = commit(&[("f", "topic\n"), ("n", "new\n")], vec![base]);
+    write_ref(&proj_root, "refs/heads/topic", topic).unwrap();
+    write_ref(&proj_root, "refs/heads/master", master).unwrap();
+    checkout_tree(&proj_root, Commit::load(&objects_root, master).unwrap().tree()).unwrap().
+        write_file(&proj_root).unwrap();
+    assert_eq!(merge_abort(&proj_root).unwrap_err(), "There is no merge to abort (MERGE_HEAD missing).");
+
+    let options = MergeOptions { squash: false, fast_forward: FastForward::Allow, message: None };
+    assert!(merge(&proj_root, "topic", &options, &user).is_err());
+    assert!(MergeState::load(&proj_root).unwrap().is_some());
+    assert!(proj_root.join("n").is_file());
+
+    merge_abort(&proj_root).unwrap();
+    assert!(MergeState::load(&proj_root).unwrap().is_none());
+    assert_eq!(Head::from_root(&proj_root).unwrap().hash(), Some(master));
+    assert_eq!(fs::read_to_string(proj_root.join("f")).unwrap(), "master\n");
+    assert!(!proj_root.join("n").exists());
+    let index = Index::from_file(&proj_root).unwrap().unwrap();
+    assert_eq!(index.entries().into_keys().collect::<Vec<_>>(), [PathBuf::from("f")]);
+}
+
+// a commit of files holding their own names
+#[cfg(test)]
+fn test_commit(objects_root: &Path, user: &CommitUser, files: &[&str], parents: Vec<Hash>) -> Hash {
+    use crate::index::IndexEntry;
+    use crate::object::{write_object, ObjType};
+
+    let mut tree = Tree::new();
+    for name in files {
+        let blob = write_object(objects_root, ObjType::Blob, name.as_bytes()).unwrap();
+        let entry = IndexEntry::without_stat(PathBuf::from(name), 0o100644, blob);
+        tree.add_tree_node(TreeNode::from_index_entry(&entry).unwrap());
+    }
+    let tree = tree.write(objects_root).unwrap();
+    let author = user.change_committer_type_as(CommitterType::Author);
+    Commit::from(tree, parents, author, user.clone(), format!("{:?}\n", files)).write(objects_root).unwrap()
+}
+
+#[test]
+fn test_merge_fast_forward_options() {
+    use crate::checkout::checkout_tree;
+
+    let proj_root = crate::common::test_dir("merge_fast_forward");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Committer);
+    let commit = |files: &[&str], parents| test_commit(&objects_root, &user, files, parents);
+    // base <- topic, base <- diverged
+    let base = commit(&[], vec![]);
+    let topic = commit(&["a"], vec![base]);
+    let diverged = commit(&["b"], vec![base]);
+    write_ref(&proj_root, "refs/heads/topic", topic).unwrap();
+    write_ref(&proj_root, "refs/heads/master", diverged).unwrap();
+    checkout_tree(&proj_root, Commit::load(&objects_root, diverged).unwrap().tree()).unwrap().
+        write_file(&proj_root).unwrap();
+    let options = |fast_forward| MergeOptions { squash: false, fast_forward, message: None };
+    let head = || Head::from_root(&proj_root).unwrap().hash().unwrap();
+
+    assert!(merge(&proj_root, "topic", &options(FastForward::Only), &user).is_err());
+    assert_eq!(head(), diverged);
+
+    // topic is ahead of base, still a merge commit with both parents
+    write_ref(&proj_root, "refs/heads/master", base).unwrap();
+    let index = Index::from_file(&proj_root).unwrap().unwrap();
+    switch_tree(&proj_root, &index, Commit::load(&objects_root, base).unwrap().tree()).unwrap();
+    merge(&proj_root, "topic", &options(FastForward::Never), &user).unwrap();
+    let merged = Commit::load(&objects_root, head()).unwrap();
+    assert_eq!(merged.parents(), &vec![base, topic]);
+    assert_eq!(merged.tree(), Commit::load(&objects_root, topic).unwrap().tree());
+
+    // HEAD stays where it was when the work tree can't be switched
+    write_ref(&proj_root, "refs/heads/master", base).unwrap();
+    let index = Index::from_file(&proj_root).unwrap().unwrap();
+    switch_tree(&proj_root, &index, Commit::load(&objects_root, base).unwrap().tree()).unwrap();
+    std::fs::create_dir_all(proj_root.join("a/untracked")).unwrap();
+    assert!(merge(&proj_root, "topic", &options(FastForward::Allow), &user).is_err());
+    assert_eq!(head(), base);
+}
+
+#[test]
+fn test_merge_squash() {
+    use crate::checkout::checkout_tree;
+    use crate::state::MergeState;
+
+    let proj_root = crate::common::test_dir("merge_squash");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Committer);
+    let commit = |files: &[&str], parents| test_commit(&objects_root, &user, files, parents);
+    // base <- topic, base <- master
+    let base = commit(&[], vec![]);
+    let topic = commit(&["a"], vec![base]);
+    let master = commit(&["b"], vec![base]);
+    write_ref(&proj_root, "refs/heads/topic", topic).unwrap();
+    write_ref(&proj_root, "refs/heads/master", master).unwrap();
+    checkout_tree(&proj_root, Commit::load(&objects_root, master).unwrap().tree()).unwrap().
+        write_file(&proj_root).unwrap();
+
+    // the merged tree is staged and checked out, but neither committed nor recorded as a merge
+    let options = MergeOptions { squash: true, fast_forward: FastForward::Allow, message: None };
+    merge(&proj_root, "topic", &options, &user).unwrap();
+    assert_eq!(Head::from_root(&proj_root).unwrap().hash(), Some(master));
+    let index = Index::from_file(&proj_root).unwrap().unwrap();
+    assert_eq!(index.entries().into_keys().collect::<Vec<_>>(), [PathBuf::from("a"), PathBuf::from("b")]);
+    assert!(proj_root.join("a").is_file());
+    assert!(MergeState::load(&proj_root).unwrap().is_none());
+    assert!(!proj_root.join(".git/ORIG_HEAD").exists());
+}
+
+// git's default message, without "into <branch>" for master and main
+fn merge_message(name: &str, is_branch: bool, branch: Option<&str>) -> String {
+    let kind = if is_branch { "branch" } else { "commit" };
+    match branch {
+        Some(branch) if branch != "master" && branch != "main" => format!("Merge {} '{}' into {}\n", kind, name, branch),
+        _ => format!("Merge {} '{}'\n", kind, name),
+    }
+}
+
+#[test]
+fn test_merge_message() {
+    assert_eq!(merge_message("topic", true, Some("master")), "Merge branch 'topic'\n");
+    assert_eq!(merge_message("topic", true, Some("dev")), "Merge branch 'topic' into dev\n");
+    assert_eq!(merge_message("v1.0", false, None), "Merge commit 'v1.0'\n");
+}
+
+fn print_stat(objects_root: &Path, old: Hash, new: Hash) -> Result<(), String> {
+    let old = Snapshot::from_tree(objects_root, old)?;
+    let new = Snapshot::from_tree(objects_root, new)?;
+    let changes = diff_snapshots(&old, &new);
+    if !changes.is_empty() {
+        print!("{}", render_stat(objects_root, &old, &new, &changes)?);
+    }
+    Ok(())
+}
+
+// whole file markers for paths changed on both sides, theirs labelled
+pub fn write_conflict_markers(proj_root: &Path, objects_root: &Path, ours: Hash, theirs: Hash, label: &str,
+                              conflicts: &[PathBuf]) -> Result<(), String> {
+    let content = |tree: Hash, path: &Path| -> Result<Option<Vec<u8>>, String> {
+        match Tree::load(objects_root, tree)?.find(objects_root, path)? {
+            Some((FileType::File, blob)) => Ok(Some(read_object(objects_root, blob)?.1)),
+            _ => Ok(None),
+        }
+    };
+    for path in conflicts {
+        if let (Some(ours), Some(theirs)) = (content(ours, path)?, content(theirs, path)?) {
+            let mut out = b"<<<<<<< HEAD\n".to_vec();
+            for side in [ours, b"=======\n".to_vec(), theirs] {
+                out.extend_from_slice(&side);
+                if !out.ends_with(b"\n") {
+                    out.push(b'\n');
+                }
+            }
+            out.extend_from_slice(format!(">>>>>>> {}\n", label).as_bytes());
+            fs::write(proj_root.join(path), out).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// three-way merge of whole files, returns the merged tree and the conflicting paths
+// a conflicting path keeps the entry of ours
+pub fn merge_trees(objects_root: &Path, base: Hash, ours: Hash, theirs: Hash) -> Result<(Hash, Vec<PathBuf>), String> {
+    let mut conflicts = Vec::new();
+    let hash = match merge_dir(objects_root, Some(base), Some(ours), Some(theirs), Path::new(""), &mut conflicts)? {
+        Some(hash) => hash,
+        None => Tree::new().write(objects_root)?,
+    };
+    Ok((hash, conflicts))
+}
+
+// None when the merged directory is empty
+fn merge_dir(objects_root: &Path, base: Option<Hash>, ours: Option<Hash>, theirs: Option<Hash>, dir: &Path,
+             conflicts: &mut Vec<PathBuf>) -> Result<Option<Hash>, String> {
+    let load = |hash: Option<Hash>| -> Result<BTreeMap<String, TreeNode>, String> {
+        match hash {
+            Some(hash) => Ok(Tree::load(objects_root, hash)?.nodes().iter().
+                map(|n| (n.file_name().to_string(), n.clone())).collect()),
+            None => Ok(BTreeMap::new()),
+        }
+    };
+    let (base, ours, theirs) = (load(base)?, load(ours)?, load(theirs)?);
+    let names: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+
+    let is_dir = |node: Option<&TreeNode>| matches!(node, Some(n) if n.is_dir());
+    let mut nodes = Vec::new();
+    for name in names {
+        let (b, o, t) = (base.get(name), ours.get(name), theirs.get(name));
+        let node = if o == t || t == b {
+            o.cloned()
+        } else if o == b {
+            t.cloned()
+        } else if is_dir(o) && is_dir(t) && (b.is_none() || is_dir(b)) {
+            // changed on both sides, merge inside the directory
+            let merged = merge_dir(objects_root, b.map(|n| n.hash()), o.map(|n| n.hash()), t.map(|n| n.hash()),
+                                   &dir.join(name), conflicts)?;
+            merged.map(|hash| TreeNode::from_tree_node(hash, name.clone()).unwrap())
+        } else {
+            conflicts.push(dir.join(name));
+            o.cloned()
+        };
+        nodes.extend(node);
+    }
+    if nodes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut tree = Tree::new();
+    for node in nodes {
+        tree.add_tree_node(node);
+    }
+    tree.sort_nodes();
+    Ok(Some(tree.write(objects_root)?))
+}
+
+#[test]
+fn test_merge_trees() {
+    use crate::object::{write_object, write_test_tree, ObjType};
+
+    let objects_root = crate::common::test_dir("merge_trees");
+    let blob = |content: &str| write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+    let tree = |entries: &[(&str, &str, Hash)]| write_test_tree(&objects_root, entries);
+    let find = |tree: Hash, path: &str| Tree::load(&objects_root, tree).unwrap().
+        find(&objects_root, Path::new(path)).unwrap().map(|(_, hash)| hash);
+    let (one, two, three) = (blob("1\n"), blob("2\n"), blob("3\n"));
+
+    let base = tree(&[("100644", "a", one), ("100644", "b", one), ("40000", "sub", tree(&[("100644", "x", one)]))]);
+    let ours = tree(&[("100644", "a", two), ("100644", "b", one), ("40000", "sub", tree(&[("100644", "x", two)]))]);
+    let theirs = tree(&[("100644", "a", one), ("100644", "b", two), ("100644", "c", one),
+                        ("40000", "sub", tree(&[("100644", "x", one), ("100644", "y", one)]))]);
+    let (merged, conflicts) = merge_trees(&objects_root, base, ours, theirs).unwrap();
+    assert!(conflicts.is_empty());
+    assert_eq!(find(merged, "a"), Some(two));
+    assert_eq!(find(merged, "b"), Some(two));
+    assert_eq!(find(merged, "c"), Some(one));
+    assert_eq!(find(merged, "sub/x"), Some(two));
+    assert_eq!(find(merged, "sub/y"), Some(one));
+
+    // both sides change a, theirs also deletes b
+    let theirs = tree(&[("100644", "a", three), ("40000", "sub", tree(&[("100644", "x", one)]))]);
+    let (merged, conflicts) = merge_trees(&objects_root, base, ours, theirs).unwrap();
+    assert_eq!(conflicts, vec![PathBuf::from("a")]);
+    assert_eq!(find(merged, "a"), Some(two));
+    assert_eq!(find(merged, "b"), None);
+}
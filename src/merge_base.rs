@@ -0,0 +1,115 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root};
+use crate::error::Error;
+use crate::hash::Hash;
+use crate::object::Commit;
+use crate::rev;
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let a = rev::resolve_commit(&proj_root, matches.value_of("a").unwrap())?;
+    let b = rev::resolve_commit(&proj_root, matches.value_of("b").unwrap())?;
+
+    if !matches.is_present("is-ancestor") {
+        let base = merge_base(&objects_root, a, b)?.ok_or("no common ancestor".to_string())?;
+        println!("{}", base.string());
+        return Ok(());
+    }
+
+    // the answer is the exit status, as git does
+    if !is_ancestor(&objects_root, a, b)? {
+        return Err(Error::Silent);
+    }
+    Ok(())
+}
+
+// true if maybe_ancestor is reachable from descendant, a commit is an ancestor of itself
+pub fn is_ancestor(objects_root: &Path, maybe_ancestor: Hash, descendant: Hash) -> Result<bool, String> {
+    let mut visit = BTreeSet::new();
+    let mut queue = VecDeque::from(vec![descendant]);
+    while let Some(hash) = queue.pop_front() {
+        if hash == maybe_ancestor {
+            return Ok(true);
+        }
+        if !visit.insert(hash) {
+            continue;
+        }
+        queue.extend(Commit::load(objects_root, hash)?.parents().iter().cloned());
+    }
+    Ok(false)
+}
+
+// the common ancestor of a and b nearest to b
+pub fn merge_base(objects_root: &Path, a: Hash, b: Hash) -> Result<Option<Hash>, String> {
+    let ancestors_of_a = ancestors(objects_root, a)?;
+    let mut visit = BTreeSet::new();
+    let mut queue = VecDeque::from(vec![b]);
+    while let Some(hash) = queue.pop_front() {
+        if ancestors_of_a.contains(&hash) {
+            return Ok(Some(hash));
+        }
+        if !visit.insert(hash) {
+            continue;
+        }
+        queue.extend(Commit::load(objects_root, hash)?.parents().iter().cloned());
+    }
+    Ok(None)
+}
+
+// (commits only in local, commits only in upstream)
+pub fn ahead_behind(objects_root: &Path, local: Hash, upstream: Hash) -> Result<(usize, usize), String> {
+    let (local, upstream) = (ancestors(objects_root, local)?, ancestors(objects_root, upstream)?);
+    Ok((local.difference(&upstream).count(), upstream.difference(&local).count()))
+}
+
+// all commits reachable from start, including itself
+pub fn ancestors(objects_root: &Path, start: Hash) -> Result<BTreeSet<Hash>, String> {
+    let mut visit = BTreeSet::new();
+    let mut stack = vec![start];
+    while let Some(hash) = stack.pop() {
+        if visit.insert(hash) {
+            stack.extend(Commit::load(objects_root, hash)?.parents().iter().cloned());
+        }
+    }
+    Ok(visit)
+}
+
+#[test]
+fn test_is_ancestor() {
+    use crate::object::{write_object, ObjType};
+
+    let objects_root = crate::common::test_dir("is_ancestor");
+    let commit = |parents: &[Hash], message: &str| {
+        let parents: String = parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let body = format!("tree {}\n{}author a <a@example.com> 0 +0000\ncommitter a <a@example.com> 0 +0000\n\n{}\n",
+                           Hash::default().string(), parents, message);
+        write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap()
+    };
+    // root - a - b
+    //         \
+    //          c - merge(b, c)
+    let root = commit(&[], "root");
+    let a = commit(&[root], "a");
+    let b = commit(&[a], "b");
+    let c = commit(&[a], "c");
+    let merge = commit(&[b, c], "merge");
+
+    assert!(is_ancestor(&objects_root, root, b).unwrap());
+    assert!(is_ancestor(&objects_root, b, b).unwrap());
+    assert!(!is_ancestor(&objects_root, b, a).unwrap());
+    assert!(!is_ancestor(&objects_root, b, c).unwrap());
+    assert!(!is_ancestor(&objects_root, c, b).unwrap());
+    assert!(is_ancestor(&objects_root, c, merge).unwrap());
+    assert!(is_ancestor(&objects_root, root, merge).unwrap());
+
+    assert_eq!(merge_base(&objects_root, b, c).unwrap(), Some(a));
+    assert_eq!(merge_base(&objects_root, merge, c).unwrap(), Some(c));
+    assert_eq!(merge_base(&objects_root, root, merge).unwrap(), Some(root));
+
+    assert_eq!(ahead_behind(&objects_root, b, c).unwrap(), (1, 1));
+    assert_eq!(ahead_behind(&objects_root, merge, c).unwrap(), (2, 0));
+    assert_eq!(ahead_behind(&objects_root, root, b).unwrap(), (0, 2));
+}
@@ -0,0 +1,101 @@
+use std::io::{self, Read};
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root};
+use crate::hash::Hash;
+use crate::object::{object_type, FileType, ObjType, Tree, TreeNode};
+
+/*
+    mktree [-z] [--missing]
+    a tree from the standard input in the `ls-tree` format, "<mode> <type> <hash>\t<name>" per line
+    the entries may come in any order, their objects must exist with the type of the mode unless --missing
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let mut input = String::new();
+    io::stdin().read_to_string(&mut input).map_err(|e| e.to_string())?;
+
+    let hash = mktree(&objects_root, &input, matches.is_present("z"), matches.is_present("missing"))?;
+    println!("{}", hash.string());
+    Ok(())
+}
+
+fn mktree(objects_root: &Path, input: &str, nul: bool, missing: bool) -> Result<Hash, String> {
+    let mut tree = Tree::new();
+    for line in input.split(if nul { '\0' } else { '\n' }).filter(|l| !l.is_empty()) {
+        let (node, obj_type) = parse_entry(line)?;
+        // submodule commits live in another repository
+        if !missing && node.file_type() != FileType::Submodule {
+            let actual = object_type(objects_root, node.hash()).
+                map_err(|_| format!("entry '{}' object {} is unavailable", node.file_name(), node.hash().string()))?;
+            if actual != obj_type {
+                return Err(format!("entry '{}' object {} is a {} but specified type was ({})",
+                                   node.file_name(), node.hash().string(), actual.name(), obj_type.name()));
+            }
+        }
+        tree.add_tree_node(node);
+    }
+    tree.sort_nodes();
+    tree.write(objects_root)
+}
+
+// "<mode> <type> <hash>\t<name>" to the entry and the type it names, which must agree with the mode
+fn parse_entry(line: &str) -> Result<(TreeNode, ObjType), String> {
+    let broken = || format!("input format error: {}", line);
+    let (meta, name) = line.split_once('\t').ok_or_else(broken)?;
+    let mut fields = meta.split(' ');
+    let (mode, obj_type, hash) = match (fields.next(), fields.next(), fields.next(), fields.next()) {
+        (Some(mode), Some(obj_type), Some(hash), None) => (mode, obj_type, hash),
+        _ => return Err(broken()),
+    };
+    if name.contains('/') {
+        return Err(format!("path {} contains slash", name));
+    }
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| broken())?;
+    let hash = Hash::from_string(hash).ok_or_else(broken)?;
+    let node = TreeNode::from_mode(mode, name.to_string(), hash).ok_or_else(broken)?;
+    let obj_type = ObjType::from_name(obj_type).ok_or_else(broken)?;
+
+    let mode_type = match node.file_type() {
+        FileType::Directory => ObjType::Tree,
+        FileType::Submodule => ObjType::Commit,
+        FileType::File | FileType::SymbolicLink => ObjType::Blob,
+    };
+    if obj_type != mode_type {
+        return Err(format!("entry '{}' object type ({}) doesn't match mode type ({})",
+                           name, obj_type.name(), mode_type.name()));
+    }
+    Ok((node, obj_type))
+}
+
+#[test]
+fn test_mktree() {
+    use crate::object::write_object;
+
+    let objects_root = crate::common::test_dir("mktree");
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap().string();
+    let sub = mktree(&objects_root, &format!("100644 blob {}\thello.txt\n", blob), false, false).unwrap().string();
+    // "a.b" sorts between "a" as a file and "a" as a directory
+    let listing = format!("100755 blob {b}\ta.b\n040000 tree {s}\ta\n120000 blob {b}\tlink\n\
+                           160000 commit {c}\tmodule\n100644 blob {b}\t0\n", b = blob, s = sub, c = "c".repeat(40));
+
+    let hash = mktree(&objects_root, &listing, false, false).unwrap();
+    let mut lines: Vec<&str> = listing.lines().collect();
+    lines.sort_by_key(|l| l.split('\t').nth(1));
+    lines.swap(1, 2);
+    assert_eq!(Tree::load(&objects_root, hash).unwrap().pretty_string(), lines.join("\n") + "\n");
+    assert_eq!(mktree(&objects_root, &listing.replace('\n', "\0"), true, false), Ok(hash));
+
+    let err = |input: &str, missing| mktree(&objects_root, input, false, missing).unwrap_err();
+    let unknown = "d".repeat(40);
+    assert_eq!(err(&format!("100644 blob {}\tx", unknown), false),
+               format!("entry 'x' object {} is unavailable", unknown));
+    assert!(mktree(&objects_root, &format!("100644 blob {}\tx", unknown), false, true).is_ok());
+    assert_eq!(err(&format!("040000 blob {}\tx", blob), true),
+               "entry 'x' object type (blob) doesn't match mode type (tree)");
+    assert_eq!(err(&format!("040000 tree {}\tx", blob), false),
+               format!("entry 'x' object {} is a blob but specified type was (tree)", blob));
+    assert_eq!(err(&format!("100644 blob {}\ta/b", blob), false), "path a/b contains slash");
+    assert!(err(&format!("100600 blob {}\tx", blob), false).starts_with("input format error"));
+}
@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::commit::current_committer;
+use crate::common::{common_dir, get_project_root};
+use crate::hash::Hash;
+use crate::index::IndexEntry;
+use crate::object::{read_object, write_object, Commit, CommitUser, CommitterType, ObjType, Tree, TreeNode};
+use crate::refs::{read_ref, write_ref};
+use crate::rev::resolve;
+
+pub const NOTES_REF: &str = "refs/notes/commits";
+
+/*
+    refs/notes/commits -> commit -> tree: <commit hash> -> blob of the note
+    written flat, notes in git's fan-out layout (ab/cdef...) are read as well
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    match matches.subcommand() {
+        Some(("add", sub)) => {
+            let message = sub.value_of("message").ok_or("no note message specified")?;
+            let commit = resolve(&proj_root, sub.value_of("rev").unwrap_or("HEAD"))?;
+            add(&proj_root, commit, message, sub.is_present("force"), &current_committer()?)
+        },
+        Some(("show", sub)) => {
+            let commit = resolve(&proj_root, sub.value_of("rev").unwrap_or("HEAD"))?;
+            let objects_root = common_dir(&proj_root).join("objects");
+            let blob = load_notes(&proj_root)?.remove(&commit).
+                ok_or(format!("no note found for object {}.", commit.string()))?;
+            print!("{}", note_text(&objects_root, blob)?);
+            Ok(())
+        },
+        Some(("remove", sub)) => {
+            let commit = resolve(&proj_root, sub.value_of("rev").unwrap_or("HEAD"))?;
+            remove(&proj_root, commit, &current_committer()?)
+        },
+        _ => Err("no notes command specified".to_string()),
+    }
+}
+
+fn add(proj_root: &Path, commit: Hash, message: &str, force: bool, committer: &CommitUser) -> Result<(), String> {
+    let mut notes = load_notes(proj_root)?;
+    if notes.contains_key(&commit) && !force {
+        return Err(format!("Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+                           commit.string()));
+    }
+    let text = format!("{}\n", message.trim_end_matches('\n'));
+    let blob = write_object(&common_dir(proj_root).join("objects"), ObjType::Blob, text.as_bytes())?;
+    notes.insert(commit, blob);
+    write_notes(proj_root, &notes, "Notes added by 'git notes add'\n", committer)
+}
+
+fn remove(proj_root: &Path, commit: Hash, committer: &CommitUser) -> Result<(), String> {
+    let mut notes = load_notes(proj_root)?;
+    if notes.remove(&commit).is_none() {
+        return Err(format!("Object {} has no note", commit.string()));
+    }
+    crate::common::info(&format!("Removing note for object {}", commit.string()));
+    write_notes(proj_root, &notes, "Notes removed by 'git notes remove'\n", committer)
+}
+
+// annotated commit -> blob of its note
+pub fn load_notes(proj_root: &Path) -> Result<BTreeMap<Hash, Hash>, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut ret = BTreeMap::new();
+    let notes_commit = match read_ref(proj_root, NOTES_REF)? {
+        Some(hash) => hash,
+        None => return Ok(ret),
+    };
+    let tree = Tree::load(&objects_root, Commit::load(&objects_root, notes_commit)?.tree())?;
+    tree.walk(&objects_root, &mut |path, node| {
+        let name: String = path.to_string_lossy().chars().filter(|c| *c != '/').collect();
+        if let Some(commit) = Hash::from_string(&name) {
+            ret.insert(commit, node.hash());
+        }
+        Ok(())
+    })?;
+    Ok(ret)
+}
+
+pub fn note_text(objects_root: &Path, blob: Hash) -> Result<String, String> {
+    let (_, body) = read_object(objects_root, blob)?;
+    Ok(String::from_utf8_lossy(&body).to_string())
+}
+
+// a new commit on the notes ref, the previous one as its parent
+fn write_notes(proj_root: &Path, notes: &BTreeMap<Hash, Hash>, message: &str, committer: &CommitUser)
+               -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    // names are hex digits of the same length, so the map order is the tree order
+    let mut tree = Tree::new();
+    for (commit, blob) in notes {
+        let entry = IndexEntry::without_stat(PathBuf::from(commit.string()), 0o100644, *blob);
+        tree.add_tree_node(TreeNode::from_index_entry(&entry).ok_or("invalid note entry")?);
+    }
+    let tree = tree.write(&objects_root)?;
+
+    let parents: Vec<Hash> = read_ref(proj_root, NOTES_REF)?.into_iter().collect();
+    let author = committer.change_committer_type_as(CommitterType::Author);
+    let commit = Commit::from(tree, parents, author, committer.clone(), message.to_string()).write(&objects_root)?;
+    write_ref(proj_root, NOTES_REF, commit)
+}
+
+#[test]
+fn test_notes() {
+    let proj_root = crate::common::test_dir("notes");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let commit = write_object(&objects_root, ObjType::Blob, b"not really a commit").unwrap();
+    let other = write_object(&objects_root, ObjType::Blob, b"another one").unwrap();
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Committer);
+    assert!(load_notes(&proj_root).unwrap().is_empty());
+
+    add(&proj_root, commit, "first note", false, &user).unwrap();
+    assert!(add(&proj_root, commit, "again", false, &user).is_err());
+    add(&proj_root, commit, "second note\n", true, &user).unwrap();
+    add(&proj_root, other, "other", false, &user).unwrap();
+    let notes = load_notes(&proj_root).unwrap();
+    assert_eq!(notes.len(), 2);
+    assert_eq!(note_text(&objects_root, notes[&commit]).unwrap(), "second note\n");
+
+    let notes_commit = read_ref(&proj_root, NOTES_REF).unwrap().unwrap();
+    assert_eq!(Commit::load(&objects_root, notes_commit).unwrap().parents().len(), 1);
+
+    remove(&proj_root, commit, &user).unwrap();
+    assert!(remove(&proj_root, commit, &user).is_err());
+    assert_eq!(load_notes(&proj_root).unwrap().keys().collect::<Vec<_>>(), vec![&other]);
+}
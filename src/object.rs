@@ -3,9 +3,12 @@ extern crate regex;
 
 use regex::Regex;
 use chrono::{DateTime, Local, FixedOffset, TimeZone};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
 use std::io::{Read, Write};
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use flate2::Compression;
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
@@ -15,13 +18,16 @@ use crate::object::CommitterType::{Author, Committer};
 use crate::object::FilePermission::{Executable, UnExecutable};
 use crate::object::FileType::{Directory, File, Submodule, SymbolicLink};
 use crate::common::{extract_until_null};
+use crate::config;
+use crate::pack::{packed_object_exists, packed_object_type, read_packed_object};
 
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,PartialEq,Clone,Copy)]
 pub enum ObjType {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl Default for ObjType {
@@ -30,19 +36,352 @@ impl Default for ObjType {
     }
 }
 
+impl ObjType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "blob" => Some(ObjType::Blob),
+            "tree" => Some(ObjType::Tree),
+            "commit" => Some(ObjType::Commit),
+            "tag" => Some(ObjType::Tag),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjType::Blob => "blob",
+            ObjType::Tree => "tree",
+            ObjType::Commit => "commit",
+            ObjType::Tag => "tag",
+        }
+    }
+}
+
+/*
+    decompressed objects already read in this run, so history walks do not inflate a commit or a tree twice
+    objects never change, only the total size is bounded by dropping the oldest ones
+ */
+const OBJECT_CACHE_BYTES: usize = 32 << 20;
+
+#[derive(Default)]
+struct ObjectCache {
+    objects: BTreeMap<(PathBuf, Hash), Vec<u8>>,
+    order: VecDeque<(PathBuf, Hash)>,
+    bytes: usize,
+}
+
+impl ObjectCache {
+    fn get(&self, objects_root: &Path, hash: Hash) -> Option<Vec<u8>> {
+        self.objects.get(&(objects_root.to_path_buf(), hash)).cloned()
+    }
+
+    fn insert(&mut self, objects_root: &Path, hash: Hash, raw: &[u8]) {
+        // large blobs would push out many small commits and trees
+        if raw.len() > OBJECT_CACHE_BYTES / 16 {
+            return;
+        }
+        while self.bytes + raw.len() > OBJECT_CACHE_BYTES {
+            match self.order.pop_front() {
+                Some(key) => self.bytes -= self.objects.remove(&key).map_or(0, |v| v.len()),
+                None => break,
+            }
+        }
+        let key = (objects_root.to_path_buf(), hash);
+        if self.objects.insert(key.clone(), raw.to_vec()).is_none() {
+            self.order.push_back(key);
+            self.bytes += raw.len();
+        }
+    }
+}
+
+thread_local! {
+    static OBJECT_CACHE: RefCell<ObjectCache> = RefCell::new(ObjectCache::default());
+}
+
+// git's hash of the tree with no entries, "tree 0\0", there even when not written
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+pub fn empty_tree() -> Hash {
+    Hash::from_string(EMPTY_TREE).unwrap()
+}
+
+#[test]
+fn test_empty_tree() {
+    assert_eq!(Tree::new().calc_hash(), empty_tree());
+    let objects_root = crate::common::test_dir("empty_tree");
+    assert_eq!(read_raw_object(&objects_root, empty_tree()), Ok(b"tree 0\0".to_vec()));
+    assert!(Tree::load(&objects_root, empty_tree()).unwrap().nodes().is_empty());
+    assert!(object_exists(&objects_root, empty_tree()));
+    assert_eq!(object_type(&objects_root, empty_tree()), Ok(ObjType::Tree));
+}
+
+// decompressed object including its header, from a loose file or a pack
+pub fn read_raw_object(objects_root: &Path, hash: Hash) -> Result<Vec<u8>, String> {
+    if let Some(raw) = OBJECT_CACHE.with(|c| c.borrow().get(objects_root, hash)) {
+        return Ok(raw);
+    }
+    let raw = if let Ok(bytes) = fs::read(objects_root.join(hash.generate_path())) {
+        let raw = extract_zlib(&bytes)?;
+        if VERIFY_OBJECTS.load(Ordering::Relaxed) && config::object_checksum() {
+            verify_object(hash, &raw)?;
+        }
+        raw
+    } else {
+        match read_packed_object(objects_root, hash)? {
+            Some((obj_type, body)) => {
+                let mut raw = format!("{} {}\0", obj_type.name(), body.len()).into_bytes();
+                raw.extend(body);
+                if VERIFY_OBJECTS.load(Ordering::Relaxed) && config::object_checksum() {
+                    verify_object(hash, &raw)?;
+                }
+                raw
+            },
+            None if hash == empty_tree() => b"tree 0\0".to_vec(),
+            None => return Err(format!("object not found: {}", hash.string())),
+        }
+    };
+    OBJECT_CACHE.with(|c| c.borrow_mut().insert(objects_root, hash, &raw));
+    Ok(raw)
+}
+
+// checked unless --no-verify-objects turns it off here, or core.objectChecksum = false
+static VERIFY_OBJECTS: AtomicBool = AtomicBool::new(true);
+
+pub fn set_verify_objects(verify: bool) {
+    VERIFY_OBJECTS.store(verify, Ordering::Relaxed);
+}
+
+// an object must hash to the name it is stored or listed under
+fn verify_object(hash: Hash, raw: &[u8]) -> Result<(), String> {
+    let actual = calc_sha1_bytes(raw);
+    if actual != hash {
+        return Err(format!("object corrupt: {} hashes to {}", hash.string(), actual.string()));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_verify_object() {
+    let objects_root = crate::common::test_dir("verify_object");
+    let hello = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let world = write_object(&objects_root, ObjType::Blob, b"world\n").unwrap();
+    let raw = extract_zlib(&fs::read(objects_root.join(hello.generate_path())).unwrap()).unwrap();
+    assert!(verify_object(hello, &raw).is_ok());
+    assert!(verify_object(world, &raw).is_err());
+
+    fs::copy(objects_root.join(world.generate_path()), objects_root.join(hello.generate_path())).unwrap();
+    let err = read_raw_object(&objects_root, hello).unwrap_err();
+    assert!(err.starts_with("object corrupt"), "{}", err);
+}
+
+#[test]
+fn test_object_cache() {
+    let objects_root = crate::common::test_dir("object_cache");
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let raw = read_raw_object(&objects_root, blob).unwrap();
+    // served from the cache once read
+    fs::remove_file(objects_root.join(blob.generate_path())).unwrap();
+    assert_eq!(read_raw_object(&objects_root, blob), Ok(raw.clone()));
+    // but only for the same repository
+    assert!(read_raw_object(&crate::common::test_dir("object_cache_other"), blob).is_err());
+
+    let mut cache = ObjectCache::default();
+    let big = vec![0u8; OBJECT_CACHE_BYTES / 16];
+    for i in 0..17u8 {
+        cache.insert(&objects_root, Hash::from(&[i; 20]).unwrap(), &big);
+    }
+    assert_eq!(cache.objects.len(), 16);
+    assert!(cache.get(&objects_root, Hash::from(&[0; 20]).unwrap()).is_none());
+    assert_eq!(cache.get(&objects_root, Hash::from(&[16; 20]).unwrap()), Some(big.clone()));
+    cache.insert(&objects_root, blob, &vec![0u8; OBJECT_CACHE_BYTES / 16 + 1]);
+    assert!(cache.get(&objects_root, blob).is_none());
+}
+
+// read an object, returning its type and the body following the header
+pub fn read_object(objects_root: &Path, hash: Hash) -> Result<(ObjType, Vec<u8>), String> {
+    let raw = read_raw_object(objects_root, hash)?;
+    let (obj_type, header_len) = parse_header(&raw).
+        ok_or(format!("broken object header: {}", hash.string()))?;
+    Ok((obj_type, raw[header_len..].to_vec()))
+}
+
+// store a body as a loose object, returning its hash
+pub fn write_object(objects_root: &Path, obj_type: ObjType, body: &[u8]) -> Result<Hash, String> {
+    let mut raw = format!("{} {}\0", obj_type.name(), body.len()).into_bytes();
+    raw.extend_from_slice(body);
+    let hash = calc_sha1_bytes(&raw);
+
+    let obj_path = objects_root.join(hash.generate_path());
+    if !obj_path.exists() {
+        fs::create_dir_all(obj_path.parent().unwrap()).map_err(|e| e.to_string())?;
+        fs::write(obj_path, depress_zlib(&raw)?).map_err(|e| e.to_string())?;
+    }
+    Ok(hash)
+}
+
+pub fn object_exists(objects_root: &Path, hash: Hash) -> bool {
+    objects_root.join(hash.generate_path()).is_file() ||
+        packed_object_exists(objects_root, hash).unwrap_or(false) || hash == empty_tree()
+}
+
+pub fn object_type(objects_root: &Path, hash: Hash) -> Result<ObjType, String> {
+    let path = objects_root.join(hash.generate_path());
+    if path.is_file() {
+        return Ok(read_object_header(&path)?.0);
+    }
+    match packed_object_type(objects_root, hash)? {
+        Some(obj_type) => Ok(obj_type),
+        None if hash == empty_tree() => Ok(ObjType::Tree),
+        None => Err(format!("object not found: {}", hash.string())),
+    }
+}
+
+#[test]
+fn test_object_type() {
+    let objects_root = crate::common::test_dir("object_type");
+    let blob = write_object(&objects_root, ObjType::Blob, "hello\n".as_bytes()).unwrap();
+    let node = TreeNode::from_tree_node(blob, "dir".to_string()).unwrap();
+    let tree = write_object(&objects_root, ObjType::Tree, &node.to_bytes()).unwrap();
+
+    assert!(object_exists(&objects_root, blob));
+    assert_eq!(object_type(&objects_root, blob), Ok(ObjType::Blob));
+    assert!(object_exists(&objects_root, tree));
+    assert_eq!(object_type(&objects_root, tree), Ok(ObjType::Tree));
+    assert!(!object_exists(&objects_root, Hash::default()));
+    assert!(object_type(&objects_root, Hash::default()).is_err());
+}
+
+// follow annotated tags through their "object" lines down to the first object that is not a tag
+pub fn peel(objects_root: &Path, hash: Hash) -> Result<(Hash, ObjType), String> {
+    let mut hash = hash;
+    loop {
+        match object_type(objects_root, hash)? {
+            ObjType::Tag => {
+                let body = read_object(objects_root, hash)?.1;
+                hash = String::from_utf8_lossy(&body).lines().next().and_then(|l| l.strip_prefix("object ")).
+                    and_then(Hash::from_string).ok_or(format!("broken tag object: {}", hash.string()))?;
+            },
+            obj_type => return Ok((hash, obj_type)),
+        }
+    }
+}
 
+// a tree of (mode, name, hash) entries written as given, whatever their order
+#[cfg(test)]
+pub fn write_test_tree(objects_root: &Path, entries: &[(&str, &str, Hash)]) -> Hash {
+    let mut body = Vec::new();
+    for (mode, name, hash) in entries {
+        body.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+        body.extend_from_slice(&hash.bytes());
+    }
+    write_object(objects_root, ObjType::Tree, &body).unwrap()
+}
+
+// an annotated tag of the target, which has the named type
+#[cfg(test)]
+pub fn write_test_tag(objects_root: &Path, target: Hash, obj_type: &str, name: &str) -> Hash {
+    let body = format!("object {}\ntype {}\ntag {}\ntagger A U Thor <a@example.com> 0 +0000\n\nmsg\n",
+                       target.string(), obj_type, name);
+    write_object(objects_root, ObjType::Tag, body.as_bytes()).unwrap()
+}
+
+#[test]
+fn test_peel() {
+    let objects_root = crate::common::test_dir("peel");
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let v1 = write_test_tag(&objects_root, blob, "blob", "v1");
+    let nested = write_test_tag(&objects_root, v1, "tag", "nested");
+
+    assert_eq!(peel(&objects_root, blob), Ok((blob, ObjType::Blob)));
+    assert_eq!(peel(&objects_root, v1), Ok((blob, ObjType::Blob)));
+    assert_eq!(peel(&objects_root, nested), Ok((blob, ObjType::Blob)));
+    let broken = write_object(&objects_root, ObjType::Tag, b"type blob\n").unwrap();
+    assert!(peel(&objects_root, broken).is_err());
+}
+
+// type and body size of a loose object, decompressing only its header
+pub fn read_object_header(path: &Path) -> Result<(ObjType, usize), String> {
+    let file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut head = Vec::new();
+    ZlibDecoder::new(file).take(64).read_to_end(&mut head).map_err(|e| e.to_string())?;
+    let header = extract_until_null(&head);
+    if header.len() == head.len() {
+        return Err(format!("broken object header: {}", path.display()));
+    }
+    let broken = || format!("broken object header: {}", path.display());
+    let header = String::from_utf8(header).map_err(|_| broken())?;
+    let (name, len) = header.split_once(' ').ok_or_else(broken)?;
+    let obj_type = ObjType::from_name(name).ok_or_else(broken)?;
+    let len: usize = len.parse().map_err(|_| broken())?;
+    Ok((obj_type, len))
+}
+
+// "<type> <len>\0" -> (type, header length)
+fn parse_header(raw: &[u8]) -> Option<(ObjType, usize)> {
+    let header = String::from_utf8(extract_until_null(raw)).ok()?;
+    if header.len() == raw.len() {
+        return None;
+    }
+    let (name, len) = header.split_once(' ')?;
+    let len: usize = len.parse().ok()?;
+    if raw.len() - header.len() - 1 != len {
+        return None;
+    }
+    Some((ObjType::from_name(name)?, header.len()+1))
+}
+
+#[test]
+fn test_parse_header() {
+    assert_eq!(parse_header("blob 5\0hello".as_bytes()), Some((ObjType::Blob, 7)));
+    assert_eq!(parse_header("tree 0\0".as_bytes()), Some((ObjType::Tree, 7)));
+    assert_eq!(parse_header("blob 6\0hello".as_bytes()), None);
+    assert_eq!(parse_header("blob 5".as_bytes()), None);
+}
+
+
+// zlib level of the objects written, core.looseCompression of the repository
 fn depress_zlib(byte: &[u8]) -> Result<Vec<u8>,String> {
-    let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+    depress_zlib_with(byte, config::loose_compression())
+}
+
+fn depress_zlib_with(byte: &[u8], level: u32) -> Result<Vec<u8>,String> {
+    let mut e = ZlibEncoder::new(Vec::new(), Compression::new(level));
     e.write_all(byte).map_err(|e| e.to_string())?;
     let out = e.finish().map_err(|e| e.to_string())?;
     Ok(out)
 }
 
+#[test]
+fn test_compression_level() {
+    let input = "All work and no play makes Jack a dull boy.\n".repeat(100);
+    let stored = depress_zlib_with(input.as_bytes(), 0).unwrap();
+    let best = depress_zlib_with(input.as_bytes(), 9).unwrap();
+    assert!(stored.len() > best.len());
+    assert_eq!(extract_zlib(&stored).unwrap(), input.as_bytes());
+    assert_eq!(extract_zlib(&best).unwrap(), input.as_bytes());
+}
+
 fn extract_zlib(byte: &[u8]) -> Result<Vec<u8>, String> {
-    let mut d = ZlibDecoder::new(&byte[..]);
+    Ok(extract_zlib_stream(byte)?.0)
+}
+
+// decompress one zlib stream, also returning how many input bytes it used
+// anything after the end of the stream, e.g. the next entry of a pack, is left unread
+pub fn extract_zlib_stream(byte: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut d = flate2::bufread::ZlibDecoder::new(byte);
     let mut s: Vec<u8> = Vec::new();
     d.read_to_end(&mut s).map_err(|e| e.to_string())?;
-    Ok(s)
+    Ok((s, d.total_in() as usize))
+}
+
+#[test]
+fn test_extract_zlib_stream() {
+    let stream = depress_zlib_with(b"hello\n", 6).unwrap();
+    let mut padded = stream.clone();
+    padded.extend_from_slice(b"PACK\0\0\0\x02");
+    assert_eq!(extract_zlib_stream(&padded), Ok((b"hello\n".to_vec(), stream.len())));
+    assert_eq!(extract_zlib(&padded), Ok(b"hello\n".to_vec()));
+    assert!(extract_zlib_stream(&stream[..stream.len()-4]).is_err());
 }
 
 
@@ -83,13 +422,6 @@ impl Blob {
         }
     }
 
-    pub fn from_file(path: &PathBuf) -> Option<Self> {
-        let mut file = fs::File::open(path).ok()?;
-        let mut buf = Vec::new();
-        let _ = file.read_to_end(&mut buf).ok()?;
-        Some(Self::new(&buf))
-    }
-
     pub fn generate_digest_string(&self) -> String {
         calc_sha1_string(&*self.payload)
     }
@@ -107,7 +439,7 @@ impl Blob {
 #[test]
 fn test_blob() {
     let input_byte = String::from("ohayo").into_bytes();
-    let mut b = Blob::new(&input_byte);
+    let b = Blob::new(&input_byte);
     b.generate_depress().unwrap();
 
     let sha = b.generate_digest_string();
@@ -141,16 +473,19 @@ impl Tree {
             return None
         }
 
-        let len_string = String::from_utf8(extract_until_null(&byte[5..])).
+        let len_string = String::from_utf8(extract_until_null(byte.get(5..)?)).
             ok()?;
         let len: usize = len_string.parse().ok()?;
         let header_len: usize = "tree ".len()+len_string.len()+1;
-        let body = &byte[header_len..];
+        let body = byte.get(header_len..)?;
+        if body.len() < len {
+            return None;
+        }
         let mut offset: usize = 0;
 
         let mut nodes:Vec<TreeNode> = Vec::new();
         while offset < len {
-            let (node, n) = TreeNode::parse(&body[offset..].to_vec()).ok()?;
+            let (node, n) = TreeNode::parse(&body[offset..len].to_vec()).ok()?;
             nodes.push(node);
             if n==0 {
                 return None;
@@ -158,7 +493,7 @@ impl Tree {
             offset+=n;
         }
 
-        let hash = Hash::from(byte)?;
+        let hash = calc_sha1_bytes(byte);
 
         Some(Tree {
             obj_type: ObjType::Tree,
@@ -172,10 +507,75 @@ impl Tree {
         Self::from_bytes(&extracted_byte)
     }
 
+    pub fn load(objects_root: &Path, hash: Hash) -> Result<Tree, String> {
+        let bytes = read_raw_object(objects_root, hash)?;
+        Self::from_bytes(&bytes).ok_or(format!("parse tree error: {}", hash.string()))
+    }
+
+    // resolve a path (relative to this tree) into the type and hash of its entry
+    pub fn find(&self, objects_root: &Path, path: &Path) -> Result<Option<(FileType, Hash)>, String> {
+        let names = path.components().
+            map(|c| c.as_os_str().to_str().ok_or("convert path to string failed".to_string())).
+            collect::<Result<Vec<_>, _>>()?;
+        if names.is_empty() {
+            let hash = self.hash.unwrap_or_else(|| calc_sha1_bytes(&self.as_bytes()));
+            return Ok(Some((Directory, hash)));
+        }
+
+        let mut subtree: Option<Tree> = None;
+        for (i, name) in names.iter().enumerate() {
+            let tree = subtree.as_ref().unwrap_or(self);
+            let node = match tree.nodes.iter().find(|n| &n.file_name == name) {
+                Some(n) => n,
+                None => return Ok(None),
+            };
+            if i == names.len()-1 {
+                return Ok(Some((node.file_type, node.hash)));
+            }
+            if node.file_type != Directory {
+                return Ok(None);
+            }
+            subtree = Some(Self::load(objects_root, node.hash)?);
+        }
+        unreachable!()
+    }
+
+    // every entry depth first, a directory before its contents, with the path relative to this tree
+    // subtrees are loaded only when reached
+    pub fn walk(&self, objects_root: &Path, f: &mut impl FnMut(&Path, &TreeNode) -> Result<(), String>)
+        -> Result<(), String> {
+        self.walk_in(objects_root, Path::new(""), f)
+    }
+
+    fn walk_in(&self, objects_root: &Path, dir: &Path, f: &mut impl FnMut(&Path, &TreeNode) -> Result<(), String>)
+        -> Result<(), String> {
+        for node in &self.nodes {
+            let path = dir.join(&node.file_name);
+            f(&path, node)?;
+            if node.is_dir() {
+                Self::load(objects_root, node.hash)?.walk_in(objects_root, &path, f)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn nodes(&self) -> &Vec<TreeNode> {
+        &self.nodes
+    }
+
     pub fn add_tree_node(&mut self, node: TreeNode) {
         self.nodes.push(node);
     }
 
+    // git orders a directory as if its name ended with '/'
+    pub fn sort_nodes(&mut self) {
+        self.nodes.sort_by_key(|n| match n.is_dir() {
+            true => format!("{}/", n.file_name),
+            false => n.file_name.clone(),
+        });
+        self.hash = None;
+    }
+
     pub fn as_bytes(&self) -> Vec<u8>{
         let body: Vec<u8> = self.nodes.iter().
             map(|x| x.to_bytes()).collect::<Vec<_>>().concat();
@@ -201,6 +601,24 @@ impl Tree {
         depress_zlib(&*self.as_bytes())
     }
 
+    // store as a loose object, the nodes must already be in git's order
+    pub fn write(&self, objects_root: &Path) -> Result<Hash, String> {
+        let body: Vec<u8> = self.nodes.iter().flat_map(|n| n.to_bytes()).collect();
+        write_object(objects_root, ObjType::Tree, &body)
+    }
+
+    // entries in the `ls-tree` format: "<mode> <type> <hash>\t<name>"
+    pub fn pretty_string(&self) -> String {
+        self.nodes.iter().map(|n| {
+            let obj_type = match n.file_type {
+                Directory => ObjType::Tree.name(),
+                Submodule => ObjType::Commit.name(),
+                File | SymbolicLink => ObjType::Blob.name(),
+            };
+            format!("{:0>6} {} {}\t{}\n", n.mode_string(), obj_type, n.hash.string(), n.file_name)
+        }).collect::<Vec<_>>().concat()
+    }
+
 }
 
 #[test]
@@ -225,12 +643,93 @@ fn test_tree_from_bytes() {
             hash: Hash::from_string("68ffd9f1fd447b83f26963cb50155532b00108f1").unwrap(),
         }],
         payload: bytes.to_vec(),
-        hash: Some(Hash::from(&*bytes)),
+        hash: Some(calc_sha1_bytes(&bytes)),
     })
 }
 
-#[derive(PartialEq, Debug)]
-enum FileType {
+#[test]
+fn test_tree_from_bytes_truncated() {
+    let bytes = [116u8, 114, 101, 101, 32, 51, 55, 0,
+        49, 48, 48, 54, 52, 52, 32, 104, 101, 108, 108, 111, 46, 116, 120, 116, 0,
+        59, 24, 229, 18, 219, 167, 158, 76, 131, 0,
+        221, 8, 174, 179, 127, 142, 114, 139, 141, 173];
+    for cut in [bytes.len()-1, bytes.len()-12, 27, 20, 12, 6] {
+        assert_eq!(Tree::from_bytes(&bytes[..cut]), None);
+    }
+
+    let node = bytes[8..bytes.len()-10].to_vec(); // cut off mid-hash
+    assert_eq!(TreeNode::parse(&node), Err("truncated tree node".to_string()));
+}
+
+#[test]
+fn test_tree_walk() {
+    let objects_root = crate::common::test_dir("tree_walk");
+    let blob_hash = Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+    let file = |name: &str| TreeNode{
+        file_type: FileType::File,
+        permission: FilePermission::UnExecutable,
+        file_name: name.to_string(),
+        hash: blob_hash,
+    };
+
+    let mut sub = Tree::new();
+    sub.add_tree_node(file("hello.txt"));
+    let sub_hash = sub.write(&objects_root).unwrap();
+    let mut root = Tree::new();
+    root.add_tree_node(file("a.txt"));
+    root.add_tree_node(TreeNode::from_tree_node(sub_hash, "sub".to_string()).unwrap());
+    root.add_tree_node(file("z.txt"));
+
+    let mut visited = Vec::new();
+    root.walk(&objects_root, &mut |path, node| {
+        visited.push((path.to_path_buf(), node.file_type()));
+        Ok(())
+    }).unwrap();
+    assert_eq!(visited, vec![
+        (std::path::PathBuf::from("a.txt"), FileType::File),
+        (std::path::PathBuf::from("sub"), FileType::Directory),
+        (std::path::PathBuf::from("sub/hello.txt"), FileType::File),
+        (std::path::PathBuf::from("z.txt"), FileType::File),
+    ]);
+
+    let err = root.walk(&objects_root, &mut |path, _| match path.to_str() {
+        Some("sub/hello.txt") => Err("stop".to_string()),
+        _ => Ok(()),
+    });
+    assert_eq!(err, Err("stop".to_string()));
+}
+
+#[test]
+fn test_tree_find() {
+    let objects_root = crate::common::test_dir("tree_find");
+    let blob_hash = Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+
+    let mut sub = Tree::new();
+    sub.add_tree_node(TreeNode{
+        file_type: FileType::File,
+        permission: FilePermission::UnExecutable,
+        file_name: "hello.txt".to_string(),
+        hash: blob_hash,
+    });
+    let sub_hash = sub.calc_hash();
+    let sub_path = objects_root.join(sub_hash.generate_path());
+    fs::create_dir_all(sub_path.parent().unwrap()).unwrap();
+    fs::write(sub_path, sub.generate_depress().unwrap()).unwrap();
+
+    let mut root = Tree::new();
+    root.add_tree_node(TreeNode::from_tree_node(sub_hash, "sub".to_string()).unwrap());
+
+    let out = root.find(&objects_root, Path::new("sub/hello.txt")).unwrap();
+    assert_eq!(out, Some((FileType::File, blob_hash)));
+    let out = root.find(&objects_root, Path::new("sub")).unwrap();
+    assert_eq!(out, Some((FileType::Directory, sub_hash)));
+    assert_eq!(root.find(&objects_root, Path::new("sub/none.txt")).unwrap(), None);
+    assert_eq!(root.find(&objects_root, Path::new("sub/hello.txt/x")).unwrap(), None);
+    assert_eq!(root.find(&objects_root, Path::new("none")).unwrap(), None);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FileType {
     Directory,
     File,
     SymbolicLink,
@@ -260,6 +759,24 @@ impl FileType {
             Submodule => "160".to_string()
         }
     }
+
+    // packed git mode such as 0o100644 or 0o40000 to the type and the permission
+    pub fn from_mode(mode: u32) -> Option<(FileType, FilePermission)> {
+        let file_type = match mode >> 12 {
+            0o04 => Directory,
+            0o10 => File,
+            0o12 => SymbolicLink,
+            0o16 => Submodule,
+            _ => return None,
+        };
+        let permission = match (file_type, mode & 0o7777) {
+            (File, 0o755) => Executable,
+            (File, 0o644) => UnExecutable,
+            (Directory | SymbolicLink | Submodule, 0) => FilePermission::Other,
+            _ => return None,
+        };
+        Some((file_type, permission))
+    }
 }
 
 #[test]
@@ -288,8 +805,29 @@ fn test_filetype_to_code_string() {
     }
 }
 
-#[derive(PartialEq, Debug)]
-enum FilePermission {
+#[test]
+fn test_filetype_from_mode() {
+    let hash = Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap();
+    let tests = [
+        (0o40000, FileType::Directory, FilePermission::Other),
+        (0o100644, FileType::File, FilePermission::UnExecutable),
+        (0o100755, FileType::File, FilePermission::Executable),
+        (0o120000, FileType::SymbolicLink, FilePermission::Other),
+        (0o160000, FileType::Submodule, FilePermission::Other),
+    ];
+    for (mode, file_type, permission) in tests {
+        assert_eq!(FileType::from_mode(mode), Some((file_type, permission.clone())));
+        let node = TreeNode { file_type, permission, file_name: "x".to_string(), hash };
+        assert_eq!(node.mode(), mode);
+        assert_eq!(u32::from_str_radix(&node.mode_string(), 8), Ok(mode));
+    }
+    for mode in [0o100664, 0o100000, 0o40755, 0o170000, 0] {
+        assert_eq!(FileType::from_mode(mode), None);
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum FilePermission {
     Other,
     Executable,
     UnExecutable,
@@ -328,7 +866,7 @@ fn test_permission_from_code_bytes() {
     }
 }
 
-#[derive(PartialEq,Debug)]
+#[derive(PartialEq,Debug,Clone)]
 pub struct TreeNode {
     file_type: FileType,
     permission: FilePermission,
@@ -338,17 +876,10 @@ pub struct TreeNode {
 
 impl TreeNode {
     pub fn from_index_entry(entry: &IndexEntry) -> Option<Self> {
-        let file_type = match entry.file_type() {
-            0b1000=> FileType::File,
-            0b1010=> FileType::SymbolicLink,
-            0b1110=> FileType::Submodule,
-            _ => return None,
-        };
-
-        let permission = match entry.permission() {
-            0b111_101_101=> FilePermission::Executable,
-            0b110_100_100=> FilePermission::UnExecutable,
-            _ => return None,
+        let mode = (entry.file_type() as u32) << 12 | entry.permission() as u32;
+        let (file_type, permission) = match FileType::from_mode(mode)? {
+            (Directory, _) => return None,
+            t => t,
         };
 
         let file_name = entry.file_name();
@@ -362,6 +893,12 @@ impl TreeNode {
         })
     }
 
+    // an entry of any type from its packed mode such as 0o100644, None for a mode git doesn't store
+    pub fn from_mode(mode: u32, file_name: String, hash: Hash) -> Option<Self> {
+        let (file_type, permission) = FileType::from_mode(mode)?;
+        Some(Self { file_type, permission, file_name, hash })
+    }
+
     pub fn from_tree_node(hash: Hash, dir_name: String) -> Option<Self> {
         let file_type = FileType::Directory;
         let permission = FilePermission::Other;
@@ -373,19 +910,62 @@ impl TreeNode {
         })
     }
 
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.hash
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.permission == Executable
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == Directory
+    }
+
+    // mode as stored in a tree, e.g. "100644", "40000" for a directory
+    pub fn mode_string(&self) -> String {
+        format!("{}{}", self.file_type.to_code_string(), self.permission.to_code_string())
+    }
+
+    // packed git mode, e.g. 0o100644, the low bits are the unix permission for files
+    pub fn mode(&self) -> u32 {
+        match self.file_type {
+            Directory => 0o40000,
+            File if self.is_executable() => 0o100755,
+            File => 0o100644,
+            SymbolicLink => 0o120000,
+            Submodule => 0o160000,
+        }
+    }
+
     fn parse(bytes: &Vec<u8>) -> Result<(Self, usize),String> {
+        let truncated = || "truncated tree node".to_string();
+
         let file_type = FileType::from_code_bytes(bytes)?;
         let mut offset = file_type.to_code_string().len();
-        let pos = &bytes[offset..];
+        let pos = bytes.get(offset..).ok_or_else(truncated)?;
 
         let permission = FilePermission::from_code_bytes(&pos.to_vec())?;
         offset += permission.to_code_string().len()+1;
 
-        let pos = &bytes[offset..];
-        let file_name = String::from_utf8(extract_until_null(pos.as_ref())).
+        let pos = bytes.get(offset..).ok_or_else(truncated)?;
+        let file_name_bytes = extract_until_null(pos);
+        if file_name_bytes.len() == pos.len() {
+            return Err(truncated());
+        }
+        let file_name = String::from_utf8(file_name_bytes).
             map_err(|e| e.to_string())?;
         offset += file_name.len()+1;
-        let hash = Hash::from(&bytes[offset..offset+20]).
+        let hash_bytes = bytes.get(offset..offset+20).ok_or_else(truncated)?;
+        let hash = Hash::from(hash_bytes).
                 ok_or("invalid hash value".to_string())?;
 
         offset += 20;
@@ -410,6 +990,23 @@ impl TreeNode {
 
 }
 
+#[test]
+fn test_tree_node_accessors() {
+    let hash = Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap();
+    let file = TreeNode {
+        file_type: FileType::File,
+        permission: FilePermission::Executable,
+        file_name: "run.sh".to_string(),
+        hash,
+    };
+    assert_eq!((file.file_name(), file.hash(), file.mode_string().as_str()), ("run.sh", hash, "100755"));
+    assert!(file.is_executable() && !file.is_dir());
+
+    let dir = TreeNode::from_tree_node(hash, "sub".to_string()).unwrap();
+    assert_eq!(dir.mode_string(), "40000");
+    assert!(dir.is_dir() && !dir.is_executable());
+}
+
 #[test]
 fn test_parse_tree_node() {
     let a = vec![49u8, 48, 48, 54, 52, 52, 32, 104, 97, 115, 32, 115, 112, 97, 99, 101, 46, 116,
@@ -438,13 +1035,10 @@ fn test_create_bytes_tree_node() {
     let refer = vec![49u8, 48, 48, 54, 52, 52, 32, 104, 97, 115, 32, 115, 112, 97, 99, 101, 46, 116,
         120, 116, 0, 6, 74, 146, 215, 131, 249, 152, 81, 209, 81, 123, 81, 186, 11, 42, 237, 74,
         29, 49, 40];
-    match source.create_bytes() {
-        Some(s) => assert_eq!(s, refer),
-        None => assert!(false)
-    }
+    assert_eq!(source.to_bytes(), refer);
 }
 
-#[derive(PartialEq,Debug,Default)]
+#[derive(PartialEq,Debug,Default,Clone)]
 pub struct Commit {
     obj_type: ObjType,
     tree: Hash,
@@ -452,6 +1046,8 @@ pub struct Commit {
     author: CommitUser, //originalのコミッター（amendではデフォルトでは変更されない）
     committer: CommitUser, //コミッター（amendで変更される）
     commit_message: String,
+    // gpgsig header, continuation lines without the leading space
+    signature: Option<String>,
 }
 
 impl Commit {
@@ -471,10 +1067,23 @@ impl Commit {
             author: Default::default(),
             committer: Default::default(),
             commit_message: "".to_string(),
+            signature: None,
         };
         let mut idx: usize = 0;
+        let mut in_signature = false;
         for line in &lines {
-            if line.starts_with("tree") {
+            if let Some(rest) = line.strip_prefix(' ').filter(|_| in_signature) {
+                let signature = commit.signature.as_mut().unwrap();
+                signature.push('\n');
+                signature.push_str(rest);
+                idx+=1;
+                continue;
+            }
+            in_signature = false;
+            if let Some(signature) = line.strip_prefix("gpgsig ") {
+                commit.signature = Some(signature.to_string());
+                in_signature = true;
+            } else if line.starts_with("tree") {
                 commit.tree = Hash::from_string(&line["tree ".len()..])?;
             } else if line.starts_with("parent") {
                 commit.parents.push(Hash::from_string(&line["parent ".len()..])?);
@@ -498,6 +1107,11 @@ impl Commit {
         Self::from_bytes(&extract_zlib(bytes).ok()?)
     }
 
+    pub fn load(objects_root: &Path, hash: Hash) -> Result<Self, String> {
+        let bytes = read_raw_object(objects_root, hash)?;
+        Self::from_bytes(&bytes).ok_or(format!("parse commit error: {}", hash.string()))
+    }
+
     pub fn from(tree_root: Hash, parents: Vec<Hash>, author: CommitUser, committer: CommitUser,
                 message: String) -> Self {
         Self {
@@ -506,26 +1120,35 @@ impl Commit {
             parents,
             author,
             committer,
-            commit_message: message
+            commit_message: message,
+            signature: None,
         }
     }
 
 
     pub fn to_bytes(&self) -> Vec<u8>{
         let body = if self.parents.len() == 0 {
-            format!("tree {}\n{}\n{}\n\n{}", self.tree.string(),
-                           self.author.to_string(), self.committer.to_string(), self.commit_message)
+            format!("tree {}\n{}\n{}\n{}\n{}", self.tree.string(),
+                           self.author.to_string(), self.committer.to_string(), self.signature_header(),
+                           self.commit_message)
         } else {
             let parents_str: Vec<String> =self.parents.clone().into_iter().
                 map(|x| format!("parent {}", x.string())).collect();
             let parents_concat: String = parents_str.join("\n");
-            format!("tree {}\n{}\n{}\n{}\n\n{}\n", self.tree.string(), parents_concat,
-                           self.author.to_string(), self.committer.to_string(), self.commit_message)
+            format!("tree {}\n{}\n{}\n{}\n{}\n{}", self.tree.string(), parents_concat,
+                           self.author.to_string(), self.committer.to_string(), self.signature_header(),
+                           self.commit_message)
         };
 
         format!("commit {}\0{}", body.len(), body).into_bytes()
     }
 
+    pub fn write(&self, objects_root: &Path) -> Result<Hash, String> {
+        let bytes = self.to_bytes();
+        let body = &bytes[bytes.iter().position(|b| *b == 0).unwrap()+1..];
+        write_object(objects_root, ObjType::Commit, body)
+    }
+
     pub fn generate_hash_and_depress(&self) -> Result<(Hash, Vec<u8>),String> {
         let bytes = self.to_bytes();
         let hash = calc_sha1_bytes(&bytes);
@@ -533,27 +1156,109 @@ impl Commit {
         Ok((hash, body))
     }
 
+    pub fn tree(&self) -> Hash {
+        self.tree
+    }
+
     pub fn parents(&self) -> &Vec<Hash> {
         &self.parents
     }
 
+    // tree of the first parent to diff against, the empty tree for a root commit
+    pub fn parent_tree(&self, objects_root: &Path) -> Result<Hash, String> {
+        match self.parents.first() {
+            Some(parent) => Ok(Commit::load(objects_root, *parent)?.tree()),
+            None => Ok(empty_tree()),
+        }
+    }
+
     pub fn log_entry(&self,hash: Hash, refs: &Vec<String>) -> String {
         let refs_string = if refs.len() == 0 {
             String::new()
         } else {
-            format!("({})", refs.join(" "))
+            format!(" ({})", refs.join(", "))
         };
         let message =
-            format!("    {}", self.commit_message.replace("\n", "\n    "));
+            format!("    {}", self.commit_message.trim_end_matches('\n').replace("\n", "\n    "));
 
-        format!("commit {} {}\nAuthor: {} <{}>\nDate:   {}\n\n{}\n",
+        format!("commit {}{}\nAuthor: {} <{}>\nDate:   {}\n\n{}\n",
                     hash.string(), refs_string, self.author.name, self.author.address,
                     self.author.time_stamp.format("%c %z").to_string(), message)
     }
 
+    // `log --pretty=raw`: the stored headers as is, the message indented
+    pub fn raw_log_entry(&self, hash: Hash) -> String {
+        let parents: String = self.parents.iter().map(|p| format!("parent {}\n", p.string())).collect();
+        let message =
+            format!("    {}", self.commit_message.trim_end_matches('\n').replace("\n", "\n    "));
+        format!("commit {}\ntree {}\n{}{}\n{}\n{}\n{}\n", hash.string(), self.tree.string(), parents,
+                self.author.to_string(), self.committer.to_string(), self.signature_header(), message)
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    // "gpgsig <block>\n" with continuation lines indented, empty when unsigned
+    fn signature_header(&self) -> String {
+        match &self.signature {
+            Some(signature) => format!("gpgsig {}\n", signature.replace("\n", "\n ")),
+            None => String::new(),
+        }
+    }
+
     pub fn timestamp(&self) -> DateTime<FixedOffset> {
         self.author.time_stamp
     }
+
+    pub fn author(&self) -> &CommitUser {
+        &self.author
+    }
+
+    // for display only, the hash of the result is not that of the commit any more
+    pub fn change_author_as(&self, author: CommitUser) -> Self {
+        let mut ret = self.clone();
+        ret.author = author;
+        ret
+    }
+
+    pub fn committer(&self) -> &CommitUser {
+        &self.committer
+    }
+
+    pub fn message(&self) -> &str {
+        &self.commit_message
+    }
+}
+
+#[test]
+fn test_raw_log_entry() {
+    let time_stamp = FixedOffset::east(9*3600).timestamp(1633332967, 0);
+    let author = CommitUser::from("a".to_string(), "a@example.com".to_string(), Author).
+        change_timestamp_as(time_stamp);
+    let committer = author.change_committer_type_as(Committer);
+    let parent = Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap();
+    let commit = Commit::from(Hash::default(), vec![parent], author, committer, "title\n\nbody\n".to_string());
+    assert_eq!(commit.raw_log_entry(parent),
+               format!("commit {}\ntree {}\nparent {}\n\
+                        author a <a@example.com> 1633332967 +0900\n\
+                        committer a <a@example.com> 1633332967 +0900\n\n    title\n    \n    body\n",
+                       parent.string(), Hash::default().string(), parent.string()));
+}
+
+#[test]
+fn test_commit_signature() {
+    let body = "tree 8adf368f15374ae6b3a3db077178797d0cf1bc12\n\
+                author a <a@example.com> 1633332967 +0900\n\
+                committer a <a@example.com> 1633332967 +0900\n\
+                gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEz\n -----END PGP SIGNATURE-----\n\n\
+                signed\n";
+    let bytes = format!("commit {}\0{}", body.len(), body).into_bytes();
+    let commit = Commit::from_bytes(&bytes).unwrap();
+    assert_eq!(commit.signature(), Some("-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----"));
+    assert_eq!(commit.commit_message, "signed\n");
+    // the signature survives a rewrite, so the hash stays the same
+    assert_eq!(commit.to_bytes(), bytes);
 }
 
 #[test]
@@ -590,6 +1295,7 @@ fn test_commit_from() {
             time_stamp: FixedOffset::east(9*3600).timestamp(1633332967, 0)
         },
         commit_message: "multiple\nlines\n".to_string(),
+        signature: None,
     });
 }
 
@@ -609,7 +1315,7 @@ fn test_commit_to_bytes() {
                      103, 109, 97, 105, 108, 46, 99, 111, 109, 62, 32, 49, 54, 51, 51, 51, 51, 50,
                      57, 54, 55, 32, 43, 48, 57, 48, 48, 10, 10, 109, 117, 108, 116, 105, 112, 108,
                      101, 10, 108, 105, 110, 101, 115, 10);
-    let mut out = Commit::from_bytes(&input).expect("error");
+    let out = Commit::from_bytes(&input).expect("error");
     let back = out.to_bytes();
     assert_eq!(input, back);
 }
@@ -660,24 +1366,42 @@ impl CommitUser {
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.address
+    }
+
+    pub fn timestamp(&self) -> DateTime<FixedOffset> {
+        self.time_stamp
+    }
+
     pub fn change_committer_type_as(& self, committer_type: CommitterType) -> Self {
         let mut ret = self.clone();
         ret.committer_type = committer_type;
         ret
     }
 
+    pub fn change_timestamp_as(&self, time_stamp: DateTime<FixedOffset>) -> Self {
+        let mut ret = self.clone();
+        ret.time_stamp = time_stamp;
+        ret
+    }
+
+    pub fn change_identity_as(&self, name: String, address: String) -> Self {
+        let mut ret = self.clone();
+        ret.name = name;
+        ret.address = address;
+        ret
+    }
+
     pub fn to_string(&self) -> String {
         let timestamp = self.time_stamp.timestamp();
         let timezone = self.time_stamp.timezone().local_minus_utc();
-        let timezone_hour = timezone/3600;
-        let timezone_min = (timezone%3600)/60;
-        let timezone_str = if timezone > 0 {
-            format!("+{:<02}{:<02}", timezone_hour, timezone_min)
-        } else if timezone < 0 {
-            format!("-{:<02}{:<02}", timezone_hour, timezone_min)
-        } else {
-            "0000".to_string()
-        };
+        let sign = if timezone < 0 { '-' } else { '+' };
+        let timezone_str = format!("{}{:02}{:02}", sign, timezone.abs()/3600, (timezone.abs()%3600)/60);
         format!("{} {} <{}> {} {}", self.committer_type.to_code_string(),
                 self.name, self.address, timestamp, timezone_str)
     }
@@ -736,6 +1460,40 @@ fn calc_time_offset(time: &str)-> Option<i32> {
     }
 }
 
+// "@<unix> <tz>", "<unix> <tz>", "YYYY-MM-DD HH:MM:SS +ZZZZ", ISO 8601 or RFC 2822
+pub fn parse_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Some((unix, tz)) = date.strip_prefix('@').unwrap_or(date).split_once(' ') {
+        if let (Ok(unix), Some(offset)) = (unix.parse::<i64>(), calc_time_offset(tz.trim())) {
+            if tz.trim().starts_with(['+', '-']) {
+                return Some(FixedOffset::east(offset).timestamp(unix, 0));
+            }
+        }
+    }
+    DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z").
+        or_else(|_| DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%z")).
+        or_else(|_| DateTime::parse_from_rfc2822(date)).ok()
+}
+
+#[test]
+fn test_parse_date() {
+    let expected = FixedOffset::east(9*3600).timestamp(1633332967, 0);
+    for date in ["@1633332967 +0900", "1633332967 +0900", "2021-10-04 16:36:07 +0900",
+                 "2021-10-04T16:36:07+09:00", "Mon, 04 Oct 2021 16:36:07 +0900"] {
+        assert_eq!(parse_date(date), Some(expected), "{}", date);
+    }
+    assert_eq!(parse_date("@0 -0130").unwrap().offset().local_minus_utc(), -5400);
+    assert_eq!(parse_date("yesterday"), None);
+}
+
+#[test]
+fn test_commit_user_timezone() {
+    let user = CommitUser::from("a".to_string(), "a@example.com".to_string(), Author);
+    for (offset, tz) in [(0, "+0000"), (9*3600, "+0900"), (-(5*3600+30*60), "-0530")] {
+        let user = user.change_timestamp_as(FixedOffset::east(offset).timestamp(0, 0));
+        assert_eq!(user.to_string(), format!("author a <a@example.com> 0 {}", tz));
+    }
+}
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum CommitterType {
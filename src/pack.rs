@@ -0,0 +1,623 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+use crate::common::bytes_to_u32;
+use crate::hash::{Hash, calc_sha1_bytes};
+use crate::object::{extract_zlib_stream, object_type, read_object, ObjType};
+
+// Pack format https://github.com/git/git/blob/v2.12.0/Documentation/technical/pack-format.txt
+pub struct Pack {
+    data: Vec<u8>,
+    object_num: u32,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PackEntryKind {
+    Base(ObjType),
+    OfsDelta(usize),
+    RefDelta(Hash),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PackEntry {
+    pub offset: usize,
+    pub kind: PackEntryKind,
+    pub data: Vec<u8>,
+}
+
+impl Pack {
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, String> {
+        if data.len() < 32 || !data.starts_with("PACK".as_bytes()) {
+            return Err("not a pack file".to_string());
+        }
+        let version = bytes_to_u32(&data[4..8]).unwrap();
+        if version != 2 && version != 3 {
+            return Err(format!("unsupported pack version: {}", version));
+        }
+        let object_num = bytes_to_u32(&data[8..12]).unwrap();
+
+        let (body, trailer) = data.split_at(data.len()-20);
+        if calc_sha1_bytes(body).bytes() != trailer {
+            return Err("pack checksum mismatch".to_string());
+        }
+
+        Ok(Self {
+            data,
+            object_num,
+        })
+    }
+
+    // random access to a pack whose checksum is not verified
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        if data.len() < 32 || !data.starts_with("PACK".as_bytes()) {
+            return Err(format!("not a pack file: {}", path.display()));
+        }
+        let object_num = bytes_to_u32(&data[8..12]).unwrap();
+        Ok(Self {
+            data,
+            object_num,
+        })
+    }
+
+    // object at the offset with its delta chain applied
+    pub fn read_object_at(&self, offset: usize, objects_root: &Path) -> Result<(ObjType, Vec<u8>), String> {
+        let (entry, _) = self.read_entry(offset)?;
+        match entry.kind {
+            PackEntryKind::Base(obj_type) => Ok((obj_type, entry.data)),
+            PackEntryKind::OfsDelta(base_offset) => {
+                let (obj_type, base) = self.read_object_at(base_offset, objects_root)?;
+                Ok((obj_type, apply_delta(&base, &entry.data)?))
+            },
+            PackEntryKind::RefDelta(base_hash) => {
+                let (obj_type, base) = read_object(objects_root, base_hash)?;
+                Ok((obj_type, apply_delta(&base, &entry.data)?))
+            },
+        }
+    }
+
+    pub fn object_num(&self) -> u32 {
+        self.object_num
+    }
+
+    pub fn entries(&self) -> Result<Vec<PackEntry>, String> {
+        let mut ret = Vec::new();
+        let mut offset = 12;
+        for _ in 0..self.object_num {
+            let (entry, next) = self.read_entry(offset)?;
+            ret.push(entry);
+            offset = next;
+        }
+        if offset != self.data.len()-20 {
+            return Err("garbage at the end of pack file".to_string());
+        }
+        Ok(ret)
+    }
+
+    // entry at the offset and the offset of the next one
+    pub fn read_entry(&self, offset: usize) -> Result<(PackEntry, usize), String> {
+        let truncated = || "truncated pack file".to_string();
+        let body = &self.data[..self.data.len()-20];
+
+        // type and size: 1xxxyyyy 1yyyyyyy ... 0yyyyyyy
+        let mut pos = offset;
+        let mut byte = *body.get(pos).ok_or_else(truncated)?;
+        pos += 1;
+        let type_code = (byte >> 4) & 0b111;
+        let mut size = (byte & 0b1111) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            if shift >= usize::BITS {
+                return Err(format!("object size overflow at {}", offset));
+            }
+            byte = *body.get(pos).ok_or_else(truncated)?;
+            pos += 1;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let kind = match type_code {
+            1 => PackEntryKind::Base(ObjType::Commit),
+            2 => PackEntryKind::Base(ObjType::Tree),
+            3 => PackEntryKind::Base(ObjType::Blob),
+            4 => PackEntryKind::Base(ObjType::Tag),
+            6 => {
+                // offset of the base, counted backwards from this entry
+                let mut byte = *body.get(pos).ok_or_else(truncated)?;
+                pos += 1;
+                let mut base = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    byte = *body.get(pos).ok_or_else(truncated)?;
+                    pos += 1;
+                    let next = base.checked_add(1).filter(|b| b.leading_zeros() >= 7).
+                        ok_or("invalid delta base offset".to_string())?;
+                    base = (next << 7) | (byte & 0x7f) as usize;
+                }
+                // the base comes strictly before, an entry can not be its own base
+                PackEntryKind::OfsDelta(offset.checked_sub(base).filter(|_| base > 0).
+                    ok_or("invalid delta base offset".to_string())?)
+            },
+            7 => {
+                let hash = Hash::from(body.get(pos..pos+20).ok_or_else(truncated)?).unwrap();
+                pos += 20;
+                PackEntryKind::RefDelta(hash)
+            },
+            _ => return Err(format!("invalid object type {} in pack", type_code)),
+        };
+
+        let (data, consumed) = extract_zlib_stream(&body[pos..])?;
+        if data.len() != size {
+            return Err(format!("size mismatch of the object at {}", offset));
+        }
+        Ok((PackEntry { offset, kind, data }, pos+consumed))
+    }
+}
+
+impl Pack {
+    // every object in the pack with deltas applied. `lookup` provides bases
+    // which are not in the pack itself (thin packs)
+    pub fn objects(&self, lookup: &dyn Fn(Hash) -> Option<(ObjType, Vec<u8>)>)
+        -> Result<Vec<(Hash, ObjType, Vec<u8>)>, String> {
+        let entries = self.entries()?;
+        let by_offset: HashMap<usize, usize> = entries.iter().enumerate().
+            map(|(i, e)| (e.offset, i)).collect();
+        let mut by_hash: BTreeMap<Hash, usize> = BTreeMap::new();
+        // reconstructed objects, also used as the cache of delta bases
+        let mut resolved: Vec<Option<(Hash, ObjType, Vec<u8>)>> = vec![None; entries.len()];
+
+        let mut remaining = entries.len();
+        while remaining > 0 {
+            let mut progress = false;
+            for (i, entry) in entries.iter().enumerate() {
+                if resolved[i].is_some() {
+                    continue;
+                }
+                let object = match &entry.kind {
+                    PackEntryKind::Base(obj_type) => Some((*obj_type, entry.data.clone())),
+                    PackEntryKind::OfsDelta(base_offset) => {
+                        let base = by_offset.get(base_offset).
+                            ok_or(format!("delta base not found at {}", base_offset))?;
+                        match &resolved[*base] {
+                            Some((_, obj_type, data)) => Some((*obj_type, apply_delta(data, &entry.data)?)),
+                            None => None,
+                        }
+                    },
+                    PackEntryKind::RefDelta(base_hash) => {
+                        let base = match by_hash.get(base_hash) {
+                            Some(base) => resolved[*base].as_ref().map(|r| (r.1, r.2.clone())),
+                            None => lookup(*base_hash),
+                        };
+                        match base {
+                            Some((obj_type, data)) => Some((obj_type, apply_delta(&data, &entry.data)?)),
+                            None => None,
+                        }
+                    },
+                };
+                if let Some((obj_type, data)) = object {
+                    let hash = object_hash(obj_type, &data);
+                    by_hash.insert(hash, i);
+                    resolved[i] = Some((hash, obj_type, data));
+                    remaining -= 1;
+                    progress = true;
+                }
+            }
+            if !progress {
+                return Err("could not resolve delta bases".to_string());
+            }
+        }
+
+        Ok(resolved.into_iter().map(|r| r.unwrap()).collect())
+    }
+}
+
+// look the object up in the .idx files of .git/objects/pack
+pub fn read_packed_object(objects_root: &Path, hash: Hash) -> Result<Option<(ObjType, Vec<u8>)>, String> {
+    match find_packed(objects_root, hash)? {
+        Some((pack, offset)) => pack.read_object_at(offset, objects_root).map(Some),
+        None => Ok(None),
+    }
+}
+
+// type of a packed object, following the delta chain without applying the deltas
+pub fn packed_object_type(objects_root: &Path, hash: Hash) -> Result<Option<ObjType>, String> {
+    let (pack, mut offset) = match find_packed(objects_root, hash)? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    loop {
+        match pack.read_entry(offset)?.0.kind {
+            PackEntryKind::Base(obj_type) => return Ok(Some(obj_type)),
+            PackEntryKind::OfsDelta(base_offset) => offset = base_offset,
+            PackEntryKind::RefDelta(base_hash) => return object_type(objects_root, base_hash).map(Some),
+        }
+    }
+}
+
+pub fn packed_object_exists(objects_root: &Path, hash: Hash) -> Result<bool, String> {
+    Ok(find_packed_offset(objects_root, hash)?.is_some())
+}
+
+fn find_packed(objects_root: &Path, hash: Hash) -> Result<Option<(Rc<Pack>, usize)>, String> {
+    match find_packed_offset(objects_root, hash)? {
+        Some((idx_path, offset)) => Ok(Some((load_pack(&idx_path)?, offset))),
+        None => Ok(None),
+    }
+}
+
+// the .idx file listing the object and its offset in the pack
+fn find_packed_offset(objects_root: &Path, hash: Hash) -> Result<Option<(PathBuf, usize)>, String> {
+    for path in idx_files(objects_root)? {
+        if let Some(offset) = find_in_idx(&load_idx(&path)?, hash)? {
+            return Ok(Some((path, offset)));
+        }
+    }
+    Ok(None)
+}
+
+/*
+    .idx files and their packs already read in this run, so a history walk does not read them per object
+    an .idx rewritten in place (by a test, or a repack reusing the name) is told by its size and mtime
+ */
+struct LoadedIdx {
+    stamp: (u64, Option<SystemTime>),
+    idx: Rc<Vec<u8>>,
+    pack: Option<Rc<Pack>>,
+}
+
+thread_local! {
+    static PACK_CACHE: RefCell<HashMap<PathBuf, LoadedIdx>> = RefCell::new(HashMap::new());
+}
+
+fn load_idx(path: &Path) -> Result<Rc<Vec<u8>>, String> {
+    let meta = fs::metadata(path).map_err(|e| e.to_string())?;
+    let stamp = (meta.len(), meta.modified().ok());
+    let cached = PACK_CACHE.with(|c| c.borrow().get(path).
+        filter(|loaded| loaded.stamp == stamp).map(|loaded| loaded.idx.clone()));
+    if let Some(idx) = cached {
+        return Ok(idx);
+    }
+    let idx = Rc::new(fs::read(path).map_err(|e| e.to_string())?);
+    PACK_CACHE.with(|c| c.borrow_mut().insert(path.to_path_buf(), LoadedIdx { stamp, idx: idx.clone(), pack: None }));
+    Ok(idx)
+}
+
+// the pack next to the .idx, which load_idx has read already
+fn load_pack(idx_path: &Path) -> Result<Rc<Pack>, String> {
+    let cached = PACK_CACHE.with(|c| c.borrow().get(idx_path).and_then(|loaded| loaded.pack.clone()));
+    if let Some(pack) = cached {
+        return Ok(pack);
+    }
+    let pack = Rc::new(Pack::open(&idx_path.with_extension("pack"))?);
+    PACK_CACHE.with(|c| if let Some(loaded) = c.borrow_mut().get_mut(idx_path) {
+        loaded.pack = Some(pack.clone());
+    });
+    Ok(pack)
+}
+
+fn idx_files(objects_root: &Path) -> Result<Vec<PathBuf>, String> {
+    let pack_dir = objects_root.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut ret = Vec::new();
+    for entry in fs::read_dir(pack_dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.extension() == Some("idx".as_ref()) {
+            ret.push(path);
+        }
+    }
+    Ok(ret)
+}
+
+// pack index version 2: magic, version, fan-out table, hashes, crc32s, offsets, large offsets
+fn find_in_idx(idx: &[u8], hash: Hash) -> Result<Option<usize>, String> {
+    let broken = || "broken pack index".to_string();
+    let (num, mut lo, mut hi) = idx_range(idx, hash.bytes()[0])?;
+
+    let hashes = 8 + 256*4;
+    while lo < hi {
+        let mid = (lo+hi)/2;
+        let current = idx.get(hashes+mid*20..hashes+mid*20+20).ok_or_else(broken)?;
+        match current.cmp(&hash.bytes()[..]) {
+            std::cmp::Ordering::Less => lo = mid+1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => {
+                let offsets = hashes + num*24;
+                let offset = bytes_to_u32(idx.get(offsets+mid*4..offsets+mid*4+4).ok_or_else(broken)?).unwrap();
+                if offset & 0x8000_0000 == 0 {
+                    return Ok(Some(offset as usize));
+                }
+                let large = offsets + num*4 + (offset & 0x7fff_ffff) as usize * 8;
+                let high = bytes_to_u32(idx.get(large..large+4).ok_or_else(broken)?).unwrap() as u64;
+                let low = bytes_to_u32(idx.get(large+4..large+8).ok_or_else(broken)?).unwrap() as u64;
+                return Ok(Some(((high << 32) | low) as usize));
+            },
+        }
+    }
+    Ok(None)
+}
+
+// the number of objects and the range of those starting with the byte, from the fan-out table
+fn idx_range(idx: &[u8], first: u8) -> Result<(usize, usize, usize), String> {
+    let broken = || "broken pack index".to_string();
+    if !idx.starts_with(&[0xff, b't', b'O', b'c']) || bytes_to_u32(idx.get(4..8).ok_or_else(broken)?) != Some(2) {
+        return Err("unsupported pack index version".to_string());
+    }
+    let fanout = |i: usize| -> Result<usize, String> {
+        Ok(bytes_to_u32(idx.get(8+i*4..12+i*4).ok_or_else(broken)?).unwrap() as usize)
+    };
+    let lo = if first == 0 { 0 } else { fanout(first as usize - 1)? };
+    Ok((fanout(255)?, lo, fanout(first as usize)?))
+}
+
+// packed objects whose hash starts with the hex prefix of at least two digits
+pub fn find_packed_by_prefix(objects_root: &Path, prefix: &str) -> Result<Vec<Hash>, String> {
+    let first = u8::from_str_radix(&prefix[..2], 16).map_err(|e| e.to_string())?;
+    let mut ret = Vec::new();
+    for idx_path in idx_files(objects_root)? {
+        let idx = load_idx(&idx_path)?;
+        let (_, lo, hi) = idx_range(&idx, first)?;
+        let hashes = 8 + 256*4;
+        for i in lo..hi {
+            let hash = idx.get(hashes+i*20..hashes+i*20+20).and_then(Hash::from).
+                ok_or("broken pack index".to_string())?;
+            if hash.string().starts_with(prefix) {
+                ret.push(hash);
+            }
+        }
+    }
+    Ok(ret)
+}
+
+fn object_hash(obj_type: ObjType, data: &[u8]) -> Hash {
+    let mut raw = format!("{} {}\0", obj_type.name(), data.len()).into_bytes();
+    raw.extend_from_slice(data);
+    calc_sha1_bytes(&raw)
+}
+
+fn read_varint(delta: &[u8], pos: &mut usize) -> Result<usize, String> {
+    let mut ret = 0;
+    let mut shift = 0;
+    loop {
+        if shift >= usize::BITS {
+            return Err("delta size overflow".to_string());
+        }
+        let byte = *delta.get(*pos).ok_or("truncated delta".to_string())?;
+        *pos += 1;
+        ret |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+}
+
+// delta: <base size> <result size> followed by copy and insert instructions
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = || "truncated delta".to_string();
+    let mut pos = 0;
+    if read_varint(delta, &mut pos)? != base.len() {
+        return Err("delta base size mismatch".to_string());
+    }
+    let size = read_varint(delta, &mut pos)?;
+
+    let mut ret = Vec::with_capacity(size);
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // copy: bits 0-3 tell which offset bytes follow, bits 4-6 the size bytes
+            let mut offset = 0usize;
+            let mut len = 0usize;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8*i);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (1 << (4+i)) != 0 {
+                    len |= (*delta.get(pos).ok_or_else(truncated)? as usize) << (8*i);
+                    pos += 1;
+                }
+            }
+            if len == 0 {
+                len = 0x10000;
+            }
+            ret.extend_from_slice(base.get(offset..offset+len).
+                ok_or("delta copies out of the base".to_string())?);
+        } else if op != 0 {
+            // insert the following op bytes
+            let len = op as usize;
+            ret.extend_from_slice(delta.get(pos..pos+len).ok_or_else(truncated)?);
+            pos += len;
+        } else {
+            return Err("invalid delta instruction".to_string());
+        }
+    }
+
+    if ret.len() != size {
+        return Err("delta result size mismatch".to_string());
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_apply_delta() {
+    let base = "hello world\n".as_bytes();
+    // copy "hello " (offset 0, size 6), insert "git", copy "\n" (offset 11, size 1)
+    let delta = vec![12u8, 10, 0x90, 6, 3, b'g', b'i', b't', 0x91, 11, 1];
+    assert_eq!(apply_delta(base, &delta).unwrap(), "hello git\n".as_bytes());
+
+    let wrong_base = vec![13u8, 10, 0x90, 6];
+    assert!(apply_delta(base, &wrong_base).is_err());
+    let out_of_base = vec![12u8, 10, 0x91, 8, 10];
+    assert!(apply_delta(base, &out_of_base).is_err());
+    let overflow = vec![0xffu8; 12];
+    assert_eq!(apply_delta(base, &overflow).err(), Some("delta size overflow".to_string()));
+}
+
+#[cfg(test)]
+pub fn build_pack(entries: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use crate::common::u32_to_bytes;
+
+    let mut data = Vec::from("PACK".as_bytes());
+    data.append(&mut u32_to_bytes(2));
+    data.append(&mut u32_to_bytes(entries.len() as u32));
+    for (type_code, body) in entries {
+        // body of a delta entry starts with its base reference
+        let (base, content) = match type_code {
+            6 => body.split_at(body.iter().position(|b| b & 0x80 == 0).unwrap()+1),
+            7 => body.split_at(20),
+            _ => body.split_at(0),
+        };
+        let mut size = content.len();
+        let mut byte = (type_code << 4) | (size & 0b1111) as u8;
+        size >>= 4;
+        while size > 0 {
+            data.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        data.push(byte);
+        data.extend_from_slice(base);
+        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+        e.write_all(content).unwrap();
+        data.append(&mut e.finish().unwrap());
+    }
+    let checksum = calc_sha1_bytes(&data);
+    data.extend_from_slice(&checksum.bytes());
+    data
+}
+
+#[test]
+fn test_pack_entries() {
+    let long = "a".repeat(300).into_bytes();
+    let data = build_pack(&[(3, "hello\n".as_bytes().to_vec()), (3, long.clone())]);
+    let pack = Pack::from_bytes(data.clone()).unwrap();
+    let entries = pack.entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].kind, PackEntryKind::Base(ObjType::Blob));
+    assert_eq!(entries[0].data, "hello\n".as_bytes());
+    assert_eq!(entries[1].data, long);
+
+    let mut broken = data;
+    broken[14] ^= 1;
+    assert_eq!(Pack::from_bytes(broken).err(), Some("pack checksum mismatch".to_string()));
+}
+
+#[test]
+fn test_pack_objects_with_ref_delta() {
+    let base = "hello world\n".as_bytes().to_vec();
+    let base_hash = object_hash(ObjType::Blob, &base);
+    let mut delta = base_hash.bytes().to_vec();
+    delta.append(&mut vec![12u8, 10, 0x90, 6, 3, b'g', b'i', b't', 0x91, 11, 1]);
+
+    // the delta comes before its base
+    let pack = Pack::from_bytes(build_pack(&[(7, delta.clone()), (3, base.clone())])).unwrap();
+    let out = pack.objects(&|_| None).unwrap();
+    assert_eq!(out[0], (object_hash(ObjType::Blob, "hello git\n".as_bytes()), ObjType::Blob,
+                        "hello git\n".as_bytes().to_vec()));
+    assert_eq!(out[1], (base_hash, ObjType::Blob, base.clone()));
+
+    // base outside of the pack
+    let thin = Pack::from_bytes(build_pack(&[(7, delta)])).unwrap();
+    assert!(thin.objects(&|_| None).is_err());
+    let out = thin.objects(&|h| if h == base_hash { Some((ObjType::Blob, base.clone())) } else { None });
+    assert_eq!(out.unwrap()[0].2, "hello git\n".as_bytes());
+}
+
+#[test]
+fn test_pack_objects_with_ofs_delta() {
+    let base = "hello world\n".as_bytes().to_vec();
+    let delta = vec![12u8, 10, 0x90, 6, 3, b'g', b'i', b't', 0x91, 11, 1];
+    // the delta entry starts where the base-only pack ends its objects
+    let delta_offset = build_pack(&[(3, base.clone())]).len() - 20;
+    let mut body = vec![(delta_offset - 12) as u8];
+    body.extend_from_slice(&delta);
+
+    let pack = Pack::from_bytes(build_pack(&[(3, base.clone()), (6, body)])).unwrap();
+    let entries = pack.entries().unwrap();
+    assert_eq!(entries[1].offset, delta_offset);
+    assert_eq!(entries[1].kind, PackEntryKind::OfsDelta(12));
+    let out = pack.objects(&|_| None).unwrap();
+    assert_eq!(out[1], (object_hash(ObjType::Blob, "hello git\n".as_bytes()), ObjType::Blob,
+                        "hello git\n".as_bytes().to_vec()));
+    assert_eq!(pack.read_object_at(delta_offset, Path::new("")).unwrap(),
+               (ObjType::Blob, "hello git\n".as_bytes().to_vec()));
+
+    // a base offset pointing before the start of the pack, or at the entry itself
+    for distance in [delta_offset + 1, 0] {
+        let mut body = vec![distance as u8];
+        body.extend_from_slice(&delta);
+        let pack = Pack::from_bytes(build_pack(&[(3, base.clone()), (6, body)])).unwrap();
+        assert_eq!(pack.entries().err(), Some("invalid delta base offset".to_string()));
+        assert!(pack.read_object_at(delta_offset, Path::new("")).is_err());
+    }
+}
+
+#[cfg(test)]
+pub fn build_idx(objects: &[(Hash, usize)]) -> Vec<u8> {
+    use crate::common::u32_to_bytes;
+
+    let mut objects = objects.to_vec();
+    objects.sort();
+    let mut idx = vec![0xff, b't', b'O', b'c'];
+    idx.append(&mut u32_to_bytes(2));
+    for i in 0..256 {
+        let n = objects.iter().filter(|(h, _)| (h.bytes()[0] as usize) <= i).count();
+        idx.append(&mut u32_to_bytes(n as u32));
+    }
+    for (hash, _) in &objects {
+        idx.extend_from_slice(&hash.bytes());
+    }
+    idx.append(&mut vec![0u8; objects.len()*4]);
+    for (_, offset) in &objects {
+        idx.append(&mut u32_to_bytes(*offset as u32));
+    }
+    idx.append(&mut vec![0u8; 40]);
+    idx
+}
+
+#[test]
+fn test_read_packed_object() {
+    let objects_root = crate::common::test_dir("read_packed_object");
+    fs::create_dir_all(objects_root.join("pack")).unwrap();
+
+    let base = "hello world\n".as_bytes().to_vec();
+    let base_hash = object_hash(ObjType::Blob, &base);
+    let mut delta = base_hash.bytes().to_vec();
+    delta.append(&mut vec![12u8, 10, 0x90, 6, 3, b'g', b'i', b't', 0x91, 11, 1]);
+    let data = build_pack(&[(3, base.clone()), (7, delta)]);
+    let pack = Pack::from_bytes(data.clone()).unwrap();
+    let offsets: Vec<usize> = pack.entries().unwrap().iter().map(|e| e.offset).collect();
+    let delta_hash = object_hash(ObjType::Blob, "hello git\n".as_bytes());
+
+    fs::write(objects_root.join("pack/pack-test.pack"), data).unwrap();
+    fs::write(objects_root.join("pack/pack-test.idx"),
+              build_idx(&[(base_hash, offsets[0]), (delta_hash, offsets[1])])).unwrap();
+
+    assert_eq!(read_packed_object(&objects_root, base_hash).unwrap(), Some((ObjType::Blob, base)));
+    assert_eq!(read_packed_object(&objects_root, delta_hash).unwrap(),
+               Some((ObjType::Blob, "hello git\n".as_bytes().to_vec())));
+    assert_eq!(read_packed_object(&objects_root, Hash::default()).unwrap(), None);
+    assert_eq!(packed_object_type(&objects_root, delta_hash).unwrap(), Some(ObjType::Blob));
+    assert!(packed_object_exists(&objects_root, delta_hash).unwrap());
+    assert_eq!(find_packed_by_prefix(&objects_root, &delta_hash.string()[..4]).unwrap(), vec![delta_hash]);
+    assert_eq!(find_packed_by_prefix(&objects_root, &base_hash.string()[..2]).unwrap(), vec![base_hash]);
+    // opened once and shared by later lookups
+    let idx_path = objects_root.join("pack/pack-test.idx");
+    assert!(Rc::ptr_eq(&load_pack(&idx_path).unwrap(), &find_packed(&objects_root, base_hash).unwrap().unwrap().0));
+
+    // an index pointing at the wrong entry
+    let wrong = Hash::from_string(&"1".repeat(40)).unwrap();
+    fs::write(objects_root.join("pack/pack-test.idx"), build_idx(&[(wrong, offsets[0])])).unwrap();
+    let err = crate::object::read_raw_object(&objects_root, wrong).unwrap_err();
+    assert!(err.starts_with("object corrupt"), "{}", err);
+}
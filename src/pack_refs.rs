@@ -0,0 +1,8 @@
+use clap::ArgMatches;
+use crate::common::get_project_root;
+use crate::refs::pack_refs;
+
+// pack-refs [--all]    the tags, or every ref with --all, into packed-refs with the peeled annotated tags
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    pack_refs(&get_project_root()?, matches.is_present("all"))
+}
@@ -0,0 +1,167 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root};
+use crate::count_objects::list_loose_objects;
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::index::Index;
+use crate::object::{object_type, read_object, read_object_header, Commit, FileType, ObjType, Tree};
+use crate::refs::list_refs;
+use crate::worktree::work_tree_roots;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let dry_run = matches.is_present("dry-run");
+
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let reachable = reachable_objects(&proj_root)?;
+
+    for path in list_loose_objects(&objects_root)? {
+        let hash = object_hash(&path).ok_or(format!("invalid object path: {}", path.display()))?;
+        if reachable.contains(&hash) {
+            continue;
+        }
+        if dry_run {
+            let (obj_type, _) = read_object_header(&path)?;
+            println!("{} {}", hash.string(), obj_type.name());
+            continue;
+        }
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+        let dir = path.parent().unwrap();
+        if fs::read_dir(dir).map_err(|e| e.to_string())?.next().is_none() {
+            fs::remove_dir(dir).map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn object_hash(path: &Path) -> Option<Hash> {
+    let rest = path.file_name()?.to_str()?;
+    let prefix = path.parent()?.file_name()?.to_str()?;
+    Hash::from_string(&format!("{}{}", prefix, rest))
+}
+
+// objects reachable from refs, HEAD, reflogs and the index
+pub fn reachable_objects(proj_root: &Path) -> Result<BTreeSet<Hash>, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut roots: Vec<Hash> = list_refs(proj_root, "refs/")?.into_values().collect();
+    roots.append(&mut reflog_hashes(&common_dir(proj_root).join("logs"))?);
+
+    // HEAD and the index of every working tree, linked ones included
+    let mut work_trees = work_tree_roots(proj_root)?;
+    work_trees.push(proj_root.to_path_buf());
+    let mut visit = BTreeSet::new();
+    for root in &work_trees {
+        if let Some(hash) = Head::from_root(root)?.hash() {
+            roots.push(hash);
+        }
+        if let Some(index) = Index::from_file(root)? {
+            for entry in index.entries().values() {
+                visit.insert(entry.hash());
+            }
+        }
+    }
+    for root in roots {
+        mark(&objects_root, root, &mut visit)?;
+    }
+    Ok(visit)
+}
+
+// everything the object leads to, with an explicit stack as histories can be deeper than the call stack
+fn mark(objects_root: &Path, root: Hash, visit: &mut BTreeSet<Hash>) -> Result<(), String> {
+    let mut stack = vec![root];
+    while let Some(hash) = stack.pop() {
+        if !visit.insert(hash) {
+            continue;
+        }
+        let obj_type = object_type(objects_root, hash).
+            map_err(|e| format!("{}; refusing to prune", e))?;
+        match obj_type {
+            ObjType::Blob => {},
+            ObjType::Tree => {
+                // submodule entries point at commits of another repository
+                stack.extend(Tree::load(objects_root, hash)?.nodes().iter().
+                    filter(|node| node.file_type() != FileType::Submodule).map(|node| node.hash()));
+            },
+            ObjType::Commit => {
+                let commit = Commit::load(objects_root, hash)?;
+                stack.push(commit.tree());
+                stack.extend(commit.parents().iter().cloned());
+            },
+            ObjType::Tag => {
+                let (_, body) = read_object(objects_root, hash)?;
+                let body = String::from_utf8(body).map_err(|e| e.to_string())?;
+                let target = body.lines().find_map(|l| l.strip_prefix("object ")).
+                    and_then(Hash::from_string).ok_or(format!("broken tag: {}", hash.string()))?;
+                stack.push(target);
+            },
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_mark_deep_history() {
+    use crate::object::{empty_tree, write_object};
+
+    let objects_root = crate::common::test_dir("mark_deep_history");
+    let mut tip = None;
+    for i in 0..500 {
+        let parent = tip.map(|p: Hash| format!("parent {}\n", p.string())).unwrap_or_default();
+        let body = format!("tree {}\n{}author a <a@example.com> {} +0000\ncommitter a <a@example.com> {} +0000\n\nc\n",
+                           empty_tree().string(), parent, i, i);
+        tip = Some(write_object(&objects_root, ObjType::Commit, body.as_bytes()).unwrap());
+    }
+    // a stack far too small for a call per commit
+    let visit = std::thread::Builder::new().stack_size(128 << 10).spawn(move || {
+        let mut visit = BTreeSet::new();
+        mark(&objects_root, tip.unwrap(), &mut visit).map(|_| visit)
+    }).unwrap().join().unwrap().unwrap();
+    // every commit and the empty tree
+    assert_eq!(visit.len(), 501);
+}
+
+// "<old> <new> <committer> <time> <tz>\t<message>" lines under .git/logs/
+fn reflog_hashes(dir: &Path) -> Result<Vec<Hash>, String> {
+    let mut ret = Vec::new();
+    if !dir.is_dir() {
+        return Ok(ret);
+    }
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            ret.append(&mut reflog_hashes(&path)?);
+            continue;
+        }
+        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        for line in content.lines() {
+            for word in line.split(' ').take(2) {
+                if let Some(hash) = Hash::from_string(word) {
+                    if hash != Hash::default() {
+                        ret.push(hash);
+                    }
+                }
+            }
+        }
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_reflog_hashes() {
+    let dir = crate::common::test_dir("reflog_hashes");
+    fs::create_dir_all(dir.join("refs/heads")).unwrap();
+    fs::write(dir.join("refs/heads/master"),
+              "0000000000000000000000000000000000000000 064a92d783f99851d1517b51ba0b2aed4a1d3128 \
+              a <a@example.com> 1633332967 +0900\tcommit (initial): first\n\
+              064a92d783f99851d1517b51ba0b2aed4a1d3128 3b18e512dba79e4c8300dd08aeb37f8e728b8dad \
+              a <a@example.com> 1633332968 +0900\tcommit: second\n").unwrap();
+    let mut out = reflog_hashes(&dir).unwrap();
+    out.sort();
+    out.dedup();
+    assert_eq!(out, vec![Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap(),
+                         Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap()]);
+}
@@ -0,0 +1,281 @@
+use std::path::Path;
+use clap::ArgMatches;
+use crate::checkout::{check_clean, switch_tree};
+use crate::commit::{current_committer, write_index_tree};
+use crate::common::{common_dir, get_project_root, info};
+use crate::hash::Hash;
+use crate::head::{detach_head, write_symbolic_ref, Head};
+use crate::index::Index;
+use crate::merge::{merge_trees, write_conflict_markers};
+use crate::merge_base::merge_base;
+use crate::object::{Commit, CommitUser};
+use crate::refs::write_ref;
+use crate::rev;
+use crate::state::{in_progress, RebaseState};
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    if matches.is_present("abort") {
+        return rebase_abort(&proj_root);
+    }
+    let committer = current_committer()?;
+    if matches.is_present("continue") {
+        return rebase_continue(&proj_root, &committer);
+    } else if matches.is_present("skip") {
+        return rebase_skip(&proj_root, &committer);
+    }
+    rebase(&proj_root, matches.value_of("upstream").unwrap(), matches.is_present("committer-date-is-author-date"),
+           &committer)
+}
+
+// replay the commits of the current branch since upstream on top of it, committed by committer
+fn rebase(proj_root: &Path, upstream: &str, committer_date_is_author_date: bool,
+          committer: &CommitUser) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    if let Some(op) = in_progress(proj_root) {
+        return Err(format!("a {} is already in progress", op.name()));
+    }
+
+    let head = Head::from_root(proj_root)?;
+    let branch = head.branch().ok_or("cannot rebase a detached HEAD".to_string())?;
+    let head_hash = head.hash().ok_or("HEAD does not have any commits yet".to_string())?;
+    let onto = rev::resolve_commit(proj_root, upstream)?;
+
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    check_clean(proj_root, &index, Some(Commit::load(&objects_root, head_hash)?.tree()), "rebase")?;
+
+    let base = merge_base(&objects_root, onto, head_hash)?.
+        ok_or(format!("no common ancestor with {}", upstream))?;
+    if base == onto {
+        info(&format!("Current branch {} is up to date.", branch));
+        return Ok(());
+    }
+
+    let mut state = RebaseState {
+        head_name: format!("refs/heads/{}", branch),
+        orig_head: head_hash,
+        onto,
+        todo: unique_commits(&objects_root, head_hash, base)?,
+        committer_date_is_author_date,
+    };
+    write_ref(proj_root, "ORIG_HEAD", head_hash)?;
+    replay(proj_root, &mut state, onto, committer)
+}
+
+// commit the resolved index for the stopped commit, then go on
+fn rebase_continue(proj_root: &Path, committer: &CommitUser) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut state = RebaseState::load(proj_root)?.ok_or("no rebase in progress".to_string())?;
+    let tip = Head::from_root(proj_root)?.hash().ok_or("HEAD is not valid".to_string())?;
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    check_clean(proj_root, &index, None, "rebase").
+        map_err(|_| "you have unstaged changes, add them and run rebase --continue".to_string())?;
+
+    // with nothing stopped, e.g. a state left by an interrupted run, the rebase just finishes
+    let stopped = match state.todo.first() {
+        Some(hash) => Commit::load(&objects_root, *hash)?,
+        None => return replay(proj_root, &mut state, tip, committer),
+    };
+    let tree = write_index_tree(proj_root, index)?;
+    state.todo.remove(0);
+    // resolved to HEAD, nothing to commit
+    let tip = if tree == Commit::load(&objects_root, tip)?.tree() {
+        tip
+    } else {
+        new_commit(&objects_root, &stopped, tree, tip, &committer_of(&state, &stopped, committer))?
+    };
+    replay(proj_root, &mut state, tip, committer)
+}
+
+// drop the stopped commit and go on
+fn rebase_skip(proj_root: &Path, committer: &CommitUser) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut state = RebaseState::load(proj_root)?.ok_or("no rebase in progress".to_string())?;
+    let tip = Head::from_root(proj_root)?.hash().ok_or("HEAD is not valid".to_string())?;
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    switch_tree(proj_root, &index, Commit::load(&objects_root, tip)?.tree())?;
+    if !state.todo.is_empty() {
+        state.todo.remove(0);
+    }
+    replay(proj_root, &mut state, tip, committer)
+}
+
+// back to the branch and the working tree before the rebase
+fn rebase_abort(proj_root: &Path) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let state = RebaseState::load(proj_root)?.ok_or("no rebase in progress".to_string())?;
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    switch_tree(proj_root, &index, Commit::load(&objects_root, state.orig_head)?.tree())?;
+    write_ref(proj_root, &state.head_name, state.orig_head)?;
+    write_symbolic_ref(proj_root, "HEAD", &state.head_name)?;
+    RebaseState::remove(proj_root)
+}
+
+// apply the commits of the todo list on top of tip, stopping at a conflict
+fn replay(proj_root: &Path, state: &mut RebaseState, mut tip: Hash, committer: &CommitUser) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    while let Some(hash) = state.todo.first().cloned() {
+        let commit = Commit::load(&objects_root, hash)?;
+        let parent_tree = Commit::load(&objects_root, commit.parents()[0])?.tree();
+        let tip_tree = Commit::load(&objects_root, tip)?.tree();
+        let (tree, conflicts) = merge_trees(&objects_root, parent_tree, tip_tree, commit.tree())?;
+        if !conflicts.is_empty() {
+            // stop on a detached HEAD, with the non-conflicting changes applied
+            let index = Index::from_file(proj_root)?.unwrap_or_default();
+            switch_tree(proj_root, &index, tree)?;
+            detach_head(proj_root, tip)?;
+            let label = format!("{} ({})", rev::abbreviate(&objects_root, hash), subject(&commit));
+            write_conflict_markers(proj_root, &objects_root, tip_tree, commit.tree(), &label, &conflicts)?;
+            state.save(proj_root)?;
+            let paths: Vec<String> = conflicts.iter().map(|p| p.display().to_string()).collect();
+            return Err(format!("could not apply {} {}, conflicts in: {}; \
+                                resolve them, add them and run rebase --continue",
+                               rev::abbreviate(&objects_root, hash), subject(&commit), paths.join(", ")));
+        }
+        state.todo.remove(0);
+        // the change is already in upstream
+        if tree == tip_tree {
+            continue;
+        }
+        tip = new_commit(&objects_root, &commit, tree, tip, &committer_of(state, &commit, committer))?;
+    }
+
+    let index = Index::from_file(proj_root)?.unwrap_or_default();
+    switch_tree(proj_root, &index, Commit::load(&objects_root, tip)?.tree())?;
+    write_ref(proj_root, &state.head_name, tip)?;
+    write_symbolic_ref(proj_root, "HEAD", &state.head_name)?;
+    if in_progress(proj_root).is_some() {
+        RebaseState::remove(proj_root)?;
+    }
+    info(&format!("Successfully rebased and updated {}.", state.head_name));
+    Ok(())
+}
+
+// with --committer-date-is-author-date, dated as the author of the replayed commit
+fn committer_of(state: &RebaseState, commit: &Commit, committer: &CommitUser) -> CommitUser {
+    match state.committer_date_is_author_date {
+        true => committer.change_timestamp_as(commit.author().timestamp()),
+        false => committer.clone(),
+    }
+}
+
+// the commit replayed on top of parent, keeping the author and the message
+fn new_commit(objects_root: &Path, commit: &Commit, tree: Hash, parent: Hash,
+              committer: &CommitUser) -> Result<Hash, String> {
+    Commit::from(tree, vec![parent], commit.author().clone(), committer.clone(),
+                 commit.message().to_string()).write(objects_root)
+}
+
+// commits from base (exclusive) to head, oldest first
+fn unique_commits(objects_root: &Path, head: Hash, base: Hash) -> Result<Vec<Hash>, String> {
+    let mut ret = Vec::new();
+    let mut hash = head;
+    while hash != base {
+        let commit = Commit::load(objects_root, hash)?;
+        match commit.parents().as_slice() {
+            [parent] => {
+                ret.push(hash);
+                hash = *parent;
+            },
+            [] => return Err(format!("{} is not an ancestor of HEAD", base.string())),
+            _ => return Err(format!("cannot rebase the merge commit {}", hash.string())),
+        }
+    }
+    ret.reverse();
+    Ok(ret)
+}
+
+fn subject(commit: &Commit) -> &str {
+    commit.message().lines().next().unwrap_or("")
+}
+
+#[test]
+fn test_rebase_with_empty_todo() {
+    use crate::object::{CommitterType, Tree};
+    use crate::refs::read_ref;
+
+    let proj_root = crate::common::test_dir("rebase_empty_todo");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let user = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Author);
+    let tree = Tree::new().write(&objects_root).unwrap();
+    let tip = Commit::from(tree, vec![], user.clone(), user.change_committer_type_as(CommitterType::Committer),
+                           "base\n".to_string()).write(&objects_root).unwrap();
+    let state = RebaseState {
+        head_name: "refs/heads/topic".to_string(),
+        orig_head: tip,
+        onto: tip,
+        todo: vec![],
+        committer_date_is_author_date: false,
+    };
+
+    // nothing left to continue with or to skip, the rebase finishes on the branch
+    for finish in [rebase_continue, rebase_skip] {
+        state.save(&proj_root).unwrap();
+        detach_head(&proj_root, tip).unwrap();
+        finish(&proj_root, &user.change_committer_type_as(CommitterType::Committer)).unwrap();
+        assert_eq!(in_progress(&proj_root), None);
+        assert_eq!(read_ref(&proj_root, "refs/heads/topic"), Ok(Some(tip)));
+        assert_eq!(Head::from_root(&proj_root).unwrap().branch(), Some("topic".to_string()));
+    }
+}
+
+#[test]
+fn test_rebase_conflict_and_continue() {
+    use std::fs;
+    use crate::checkout::checkout_tree;
+    use crate::object::{write_object, write_test_tree, CommitterType, ObjType};
+    use crate::refs::read_ref;
+
+    let proj_root = crate::common::test_dir("rebase_continue");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let author = CommitUser::from("A".to_string(), "a@example.com".to_string(), CommitterType::Author);
+    let committer = CommitUser::from("C".to_string(), "c@example.com".to_string(), CommitterType::Committer);
+    let blob = |content: &str| write_object(&objects_root, ObjType::Blob, content.as_bytes()).unwrap();
+    let commit = |files: &[(&str, &str)], parent: Option<Hash>, message: &str| {
+        let entries: Vec<_> = files.iter().map(|(name, content)| ("100644", *name, blob(content))).collect();
+        Commit::from(write_test_tree(&objects_root, &entries), parent.into_iter().collect(), author.clone(),
+                     author.change_committer_type_as(CommitterType::Committer), format!("{}\n", message)).
+            write(&objects_root).unwrap()
+    };
+    // base <- m1 on master, base <- t1 <- t2 on topic, t2 conflicting with m1
+    let base = commit(&[("a.txt", "1\n")], None, "base");
+    let m1 = commit(&[("a.txt", "2\n")], Some(base), "m1");
+    let t1 = commit(&[("a.txt", "1\n"), ("b.txt", "b\n")], Some(base), "t1");
+    let t2 = commit(&[("a.txt", "3\n"), ("b.txt", "b\n")], Some(t1), "t2");
+    write_ref(&proj_root, "refs/heads/master", m1).unwrap();
+    write_ref(&proj_root, "refs/heads/topic", t2).unwrap();
+    write_symbolic_ref(&proj_root, "HEAD", "refs/heads/topic").unwrap();
+    checkout_tree(&proj_root, Commit::load(&objects_root, t2).unwrap().tree()).unwrap().write_file(&proj_root).
+        unwrap();
+
+    // t1 applies, t2 stops with HEAD detached at the replayed t1
+    let err = rebase(&proj_root, "master", false, &committer).unwrap_err();
+    assert!(err.starts_with("could not apply"), "{}", err);
+    assert!(err.ends_with("conflicts in: a.txt; resolve them, add them and run rebase --continue"), "{}", err);
+    let head = Head::from_root(&proj_root).unwrap();
+    assert_eq!(head.branch(), None);
+    let t1_new = Commit::load(&objects_root, head.hash().unwrap()).unwrap();
+    assert_eq!(t1_new.parents(), &vec![m1]);
+    assert!(fs::read_to_string(proj_root.join("a.txt")).unwrap().starts_with("<<<<<<< HEAD\n"));
+    // not until the resolution is added
+    assert!(rebase_continue(&proj_root, &committer).is_err());
+
+    fs::write(proj_root.join("a.txt"), "resolved\n").unwrap();
+    let mut index = Index::from_file(&proj_root).unwrap().unwrap();
+    index.add_entry_in(&proj_root, std::path::Path::new("a.txt"), blob("resolved\n")).unwrap();
+    index.write_file(&proj_root).unwrap();
+    rebase_continue(&proj_root, &committer).unwrap();
+
+    assert_eq!(in_progress(&proj_root), None);
+    assert_eq!(Head::from_root(&proj_root).unwrap().branch(), Some("topic".to_string()));
+    let tip = read_ref(&proj_root, "refs/heads/topic").unwrap().unwrap();
+    let t2_new = Commit::load(&objects_root, tip).unwrap();
+    assert_eq!(t2_new.parents(), &vec![head.hash().unwrap()]);
+    assert_eq!(t2_new.message(), "t2\n");
+    assert_eq!(t2_new.author().name(), "A");
+    assert_eq!(t2_new.committer().name(), "C");
+    assert_eq!(t2_new.tree(), write_test_tree(&objects_root, &[("100644", "a.txt", blob("resolved\n")),
+                                                               ("100644", "b.txt", blob("b\n"))]));
+}
@@ -0,0 +1,431 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use crate::common::{common_dir, git_dir};
+use crate::hash::Hash;
+use crate::object::peel;
+
+// refs/ are shared by the working trees, HEAD and the like belong to one
+pub fn ref_path(proj_root: &Path, refname: &str) -> PathBuf {
+    match refname.starts_with("refs/") {
+        true => common_dir(proj_root).join(refname),
+        false => git_dir(proj_root).join(refname),
+    }
+}
+
+// read a ref such as "refs/heads/master" from loose files, then packed-refs
+pub fn read_ref(proj_root: &Path, refname: &str) -> Result<Option<Hash>, String> {
+    if refname.starts_with("refs/") {
+        return read_ref_in(&common_dir(proj_root), refname);
+    }
+    // HEAD and the like, which may point into the shared refs
+    let path = git_dir(proj_root).join(refname);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    let content = content.trim_end();
+    match content.strip_prefix("ref: ") {
+        Some(target) => read_ref(proj_root, target),
+        None => Hash::from_string(content).map(Some).ok_or(format!("broken ref: {}", refname)),
+    }
+}
+
+// same as read_ref, for a git directory which may be bare
+pub fn read_ref_in(git_dir: &Path, refname: &str) -> Result<Option<Hash>, String> {
+    let path = git_dir.join(refname);
+    if path.is_file() {
+        let content = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let content = content.trim_end();
+        if let Some(target) = content.strip_prefix("ref: ") {
+            return read_ref_in(git_dir, target);
+        }
+        let hash = Hash::from_string(content).
+            ok_or(format!("broken ref: {}", refname))?;
+        return Ok(Some(hash));
+    }
+
+    Ok(read_packed_refs(git_dir)?.get(refname).cloned())
+}
+
+pub fn write_ref(proj_root: &Path, refname: &str, hash: Hash) -> Result<(), String> {
+    let path = ref_path(proj_root, refname);
+    fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+    fs::write(path, format!("{}\n", hash.string())).map_err(|e| e.to_string())
+}
+
+/*
+    compare-and-swap through <ref>.lock, old is the value the caller based the update on, None for a new ref
+    the lock is taken before the ref is read again, so another writer either fails on it or came first
+ */
+pub fn update_ref(proj_root: &Path, refname: &str, new: Hash, old: Option<Hash>) -> Result<(), String> {
+    let path = ref_path(proj_root, refname);
+    fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+    let lock_path = PathBuf::from(format!("{}.lock", path.display()));
+    let mut lock = fs::OpenOptions::new().write(true).create_new(true).open(&lock_path).
+        map_err(|e| format!("unable to create '{}': {}; another process may be updating {}",
+                            lock_path.display(), e, refname))?;
+
+    let result = match read_ref(proj_root, refname) {
+        Ok(current) if current == old => lock.write_all(format!("{}\n", new.string()).as_bytes()).
+            and_then(|_| fs::rename(&lock_path, &path)).map_err(|e| e.to_string()),
+        Ok(_) => Err(format!("ref was updated concurrently: {}", refname)),
+        Err(e) => Err(e),
+    };
+    if result.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+    result
+}
+
+// remove the packed-refs entry and the loose file, false if the ref did not exist
+// packed-refs is rewritten through packed-refs.lock as pack_refs does
+pub fn delete_ref(proj_root: &Path, refname: &str) -> Result<bool, String> {
+    let common_dir = common_dir(proj_root);
+    let mut deleted = false;
+    // pseudo refs such as MERGE_HEAD are never packed
+    if refname.starts_with("refs/") {
+        let lock = lock_packed_refs(&common_dir)?;
+        let result = remove_packed_ref(&common_dir, lock, refname);
+        if result != Ok(true) {
+            let _ = fs::remove_file(common_dir.join("packed-refs.lock"));
+        }
+        deleted = result?;
+    }
+    // the loose file goes last, so the packed value never shows through
+    let path = ref_path(proj_root, refname);
+    if path.is_file() {
+        fs::remove_file(path).map_err(|e| e.to_string())?;
+        deleted = true;
+    }
+    Ok(deleted)
+}
+
+// packed-refs without the ref, written into the lock and renamed into place; false when it wasn't there
+fn remove_packed_ref(common_dir: &Path, mut lock: fs::File, refname: &str) -> Result<bool, String> {
+    let packed_path = common_dir.join("packed-refs");
+    if !packed_path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(&packed_path).map_err(|e| e.to_string())?;
+    let mut kept = String::new();
+    let mut in_deleted = false;
+    for line in content.lines() {
+        // the peeled line belongs to the ref just before it
+        if line.starts_with('^') && in_deleted {
+            continue;
+        }
+        in_deleted = line.split_once(' ').map(|(_, name)| name) == Some(refname);
+        if !in_deleted {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    if kept == content {
+        return Ok(false);
+    }
+    lock.write_all(kept.as_bytes()).map_err(|e| e.to_string())?;
+    fs::rename(common_dir.join("packed-refs.lock"), packed_path).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn lock_packed_refs(common_dir: &Path) -> Result<fs::File, String> {
+    let lock_path = common_dir.join("packed-refs.lock");
+    fs::OpenOptions::new().write(true).create_new(true).open(&lock_path).
+        map_err(|e| format!("unable to create '{}': {}; another process may be updating packed-refs",
+                            lock_path.display(), e))
+}
+
+/*
+    move loose refs into packed-refs, the tags and the refs packed already, or every one with all
+    an annotated tag is followed by a "^<hash>" line with the object it peels to, as git writes them
+    symbolic refs stay loose, packed-refs is written through packed-refs.lock as update_ref does
+ */
+pub fn pack_refs(proj_root: &Path, all: bool) -> Result<(), String> {
+    let common_dir = common_dir(proj_root);
+    let lock_path = common_dir.join("packed-refs.lock");
+    let lock = lock_packed_refs(&common_dir)?;
+    let result = write_packed_refs(&common_dir, lock, all);
+    if result.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+    result
+}
+
+fn write_packed_refs(common_dir: &Path, mut lock: fs::File, all: bool) -> Result<(), String> {
+    let objects_root = common_dir.join("objects");
+    let mut packed = read_packed_refs(common_dir)?;
+    let mut loose = Vec::new();
+    collect_loose_refs(common_dir, &common_dir.join("refs"), &mut loose)?;
+    loose.retain(|name| all || name.starts_with("refs/tags/") || packed.contains_key(name));
+
+    let mut moved = Vec::new();
+    for name in loose {
+        let content = fs::read_to_string(common_dir.join(&name)).map_err(|e| e.to_string())?;
+        if content.starts_with("ref: ") {
+            continue;
+        }
+        let hash = Hash::from_string(content.trim_end()).ok_or(format!("broken ref: {}", name))?;
+        packed.insert(name.clone(), hash);
+        moved.push(name);
+    }
+
+    let mut content = "# pack-refs with: peeled fully-peeled sorted \n".to_string();
+    for (name, hash) in &packed {
+        content.push_str(&format!("{} {}\n", hash.string(), name));
+        let (peeled, _) = peel(&objects_root, *hash)?;
+        if peeled != *hash {
+            content.push_str(&format!("^{}\n", peeled.string()));
+        }
+    }
+    lock.write_all(content.as_bytes()).map_err(|e| e.to_string())?;
+    fs::rename(common_dir.join("packed-refs.lock"), common_dir.join("packed-refs")).map_err(|e| e.to_string())?;
+    for name in moved {
+        fs::remove_file(common_dir.join(&name)).map_err(|e| e.to_string())?;
+        // the directories left empty, but not refs/heads and the like themselves
+        for dir in Path::new(&name).ancestors().skip(1).take_while(|d| d.components().count() > 2) {
+            if fs::remove_dir(common_dir.join(dir)).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// all refs whose name starts with the prefix, e.g. "refs/heads/"
+pub fn list_refs(proj_root: &Path, prefix: &str) -> Result<BTreeMap<String, Hash>, String> {
+    list_refs_in(&common_dir(proj_root), prefix)
+}
+
+pub fn list_refs_in(git_dir: &Path, prefix: &str) -> Result<BTreeMap<String, Hash>, String> {
+    let mut ret: BTreeMap<String, Hash> = read_packed_refs(git_dir)?.into_iter().
+        filter(|(name, _)| name.starts_with(prefix)).collect();
+
+    let mut loose = Vec::new();
+    collect_loose_refs(git_dir, &git_dir.join("refs"), &mut loose)?;
+    for name in loose {
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        if let Some(hash) = read_ref_in(git_dir, &name)? {
+            ret.insert(name, hash);
+        }
+    }
+    Ok(ret)
+}
+
+// name used for decoration, e.g. "master", "origin/master", "tag: v1.0"
+pub fn shorten_ref(refname: &str) -> String {
+    if let Some(name) = refname.strip_prefix("refs/heads/") {
+        name.to_string()
+    } else if let Some(name) = refname.strip_prefix("refs/remotes/") {
+        name.to_string()
+    } else if let Some(name) = refname.strip_prefix("refs/tags/") {
+        format!("tag: {}", name)
+    } else {
+        refname.to_string()
+    }
+}
+
+// a subset of git check-ref-format rules for a branch name
+pub fn check_branch_name(name: &str) -> Result<(), String> {
+    let invalid = name.is_empty() || name.starts_with(['-', '/', '.']) || name.ends_with(['/', '.']) ||
+        name.ends_with(".lock") || name.contains("..") || name.contains("//") || name.contains("@{") ||
+        name.contains(|c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c));
+    if invalid {
+        return Err(format!("'{}' is not a valid branch name", name));
+    }
+    Ok(())
+}
+
+fn collect_loose_refs(git_dir: &Path, dir: &Path, refs: &mut Vec<String>) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            collect_loose_refs(git_dir, &path, refs)?;
+        } else if path.extension() != Some("lock".as_ref()) {
+            let name = path.strip_prefix(git_dir).map_err(|e| e.to_string())?;
+            refs.push(name.to_str().ok_or("convert path to string failed")?.to_string());
+        }
+    }
+    Ok(())
+}
+
+fn read_packed_refs(git_dir: &Path) -> Result<BTreeMap<String, Hash>, String> {
+    let path = git_dir.join("packed-refs");
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse_packed_refs(&content)
+}
+
+fn parse_packed_refs(content: &str) -> Result<BTreeMap<String, Hash>, String> {
+    let mut ret = BTreeMap::new();
+    for line in content.lines() {
+        // comments and peeled values of annotated tags
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let (hash, name) = line.split_once(' ').
+            ok_or(format!("broken packed-refs line: {}", line))?;
+        let hash = Hash::from_string(hash).
+            ok_or(format!("broken packed-refs line: {}", line))?;
+        ret.insert(name.to_string(), hash);
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_parse_packed_refs() {
+    let content = "# pack-refs with: peeled fully-peeled sorted \n\
+        064a92d783f99851d1517b51ba0b2aed4a1d3128 refs/heads/master\n\
+        3b18e512dba79e4c8300dd08aeb37f8e728b8dad refs/tags/v1.0\n\
+        ^68ffd9f1fd447b83f26963cb50155532b00108f1\n";
+    let out = parse_packed_refs(content).unwrap();
+    assert_eq!(out.len(), 2);
+    assert_eq!(out.get("refs/tags/v1.0"),
+               Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").as_ref());
+}
+
+#[test]
+fn test_update_ref() {
+    let proj_root = crate::common::test_dir("update_ref");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    let a = Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap();
+    let b = Hash::from_string("3b18e512dba79e4c8300dd08aeb37f8e728b8dad").unwrap();
+
+    update_ref(&proj_root, "refs/heads/master", a, None).unwrap();
+    assert!(update_ref(&proj_root, "refs/heads/master", b, None).is_err());
+    // another process moved it to b after we read a
+    write_ref(&proj_root, "refs/heads/master", b).unwrap();
+    let err = update_ref(&proj_root, "refs/heads/master", a, Some(a)).unwrap_err();
+    assert!(err.starts_with("ref was updated concurrently"), "{}", err);
+    assert_eq!(read_ref(&proj_root, "refs/heads/master"), Ok(Some(b)));
+    assert!(!proj_root.join(".git/refs/heads/master.lock").exists());
+
+    fs::write(proj_root.join(".git/refs/heads/master.lock"), "").unwrap();
+    assert!(update_ref(&proj_root, "refs/heads/master", a, Some(b)).is_err());
+    fs::remove_file(proj_root.join(".git/refs/heads/master.lock")).unwrap();
+    update_ref(&proj_root, "refs/heads/master", a, Some(b)).unwrap();
+    assert_eq!(read_ref(&proj_root, "refs/heads/master"), Ok(Some(a)));
+}
+
+#[test]
+fn test_list_refs() {
+    let proj_root = crate::common::test_dir("list_refs");
+    let hash = "064a92d783f99851d1517b51ba0b2aed4a1d3128";
+    let packed = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+    fs::create_dir_all(proj_root.join(".git/refs/heads/feature")).unwrap();
+    fs::create_dir_all(proj_root.join(".git/refs/tags")).unwrap();
+    fs::write(proj_root.join(".git/refs/heads/master"), hash).unwrap();
+    fs::write(proj_root.join(".git/refs/heads/feature/x"), hash).unwrap();
+    fs::write(proj_root.join(".git/refs/tags/v1"), hash).unwrap();
+    fs::write(proj_root.join(".git/packed-refs"),
+              format!("{} refs/heads/master\n{} refs/heads/old\n", packed, packed)).unwrap();
+
+    let out = list_refs(&proj_root, "refs/heads/").unwrap();
+    let names: Vec<&str> = out.keys().map(|k| k.as_str()).collect();
+    assert_eq!(names, vec!["refs/heads/feature/x", "refs/heads/master", "refs/heads/old"]);
+    // loose refs take precedence over packed ones
+    assert_eq!(out["refs/heads/master"], Hash::from_string(hash).unwrap());
+    assert_eq!(list_refs(&proj_root, "refs/").unwrap().len(), 4);
+}
+
+#[test]
+fn test_list_remote_refs() {
+    let proj_root = crate::common::test_dir("list_remote_refs");
+    let hash = "064a92d783f99851d1517b51ba0b2aed4a1d3128";
+    let packed = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+    fs::create_dir_all(proj_root.join(".git/refs/remotes/origin")).unwrap();
+    fs::write(proj_root.join(".git/refs/remotes/origin/master"), hash).unwrap();
+    fs::write(proj_root.join(".git/refs/remotes/origin/HEAD"), "ref: refs/remotes/origin/master\n").unwrap();
+    fs::write(proj_root.join(".git/packed-refs"),
+              format!("{} refs/remotes/origin/old\n{} refs/heads/master\n", packed, packed)).unwrap();
+
+    let out = list_refs(&proj_root, "refs/remotes/").unwrap();
+    let names: Vec<String> = out.keys().map(|k| shorten_ref(k)).collect();
+    assert_eq!(names, vec!["origin/HEAD", "origin/master", "origin/old"]);
+    assert_eq!(out["refs/remotes/origin/HEAD"], Hash::from_string(hash).unwrap());
+    assert_eq!(shorten_ref("refs/tags/v1"), "tag: v1");
+}
+
+#[test]
+fn test_check_branch_name() {
+    for name in ["master", "feature/x", "v1.0", "gh-pages"] {
+        assert!(check_branch_name(name).is_ok(), "{}", name);
+    }
+    for name in ["", "-b", "a..b", "a b", "a:b", "a~1", "x.lock", "a/", "/a", "a//b", "a@{1}", ".a"] {
+        assert!(check_branch_name(name).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn test_delete_ref() {
+    let proj_root = crate::common::test_dir("delete_ref");
+    let hash = "064a92d783f99851d1517b51ba0b2aed4a1d3128";
+    fs::create_dir_all(proj_root.join(".git/refs/tags")).unwrap();
+    fs::write(proj_root.join(".git/refs/tags/loose"), hash).unwrap();
+    fs::write(proj_root.join(".git/packed-refs"),
+              format!("# pack-refs with: peeled\n{} refs/tags/packed\n^{}\n{} refs/tags/other\n",
+                      hash, hash, hash)).unwrap();
+
+    // another writer holds packed-refs
+    fs::write(proj_root.join(".git/packed-refs.lock"), "").unwrap();
+    assert!(delete_ref(&proj_root, "refs/tags/packed").unwrap_err().starts_with("unable to create"));
+    assert!(read_ref(&proj_root, "refs/tags/packed").unwrap().is_some());
+    fs::remove_file(proj_root.join(".git/packed-refs.lock")).unwrap();
+
+    assert_eq!(delete_ref(&proj_root, "refs/tags/loose"), Ok(true));
+    assert_eq!(delete_ref(&proj_root, "refs/tags/packed"), Ok(true));
+    assert_eq!(delete_ref(&proj_root, "refs/tags/packed"), Ok(false));
+    let names: Vec<String> = list_refs(&proj_root, "refs/").unwrap().into_keys().collect();
+    assert_eq!(names, vec!["refs/tags/other"]);
+    assert_eq!(fs::read_to_string(proj_root.join(".git/packed-refs")).unwrap(),
+               format!("# pack-refs with: peeled\n{} refs/tags/other\n", hash));
+    assert!(!proj_root.join(".git/packed-refs.lock").exists());
+}
+
+#[test]
+fn test_pack_refs() {
+    use crate::object::{write_object, write_test_tag, ObjType};
+
+    let proj_root = crate::common::test_dir("pack_refs");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let tag = write_test_tag(&objects_root, blob, "blob", "v1");
+    write_ref(&proj_root, "refs/heads/master", blob).unwrap();
+    write_ref(&proj_root, "refs/tags/light", blob).unwrap();
+    write_ref(&proj_root, "refs/tags/v1", tag).unwrap();
+    write_ref(&proj_root, "refs/heads/feature/x", blob).unwrap();
+    fs::create_dir_all(proj_root.join(".git/refs/remotes/origin")).unwrap();
+    fs::write(proj_root.join(".git/refs/remotes/origin/HEAD"), "ref: refs/heads/master\n").unwrap();
+
+    pack_refs(&proj_root, false).unwrap();
+    assert_eq!(fs::read_to_string(proj_root.join(".git/packed-refs")).unwrap(),
+               format!("# pack-refs with: peeled fully-peeled sorted \n{} refs/tags/light\n{} refs/tags/v1\n^{}\n",
+                       blob.string(), tag.string(), blob.string()));
+    assert!(!proj_root.join(".git/refs/tags/v1").exists());
+    assert!(proj_root.join(".git/refs/heads/master").exists());
+    assert_eq!(read_ref(&proj_root, "refs/tags/v1"), Ok(Some(tag)));
+
+    assert!(!proj_root.join(".git/packed-refs.lock").exists());
+
+    // another writer holds the lock
+    fs::write(proj_root.join(".git/packed-refs.lock"), "").unwrap();
+    assert!(pack_refs(&proj_root, true).unwrap_err().starts_with("unable to create"));
+    assert!(proj_root.join(".git/refs/heads/master").exists());
+    fs::remove_file(proj_root.join(".git/packed-refs.lock")).unwrap();
+
+    pack_refs(&proj_root, true).unwrap();
+    assert!(!proj_root.join(".git/refs/heads/master").exists());
+    assert!(!proj_root.join(".git/refs/heads/feature").exists());
+    assert!(proj_root.join(".git/refs/heads").is_dir());
+    assert!(proj_root.join(".git/refs/remotes/origin/HEAD").exists());
+    assert_eq!(list_refs(&proj_root, "refs/").unwrap().len(), 5);
+}
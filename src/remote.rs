@@ -0,0 +1,53 @@
+use clap::ArgMatches;
+use crate::common::get_project_root;
+use crate::config::{local_config_path, GitConfig};
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let config_path = local_config_path(&proj_root);
+    let mut config = GitConfig::from_file(&config_path)?;
+
+    match matches.subcommand() {
+        Some(("add", sub)) => {
+            let name = sub.value_of("name").ok_or("no remote name specified")?;
+            let url = sub.value_of("url").ok_or("no url specified")?;
+            add_remote(&mut config, name, url)?;
+            config.write_file(&config_path)
+        },
+        Some(("remove", sub)) => {
+            let name = sub.value_of("name").ok_or("no remote name specified")?;
+            if !config.remove_section("remote", Some(name)) {
+                return Err(format!("no such remote: '{}'", name));
+            }
+            config.write_file(&config_path)
+        },
+        _ => {
+            for section in config.sections("remote") {
+                let name = match section.subsection() {
+                    Some(n) => n,
+                    None => continue,
+                };
+                if matches.is_present("verbose") {
+                    let url = section.get("url").unwrap_or("");
+                    println!("{}\t{} (fetch)", name, url);
+                    println!("{}\t{} (push)", name, section.get("pushurl").unwrap_or(url));
+                } else {
+                    println!("{}", name);
+                }
+            }
+            Ok(())
+        },
+    }
+}
+
+pub fn add_remote(config: &mut GitConfig, name: &str, url: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '"' || c == '/') {
+        return Err(format!("'{}' is not a valid remote name", name));
+    }
+    if config.has_section("remote", Some(name)) {
+        return Err(format!("remote {} already exists.", name));
+    }
+    config.add("remote", Some(name), "url", url);
+    config.add("remote", Some(name), "fetch", &format!("+refs/heads/*:refs/remotes/{}/*", name));
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::checkout::switch_tree;
+use crate::common::{common_dir, get_project_root, info, pathspec_from_cwd, pathspec_matches};
+use crate::hash::Hash;
+use crate::head::{update_head, Head};
+use crate::index::{Index, IndexEntry};
+use crate::object::{peel, Commit, Tree};
+use crate::refs::write_ref;
+use crate::rev::{self, abbreviate};
+use crate::state::MergeState;
+
+/*
+    reset [<tree-ish>] <path>...                  index entries back to the tree-ish, HEAD by default
+                                                  the working tree is left as it is
+    reset [--soft | --mixed | --hard] [<commit>]  move the current branch, or a detached HEAD, to the commit
+                                                  the index too unless --soft, the working tree too with --hard
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let mut args: Vec<&str> = matches.values_of("args").map(|v| v.collect()).unwrap_or_default();
+    let mut index = Index::from_file(&proj_root)?.unwrap_or_default();
+
+    // the first argument is a tree-ish unless it names a path
+    let is_path = |arg: &str| proj_root.join(arg).exists() || pathspec_from_cwd(arg).
+        is_ok_and(|spec| index.entries().keys().any(|p| pathspec_matches(&spec, p)));
+    let rev = match args.first() {
+        Some(first) if !is_path(first) && rev::resolve_tree(&proj_root, first).is_ok() => Some(args.remove(0)),
+        _ => None,
+    };
+
+    let mode = ["soft", "mixed", "hard"].iter().copied().find(|m| matches.is_present(m));
+    if args.is_empty() {
+        return reset_commit(&proj_root, rev.unwrap_or("HEAD"), mode.unwrap_or("mixed"));
+    }
+    if let Some(mode) = mode {
+        return Err(format!("cannot do a --{} reset with paths", mode));
+    }
+    let tree = match rev {
+        Some(rev) => Some(rev::resolve_tree(&proj_root, rev)?),
+        // an unborn branch has nothing to reset to, the paths are just unstaged
+        None => match Head::from_root(&proj_root)?.hash() {
+            Some(_) => Some(rev::resolve_tree(&proj_root, "HEAD")?),
+            None => None,
+        },
+    };
+
+    let specs = args.iter().map(|a| pathspec_from_cwd(a)).collect::<Result<Vec<_>, _>>()?;
+    reset_paths(&common_dir(&proj_root).join("objects"), &mut index, tree, &specs)?;
+    index.write_file(&proj_root)
+}
+
+// the previous HEAD is kept in ORIG_HEAD, so `reset ORIG_HEAD` undoes it
+fn reset_commit(proj_root: &Path, rev: &str, mode: &str) -> Result<(), String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let hash = peel(&objects_root, rev::resolve(proj_root, rev)?)?.0;
+    let commit = Commit::load(&objects_root, hash).map_err(|_| format!("{} is not a commit", rev))?;
+    let mut index = Index::from_file(proj_root)?.unwrap_or_default();
+
+    match mode {
+        "soft" => {},
+        "mixed" => {
+            reset_paths(&objects_root, &mut index, Some(commit.tree()), &[String::new()])?;
+            index.write_file(proj_root)?;
+        },
+        _ => {
+            switch_tree(proj_root, &index, commit.tree())?;
+        },
+    }
+    let old = Head::from_root(proj_root)?.hash();
+    if let Some(old) = old {
+        write_ref(proj_root, "ORIG_HEAD", old)?;
+    }
+    update_head(proj_root, hash, old)?;
+    // as in git, resetting gives up a merge stopped by a conflict
+    MergeState::remove(proj_root)?;
+    if mode == "hard" {
+        info(&format!("HEAD is now at {} {}", abbreviate(&objects_root, hash),
+                      commit.message().lines().next().unwrap_or("")));
+    }
+    Ok(())
+}
+
+// entries matching the pathspecs replaced by those of the tree, or removed when the tree has none
+fn reset_paths(objects_root: &Path, index: &mut Index, tree: Option<Hash>, specs: &[String]) -> Result<(), String> {
+    let mut tree_files: BTreeMap<PathBuf, (u32, Hash)> = BTreeMap::new();
+    if let Some(tree) = tree {
+        Tree::load(objects_root, tree)?.walk(objects_root, &mut |path, node| {
+            if !node.is_dir() {
+                tree_files.insert(path.to_path_buf(), (node.mode(), node.hash()));
+            }
+            Ok(())
+        })?;
+    }
+
+    let entries = index.entries();
+    // the root pathspec "" matches everything, an empty index and tree included
+    for spec in specs.iter().filter(|s| !s.is_empty()) {
+        let in_index = entries.keys().any(|p| pathspec_matches(spec, p));
+        if !in_index && !tree_files.keys().any(|p| pathspec_matches(spec, p)) {
+            return Err(format!("pathspec '{}' did not match any files", spec));
+        }
+    }
+    let matches = |path: &Path| specs.iter().any(|s| pathspec_matches(s, path));
+
+    for (path, entry) in entries.iter().filter(|(p, _)| matches(p)) {
+        match tree_files.get(path) {
+            // unchanged entries keep their stat
+            Some((mode, hash)) if entry.mode() == *mode && entry.hash() == *hash => {},
+            _ => index.delete_entry(path)?,
+        }
+    }
+    let kept = index.entries();
+    for (path, (mode, hash)) in tree_files.iter().filter(|(p, _)| matches(p)) {
+        if !kept.contains_key(path) {
+            index.add_entry_with_stat(IndexEntry::without_stat(path.clone(), *mode, *hash));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reset_paths() {
+    use crate::object::{write_object, ObjType, TreeNode};
+
+    let objects_root = crate::common::test_dir("reset_paths");
+    let blob = |content: &[u8]| write_object(&objects_root, ObjType::Blob, content).unwrap();
+    let entry = |path: &str, hash| IndexEntry::without_stat(PathBuf::from(path), 0o100644, hash);
+    let mut tree = Tree::new();
+    tree.add_tree_node(TreeNode::from_index_entry(&entry("a.txt", blob(b"a\n"))).unwrap());
+    tree.add_tree_node(TreeNode::from_index_entry(&entry("b.txt", blob(b"b\n"))).unwrap());
+    let tree = tree.write(&objects_root).unwrap();
+
+    let mut index = Index::new();
+    index.add_entry_with_stat(entry("a.txt", blob(b"changed\n")));
+    index.add_entry_with_stat(entry("b.txt", blob(b"changed\n")));
+    index.add_entry_with_stat(entry("new.txt", blob(b"new\n")));
+
+    reset_paths(&objects_root, &mut index, Some(tree), &["a.txt".to_string(), "new.txt".to_string()]).unwrap();
+    let entries = index.entries();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), vec![Path::new("a.txt"), Path::new("b.txt")]);
+    assert_eq!(entries[Path::new("a.txt")].hash(), blob(b"a\n"));
+    assert_eq!(entries[Path::new("b.txt")].hash(), blob(b"changed\n"));
+    assert!(reset_paths(&objects_root, &mut index, Some(tree), &["none".to_string()]).is_err());
+
+    // no commits yet
+    reset_paths(&objects_root, &mut index, None, &["*.txt".to_string()]).unwrap();
+    assert!(index.entries().is_empty());
+}
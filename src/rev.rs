@@ -0,0 +1,277 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::common::common_dir;
+use crate::config;
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::index::Index;
+use crate::object::{peel, ObjType, Commit, Tree};
+use crate::pack::find_packed_by_prefix;
+use crate::refs;
+
+/*
+    revision syntax
+    <hash>, <short hash>, HEAD, ORIG_HEAD, <branch>, <tag>, refs/...
+    <rev>:<path>  entry in the tree of <rev>
+    :<path>, :0:<path>  entry in the index
+ */
+pub fn resolve(proj_root: &Path, rev: &str) -> Result<Hash, String> {
+    if let Some(spec) = rev.strip_prefix(':') {
+        return resolve_index_path(proj_root, spec);
+    }
+    if let Some((base, path)) = rev.split_once(':') {
+        return resolve_tree_path(proj_root, base, path);
+    }
+    resolve_ancestry(proj_root, rev)
+}
+
+// the commit a revision names, annotated tags peeled down to it
+pub fn resolve_commit(proj_root: &Path, rev: &str) -> Result<Hash, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    match peel(&objects_root, resolve(proj_root, rev)?)? {
+        (hash, ObjType::Commit) => Ok(hash),
+        _ => Err(format!("{} is not a commit", rev)),
+    }
+}
+
+// <name> followed by any number of ~<n> (n-th first parent) and ^<n> (n-th parent)
+fn resolve_ancestry(proj_root: &Path, rev: &str) -> Result<Hash, String> {
+    let pos = rev.find(['~', '^']).unwrap_or(rev.len());
+    let mut hash = resolve_name(proj_root, &rev[..pos])?;
+
+    let objects_root = common_dir(proj_root).join("objects");
+    let mut rest = &rev[pos..];
+    if !rest.is_empty() {
+        // "<tag>~1" is about the tagged commit
+        hash = peel(&objects_root, hash)?.0;
+    }
+    while let Some(op) = rest.chars().next() {
+        if op != '~' && op != '^' {
+            return Err(format!("invalid revision: {}", rev));
+        }
+        let digits = rest[1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len()-1);
+        let n: usize = if digits == 0 {
+            1
+        } else {
+            rest[1..1+digits].parse().map_err(|_| format!("invalid revision: {}", rev))?
+        };
+        rest = &rest[1+digits..];
+
+        if op == '~' {
+            for _ in 0..n {
+                let commit = Commit::load(&objects_root, hash)?;
+                hash = *commit.parents().first().ok_or(format!("invalid revision: {}", rev))?;
+            }
+        } else if n > 0 {
+            let commit = Commit::load(&objects_root, hash)?;
+            hash = *commit.parents().get(n-1).ok_or(format!("invalid revision: {}", rev))?;
+        }
+    }
+    Ok(hash)
+}
+
+fn resolve_name(proj_root: &Path, name: &str) -> Result<Hash, String> {
+    if name == "HEAD" {
+        return Head::from_root(proj_root)?.hash().
+            ok_or("HEAD does not point to any commit".to_string());
+    }
+
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for refname in &candidates {
+        if let Some(hash) = refs::read_ref(proj_root, refname)? {
+            return Ok(hash);
+        }
+    }
+
+    let is_hex = name.chars().all(|c| c.is_ascii_hexdigit());
+    if is_hex && name.len() == 40 {
+        return Hash::from_string(&name.to_ascii_lowercase()).
+            ok_or(format!("invalid object name: {}", name));
+    }
+    if is_hex && name.len() >= 4 {
+        let objects_root = common_dir(proj_root).join("objects");
+        let found = find_objects_by_prefix(&objects_root, &name.to_ascii_lowercase())?;
+        return match found.len() {
+            0 => Err(format!("unknown revision: {}", name)),
+            1 => Ok(found[0]),
+            _ => Err(format!("short object ID {} is ambiguous", name)),
+        };
+    }
+
+    Err(format!("unknown revision: {}", name))
+}
+
+// loose and packed objects, an object in both is listed once
+pub fn find_objects_by_prefix(objects_root: &Path, prefix: &str) -> Result<Vec<Hash>, String> {
+    let mut ret = find_packed_by_prefix(objects_root, prefix)?;
+    let dir: PathBuf = objects_root.join(&prefix[..2]);
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+            let entry = entry.map_err(|e| e.to_string())?;
+            let rest = entry.file_name();
+            let rest = rest.to_str().unwrap_or("");
+            if rest.starts_with(&prefix[2..]) {
+                if let Some(hash) = Hash::from_string(&format!("{}{}", &prefix[..2], rest)) {
+                    ret.push(hash);
+                }
+            }
+        }
+    }
+    ret.sort();
+    ret.dedup();
+    Ok(ret)
+}
+
+thread_local! {
+    // --abbrev, per thread so that tests don't see each other's, core.abbrev when not given
+    static MIN_ABBREV: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+pub fn set_abbrev(digits: usize) {
+    MIN_ABBREV.with(|m| m.set(Some(digits.clamp(4, 40))));
+}
+
+/*
+    the shortest prefix, --abbrev digits (7 by default) at least, no other object starts with
+    just the minimum when the objects can't be listed
+ */
+pub fn abbreviate(objects_root: &Path, hash: Hash) -> String {
+    let full = hash.string();
+    let min = MIN_ABBREV.with(|m| m.get()).unwrap_or_else(config::abbrev);
+    let others = match find_objects_by_prefix(objects_root, &full[..2]) {
+        Ok(others) => others,
+        Err(_) => return full[..min].to_string(),
+    };
+    let common = others.iter().filter(|h| **h != hash).
+        map(|h| h.string().chars().zip(full.chars()).take_while(|(a, b)| a == b).count()).max().unwrap_or(0);
+    full[..(common + 1).clamp(min, full.len())].to_string()
+}
+
+// tree of a commit, or the tree itself
+pub fn resolve_tree(proj_root: &Path, rev: &str) -> Result<Hash, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    match peel(&objects_root, resolve_ancestry(proj_root, rev)?)? {
+        (hash, ObjType::Commit) => Ok(Commit::load(&objects_root, hash)?.tree()),
+        (hash, ObjType::Tree) => Ok(hash),
+        _ => Err(format!("{} is not a tree-ish", rev)),
+    }
+}
+
+fn resolve_tree_path(proj_root: &Path, base: &str, path: &str) -> Result<Hash, String> {
+    let objects_root = common_dir(proj_root).join("objects");
+    let tree = Tree::load(&objects_root, resolve_tree(proj_root, base)?)?;
+    match tree.find(&objects_root, Path::new(path))? {
+        Some((_, hash)) => Ok(hash),
+        None => Err(format!("path '{}' does not exist in '{}'", path, base)),
+    }
+}
+
+fn resolve_index_path(proj_root: &Path, spec: &str) -> Result<Hash, String> {
+    // ":<stage>:<path>"
+    let path = match spec.split_once(':') {
+        Some((stage, path)) if stage.len() == 1 && stage.chars().all(|c| c.is_ascii_digit()) => {
+            if stage != "0" {
+                return Err(format!("stage {} is not supported", stage));
+            }
+            path
+        },
+        _ => spec,
+    };
+
+    let index = Index::from_file(proj_root)?.ok_or("no index found".to_string())?;
+    match index.entries().get(Path::new(path)) {
+        Some(entry) => Ok(entry.hash()),
+        None => Err(format!("path '{}' is not in the index", path)),
+    }
+}
+
+#[test]
+fn test_resolve_name() {
+    let proj_root = crate::common::test_dir("resolve_name");
+    let hash = "064a92d783f99851d1517b51ba0b2aed4a1d3128";
+    fs::create_dir_all(proj_root.join(".git/refs/tags")).unwrap();
+    fs::write(proj_root.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+    fs::write(proj_root.join(".git/packed-refs"), format!("{} refs/heads/master\n", hash)).unwrap();
+    fs::write(proj_root.join(".git/refs/tags/v1"), format!("{}\n", hash)).unwrap();
+
+    let expected = Hash::from_string(hash).unwrap();
+    for rev in ["HEAD", "master", "heads/master", "refs/heads/master", "v1", hash] {
+        assert_eq!(resolve(&proj_root, rev), Ok(expected), "{}", rev);
+    }
+    assert!(resolve(&proj_root, "unknown").is_err());
+}
+
+#[test]
+fn test_resolve_ancestry() {
+    let proj_root = crate::common::test_dir("resolve_ancestry");
+    let objects_root = common_dir(&proj_root).join("objects");
+    let write_commit = |parents: Vec<Hash>, message: &str| -> Hash {
+        let author = crate::object::CommitUser::from("a".to_string(), "a@example.com".to_string(),
+                                                     crate::object::CommitterType::Author);
+        let committer = author.change_committer_type_as(crate::object::CommitterType::Committer);
+        let commit = Commit::from(Hash::default(), parents, author, committer, message.to_string());
+        let (hash, body) = commit.generate_hash_and_depress().unwrap();
+        let path = objects_root.join(hash.generate_path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, body).unwrap();
+        hash
+    };
+    let root = write_commit(vec![], "root\n");
+    let first = write_commit(vec![root], "first\n");
+    let side = write_commit(vec![root], "side\n");
+    let merge = write_commit(vec![first, side], "merge\n");
+    fs::write(proj_root.join(".git/HEAD"), format!("{}\n", merge.string())).unwrap();
+
+    let tests = [
+        ("HEAD", Ok(merge)),
+        ("HEAD^", Ok(first)),
+        ("HEAD^1", Ok(first)),
+        ("HEAD^2", Ok(side)),
+        ("HEAD^0", Ok(merge)),
+        ("HEAD~", Ok(first)),
+        ("HEAD~2", Ok(root)),
+        ("HEAD^2~1", Ok(root)),
+        ("HEAD^^", Ok(root)),
+        ("HEAD~3", Err("invalid revision: HEAD~3".to_string())),
+        ("HEAD^3", Err("invalid revision: HEAD^3".to_string())),
+        ("HEAD~x", Err("invalid revision: HEAD~x".to_string())),
+        ("HEAD~1abc", Err("invalid revision: HEAD~1abc".to_string())),
+        ("HEAD^é", Err("invalid revision: HEAD^é".to_string())),
+        ("HEAD~é", Err("invalid revision: HEAD~é".to_string())),
+    ];
+    for t in tests {
+        assert_eq!(resolve(&proj_root, t.0), t.1, "{}", t.0);
+    }
+}
+
+#[test]
+fn test_abbreviate() {
+    let objects_root = crate::common::test_dir("abbreviate").join("objects");
+    let hash = Hash::from_string("064a92d783f99851d1517b51ba0b2aed4a1d3128").unwrap();
+    assert_eq!(abbreviate(&objects_root, hash), "064a92d");
+
+    // only the file names are looked at
+    for name in ["064a92d783f99851d1517b51ba0b2aed4a1d3128", "064a92d7ffffffffffffffffffffffffffffffff",
+                 "064b000000000000000000000000000000000000"] {
+        fs::create_dir_all(objects_root.join(&name[..2])).unwrap();
+        fs::write(objects_root.join(&name[..2]).join(&name[2..]), "").unwrap();
+    }
+    assert_eq!(abbreviate(&objects_root, hash), "064a92d78");
+    let other = Hash::from_string("064b000000000000000000000000000000000000").unwrap();
+    assert_eq!(abbreviate(&objects_root, other), "064b000");
+
+    // and the objects listed in pack indexes
+    let packed = Hash::from_string("064a92d783f9ffffffffffffffffffffffffffff").unwrap();
+    fs::create_dir_all(objects_root.join("pack")).unwrap();
+    fs::write(objects_root.join("pack/pack-test.idx"), crate::pack::build_idx(&[(packed, 12), (hash, 40)])).unwrap();
+    assert_eq!(abbreviate(&objects_root, hash), "064a92d783f99");
+    assert_eq!(find_objects_by_prefix(&objects_root, "064a92d783f").unwrap(), vec![hash, packed]);
+}
@@ -0,0 +1,13 @@
+use clap::ArgMatches;
+use crate::common::get_project_root;
+use crate::rev;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let rev = matches.value_of("rev").ok_or("no revision specified")?;
+
+    let proj_root = get_project_root()?;
+    let hash = rev::resolve(&proj_root, rev)?;
+    println!("{}", hash.string());
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::io;
+use std::io::Write;
+use clap::ArgMatches;
+use crate::changes::Snapshot;
+use crate::common::{common_dir, get_project_root};
+use crate::diff::render_changes;
+use crate::object::Commit;
+use crate::rev;
+
+// a commit and its changes from the first parent
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let hash = rev::resolve(&proj_root, matches.value_of("rev").unwrap_or("HEAD"))?;
+    let commit = Commit::load(&objects_root, hash)?;
+
+    let old = Snapshot::from_tree(&objects_root, commit.parent_tree(&objects_root)?)?;
+    let new = Snapshot::from_tree(&objects_root, commit.tree())?;
+
+    let mut out = commit.log_entry(hash, &Vec::new());
+    let changes = render_changes(matches, &objects_root, &old, &new)?;
+    if !changes.is_empty() {
+        out.push('\n');
+        out.push_str(&changes);
+    }
+    io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string())
+}
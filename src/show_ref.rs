@@ -0,0 +1,66 @@
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::get_project_root;
+use crate::error::Error;
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::refs::list_refs;
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let proj_root = get_project_root()?;
+    let refs = show_refs(&proj_root, matches.is_present("heads"), matches.is_present("tags"),
+                         matches.is_present("head"))?;
+
+    // nothing to show is reported only by the exit status, as git does
+    if refs.is_empty() {
+        return Err(Error::Silent);
+    }
+    for (name, hash) in refs {
+        if matches.is_present("hash") {
+            println!("{}", hash.string());
+        } else {
+            println!("{} {}", hash.string(), name);
+        }
+    }
+    Ok(())
+}
+
+// every ref under refs/, or only branches and/or tags, HEAD first when asked for
+fn show_refs(proj_root: &Path, heads: bool, tags: bool, head: bool) -> Result<Vec<(String, Hash)>, String> {
+    let mut ret = Vec::new();
+    if head {
+        if let Some(hash) = Head::from_root(proj_root)?.hash() {
+            ret.push(("HEAD".to_string(), hash));
+        }
+    }
+    let refs = list_refs(proj_root, "refs/")?.into_iter().filter(|(name, _)| {
+        (!heads && !tags) || (heads && name.starts_with("refs/heads/")) || (tags && name.starts_with("refs/tags/"))
+    });
+    ret.extend(refs);
+    Ok(ret)
+}
+
+#[test]
+fn test_show_refs() {
+    use std::fs;
+
+    let proj_root = crate::common::test_dir("show_refs");
+    let a = "064a92d783f99851d1517b51ba0b2aed4a1d3128";
+    let b = "a9993e364706816aba3e25717850c26c9cd0d89d";
+    fs::create_dir_all(proj_root.join(".git/refs/heads")).unwrap();
+    fs::create_dir_all(proj_root.join(".git/refs/remotes/origin")).unwrap();
+    fs::write(proj_root.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+    fs::write(proj_root.join(".git/packed-refs"),
+              format!("# pack-refs with: peeled\n{} refs/heads/master\n{} refs/tags/v1\n", b, a)).unwrap();
+    fs::write(proj_root.join(".git/refs/heads/master"), format!("{}\n", a)).unwrap();
+    fs::write(proj_root.join(".git/refs/remotes/origin/master"), format!("{}\n", b)).unwrap();
+
+    let names = |refs: Vec<(String, Hash)>| refs.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+    let all = show_refs(&proj_root, false, false, false).unwrap();
+    assert_eq!(names(all.clone()), vec!["refs/heads/master", "refs/remotes/origin/master", "refs/tags/v1"]);
+    // a loose ref overrides the packed one
+    assert_eq!(all[0].1, Hash::from_string(a).unwrap());
+    assert_eq!(names(show_refs(&proj_root, true, false, false).unwrap()), vec!["refs/heads/master"]);
+    assert_eq!(names(show_refs(&proj_root, false, true, true).unwrap()), vec!["HEAD", "refs/tags/v1"]);
+    assert_eq!(names(show_refs(&proj_root, true, true, false).unwrap()), vec!["refs/heads/master", "refs/tags/v1"]);
+}
@@ -0,0 +1,248 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::common::git_dir;
+use crate::hash::Hash;
+use crate::refs::{delete_ref, list_refs, read_ref, write_ref};
+
+/*
+    state of an operation stopped by a conflict, kept under .git/ between invocations
+    rebase: .git/rebase-apply/
+    merge: .git/MERGE_HEAD
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Rebase,
+    Merge,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Rebase => "rebase",
+            Operation::Merge => "merge",
+        }
+    }
+}
+
+pub fn in_progress(proj_root: &Path) -> Option<Operation> {
+    let git_dir = git_dir(proj_root);
+    if git_dir.join("rebase-apply").is_dir() {
+        Some(Operation::Rebase)
+    } else if git_dir.join("MERGE_HEAD").is_file() {
+        Some(Operation::Merge)
+    } else {
+        None
+    }
+}
+
+/*
+    .git/rebase-apply
+    head-name: branch being rebased
+    orig-head: the branch tip before the rebase
+    onto: <upstream>
+    todo: commits still to be applied, the stopped one first
+    cdate_is_adate: present with --committer-date-is-author-date
+ */
+#[derive(Debug, PartialEq)]
+pub struct RebaseState {
+    pub head_name: String,
+    pub orig_head: Hash,
+    pub onto: Hash,
+    pub todo: Vec<Hash>,
+    pub committer_date_is_author_date: bool,
+}
+
+impl RebaseState {
+    pub fn load(proj_root: &Path) -> Result<Option<Self>, String> {
+        let dir = rebase_dir(proj_root);
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).map_err(|e| e.to_string());
+        let hash = |name: &str| -> Result<Hash, String> {
+            let value = read(name)?;
+            Hash::from_string(value.trim_end()).ok_or(format!("broken rebase state: {}", name))
+        };
+        let todo = read("todo")?.lines().
+            map(|l| Hash::from_string(l).ok_or("broken rebase state: todo".to_string())).
+            collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Self {
+            head_name: read("head-name")?.trim_end().to_string(),
+            orig_head: hash("orig-head")?,
+            onto: hash("onto")?,
+            todo,
+            committer_date_is_author_date: dir.join("cdate_is_adate").is_file(),
+        }))
+    }
+
+    pub fn save(&self, proj_root: &Path) -> Result<(), String> {
+        let dir = rebase_dir(proj_root);
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let todo: String = self.todo.iter().map(|h| format!("{}\n", h.string())).collect();
+        for (name, content) in [("head-name", format!("{}\n", self.head_name)),
+                                ("orig-head", format!("{}\n", self.orig_head.string())),
+                                ("onto", format!("{}\n", self.onto.string())),
+                                ("todo", todo)] {
+            fs::write(dir.join(name), content).map_err(|e| e.to_string())?;
+        }
+        if self.committer_date_is_author_date {
+            fs::write(dir.join("cdate_is_adate"), "").map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(proj_root: &Path) -> Result<(), String> {
+        fs::remove_dir_all(rebase_dir(proj_root)).map_err(|e| e.to_string())
+    }
+}
+
+fn rebase_dir(proj_root: &Path) -> PathBuf {
+    git_dir(proj_root).join("rebase-apply")
+}
+
+#[test]
+fn test_rebase_state() {
+    let proj_root = crate::common::test_dir("rebase_state");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    assert_eq!(RebaseState::load(&proj_root).unwrap(), None);
+    assert_eq!(in_progress(&proj_root), None);
+
+    let hash = |s: &str| Hash::from_string(&s.repeat(40)).unwrap();
+    let state = RebaseState {
+        head_name: "refs/heads/topic".to_string(),
+        orig_head: hash("1"),
+        onto: hash("2"),
+        todo: vec![hash("3"), hash("4")],
+        committer_date_is_author_date: true,
+    };
+    state.save(&proj_root).unwrap();
+    assert_eq!(in_progress(&proj_root), Some(Operation::Rebase));
+    assert_eq!(RebaseState::load(&proj_root).unwrap(), Some(state));
+
+    RebaseState::remove(&proj_root).unwrap();
+    assert_eq!(in_progress(&proj_root), None);
+}
+
+/*
+    merge stopped by a conflict, in the same files as git
+    .git/MERGE_HEAD: the merged commit, the second parent of the commit concluding the merge
+    .git/MERGE_MSG: the message of that commit
+ */
+#[derive(Debug, PartialEq)]
+pub struct MergeState {
+    pub head: Hash,
+    pub message: String,
+}
+
+impl MergeState {
+    pub fn load(proj_root: &Path) -> Result<Option<Self>, String> {
+        let head = match read_ref(proj_root, "MERGE_HEAD")? {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let message = fs::read_to_string(git_dir(proj_root).join("MERGE_MSG")).unwrap_or_default();
+        Ok(Some(Self { head, message }))
+    }
+
+    pub fn save(&self, proj_root: &Path) -> Result<(), String> {
+        fs::write(git_dir(proj_root).join("MERGE_MSG"), &self.message).map_err(|e| e.to_string())?;
+        write_ref(proj_root, "MERGE_HEAD", self.head)
+    }
+
+    // nothing to do without a merge in progress
+    pub fn remove(proj_root: &Path) -> Result<(), String> {
+        delete_ref(proj_root, "MERGE_HEAD")?;
+        let msg_path = git_dir(proj_root).join("MERGE_MSG");
+        if msg_path.is_file() {
+            fs::remove_file(msg_path).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_merge_state() {
+    let proj_root = crate::common::test_dir("merge_state");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    assert_eq!(MergeState::load(&proj_root).unwrap(), None);
+
+    let state = MergeState {
+        head: Hash::from_string(&"1".repeat(40)).unwrap(),
+        message: "Merge branch 'topic'\n".to_string(),
+    };
+    state.save(&proj_root).unwrap();
+    assert_eq!(in_progress(&proj_root), Some(Operation::Merge));
+    assert_eq!(MergeState::load(&proj_root).unwrap(), Some(state));
+
+    MergeState::remove(&proj_root).unwrap();
+    assert_eq!(in_progress(&proj_root), None);
+    MergeState::remove(&proj_root).unwrap();
+}
+
+/*
+    bisect, in the same files as git
+    .git/BISECT_START: the branch, or the commit when detached, to go back to on reset
+    refs/bisect/bad, refs/bisect/good-<hash>
+ */
+#[derive(Debug, PartialEq)]
+pub struct BisectState {
+    pub start: String,
+    pub bad: Option<Hash>,
+    pub good: Vec<Hash>,
+}
+
+impl BisectState {
+    pub fn load(proj_root: &Path) -> Result<Option<Self>, String> {
+        let start_path = git_dir(proj_root).join("BISECT_START");
+        if !start_path.is_file() {
+            return Ok(None);
+        }
+        let start = fs::read_to_string(start_path).map_err(|e| e.to_string())?.trim_end().to_string();
+        let refs = list_refs(proj_root, "refs/bisect/")?;
+        Ok(Some(Self {
+            start,
+            bad: refs.get("refs/bisect/bad").cloned(),
+            good: refs.iter().filter(|(name, _)| name.starts_with("refs/bisect/good-")).map(|(_, h)| *h).collect(),
+        }))
+    }
+
+    pub fn save(&self, proj_root: &Path) -> Result<(), String> {
+        fs::write(git_dir(proj_root).join("BISECT_START"), format!("{}\n", self.start)).
+            map_err(|e| e.to_string())?;
+        if let Some(bad) = self.bad {
+            write_ref(proj_root, "refs/bisect/bad", bad)?;
+        }
+        for good in &self.good {
+            write_ref(proj_root, &format!("refs/bisect/good-{}", good.string()), *good)?;
+        }
+        Ok(())
+    }
+
+    pub fn remove(proj_root: &Path) -> Result<(), String> {
+        for name in list_refs(proj_root, "refs/bisect/")?.keys() {
+            delete_ref(proj_root, name)?;
+        }
+        fs::remove_file(git_dir(proj_root).join("BISECT_START")).map_err(|e| e.to_string())
+    }
+}
+
+#[test]
+fn test_bisect_state() {
+    let proj_root = crate::common::test_dir("bisect_state");
+    fs::create_dir_all(proj_root.join(".git")).unwrap();
+    assert_eq!(BisectState::load(&proj_root).unwrap(), None);
+
+    let hash = |s: &str| Hash::from_string(&s.repeat(40)).unwrap();
+    let mut state = BisectState { start: "master".to_string(), bad: None, good: vec![] };
+    state.save(&proj_root).unwrap();
+    assert_eq!(BisectState::load(&proj_root).unwrap().as_ref(), Some(&state));
+
+    state.bad = Some(hash("1"));
+    state.good = vec![hash("2"), hash("3")];
+    state.save(&proj_root).unwrap();
+    assert_eq!(BisectState::load(&proj_root).unwrap(), Some(state));
+
+    BisectState::remove(&proj_root).unwrap();
+    assert_eq!(BisectState::load(&proj_root).unwrap(), None);
+    assert!(list_refs(&proj_root, "refs/bisect/").unwrap().is_empty());
+}
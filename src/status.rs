@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::branch::upstream_of;
+use crate::changes::{diff_snapshots, FileChange, Snapshot};
+use crate::common::{cmp_path_bytes, common_dir, get_project_root, pathspec_from_cwd, pathspec_matches};
+use crate::hash::Hash;
+use crate::head::Head;
+use crate::ignore::Ignore;
+use crate::index::Index;
+use crate::merge_base::ahead_behind;
+use crate::object::Commit;
+use crate::refs::{read_ref, shorten_ref};
+use crate::rev::abbreviate;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let head = Head::from_root(&proj_root)?;
+    let index = Index::from_file(&proj_root)?.unwrap_or_default();
+
+    let head_snapshot = match head.hash() {
+        Some(hash) => Snapshot::from_tree(&objects_root, Commit::load(&objects_root, hash)?.tree())?,
+        None => Snapshot::empty(),
+    };
+    // only the paths matching one of the pathspecs, all of them without any
+    let specs = matches.values_of("pathspec").map_or(Ok(Vec::new()), |v| v.map(pathspec_from_cwd).collect())?;
+    let selected = |path: &Path| specs.is_empty() || specs.iter().any(|s| pathspec_matches(s, path));
+
+    let staged = Snapshot::from_index(&index);
+    let mut to_be_committed = diff_snapshots(&head_snapshot, &staged);
+    to_be_committed.retain(|c| selected(&c.path));
+    let mut not_staged = diff_snapshots(&staged, &Snapshot::from_work_tree(&proj_root, &index)?);
+    not_staged.retain(|c| selected(&c.path));
+    let tracked: BTreeSet<PathBuf> = index.entries().into_keys().collect();
+    let ignore = Ignore::from_work_tree(&proj_root)?;
+    let mut untracked = Vec::new();
+    for path in untracked_files(&proj_root, Path::new(""), &tracked, &ignore)? {
+        // a directory outside of the pathspecs may still have matching files in it
+        match path.strip_suffix('/') {
+            Some(dir) if !selected(Path::new(dir)) => {
+                untracked.extend(files_under(&proj_root, Path::new(dir), Some(&ignore))?)
+            },
+            _ => untracked.push(path),
+        }
+    }
+    untracked.retain(|p| selected(Path::new(p)));
+    // byte-wise as git shows them, whatever order read_dir gives
+    to_be_committed.sort_by(|a, b| cmp_path_bytes(&a.path, &b.path));
+    not_staged.sort_by(|a, b| cmp_path_bytes(&a.path, &b.path));
+    untracked.sort();
+
+    // -z implies --porcelain, as in git
+    if matches.is_present("porcelain") || matches.is_present("null") {
+        let out = porcelain(&to_be_committed, &not_staged, &untracked, matches.is_present("null"));
+        return io::stdout().write_all(out.as_bytes()).map_err(|e| e.to_string());
+    }
+
+    match head.branch() {
+        Some(branch) => println!("On branch {}", branch),
+        None => println!("HEAD detached at {}", abbreviate(&objects_root, head.hash().unwrap_or_default())),
+    }
+    if let Some(branch) = head.branch() {
+        if let Some(line) = tracking_summary(&proj_root, &branch, head.hash())? {
+            println!("{}", line);
+        }
+    }
+    if head.hash().is_none() {
+        println!("\nNo commits yet");
+    }
+    print_changes("Changes to be committed:", &to_be_committed);
+    print_changes("Changes not staged for commit:", &not_staged);
+    if !untracked.is_empty() {
+        println!("\nUntracked files:");
+        for path in &untracked {
+            println!("\t{}", path);
+        }
+    }
+
+    if to_be_committed.is_empty() {
+        match (not_staged.is_empty(), untracked.is_empty()) {
+            (false, _) => println!("\nno changes added to commit"),
+            (true, false) => println!("\nnothing added to commit but untracked files present"),
+            (true, true) => println!("\nnothing to commit, working tree clean"),
+        }
+    }
+    Ok(())
+}
+
+/*
+    "XY <path>" per path, X for the index and Y for the working tree, "?? <path>" for untracked ones
+    a rename is "R  <old> -> <new>", paths with special characters quoted as git does
+    with nul, records end with NUL and the paths are as is, a rename being "R  <new>\0<old>"
+ */
+fn porcelain(staged: &[FileChange], unstaged: &[FileChange], untracked: &[String], nul: bool) -> String {
+    // ordered by the whole path strings as in git, "a-b" before "a/b"
+    let mut records: BTreeMap<String, (Option<&FileChange>, Option<&FileChange>)> = BTreeMap::new();
+    for change in staged {
+        records.entry(change.path.to_string_lossy().to_string()).or_default().0 = Some(change);
+    }
+    for change in unstaged {
+        records.entry(change.path.to_string_lossy().to_string()).or_default().1 = Some(change);
+    }
+    let path_str = |path: &str| match nul {
+        true => path.to_string(),
+        false => quote_path(path),
+    };
+    let term = if nul { '\0' } else { '\n' };
+
+    let mut ret = String::new();
+    for (path, (x, y)) in records {
+        ret.push(x.map_or(' ', |c| c.status()));
+        ret.push(y.map_or(' ', |c| c.status()));
+        ret.push(' ');
+        match x.and_then(|c| c.old_path.as_ref()).map(|p| p.to_string_lossy()) {
+            Some(old_path) if nul => ret.push_str(&format!("{}\0{}", path_str(&path), path_str(&old_path))),
+            Some(old_path) => ret.push_str(&format!("{} -> {}", path_str(&old_path), path_str(&path))),
+            None => ret.push_str(&path_str(&path)),
+        }
+        ret.push(term);
+    }
+    for path in untracked {
+        ret.push_str(&format!("?? {}{}", path_str(path), term));
+    }
+    ret
+}
+
+// in double quotes with C style escapes when the path has control characters, '"', '\\' or non-ASCII bytes
+fn quote_path(path: &str) -> String {
+    if !path.bytes().any(|b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f) {
+        return path.to_string();
+    }
+    let mut ret = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'\t' => ret.push_str("\\t"),
+            b'\n' => ret.push_str("\\n"),
+            b'\r' => ret.push_str("\\r"),
+            b'"' => ret.push_str("\\\""),
+            b'\\' => ret.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => ret.push_str(&format!("\\{:03o}", b)),
+            b => ret.push(b as char),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[test]
+fn test_porcelain() {
+    let hash = |s: &str| Some(Hash::from_string(&s.repeat(40)).unwrap());
+    let change = |path: &str, old, new, old_path: Option<&str>| FileChange {
+        path: PathBuf::from(path), old, new, old_path: old_path.map(PathBuf::from), old_mode: None, new_mode: None,
+    };
+    let odd = "dir/a b\nc.txt";
+    let staged = vec![change("new.txt", None, hash("1"), None), change(odd, hash("1"), hash("2"), None),
+                      change("moved.txt", hash("1"), hash("1"), Some("orig.txt"))];
+    let unstaged = vec![change(odd, hash("2"), hash("3"), None), change("gone.txt", hash("1"), None, None)];
+    let untracked = vec!["tmp/".to_string()];
+
+    assert_eq!(porcelain(&staged, &unstaged, &untracked, false),
+               "MM \"dir/a b\\nc.txt\"\n D gone.txt\nR  orig.txt -> moved.txt\nA  new.txt\n?? tmp/\n");
+
+    // read back as a consumer of -z would
+    let out = porcelain(&staged, &unstaged, &untracked, true);
+    let mut fields = out.strip_suffix('\0').unwrap().split('\0');
+    let mut records = Vec::new();
+    while let Some(record) = fields.next() {
+        let (status, path) = record.split_at(3);
+        let old_path = if status.starts_with('R') { fields.next() } else { None };
+        records.push((status.trim_end(), path, old_path));
+    }
+    assert_eq!(records, vec![("MM", odd, None), (" D", "gone.txt", None), ("R", "moved.txt", Some("orig.txt")),
+                             ("A", "new.txt", None), ("??", "tmp/", None)]);
+}
+
+// relation of the branch to its upstream, None without an upstream
+fn tracking_summary(proj_root: &Path, branch: &str, local: Option<Hash>) -> Result<Option<String>, String> {
+    let upstream = match upstream_of(proj_root, branch)? {
+        Some(u) => u,
+        None => return Ok(None),
+    };
+    let name = shorten_ref(&upstream);
+    let (local, remote) = match (local, read_ref(proj_root, &upstream)?) {
+        (Some(l), Some(r)) => (l, r),
+        (_, None) => return Ok(Some(format!("Your branch is based on '{}', but the upstream is gone.", name))),
+        (None, Some(_)) => return Ok(None),
+    };
+
+    let plural = |n: usize| if n == 1 { "commit" } else { "commits" };
+    let line = match ahead_behind(&common_dir(proj_root).join("objects"), local, remote)? {
+        (0, 0) => format!("Your branch is up to date with '{}'.", name),
+        (ahead, 0) => format!("Your branch is ahead of '{}' by {} {}.", name, ahead, plural(ahead)),
+        (0, behind) => format!("Your branch is behind '{}' by {} {}, and can be fast-forwarded.",
+                               name, behind, plural(behind)),
+        (ahead, behind) => format!("Your branch and '{}' have diverged,\n\
+                                    and have {} and {} different commits each, respectively.",
+                                   name, ahead, behind),
+    };
+    Ok(Some(line))
+}
+
+fn print_changes(title: &str, changes: &[FileChange]) {
+    if changes.is_empty() {
+        return;
+    }
+    println!("\n{}", title);
+    for change in changes {
+        let label = match change.status() {
+            'A' => "new file:",
+            'D' => "deleted:",
+            'R' => "renamed:",
+            _ => "modified:",
+        };
+        let path = match &change.old_path {
+            Some(old_path) => format!("{} -> {}", old_path.display(), change.path.display()),
+            None => change.path.display().to_string(),
+        };
+        println!("\t{:<12}{}", label, path);
+    }
+}
+
+/*
+    files outside of the index and not ignored, sorted byte-wise
+    a directory without tracked files is shown as "dir/", unless all of its files are ignored
+ */
+fn untracked_files(proj_root: &Path, dir: &Path, tracked: &BTreeSet<PathBuf>, ignore: &Ignore)
+                   -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    let mut entries = fs::read_dir(proj_root.join(dir)).map_err(|e| e.to_string())?.
+        map(|e| e.map(|e| e.file_name()).map_err(|e| e.to_string())).
+        collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for name in entries {
+        if name == ".git" {
+            continue;
+        }
+        let path = dir.join(&name);
+        let meta = fs::symlink_metadata(proj_root.join(&path)).map_err(|e| e.to_string())?;
+        if meta.is_dir() {
+            if tracked.iter().any(|t| t.starts_with(&path)) {
+                ret.extend(untracked_files(proj_root, &path, tracked, ignore)?);
+            } else if !files_under(proj_root, &path, Some(ignore))?.is_empty() {
+                ret.push(format!("{}/", path.display()));
+            }
+        } else if !tracked.contains(&path) && !ignore.is_ignored(&path, false) {
+            ret.push(path.display().to_string());
+        }
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+// every file under dir tracked or not, ignored directories are not entered, in no particular order
+pub fn files_under(proj_root: &Path, dir: &Path, ignore: Option<&Ignore>) -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    for entry in fs::read_dir(proj_root.join(dir)).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        let path = dir.join(entry.file_name());
+        let is_dir = entry.file_type().map_err(|e| e.to_string())?.is_dir();
+        if ignore.is_some_and(|i| i.is_ignored(&path, is_dir)) {
+            continue;
+        }
+        if is_dir {
+            ret.extend(files_under(proj_root, &path, ignore)?);
+        } else {
+            ret.push(path.display().to_string());
+        }
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_untracked_files() {
+    let proj_root = crate::common::test_dir("untracked_files");
+    for dir in [".git", "src", "new/deep", "empty/deeper", "build", "logs"] {
+        fs::create_dir_all(proj_root.join(dir)).unwrap();
+    }
+    for file in ["README", "a.txt", "src/main.rs", "src/lib.rs", "src-old.rs", "new/deep/x", ".git/HEAD",
+                 "src/main.o", "build/out", "logs/a.log"] {
+        fs::write(proj_root.join(file), "").unwrap();
+    }
+    let tracked: BTreeSet<PathBuf> = ["README", "src/main.rs"].iter().map(PathBuf::from).collect();
+    let untracked = |ignore: &Ignore| untracked_files(&proj_root, Path::new(""), &tracked, ignore).unwrap();
+    // "src-old.rs" before the files in "src/", the same on every run
+    for _ in 0..3 {
+        assert_eq!(untracked(&Ignore::default()),
+                   vec!["a.txt", "build/", "logs/", "new/", "src-old.rs", "src/lib.rs", "src/main.o"]);
+    }
+    // nor a directory with only ignored files in it
+    let ignore = Ignore::parse("*.o\nbuild/\n*.log\n", Path::new(""), Path::new(".gitignore"));
+    assert_eq!(untracked(&ignore), vec!["a.txt", "new/", "src-old.rs", "src/lib.rs"]);
+}
@@ -0,0 +1,21 @@
+use clap::ArgMatches;
+use crate::common::get_project_root;
+use crate::head::{read_symbolic_ref, write_symbolic_ref};
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let name = matches.value_of("name").ok_or("no ref name specified")?;
+    let proj_root = get_project_root()?;
+
+    if let Some(target) = matches.value_of("target") {
+        return write_symbolic_ref(&proj_root, name, target);
+    }
+
+    let target = read_symbolic_ref(&proj_root, name)?.
+        ok_or(format!("ref {} is not a symbolic ref", name))?;
+    if matches.is_present("short") {
+        println!("{}", target.strip_prefix("refs/heads/").unwrap_or(&target));
+    } else {
+        println!("{}", target);
+    }
+    Ok(())
+}
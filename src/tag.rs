@@ -0,0 +1,46 @@
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root, glob_match, info};
+use crate::object::object_exists;
+use crate::refs::{check_branch_name, delete_ref, list_refs, read_ref, write_ref};
+use crate::rev;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+
+    if let Some(name) = matches.value_of("delete") {
+        let hash = read_ref(&proj_root, &format!("refs/tags/{}", name))?.
+            ok_or(format!("tag '{}' not found.", name))?;
+        delete_ref(&proj_root, &format!("refs/tags/{}", name))?;
+        info(&format!("Deleted tag '{}' (was {})", name, rev::abbreviate(&common_dir(&proj_root).join("objects"), hash)));
+        return Ok(());
+    }
+
+    let name = match matches.value_of("name") {
+        Some(n) if !matches.is_present("list") => n,
+        // -l takes the positional argument as the pattern
+        n => {
+            let pattern = matches.value_of("list").or(n);
+            for tag in list_refs(&proj_root, "refs/tags/")?.keys() {
+                let tag = tag.strip_prefix("refs/tags/").unwrap();
+                match pattern {
+                    Some(p) if !glob_match(p, tag, false) => {},
+                    _ => println!("{}", tag),
+                }
+            }
+            return Ok(());
+        },
+    };
+
+    // tag names follow the same rules as branch names
+    check_branch_name(name).map_err(|_| format!("'{}' is not a valid tag name.", name))?;
+    let refname = format!("refs/tags/{}", name);
+    if read_ref(&proj_root, &refname)?.is_some() {
+        return Err(format!("tag '{}' already exists", name));
+    }
+    let target = matches.value_of("commit").unwrap_or("HEAD");
+    let hash = rev::resolve(&proj_root, target)?;
+    if !object_exists(&common_dir(&proj_root).join("objects"), hash) {
+        return Err(format!("{} is not a valid object name", target));
+    }
+    write_ref(&proj_root, &refname, hash)
+}
@@ -0,0 +1,84 @@
+/*
+    git-style trailers, "Key: value" lines in the last paragraph of a commit message
+    the paragraph is a trailer block only when every line is one, or continues the one before it
+    with leading whitespace; the subject is never a trailer
+ */
+
+// trailers of the message as (key, value), empty without a trailer block
+pub fn parse_trailers(message: &str) -> Vec<(String, String)> {
+    let mut ret: Vec<(String, String)> = Vec::new();
+    for line in trailer_block(message).unwrap_or_default() {
+        match (line.starts_with([' ', '\t']), ret.last_mut()) {
+            (true, Some((_, value))) => {
+                value.push(' ');
+                value.push_str(line.trim());
+            },
+            _ => {
+                let (key, value) = split_trailer(line).unwrap();
+                ret.push((key.to_string(), value.to_string()));
+            },
+        }
+    }
+    ret
+}
+
+// the trailer appended to the block, which is started after a blank line when there is none
+// nothing is added when the block has the same trailer already
+pub fn add_trailer(message: &str, key: &str, value: &str) -> String {
+    let body = message.trim_end_matches('\n');
+    let line = format!("{}: {}", key, value);
+    if body.is_empty() {
+        return format!("{}\n", line);
+    }
+    if parse_trailers(body).iter().any(|(k, v)| k == key && v == value) {
+        return format!("{}\n", body);
+    }
+    let separator = if trailer_block(body).is_some() { "\n" } else { "\n\n" };
+    format!("{}{}{}\n", body, separator, line)
+}
+
+fn trailer_block(message: &str) -> Option<Vec<&str>> {
+    let body = message.trim_end_matches('\n');
+    let (_, last) = body.rsplit_once("\n\n")?;
+    let lines: Vec<&str> = last.lines().collect();
+    let is_trailer = |(i, line): (usize, &&str)| split_trailer(line).is_some() ||
+        (i > 0 && line.starts_with([' ', '\t']));
+    match !lines.is_empty() && lines.iter().enumerate().all(is_trailer) {
+        true => Some(lines),
+        false => None,
+    }
+}
+
+// "Key: value", the key made of letters, digits and '-'
+fn split_trailer(line: &str) -> Option<(&str, &str)> {
+    let (key, value) = line.split_once(':')?;
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+#[test]
+fn test_parse_trailers() {
+    let message = "subject\n\nbody\n\nReviewed-by: A <a@example.com>\nSigned-off-by: B\n  continued\n";
+    assert_eq!(parse_trailers(message), vec![("Reviewed-by".to_string(), "A <a@example.com>".to_string()),
+                                             ("Signed-off-by".to_string(), "B continued".to_string())]);
+    // the subject, and a paragraph that isn't only trailers
+    assert!(parse_trailers("Fix: the thing\n").is_empty());
+    assert!(parse_trailers("subject\n\nNote: this is prose\nwhich goes on\n").is_empty());
+    assert!(parse_trailers("subject\n\nsee http://example.com\n").is_empty());
+}
+
+#[test]
+fn test_add_trailer() {
+    let sob = |m: &str| add_trailer(m, "Signed-off-by", "A U Thor <author@example.com>");
+    assert_eq!(sob("subject\n"), "subject\n\nSigned-off-by: A U Thor <author@example.com>\n");
+    assert_eq!(sob("subject\n\nbody: not a trailer block\nat all\n\n"),
+               "subject\n\nbody: not a trailer block\nat all\n\nSigned-off-by: A U Thor <author@example.com>\n");
+    assert_eq!(sob("subject\n\nReviewed-by: B\n"),
+               "subject\n\nReviewed-by: B\nSigned-off-by: A U Thor <author@example.com>\n");
+    let signed = sob("subject\n");
+    assert_eq!(sob(&signed), signed);
+    assert_eq!(add_trailer(&signed, "Reviewed-by", "B"), format!("{}Reviewed-by: B\n", signed));
+    assert_eq!(sob(""), "Signed-off-by: A U Thor <author@example.com>\n");
+}
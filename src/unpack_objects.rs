@@ -0,0 +1,28 @@
+use std::fs;
+use std::io;
+use std::io::Read;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root, info};
+use crate::object::{read_object, write_object};
+use crate::pack::Pack;
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let data = match matches.value_of("pack") {
+        Some(path) => fs::read(path).map_err(|e| e.to_string())?,
+        None => {
+            let mut buf = Vec::new();
+            io::stdin().read_to_end(&mut buf).map_err(|e| e.to_string())?;
+            buf
+        },
+    };
+
+    let objects_root = common_dir(&get_project_root()?).join("objects");
+    let pack = Pack::from_bytes(data)?;
+    let lookup = |hash| read_object(&objects_root, hash).ok();
+    for (_, obj_type, data) in pack.objects(&lookup)? {
+        write_object(&objects_root, obj_type, &data)?;
+    }
+    info(&format!("Unpacking objects: {}, done.", pack.object_num()));
+
+    Ok(())
+}
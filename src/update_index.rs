@@ -0,0 +1,63 @@
+use std::env::current_dir;
+use std::path::Path;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_path_from_project_root, get_project_root, path_from_root};
+use crate::hash::Hash;
+use crate::index::{Index, IndexEntry};
+use crate::object::{object_type, FileType, ObjType};
+
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let values: Vec<&str> = matches.values_of("cacheinfo").ok_or("nothing specified, nothing updated")?.collect();
+    // <mode>,<hash>,<path> in one argument or as three
+    let cacheinfo: Vec<&str> = match values.as_slice() {
+        [one] => one.splitn(3, ',').collect(),
+        _ => values,
+    };
+    let (mode, hash, path) = match cacheinfo.as_slice() {
+        [mode, hash, path] => (*mode, *hash, *path),
+        _ => return Err("option 'cacheinfo' expects <mode>,<object>,<path>".to_string()),
+    };
+
+    let proj_root = get_project_root()?;
+    let prefix = get_path_from_project_root(&current_dir().map_err(|e| e.to_string())?)?;
+    let path = match path_from_root(&prefix, path)? {
+        p if p.as_os_str().is_empty() => return Err(format!("invalid path '{}'", path)),
+        p => p,
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| format!("invalid mode {}", mode))?;
+    let hash = Hash::from_string(hash).ok_or(format!("invalid object name {}", hash))?;
+    check_cacheinfo(&common_dir(&proj_root).join("objects"), mode, hash, &path)?;
+
+    let mut index = Index::from_file(&proj_root)?.unwrap_or_default();
+    index.add_entry_with_stat(IndexEntry::without_stat(path, mode, hash));
+    index.write_file(&proj_root)
+}
+
+// a file mode whose object exists with the matching type, a gitlink may point outside the repository
+fn check_cacheinfo(objects_root: &Path, mode: u32, hash: Hash, path: &Path) -> Result<(), String> {
+    let cannot_add = |reason: &str| format!("--cacheinfo cannot add {}: {}", path.display(), reason);
+    let expected = match FileType::from_mode(mode) {
+        Some((FileType::File, _)) | Some((FileType::SymbolicLink, _)) => ObjType::Blob,
+        Some((FileType::Submodule, _)) => return Ok(()),
+        _ => return Err(cannot_add(&format!("invalid mode {:o}", mode))),
+    };
+    match object_type(objects_root, hash) {
+        Ok(obj_type) if obj_type == expected => Ok(()),
+        Ok(obj_type) => Err(cannot_add(&format!("{} is a {}, not a {}", hash.string(), obj_type.name(),
+                                                expected.name()))),
+        Err(_) => Err(cannot_add(&format!("object {} does not exist", hash.string()))),
+    }
+}
+
+#[test]
+fn test_check_cacheinfo() {
+    let objects_root = crate::common::test_dir("update_index_cacheinfo");
+    let blob = crate::object::write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let path = Path::new("a.txt");
+    assert!(check_cacheinfo(&objects_root, 0o100644, blob, path).is_ok());
+    assert!(check_cacheinfo(&objects_root, 0o120000, blob, path).is_ok());
+    assert!(check_cacheinfo(&objects_root, 0o160000, Hash::default(), path).is_ok());
+    assert!(check_cacheinfo(&objects_root, 0o100664, blob, path).is_err());
+    assert!(check_cacheinfo(&objects_root, 0o40000, blob, path).is_err());
+    assert!(check_cacheinfo(&objects_root, 0o100644, Hash::default(), path).is_err());
+}
@@ -0,0 +1,22 @@
+use std::io;
+use std::io::Write;
+use clap::ArgMatches;
+use crate::common::{common_dir, get_project_root};
+use crate::object::Commit;
+use crate::rev;
+
+// only the presence of a signature is checked, it is not verified with gpg yet
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    let objects_root = common_dir(&proj_root).join("objects");
+    let rev = matches.value_of("rev").unwrap();
+    let commit = Commit::load(&objects_root, rev::resolve(&proj_root, rev)?)?;
+
+    let report = signature_report(&commit).ok_or(format!("no signature found in {}", rev))?;
+    io::stdout().write_all(report.as_bytes()).map_err(|e| e.to_string())
+}
+
+// "gpg: signature present (unverified)" and the signature block
+pub fn signature_report(commit: &Commit) -> Option<String> {
+    commit.signature().map(|signature| format!("gpg: signature present (unverified)\n{}\n", signature))
+}
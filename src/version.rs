@@ -0,0 +1,24 @@
+use clap::ArgMatches;
+use crate::hash::OBJECT_FORMATS;
+use crate::index::INDEX_VERSIONS;
+
+pub fn run(_matches: &ArgMatches) -> Result<(), String> {
+    println!("mygit version {}", env!("CARGO_PKG_VERSION"));
+    print!("{}", capabilities());
+    Ok(())
+}
+
+// for `--version`, clap puts the program name in front
+pub fn long_version() -> String {
+    format!("{}\n{}", env!("CARGO_PKG_VERSION"), capabilities().trim_end())
+}
+
+fn capabilities() -> String {
+    let index_versions: Vec<String> = INDEX_VERSIONS.iter().map(|v| v.to_string()).collect();
+    format!("object formats: {}\nindex versions: {}\n", OBJECT_FORMATS.join(", "), index_versions.join(", "))
+}
+
+#[test]
+fn test_capabilities() {
+    assert_eq!(capabilities(), "object formats: sha1\nindex versions: 2, 3\n");
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use clap::ArgMatches;
+use crate::checkout::checkout_tree;
+use crate::common::{common_dir, get_project_root, info};
+use crate::head::{write_symbolic_ref, Head};
+use crate::object::Commit;
+use crate::refs::read_ref;
+use crate::rev::abbreviate;
+
+/*
+    linked working trees, sharing objects and refs with the main one
+    <path>/.git: "gitdir: <common dir>/worktrees/<name>"
+    <common dir>/worktrees/<name> -- HEAD
+                                  |- index
+                                  |- commondir: "../.."
+                                  |- gitdir: "<path>/.git"
+ */
+pub fn run(matches: &ArgMatches) -> Result<(), String> {
+    let proj_root = get_project_root()?;
+    match matches.subcommand() {
+        Some(("add", sub)) => {
+            let path = sub.value_of("path").ok_or("no path specified")?;
+            let branch = sub.value_of("branch").ok_or("no branch specified")?;
+            add(&proj_root, Path::new(path), branch)
+        },
+        Some(("list", _)) => list(&proj_root),
+        _ => Err("no worktree command specified".to_string()),
+    }
+}
+
+fn add(proj_root: &Path, path: &Path, branch: &str) -> Result<(), String> {
+    let refname = format!("refs/heads/{}", branch);
+    let hash = read_ref(proj_root, &refname)?.ok_or(format!("invalid reference: {}", branch))?;
+    for root in work_tree_roots(proj_root)? {
+        if Head::from_root(&root)?.branch().as_deref() == Some(branch) {
+            return Err(format!("'{}' is already used by worktree at '{}'", branch, root.display()));
+        }
+    }
+    if path.exists() && fs::read_dir(path).map_err(|e| e.to_string())?.next().is_some() {
+        return Err(format!("'{}' already exists", path.display()));
+    }
+
+    let common_dir = common_dir(proj_root);
+    let name = path.file_name().ok_or(format!("invalid path '{}'", path.display()))?.to_string_lossy();
+    // a number is appended when the name is taken
+    let mut git_dir = common_dir.join("worktrees").join(name.as_ref());
+    let mut n = 1;
+    while git_dir.exists() {
+        git_dir = common_dir.join("worktrees").join(format!("{}{}", name, n));
+        n += 1;
+    }
+    fs::create_dir_all(&git_dir).map_err(|e| e.to_string())?;
+    fs::create_dir_all(path).map_err(|e| e.to_string())?;
+    let path = fs::canonicalize(path).map_err(|e| e.to_string())?;
+
+    fs::write(path.join(".git"), format!("gitdir: {}\n", git_dir.display())).map_err(|e| e.to_string())?;
+    fs::write(git_dir.join("commondir"), "../..\n").map_err(|e| e.to_string())?;
+    fs::write(git_dir.join("gitdir"), format!("{}\n", path.join(".git").display())).map_err(|e| e.to_string())?;
+    write_symbolic_ref(&path, "HEAD", &refname)?;
+
+    info(&format!("Preparing worktree (checking out '{}')", branch));
+    let commit = Commit::load(&common_dir.join("objects"), hash)?;
+    checkout_tree(&path, commit.tree())?.write_file(&path)
+}
+
+fn list(proj_root: &Path) -> Result<(), String> {
+    let roots = work_tree_roots(proj_root)?;
+    let objects_root = common_dir(proj_root).join("objects");
+    // one space more than the longest path, as git does
+    let width = roots.iter().map(|r| r.display().to_string().len()).max().unwrap_or(0) + 1;
+    for root in roots {
+        let head = Head::from_root(&root)?;
+        let hash = head.hash().map(|h| abbreviate(&objects_root, h)).unwrap_or_else(|| "0000000".to_string());
+        let branch = match head.branch() {
+            Some(branch) => format!("[{}]", branch),
+            None => "(detached HEAD)".to_string(),
+        };
+        println!("{:<width$} {} {}", root.display().to_string(), hash, branch, width = width);
+    }
+    Ok(())
+}
+
+// the main working tree first, then the linked ones by name
+pub fn work_tree_roots(proj_root: &Path) -> Result<Vec<PathBuf>, String> {
+    let common_dir = common_dir(proj_root);
+    let mut ret = Vec::new();
+    if let Some(main) = common_dir.parent().filter(|_| common_dir.ends_with(".git")) {
+        ret.push(main.to_path_buf());
+    }
+
+    let worktrees = common_dir.join("worktrees");
+    if !worktrees.is_dir() {
+        return Ok(ret);
+    }
+    let mut names: Vec<PathBuf> = fs::read_dir(&worktrees).map_err(|e| e.to_string())?.
+        filter_map(|e| e.ok().map(|e| e.path())).collect();
+    names.sort();
+    for git_dir in names {
+        // a working tree removed without `worktree remove` has no .git to point back to
+        let dot_git = match fs::read_to_string(git_dir.join("gitdir")) {
+            Ok(content) => PathBuf::from(content.trim_end()),
+            Err(_) => continue,
+        };
+        if let Some(root) = dot_git.parent().filter(|_| dot_git.is_file()) {
+            ret.push(root.to_path_buf());
+        }
+    }
+    Ok(ret)
+}
+
+#[test]
+fn test_worktree_add() {
+    use crate::common::git_dir;
+    use crate::object::{write_object, ObjType, Tree, TreeNode};
+
+    let proj_root = crate::common::test_dir("worktree_main");
+    crate::init::create_layout(&proj_root.join(".git"), false).unwrap();
+    let objects_root = proj_root.join(".git/objects");
+    let blob = write_object(&objects_root, ObjType::Blob, b"hello\n").unwrap();
+    let mut tree = Tree::new();
+    let entry = crate::index::IndexEntry::without_stat(PathBuf::from("a.txt"), 0o100644, blob);
+    tree.add_tree_node(TreeNode::from_index_entry(&entry).unwrap());
+    let commit = format!("tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nfirst\n",
+                         tree.write(&objects_root).unwrap().string());
+    let commit = write_object(&objects_root, ObjType::Commit, commit.as_bytes()).unwrap();
+    crate::refs::write_ref(&proj_root, "refs/heads/master", commit).unwrap();
+    crate::refs::write_ref(&proj_root, "refs/heads/feature", commit).unwrap();
+
+    let path = crate::common::test_dir("worktree_linked").join("wt");
+    assert!(add(&proj_root, &path, "master").is_err());
+    assert!(add(&proj_root, &path, "none").is_err());
+    add(&proj_root, &path, "feature").unwrap();
+
+    let path = fs::canonicalize(&path).unwrap();
+    assert_eq!(git_dir(&path), proj_root.join(".git/worktrees/wt"));
+    assert_eq!(common_dir(&path), fs::canonicalize(proj_root.join(".git")).unwrap());
+    assert_eq!(fs::read_to_string(path.join("a.txt")).unwrap(), "hello\n");
+    assert!(proj_root.join(".git/worktrees/wt/index").is_file());
+    let head = Head::from_root(&path).unwrap();
+    assert_eq!((head.branch(), head.hash()), (Some("feature".to_string()), Some(commit)));
+    assert_eq!(work_tree_roots(&path).unwrap(), vec![fs::canonicalize(&proj_root).unwrap(), path.clone()]);
+    assert!(add(&proj_root, &path.with_file_name("wt2"), "feature").is_err());
+}